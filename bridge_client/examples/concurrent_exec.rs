@@ -0,0 +1,31 @@
+// Runs ten Execs at once over one connection and prints their results in order.
+//
+//   cargo run -p bridge_client --features tokio --example concurrent_exec [SOCKET]
+
+use std::time::Instant;
+
+use bridge_client::AsyncBridgeClient;
+
+#[tokio::main]
+async fn main() -> Result<(), bridge_client::Error> {
+    let socket = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/tmp/bridge.sock".to_string());
+    let client = AsyncBridgeClient::connect(socket).await?;
+
+    let started = Instant::now();
+    // Every request is on the server as soon as exec returns, so the ten one-second sleeps
+    // overlap and the whole run takes about a second.
+    let replies: Vec<_> = (0..10)
+        .map(|i| {
+            let script = format!("sleep 1; echo job {} on $(getprop ro.product.model)", i);
+            client.exec("sh", &["-c", &script])
+        })
+        .collect();
+    for reply in replies {
+        let output = reply.await?;
+        println!("{} (exit code {})", output.stdout.trim_end(), output.code);
+    }
+    println!("Finished in {} ms", started.elapsed().as_millis());
+    Ok(())
+}
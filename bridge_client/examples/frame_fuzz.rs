@@ -0,0 +1,170 @@
+// Feeds the frame readers random length headers, with payloads that are complete, cut short
+// or missing, and checks each outcome: the payload for a frame within the limit, FrameTooLarge
+// for one above it, and never an allocation bigger than what actually arrived. Given a
+// socket, it then sends a running server a header over its --max-frame-size and expects
+// FrameTooLarge back. Exits 1 on the first failure.
+//
+//   cargo run -p bridge_client --example frame_fuzz [SOCKET]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bridge_client::{handshake, read_response};
+use bridge_core::proto::FrameError;
+use bridge_core::{BridgeResponse, Codec};
+
+const ROUNDS: u32 = 2_000;
+const LIMIT: u64 = 64 * 1024;
+
+// Tracks the largest single allocation, to catch a reader trusting the header.
+struct Largest;
+
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Largest {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Largest = Largest;
+
+// xorshift64*, so every run checks the same headers.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Mostly lengths around the limit, where mistakes hide, and some of any size.
+    fn length(&mut self) -> u64 {
+        match self.next() % 4 {
+            0 => self.next(),
+            1 => self.next() >> (self.next() % 64),
+            2 => LIMIT - 16 + self.next() % 32,
+            _ => self.next() % (2 * LIMIT),
+        }
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for round in 0..ROUNDS {
+        if let Err(e) = bincode_round(&mut rng) {
+            fail(round, &e);
+        }
+        if let Err(e) = json_round(&mut rng) {
+            fail(round, &e);
+        }
+    }
+    println!("{} rounds of random length headers: ok", ROUNDS);
+
+    if let Some(socket) = std::env::args().nth(1) {
+        match against_server(&socket) {
+            Ok(message) => println!("server: ok, {}", message),
+            Err(e) => {
+                println!("server: FAILED, {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn fail(round: u32, e: &str) -> ! {
+    println!("round {}: FAILED, {}", round, e);
+    std::process::exit(1);
+}
+
+// A length header, then all of the payload it announces, part of it, or none.
+fn bincode_round(rng: &mut Rng) -> Result<(), String> {
+    let len = rng.length();
+    let sent = match rng.next() % 3 {
+        0 => len.min(4 * LIMIT),
+        1 => rng.next() % (len.min(4 * LIMIT) + 1),
+        _ => 0,
+    };
+    let mut wire = len.to_be_bytes().to_vec();
+    wire.resize(8 + sent as usize, 0xAB);
+
+    LARGEST.store(0, Ordering::Relaxed);
+    let result = Codec::Bincode.read_frame(&mut Cursor::new(&wire), LIMIT);
+    let largest = LARGEST.load(Ordering::Relaxed) as u64;
+    // The reader buffers what arrived, rounded up as a Vec grows.
+    if largest > 2 * sent.max(64) {
+        return Err(format!(
+            "header {} with {} bytes sent allocated {}",
+            len, sent, largest
+        ));
+    }
+    match result {
+        Ok(Some(payload)) if len <= LIMIT && sent == len && payload.len() as u64 == len => Ok(()),
+        Err(FrameError::Malformed(_)) if len >> 32 != 0 => Ok(()),
+        Err(FrameError::Io(e)) if len <= LIMIT && sent < len && truncated(&e) => Ok(()),
+        Err(FrameError::TooLarge(e)) if len > LIMIT && e.len == len && e.limit == LIMIT => Ok(()),
+        result => Err(format!(
+            "header {} with {} bytes sent read as {:?}",
+            len,
+            sent,
+            result.map(|payload| payload.map(|payload| payload.len()))
+        )),
+    }
+}
+
+// A JSON line of random length, with or without its newline.
+fn json_round(rng: &mut Rng) -> Result<(), String> {
+    let len = rng.next() % (2 * LIMIT);
+    let complete = rng.next().is_multiple_of(2);
+    let mut wire = vec![b'x'; len as usize];
+    if complete {
+        wire.push(b'\n');
+    }
+    match Codec::Json.read_frame(&mut Cursor::new(&wire), LIMIT) {
+        Ok(Some(line)) if len <= LIMIT && complete && line.len() as u64 == len => Ok(()),
+        // An empty line is skipped, leaving nothing.
+        Ok(None) if len == 0 => Ok(()),
+        Err(FrameError::TooLarge(e)) if len > LIMIT && e.limit == LIMIT => Ok(()),
+        Err(FrameError::Io(e)) if len <= LIMIT && !complete && truncated(&e) => Ok(()),
+        result => Err(format!(
+            "{} byte line (newline: {}) read as {:?}",
+            len,
+            complete,
+            result.map(|line| line.map(|line| line.len()))
+        )),
+    }
+}
+
+fn truncated(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::UnexpectedEof
+}
+
+// Announces a 1 GiB request, far over any --max-frame-size the server accepts.
+fn against_server(socket: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| e.to_string())?;
+    handshake(&mut stream, "").map_err(|e| e.to_string())?;
+    stream
+        .write_all(&(1u64 << 30).to_be_bytes())
+        .map_err(|e| e.to_string())?;
+    match read_response(&mut stream) {
+        Ok(BridgeResponse::FrameTooLarge { len, limit }) if len == 1 << 30 => {
+            Ok(format!("FrameTooLarge for {} bytes, limit {}", len, limit))
+        }
+        response => Err(format!("expected FrameTooLarge, got {:?}", response)),
+    }
+}
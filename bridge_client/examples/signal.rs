@@ -0,0 +1,120 @@
+// Sends Signal to a background job and to a request in flight on a multiplexed connection of
+// a running server, and checks that both processes trap the signal and keep running until
+// they choose to exit. A job that is gone must fail with NotFound. Exits 1 on the first
+// failure.
+//
+//   cargo run -p bridge_client --example signal SOCKET
+
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+
+use bridge_client::multiplex::Multiplexer;
+use bridge_client::{BridgeClient, handshake};
+use bridge_core::{BridgeCommand, BridgeResponse, ErrorKind, ProcessOptions, SignalTarget};
+
+// Prints a line for each SIGHUP and exits on SIGUSR1.
+const TRAPS: &str =
+    "trap 'echo hup' HUP; trap 'echo usr1; exit 3' USR1; echo ready; while :; do sleep 0.05; done";
+
+fn main() {
+    let Some(socket) = std::env::args().nth(1) else {
+        eprintln!("usage: signal SOCKET");
+        std::process::exit(2);
+    };
+    if let Err(e) = job(&socket) {
+        fail("job", &e);
+    }
+    println!("job: ok");
+    if let Err(e) = request(&socket) {
+        fail("request", &e);
+    }
+    println!("request: ok");
+}
+
+fn job(socket: &str) -> Result<(), String> {
+    let mut client = BridgeClient::connect(socket).map_err(|e| e.to_string())?;
+    let id = client
+        .start_job("sh", &["-c", TRAPS])
+        .map_err(|e| e.to_string())?;
+    thread::sleep(Duration::from_millis(300));
+    for _ in 0..2 {
+        client
+            .signal(SignalTarget::Job(id), libc::SIGHUP)
+            .map_err(|e| format!("SIGHUP: {}", e))?;
+        thread::sleep(Duration::from_millis(200));
+    }
+    client
+        .signal(SignalTarget::Job(id), libc::SIGUSR1)
+        .map_err(|e| format!("SIGUSR1: {}", e))?;
+    thread::sleep(Duration::from_millis(300));
+
+    let output = job_output(socket, id)?;
+    if output != "ready\nhup\nhup\nusr1\n" {
+        return Err(format!("the job wrote {:?}", output));
+    }
+    match client.request(BridgeCommand::Signal {
+        target: SignalTarget::Job(u64::MAX),
+        signal: libc::SIGHUP,
+    }) {
+        Ok(BridgeResponse::Failed(e)) if e.kind == ErrorKind::NotFound => Ok(()),
+        other => Err(format!("signalling no job answered {:?}", other)),
+    }
+}
+
+// What job `id` wrote to stdout, read on a connection of its own.
+fn job_output(socket: &str, id: u64) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| e.to_string())?;
+    handshake(&mut stream, "").map_err(|e| e.to_string())?;
+    bridge_client::send_command(&mut stream, &BridgeCommand::JobOutput { id, follow: false })
+        .map_err(|e| e.to_string())?;
+    let mut stdout = String::new();
+    loop {
+        match bridge_client::read_response(&mut stream).map_err(|e| e.to_string())? {
+            BridgeResponse::StdoutChunk(bytes) => stdout.push_str(&String::from_utf8_lossy(&bytes)),
+            BridgeResponse::StderrChunk(_) => {}
+            _ => return Ok(stdout),
+        }
+    }
+}
+
+fn request(socket: &str) -> Result<(), String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| e.to_string())?;
+    handshake(&mut stream, "").map_err(|e| e.to_string())?;
+    let multiplexer = Multiplexer::new(Box::new(stream)).map_err(|e| e.to_string())?;
+    let exec = multiplexer
+        .start(BridgeCommand::Exec {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), TRAPS.to_string()],
+            stdin: false,
+            options: ProcessOptions::default(),
+        })
+        .map_err(|e| e.to_string())?;
+    let id = exec.id;
+    thread::sleep(Duration::from_millis(300));
+    for signal in [libc::SIGHUP, libc::SIGUSR1] {
+        match multiplexer.signal(id, signal) {
+            Ok(BridgeResponse::Success(_)) => {}
+            other => return Err(format!("signal {} answered {:?}", signal, other)),
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    match exec.wait().map_err(|e| e.to_string())? {
+        BridgeResponse::Completed { stdout, code, .. }
+            if stdout == "ready\nhup\nusr1\n" && code == 3 => {}
+        other => return Err(format!("the request ended with {:?}", other)),
+    }
+    // The request is over, and its id no longer names anything.
+    match multiplexer.signal(id, libc::SIGHUP) {
+        Ok(BridgeResponse::Failed(e)) if e.kind == ErrorKind::NotFound => Ok(()),
+        other => Err(format!(
+            "signalling a finished request answered {:?}",
+            other
+        )),
+    }
+}
+
+fn fail(name: &str, e: &str) -> ! {
+    println!("{}: FAILED, {}", name, e);
+    std::process::exit(1);
+}
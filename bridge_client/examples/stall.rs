@@ -0,0 +1,129 @@
+// Plays clients that stall to check the server's --idle-timeout: one that sends nothing, one
+// that stops halfway through its hello, and one halfway through a request. Each must get an
+// Idle Timeout error and see the connection close in time. A Stream that stays quiet for
+// longer than the timeout and a client that waits that long between requests must both go
+// through. Exits 1 if anything goes wrong.
+//
+//   cargo run -p bridge_client --example stall [SOCKET] [SECS]
+//
+// Meant for a server started with `--idle-timeout SECS` (default 2), since every case waits
+// for it to run out.
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_client::{BridgeClient, Chunk, handshake, read_response};
+use bridge_core::BridgeResponse;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let socket: PathBuf = args
+        .next()
+        .unwrap_or_else(|| "/tmp/bridge.sock".to_string())
+        .into();
+    let secs: u64 = args.next().map_or(2, |secs| secs.parse().expect("SECS"));
+    let timeout = Duration::from_secs(secs);
+
+    let results = [
+        ("silent client", stalled(&socket, timeout, |_| Ok(()))),
+        (
+            "half a hello",
+            // The first half of the length prefix.
+            stalled(&socket, timeout, |stream| stream.write_all(&[0, 0, 0, 0])),
+        ),
+        (
+            "half a request",
+            stalled(&socket, timeout, |stream| {
+                handshake(stream, "")?;
+                // A length prefix promising 100 bytes, and 3 of them.
+                stream.write_all(&100u64.to_be_bytes())?;
+                stream.write_all(b"abc")
+            }),
+        ),
+        ("quiet stream", quiet_stream(&socket, timeout)),
+        ("idle client", idle_client(&socket, timeout)),
+    ];
+
+    let mut failed = false;
+    for (name, result) in results {
+        match result {
+            Ok(()) => println!("{}: ok", name),
+            Err(e) => {
+                println!("{}: FAILED, {}", name, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// Connects, does `stall` and then nothing more. The server has to answer with an Idle
+// Timeout error once `timeout` is up, and close the connection.
+fn stalled(
+    socket: &PathBuf,
+    timeout: Duration,
+    stall: impl FnOnce(&mut UnixStream) -> std::io::Result<()>,
+) -> Result<(), String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| e.to_string())?;
+    // Well past the point where the server should have given up.
+    stream
+        .set_read_timeout(Some(timeout * 2 + Duration::from_secs(2)))
+        .map_err(|e| e.to_string())?;
+    let started = Instant::now();
+    stall(&mut stream).map_err(|e| e.to_string())?;
+    match read_response(&mut stream) {
+        Ok(BridgeResponse::Error(e)) if e.starts_with("Idle Timeout") => {}
+        Ok(response) => {
+            return Err(format!(
+                "expected an Idle Timeout error, got {:?}",
+                response
+            ));
+        }
+        Err(e) => return Err(format!("expected an Idle Timeout error: {}", e)),
+    }
+    let waited = started.elapsed();
+    if waited < timeout {
+        return Err(format!("closed after {:?}, before the timeout", waited));
+    }
+    match read_response(&mut stream) {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+        Ok(response) => Err(format!("connection still open, got {:?}", response)),
+        Err(e) => Err(format!("connection not closed: {}", e)),
+    }
+}
+
+// A Stream whose command prints nothing until after the timeout: the running command keeps
+// the connection open.
+fn quiet_stream(socket: &PathBuf, timeout: Duration) -> Result<(), String> {
+    let mut client = BridgeClient::connect(socket).map_err(|e| e.to_string())?;
+    let script = format!("sleep {}; echo done", timeout.as_secs() + 1);
+    let chunks = client
+        .stream("sh", &["-c", &script])
+        .map_err(|e| e.to_string())?;
+    let mut stdout = Vec::new();
+    for chunk in chunks {
+        match chunk {
+            Chunk::Stdout(bytes) => stdout.extend(bytes),
+            Chunk::Exit { code: Some(0), .. } if stdout == b"done\n" => return Ok(()),
+            chunk => return Err(format!("unexpected {:?}", chunk)),
+        }
+    }
+    Err("the stream ended without an exit status".to_string())
+}
+
+// A client that waits out the timeout between two requests: the server closes the idle
+// connection, and the second request goes out on a new one.
+fn idle_client(socket: &PathBuf, timeout: Duration) -> Result<(), String> {
+    let mut client = BridgeClient::connect(socket).map_err(|e| e.to_string())?;
+    client.ping().map_err(|e| e.to_string())?;
+    thread::sleep(timeout + Duration::from_secs(1));
+    client
+        .ping()
+        .map(|_| ())
+        .map_err(|e| format!("second ping: {}", e))
+}
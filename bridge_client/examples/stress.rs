@@ -0,0 +1,84 @@
+// Fires 200 Pings and 50 Execs at the server at once, each on a connection of its own, and
+// checks that every one of them gets its answer. Exits 1 if any is lost or wrong.
+//
+//   cargo run -p bridge_client --example stress [SOCKET]
+//
+// Meant for a server with its default --max-connections and --max-jobs, which are far below
+// these numbers: connections beyond the limit wait for a free thread and Execs for a free
+// process slot, so nothing may fail. Under `--when-busy reject` the Execs that find no slot
+// are counted as busy instead.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use bridge_client::{BridgeClient, Error};
+
+const PINGS: u32 = 200;
+const EXECS: u32 = 50;
+
+fn main() {
+    let socket: PathBuf = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/tmp/bridge.sock".to_string())
+        .into();
+    let failed = Arc::new(AtomicU32::new(0));
+    let busy = Arc::new(AtomicU32::new(0));
+
+    let started = Instant::now();
+    let pings = (0..PINGS).map(|i| {
+        let (socket, failed) = (socket.clone(), Arc::clone(&failed));
+        thread::spawn(move || {
+            let answer = BridgeClient::connect(&socket).and_then(|mut client| client.ping());
+            if let Err(e) = answer {
+                eprintln!("ping {}: {}", i, e);
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    });
+    let execs = (0..EXECS).map(|i| {
+        let (socket, failed, busy) = (socket.clone(), Arc::clone(&failed), Arc::clone(&busy));
+        thread::spawn(move || {
+            // Long enough for the Execs to overlap, and for the slots to run out.
+            let script = format!("sleep 0.2; echo {}", i);
+            let output = BridgeClient::connect(&socket)
+                .and_then(|mut client| client.exec("sh", &["-c", &script]));
+            match output {
+                Ok(output) if output.code == 0 && output.stdout.trim() == i.to_string() => {}
+                Ok(output) => {
+                    eprintln!(
+                        "exec {}: exit code {}, stdout {:?}",
+                        i, output.code, output.stdout
+                    );
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(Error::Busy { .. }) => {
+                    busy.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    eprintln!("exec {}: {}", i, e);
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })
+    });
+    let threads: Vec<_> = pings.chain(execs).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let (failed, busy) = (failed.load(Ordering::Relaxed), busy.load(Ordering::Relaxed));
+    println!(
+        "{} Pings and {} Execs in {} ms: {} failed, {} busy",
+        PINGS,
+        EXECS,
+        started.elapsed().as_millis(),
+        failed,
+        busy
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,500 @@
+// `andro app`: launching, stopping, clearing, installing and listing apps without the `am`
+// and `pm` incantations. Each action runs the host commands it stands for over one session
+// and reads their output, which is meant for people, back into a result: what was done, or
+// the error am or pm reported (`Error type 3`, `Failure [INSTALL_FAILED_...]`). With --json
+// the result is one object holding the parsed fields.
+
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Subcommand;
+use serde::Serialize;
+
+use bridge_client::session::Session;
+use bridge_client::{
+    Retry, Server, WireCodec, connect_retrying, handshake, read_response, send_command,
+};
+use bridge_core::{BridgeCommand, BridgeResponse, ErrorKind, ProcessOptions};
+
+use crate::progress::Progress;
+use crate::{fail, failed_code, print_response, read_failed, report, transfer};
+
+// Where APKs wait for `pm install`; readable by the package manager, unlike most places.
+const STAGING_DIR: &str = "/data/local/tmp";
+
+#[derive(Subcommand)]
+pub enum AppAction {
+    /// Start an app through its launcher activity, or the given one
+    /// (`com.example.app/.MainActivity`)
+    Launch {
+        #[arg(value_name = "PACKAGE[/ACTIVITY]")]
+        target: String,
+    },
+    /// Force-stop an app
+    Stop { package: String },
+    /// Delete an app's data and cache
+    Clear { package: String },
+    /// Install or update an app from an APK on this side; it is pushed to the host, installed
+    /// with pm and removed again
+    Install { apk: PathBuf },
+    /// List the installed packages
+    List {
+        /// Only apps the user installed, not those of the system
+        #[arg(short = '3', long)]
+        third_party: bool,
+    },
+}
+
+// What an action did; the fields besides `status` and `exit_code` are set as they apply.
+#[derive(Serialize, Default)]
+struct Outcome {
+    status: &'static str,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<String>,
+    // The activity `launch` started, as `package/activity`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    component: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packages: Option<Vec<String>>,
+    // What am said besides starting the activity, e.g. that the app was already in front.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+    // "not_installed", "no_launcher", "am" or "pm".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    // am's `Error type N`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_type: Option<u32>,
+    // pm's `Failure [CODE]`, e.g. INSTALL_FAILED_VERSION_DOWNGRADE.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<String>,
+    exit_code: i32,
+}
+
+impl Outcome {
+    fn success(action: &'static str) -> Outcome {
+        Outcome {
+            status: "success",
+            action,
+            ..Outcome::default()
+        }
+    }
+
+    fn error(action: &'static str, kind: &'static str, message: String, exit_code: i32) -> Outcome {
+        Outcome {
+            status: "error",
+            action,
+            kind: Some(kind),
+            message: Some(message),
+            exit_code,
+            ..Outcome::default()
+        }
+    }
+}
+
+// A response other than Completed ends the action with what it says, printed the usual way;
+// this is its exit code.
+struct Ended(i32);
+
+type Step<T> = Result<T, Ended>;
+
+// Returns the exit code: 0 if the action succeeded.
+pub fn run(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    action: AppAction,
+) -> i32 {
+    // Opened first, so a typo in the path fails without touching the host.
+    let apk = match &action {
+        AppAction::Install { apk } => match File::open(apk) {
+            Ok(file) => Some(file),
+            Err(e) => fail("usage", &format!("cannot open {}: {}", apk.display(), e), 2),
+        },
+        _ => None,
+    };
+    let mut session = match Session::open(server.clone(), codec, token.clone(), retry) {
+        Ok(session) => session,
+        Err(e) => fail(
+            "connect",
+            &format!("cannot connect to {}: {}", server, e),
+            1,
+        ),
+    };
+    let outcome = match action {
+        AppAction::Launch { target } => launch(&mut session, &target),
+        AppAction::Stop { package } => stop(&mut session, package),
+        AppAction::Clear { package } => clear(&mut session, package),
+        AppAction::Install { apk: path } => {
+            let pushed = push(&server, codec, &token, &retry, apk.unwrap(), &path);
+            pushed.and_then(|staged| install(&mut session, &path, &staged))
+        }
+        AppAction::List { third_party } => list(&mut session, third_party),
+    };
+    match outcome {
+        Ok(outcome) => show(&outcome),
+        Err(Ended(code)) => code,
+    }
+}
+
+fn launch(session: &mut Session, target: &str) -> Step<Outcome> {
+    let package = target.split('/').next().unwrap_or_default().to_string();
+    let component = match target.contains('/') {
+        true => target.to_string(),
+        false => {
+            if let Some(outcome) = not_installed(session, "launch", &package)? {
+                return Ok(outcome);
+            }
+            let output = exec(
+                session,
+                "cmd",
+                &[
+                    "package",
+                    "resolve-activity",
+                    "--brief",
+                    "-c",
+                    "android.intent.category.LAUNCHER",
+                    &package,
+                ],
+            )?;
+            match launcher(&output.stdout) {
+                Some(component) => component,
+                None => {
+                    let message = format!("{} has no launcher activity", package);
+                    return Ok(Outcome {
+                        package: Some(package),
+                        ..Outcome::error("launch", "no_launcher", message, 1)
+                    });
+                }
+            }
+        }
+    };
+    let output = exec(session, "am", &["start", "-n", &component])?;
+    let mut outcome = match am_start(&output) {
+        Ok(warning) => Outcome {
+            component: Some(component),
+            warning,
+            ..Outcome::success("launch")
+        },
+        Err((error_type, message)) => Outcome {
+            error_type,
+            ..Outcome::error("launch", "am", message, 1)
+        },
+    };
+    outcome.package = Some(package);
+    Ok(outcome)
+}
+
+fn stop(session: &mut Session, package: String) -> Step<Outcome> {
+    if let Some(outcome) = not_installed(session, "stop", &package)? {
+        return Ok(outcome);
+    }
+    // force-stop says nothing, whether there was anything to stop or not.
+    let output = exec(session, "am", &["force-stop", &package])?;
+    let outcome = match output.code {
+        0 => Outcome::success("stop"),
+        code => Outcome::error("stop", "am", error_message(&output), code),
+    };
+    Ok(Outcome {
+        package: Some(package),
+        ..outcome
+    })
+}
+
+fn clear(session: &mut Session, package: String) -> Step<Outcome> {
+    if let Some(outcome) = not_installed(session, "clear", &package)? {
+        return Ok(outcome);
+    }
+    let output = exec(session, "pm", &["clear", &package])?;
+    let outcome = match pm_result(&output) {
+        Ok(()) => Outcome::success("clear"),
+        Err((failure, message)) => Outcome {
+            failure,
+            // All older pm says is "Failed".
+            ..Outcome::error(
+                "clear",
+                "pm",
+                format!("cannot clear {}: {}", package, message),
+                1,
+            )
+        },
+    };
+    Ok(Outcome {
+        package: Some(package),
+        ..outcome
+    })
+}
+
+// Sends the APK to STAGING_DIR on a connection of its own, since a push takes one over;
+// returns where it went.
+fn push(
+    server: &Server,
+    codec: WireCodec,
+    token: &str,
+    retry: &Retry,
+    mut apk: File,
+    path: &Path,
+) -> Step<String> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staged = format!("{}/andro-{}-{}", STAGING_DIR, process::id(), name);
+    let size = apk.metadata().map_err(|e| io_failed(&e))?.len();
+    let mut stream = match connect_retrying(server, codec, retry) {
+        Ok(stream) => stream,
+        Err(e) => fail(
+            "connect",
+            &format!("cannot connect to {}: {}", server, e),
+            1,
+        ),
+    };
+    let cmd = BridgeCommand::PushFile {
+        dest: staged.clone(),
+        mode: 0o644,
+        resume: false,
+        verify: false,
+    };
+    handshake(&mut *stream, token)
+        .and_then(|()| send_command(&mut *stream, &cmd))
+        .map_err(|e| io_failed(&e))?;
+    match read_response(&mut *stream).map_err(|e| Ended(read_failed(&e)))? {
+        BridgeResponse::TransferReady { .. } => {}
+        response => return Err(Ended(print_response(response))),
+    }
+    let quiet = report::enabled() || !io::stderr().is_terminal();
+    let mut progress = Progress::new(Some(size), 0, quiet);
+    let sent = transfer::send_file(&mut *stream, &mut apk, &mut progress);
+    progress.finish();
+    // A server that gave up on the file says why before closing.
+    match read_response(&mut *stream) {
+        Ok(BridgeResponse::Transferred { .. }) => Ok(staged),
+        Ok(response) => Err(Ended(print_response(response))),
+        Err(e) => Err(Ended(read_failed(&sent.err().unwrap_or(e)))),
+    }
+}
+
+fn install(session: &mut Session, path: &Path, staged: &str) -> Step<Outcome> {
+    // -r replaces an installed version, which newer pm versions do anyway.
+    let installed = exec(session, "pm", &["install", "-r", staged]);
+    // Removed whatever became of the install.
+    let _ = exec(session, "rm", &["-f", staged]);
+    let output = installed?;
+    let outcome = match pm_result(&output) {
+        Ok(()) => Outcome::success("install"),
+        Err((failure, message)) => Outcome {
+            failure,
+            ..Outcome::error("install", "pm", message, 1)
+        },
+    };
+    Ok(Outcome {
+        message: outcome
+            .message
+            .or_else(|| Some(format!("installed {}", path.display()))),
+        ..outcome
+    })
+}
+
+fn list(session: &mut Session, third_party: bool) -> Step<Outcome> {
+    let mut args = vec!["list", "packages"];
+    if third_party {
+        args.push("-3");
+    }
+    let output = exec(session, "pm", &args)?;
+    if output.code != 0 {
+        return Ok(Outcome::error(
+            "list",
+            "pm",
+            error_message(&output),
+            output.code,
+        ));
+    }
+    let mut packages: Vec<String> = output
+        .stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(str::to_string)
+        .collect();
+    packages.sort();
+    Ok(Outcome {
+        packages: Some(packages),
+        ..Outcome::success("list")
+    })
+}
+
+// An error outcome if `package` is not installed, which am and pm do not always say.
+fn not_installed(
+    session: &mut Session,
+    action: &'static str,
+    package: &str,
+) -> Step<Option<Outcome>> {
+    let output = exec(session, "pm", &["path", package])?;
+    if output.code == 0 && output.stdout.contains("package:") {
+        return Ok(None);
+    }
+    let message = format!("{} is not installed", package);
+    Ok(Some(Outcome {
+        package: Some(package.to_string()),
+        ..Outcome::error(
+            action,
+            "not_installed",
+            message,
+            failed_code(ErrorKind::NotFound),
+        )
+    }))
+}
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+fn exec(session: &mut Session, program: &str, args: &[&str]) -> Step<Output> {
+    let cmd = BridgeCommand::Exec {
+        program: program.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        stdin: false,
+        options: ProcessOptions::default(),
+    };
+    match session.request(cmd) {
+        Ok(BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        }) => Ok(Output {
+            stdout,
+            stderr,
+            code,
+        }),
+        Ok(response) => Err(Ended(print_response(response))),
+        Err(e) => Err(Ended(read_failed(&e))),
+    }
+}
+
+fn io_failed(e: &io::Error) -> Ended {
+    if report::enabled() {
+        report::error("connect", &e.to_string(), 1);
+    } else {
+        eprintln!("Error: {}", e);
+    }
+    Ended(1)
+}
+
+// The component in the output of `cmd package resolve-activity --brief`, whose last line
+// is `package/activity` if there is one; otherwise it says "No activity found".
+fn launcher(stdout: &str) -> Option<String> {
+    let last = stdout
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())?;
+    (last.contains('/') && !last.contains(' ')).then(|| last.to_string())
+}
+
+// Reads `am start`: Ok with its warning, if any, or am's error type and message. Older am
+// exits 0 even when it fails, and writes errors to stdout or stderr depending on the version.
+fn am_start(output: &Output) -> Result<Option<String>, (Option<u32>, String)> {
+    let lines = || {
+        output
+            .stdout
+            .lines()
+            .chain(output.stderr.lines())
+            .map(str::trim)
+    };
+    let error_type = lines()
+        .find_map(|line| line.strip_prefix("Error type "))
+        .and_then(|n| n.trim().parse().ok());
+    let error = lines().find_map(|line| line.strip_prefix("Error: "));
+    match error {
+        Some(message) => Err((error_type, message.to_string())),
+        None if error_type.is_some() || output.code != 0 => {
+            Err((error_type, error_message(output)))
+        }
+        None => Ok(lines()
+            .find_map(|line| line.strip_prefix("Warning: "))
+            .map(str::to_string)),
+    }
+}
+
+// Reads what pm install and pm clear print: a line saying Success, or `Failure [CODE]`
+// (`Failure [CODE: details]`), an `Error: ...` line, or for clear just "Failed". The error
+// is the failure code, if there is one, and the message.
+fn pm_result(output: &Output) -> Result<(), (Option<String>, String)> {
+    let lines = || {
+        output
+            .stdout
+            .lines()
+            .chain(output.stderr.lines())
+            .map(str::trim)
+    };
+    if lines().any(|line| line == "Success") {
+        return Ok(());
+    }
+    if let Some(failure) = lines().find_map(|line| line.strip_prefix("Failure [")) {
+        let failure = failure.trim_end_matches(']');
+        let (code, details) = match failure.split_once(':') {
+            Some((code, details)) => (code.trim(), details.trim()),
+            None => (failure.trim(), ""),
+        };
+        let message = match details {
+            "" => code.to_string(),
+            details => format!("{}: {}", code, details),
+        };
+        return Err((Some(code.to_string()), message));
+    }
+    match lines().find_map(|line| line.strip_prefix("Error: ")) {
+        Some(message) => Err((None, message.to_string())),
+        None => Err((None, error_message(output))),
+    }
+}
+
+// What a command that failed without a recognized error said, for its message.
+fn error_message(output: &Output) -> String {
+    let said = match output.stderr.trim() {
+        "" => output.stdout.trim(),
+        stderr => stderr,
+    };
+    match said {
+        "" => format!("exit code {}", output.code),
+        said => said.to_string(),
+    }
+}
+
+fn show(outcome: &Outcome) -> i32 {
+    if report::enabled() {
+        report::app(outcome);
+        return outcome.exit_code;
+    }
+    let package = outcome.package.as_deref().unwrap_or_default();
+    if outcome.status != "success" {
+        let message = outcome.message.as_deref().unwrap_or_default();
+        match outcome.error_type {
+            Some(error_type) => eprintln!("Error: {} (am error type {})", message, error_type),
+            None => eprintln!("Error: {}", message),
+        }
+        return outcome.exit_code;
+    }
+    match outcome.action {
+        "launch" => {
+            println!(
+                "Started {}",
+                outcome.component.as_deref().unwrap_or(package)
+            );
+            if let Some(warning) = &outcome.warning {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+        "stop" => println!("Stopped {}", package),
+        "clear" => println!("Cleared the data of {}", package),
+        "install" => println!("{}", outcome.message.as_deref().unwrap_or_default()),
+        _ => {
+            for package in outcome.packages.iter().flatten() {
+                println!("{}", package);
+            }
+        }
+    }
+    0
+}
@@ -0,0 +1,488 @@
+// An async client over one multiplexed connection, for tokio (the `tokio` feature).
+// Connecting, the handshake and asking the server what it can do run on tokio's sockets;
+// the connection is then handed to the multiplexer, whose reader thread completes requests
+// and wakes whichever task awaits them, so no task blocks and nothing needs spawn_blocking.
+// Frames are built and parsed by the same bridge_core code as in the blocking client; only
+// the waiting differs.
+
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use bridge_core::proto::FrameError;
+use bridge_core::transport::{JsonLines, Transport};
+use bridge_core::{BridgeCommand, BridgeResponse, Codec, ProcessOptions, abstract_name};
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::client::{chunk, done, exec_command, output, stream_command, tap_command};
+use crate::multiplex::{Deliver, Multiplexer};
+use crate::session::features;
+use crate::{
+    Chunk, Error, Output, Result, Server, WireCodec, decode_response, handshake_result,
+    hello_version, max_frame_len, server_hello, server_protocol,
+};
+
+/// A connection for async code. Its methods take `&self` and the client is cheap to clone,
+/// so any number of requests can be in flight from different tasks.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), bridge_client::Error> {
+/// let client = bridge_client::AsyncBridgeClient::connect("/tmp/bridge.sock").await?;
+/// let model = client.exec("getprop", &["ro.product.model"]);
+/// let battery = client.exec("dumpsys", &["battery"]);
+/// // Both already run on the server; awaiting only collects the results.
+/// println!("{}", model.await?.stdout);
+/// println!("{}", battery.await?.stdout);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncBridgeClient {
+    multiplexer: Multiplexer,
+}
+
+// Responses waiting for the task that reads them.
+#[derive(Default)]
+struct Queue {
+    responses: VecDeque<BridgeResponse>,
+    // No more responses are coming.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+type Shared = Arc<Mutex<Queue>>;
+
+// The multiplexer's end of a Queue; dropping it closes the queue.
+struct Feed(Shared);
+
+impl Deliver for Feed {
+    fn deliver(&self, response: BridgeResponse) {
+        let mut queue = self.0.lock().unwrap();
+        queue.responses.push_back(response);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().unwrap();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// The next response, None once the queue is closed and empty.
+fn poll_queue(queue: &Shared, cx: &mut Context) -> Poll<Option<BridgeResponse>> {
+    let mut queue = queue.lock().unwrap();
+    if let Some(response) = queue.responses.pop_front() {
+        return Poll::Ready(Some(response));
+    }
+    if queue.closed {
+        return Poll::Ready(None);
+    }
+    queue.waker = Some(cx.waker().clone());
+    Poll::Pending
+}
+
+impl AsyncBridgeClient {
+    /// Connects to the server's Unix socket without a token.
+    pub async fn connect(socket: impl AsRef<Path>) -> Result<AsyncBridgeClient> {
+        let server = Server::Unix(socket.as_ref().to_path_buf());
+        AsyncBridgeClient::connect_to(server, WireCodec::Bincode, "").await
+    }
+
+    /// Connects to any server that multiplexes, presenting `token` to those that require one.
+    pub async fn connect_to(
+        server: Server,
+        codec: WireCodec,
+        token: &str,
+    ) -> Result<AsyncBridgeClient> {
+        let codec = match codec {
+            WireCodec::Bincode => Codec::Bincode,
+            WireCodec::Json => Codec::Json,
+        };
+        let failed = |e: io::Error| {
+            io::Error::new(e.kind(), format!("Failed to connect to {}: {}", server, e))
+        };
+        let (stream, features): (Box<dyn Transport>, _) = match &server {
+            Server::Unix(socket) => {
+                // tokio takes a leading NUL for the abstract namespace.
+                let mut stream = match abstract_name(socket) {
+                    Some(name) => {
+                        let path = [b"\0", name].concat();
+                        UnixStream::connect(OsStr::from_bytes(&path)).await
+                    }
+                    None => UnixStream::connect(socket).await,
+                }
+                .map_err(failed)?;
+                let features = introduce(&mut stream, codec, token).await?;
+                let stream = stream.into_std()?;
+                stream.set_nonblocking(false)?;
+                (Box::new(stream), features)
+            }
+            Server::Tcp(address) => {
+                let mut stream = TcpStream::connect(address).await.map_err(failed)?;
+                stream.set_nodelay(true)?;
+                let features = introduce(&mut stream, codec, token).await?;
+                let stream = stream.into_std()?;
+                stream.set_nonblocking(false)?;
+                (Box::new(stream), features)
+            }
+        };
+        if !features.iter().any(|feature| feature == "multiplex") {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the server does not multiplex; the async client needs a newer bridge_server",
+            )));
+        }
+        let stream = match codec {
+            Codec::Bincode => stream,
+            Codec::Json => Box::new(JsonLines(stream)),
+        };
+        Ok(AsyncBridgeClient {
+            multiplexer: Multiplexer::new(stream)?,
+        })
+    }
+
+    /// Sends any command with a single response; see [`BridgeClient::request`].
+    ///
+    /// [`BridgeClient::request`]: crate::BridgeClient::request
+    pub fn request(&self, cmd: BridgeCommand) -> Reply<BridgeResponse> {
+        self.send(cmd, Ok)
+    }
+
+    /// Runs `program` on the host. The request is sent right away; the future resolves once
+    /// the process has finished.
+    pub fn exec(&self, program: &str, args: &[&str]) -> Reply<Output> {
+        self.exec_with(program, args, ProcessOptions::default())
+    }
+
+    pub fn exec_with(
+        &self,
+        program: &str,
+        args: &[&str],
+        options: ProcessOptions,
+    ) -> Reply<Output> {
+        self.send(exec_command(program, args, options), output)
+    }
+
+    /// Taps the screen at (`x`, `y`) in pixels. Needs a server built with direct input.
+    pub fn tap(&self, x: i32, y: i32) -> Reply<()> {
+        self.send(tap_command(x, y), done)
+    }
+
+    /// Runs `program` on the host and returns its output as it is written; see
+    /// [`AsyncChunks`].
+    pub fn stream(&self, program: &str, args: &[&str]) -> Result<AsyncChunks> {
+        self.stream_with(program, args, ProcessOptions::default())
+    }
+
+    pub fn stream_with(
+        &self,
+        program: &str,
+        args: &[&str],
+        options: ProcessOptions,
+    ) -> Result<AsyncChunks> {
+        let queue = Shared::default();
+        let cmd = stream_command(program, args, options);
+        let id = self
+            .multiplexer
+            .send(cmd, Box::new(Feed(Arc::clone(&queue))))?;
+        Ok(AsyncChunks {
+            queue,
+            cancel: Some((self.multiplexer.clone(), id)),
+        })
+    }
+
+    fn send<T>(&self, cmd: BridgeCommand, convert: fn(BridgeResponse) -> Result<T>) -> Reply<T> {
+        let queue = Shared::default();
+        let state = match self
+            .multiplexer
+            .send(cmd, Box::new(Feed(Arc::clone(&queue))))
+        {
+            Ok(_) => Ok(queue),
+            Err(e) => Err(Some(Error::Io(e))),
+        };
+        Reply { state, convert }
+    }
+}
+
+/// The answer to a request of an [`AsyncBridgeClient`]. The request runs whether or not
+/// this is awaited.
+pub struct Reply<T> {
+    // The error, until it is handed out, if the request could not even be sent.
+    state: std::result::Result<Shared, Option<Error>>,
+    convert: fn(BridgeResponse) -> Result<T>,
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        let reply = self.get_mut();
+        let queue = match &mut reply.state {
+            Ok(queue) => queue,
+            Err(e) => {
+                let e = e.take().expect("Reply polled after it completed");
+                return Poll::Ready(Err(e));
+            }
+        };
+        poll_queue(queue, cx).map(|response| match response {
+            Some(response) => (reply.convert)(response),
+            None => Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the connection closed before the response arrived",
+            ))),
+        })
+    }
+}
+
+/// The output of an [`AsyncBridgeClient::stream`] as a [`futures_core::Stream`], ending
+/// after [`Chunk::Exit`] or early if the connection breaks. Dropping it before then stops the
+/// process.
+///
+/// ```no_run
+/// # async fn run(client: bridge_client::AsyncBridgeClient) -> Result<(), bridge_client::Error> {
+/// let mut log = client.stream("logcat", &[])?;
+/// while let Some(chunk) = log.next().await {
+///     if let bridge_client::Chunk::Stdout(bytes) = chunk {
+///         print!("{}", String::from_utf8_lossy(&bytes));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncChunks {
+    queue: Shared,
+    // The request to cancel if the stream is dropped early.
+    cancel: Option<(Multiplexer, u64)>,
+}
+
+impl AsyncChunks {
+    /// The next chunk, for callers without a `StreamExt` at hand.
+    pub async fn next(&mut self) -> Option<Chunk> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for AsyncChunks {
+    type Item = Chunk;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Chunk>> {
+        let chunks = self.get_mut();
+        loop {
+            let Some(response) = std::task::ready!(poll_queue(&chunks.queue, cx)) else {
+                chunks.cancel = None;
+                return Poll::Ready(None);
+            };
+            if let Some(chunk) = chunk(response) {
+                if let Chunk::Exit { .. } = chunk {
+                    chunks.cancel = None;
+                }
+                return Poll::Ready(Some(chunk));
+            }
+        }
+    }
+}
+
+impl Drop for AsyncChunks {
+    fn drop(&mut self) {
+        if let Some((multiplexer, id)) = self.cancel.take() {
+            multiplexer.canceller(id)();
+        }
+    }
+}
+
+// The hello exchange and Info on a fresh connection: what the server can do.
+async fn introduce<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    codec: Codec,
+    token: &str,
+) -> io::Result<Vec<String>> {
+    let mut arrived = Vec::new();
+    let mut hello = Vec::new();
+    codec.write_hello(&mut hello, hello_version(codec), token.as_bytes())?;
+    stream.write_all(&hello).await?;
+    server_hello(codec, &next_frame(stream, codec, &mut arrived).await?)?;
+    let verdict = next_frame(stream, codec, &mut arrived).await?;
+    handshake_result(decode_response(codec, &verdict)?)?;
+
+    let mut info = Vec::new();
+    let payload = codec.encode_command_for(&BridgeCommand::Info, server_protocol())?;
+    codec.write_frame(&mut info, &payload)?;
+    stream.write_all(&info).await?;
+    let answer = next_frame(stream, codec, &mut arrived).await?;
+    // The server says nothing unasked, so nothing can be left for the multiplexer.
+    debug_assert!(arrived.is_empty());
+    Ok(features(decode_response(codec, &answer)?))
+}
+
+// The next frame from the server. `arrived` collects what comes in until bridge_core's frame
+// reader finds a whole frame in it, and keeps whatever follows that frame.
+async fn next_frame<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    codec: Codec,
+    arrived: &mut Vec<u8>,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let mut unread = &arrived[..];
+        match codec.read_frame(&mut unread, max_frame_len()) {
+            Ok(Some(frame)) => {
+                let used = arrived.len() - unread.len();
+                arrived.drain(..used);
+                return Ok(frame);
+            }
+            // Not all of it yet.
+            Ok(None) => {}
+            Err(FrameError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) => return Err(e.into()),
+        }
+        if stream.read_buf(arrived).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the server closed the connection",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+
+    use bridge_core::{Load, PROTOCOL_VERSION, ServerInfo};
+
+    // A server on a temp socket that answers Info with `features`, then Tagged requests: Exec
+    // echoes its arguments, Stream writes one chunk per argument.
+    fn test_server(name: &str, features: &'static [&'static str]) -> std::path::PathBuf {
+        let socket = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                thread::spawn(move || serve(stream, features));
+            }
+        });
+        socket
+    }
+
+    fn serve(mut stream: UnixStream, features: &[&str]) {
+        let codec = Codec::Bincode;
+        let send = |stream: &mut UnixStream, response: BridgeResponse| {
+            let payload = codec.encode_response(&response).unwrap();
+            codec.write_frame(stream, &payload)
+        };
+        let next = |stream: &mut UnixStream| codec.read_frame(stream, 1 << 20).ok().flatten();
+        if next(&mut stream).is_none() {
+            return;
+        }
+        codec
+            .write_hello(&mut stream, PROTOCOL_VERSION, b"")
+            .unwrap();
+        send(&mut stream, BridgeResponse::Success(String::new())).unwrap();
+        while let Some(payload) = next(&mut stream) {
+            let (request_id, cmd) = match codec.decode_command(&payload).unwrap() {
+                BridgeCommand::Info => {
+                    let info = ServerInfo {
+                        version: "test".to_string(),
+                        protocol: PROTOCOL_VERSION,
+                        features: features.iter().map(|f| f.to_string()).collect(),
+                        touch_device: None,
+                        commands: Vec::new(),
+                        load: Load::default(),
+                    };
+                    send(&mut stream, BridgeResponse::ServerInfo(info)).unwrap();
+                    continue;
+                }
+                BridgeCommand::Tagged {
+                    request_id,
+                    command,
+                } => (request_id, *command),
+                other => panic!("unexpected {:?}", other),
+            };
+            let tagged = |response| BridgeResponse::Tagged {
+                request_id,
+                response: Box::new(response),
+            };
+            let responses = match cmd {
+                BridgeCommand::Exec { program, args, .. } => vec![BridgeResponse::Completed {
+                    stdout: format!("{} {}\n", program, args.join(" ")),
+                    stderr: String::new(),
+                    code: 0,
+                }],
+                BridgeCommand::Stream { args, .. } => args
+                    .into_iter()
+                    .map(|arg| BridgeResponse::StdoutChunk(arg.into_bytes()))
+                    .chain([BridgeResponse::StreamEnd {
+                        code: Some(0),
+                        signal: None,
+                    }])
+                    .collect(),
+                other => vec![BridgeResponse::Error(format!("unexpected {:?}", other))],
+            };
+            for response in responses {
+                if send(&mut stream, tagged(response)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_requests_side_by_side_on_one_connection() {
+        let socket = test_server("async-client", &["persistent", "multiplex"]);
+        let client = AsyncBridgeClient::connect(&socket).await.unwrap();
+
+        let replies: Vec<_> = (0..10)
+            .map(|i| client.exec("echo", &[&i.to_string()]))
+            .collect();
+        for (i, reply) in replies.into_iter().enumerate() {
+            assert_eq!(reply.await.unwrap().stdout, format!("echo {}\n", i));
+        }
+
+        fn stream_of_chunks(_: &impl Stream<Item = Chunk>) {}
+        let mut chunks = client.stream("cat", &["one", "two"]).unwrap();
+        stream_of_chunks(&chunks);
+        let mut stdout = Vec::new();
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Chunk::Stdout(bytes) => stdout.extend(bytes),
+                Chunk::Exit { code, signal } => assert_eq!((code, signal), (Some(0), None)),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(stdout, b"onetwo");
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[tokio::test]
+    async fn servers_that_do_not_multiplex_are_refused() {
+        let socket = test_server("async-client-plain", &["persistent"]);
+        match AsyncBridgeClient::connect(&socket).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+            other => panic!("expected Unsupported, got {:?}", other.err()),
+        }
+        std::fs::remove_file(&socket).unwrap();
+
+        let none = std::env::temp_dir().join(format!("async-none-{}.sock", std::process::id()));
+        match AsyncBridgeClient::connect(&none).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected NotFound, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Scripts for `andro batch` and `andro run` (and lines typed into `andro shell`): one andro
+// command line per line, split into words the way a shell would, so `type "hello world"` and
+// `-e sh -c 'echo $HOME'` work. Empty lines and lines starting with `#` are skipped.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+// The words of each command in `source` (stdin for `-`), with its line number. `expand`
+// substitutes environment variables (see `words`).
+pub fn load(source: &Path, expand: bool) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let text = if source.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("cannot read stdin: {}", e))?;
+        text
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| format!("cannot read {}: {}", source.display(), e))?
+    };
+    let mut commands = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words = words(line, expand).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        commands.push((index + 1, words));
+    }
+    if commands.is_empty() {
+        return Err(format!("{} holds no commands", source.display()));
+    }
+    Ok(commands)
+}
+
+// Splits on whitespace outside quotes. Single quotes keep everything literally; in double
+// quotes and unquoted text a backslash escapes the next character.
+pub fn split(line: &str) -> Result<Vec<String>, String> {
+    words(line, false)
+}
+
+// `split`, with `expand` also replacing `$NAME` and `${NAME}` outside single quotes with the
+// environment variable, which must be set. The value stays part of its word even if it
+// holds spaces; `\$` is a literal dollar sign.
+fn words(line: &str, expand: bool) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Distinguishes `""` (an empty word) from no word at all.
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some('$') if expand => word.push_str(&variable(&mut chars)?),
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => return Err("trailing backslash".to_string()),
+                }
+            }
+            '$' if expand => {
+                in_word = true;
+                word.push_str(&variable(&mut chars)?);
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// The value for the name after a `$`. A `$` not followed by a name stays as it is.
+fn variable(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+        name.push(c);
+    }
+    if braced && chars.next() != Some('}') {
+        return Err(format!("missing }} after ${{{}", name));
+    }
+    match (name.is_empty(), braced) {
+        (true, true) => return Err("empty variable name in ${}".to_string()),
+        (true, false) => return Ok("$".to_string()),
+        _ => {}
+    }
+    env::var(&name).map_err(|_| format!("${} is not set", name))
+}
@@ -0,0 +1,607 @@
+// A blocking client for the requests most tools need, built on a Session so it works with
+// any server: replies to one request at a time, streams side by side where the server
+// multiplexes, and on a connection of their own otherwise.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+use bridge_core::transport::Transport;
+use bridge_core::{
+    BackgroundJob, BridgeCommand, BridgeResponse, Coord, DeviceInfo, ErrorKind, Job, Point,
+    ProcessOptions, ServerInfo, SignalTarget,
+};
+
+use crate::multiplex::Multiplexer;
+use crate::session::Session;
+use crate::{
+    Retry, Server, WireCodec, busy, frame_too_large, read_response, send_command, too_old,
+};
+
+/// Everything that can go wrong with a request.
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting failed, or the connection broke.
+    Io(io::Error),
+    /// The server answered with an error, e.g. a refused command.
+    Remote(String),
+    /// The server answered with an error of a known kind, e.g. a refused program.
+    Failed { kind: ErrorKind, message: String },
+    /// The server is too old for the command; `kind` is its discriminant.
+    Unsupported { kind: u32 },
+    /// The server did not start the command's process: `limit` (its --max-jobs) are running.
+    Busy { running: u32, limit: u32 },
+    /// The request was `len` bytes, over the server's --max-frame-size (`limit`).
+    FrameTooLarge { len: u64, limit: u64 },
+    /// The program never ran, e.g. it does not exist; `os_error` is the errno of the spawn.
+    SpawnFailed {
+        os_error: Option<i32>,
+        message: String,
+    },
+    /// The command ran past its timeout and was killed, with the output it had written.
+    TimedOut {
+        timeout_ms: u64,
+        stdout: String,
+        stderr: String,
+    },
+    /// A response that does not fit the request.
+    Unexpected(Box<BridgeResponse>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Remote(e) => write!(f, "Remote Error: {}", e),
+            Error::Failed { message, .. } => write!(f, "Remote Error: {}", message),
+            Error::Unsupported { kind } => write!(f, "{}", too_old(*kind)),
+            Error::Busy { running, limit } => write!(f, "{}", busy(*running, *limit)),
+            Error::FrameTooLarge { len, limit } => write!(f, "{}", frame_too_large(*len, *limit)),
+            Error::SpawnFailed { message, .. } => write!(f, "Remote Error: {}", message),
+            Error::TimedOut { timeout_ms, .. } => write!(f, "timed out after {} ms", timeout_ms),
+            Error::Unexpected(response) => write!(f, "unexpected response: {:?}", response),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// The errors every request can get instead of its own response.
+pub(crate) fn failure(response: BridgeResponse) -> Error {
+    match response {
+        BridgeResponse::Error(e) => Error::Remote(e),
+        BridgeResponse::Failed(e) => Error::Failed {
+            kind: e.kind,
+            message: e.message,
+        },
+        BridgeResponse::Unsupported { kind } => Error::Unsupported { kind },
+        BridgeResponse::Busy { running, limit } => Error::Busy { running, limit },
+        BridgeResponse::FrameTooLarge { len, limit } => Error::FrameTooLarge { len, limit },
+        BridgeResponse::SpawnFailed { os_error, message } => {
+            Error::SpawnFailed { os_error, message }
+        }
+        response => Error::Unexpected(Box::new(response)),
+    }
+}
+
+// The requests and answers shared with the async client.
+
+pub(crate) fn exec_command(program: &str, args: &[&str], options: ProcessOptions) -> BridgeCommand {
+    BridgeCommand::Exec {
+        program: program.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        stdin: false,
+        options,
+    }
+}
+
+pub(crate) fn stream_command(
+    program: &str,
+    args: &[&str],
+    options: ProcessOptions,
+) -> BridgeCommand {
+    BridgeCommand::Stream {
+        program: program.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        stdin: false,
+        options,
+    }
+}
+
+pub(crate) fn tap_command(x: i32, y: i32) -> BridgeCommand {
+    BridgeCommand::DirectTap {
+        at: Point {
+            x: Coord::Pixels(x),
+            y: Coord::Pixels(y),
+        },
+        dry_run: false,
+    }
+}
+
+pub(crate) fn output(response: BridgeResponse) -> Result<Output> {
+    match response {
+        BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        } => Ok(Output {
+            stdout,
+            stderr,
+            code,
+        }),
+        BridgeResponse::TimedOut {
+            timeout_ms,
+            stdout,
+            stderr,
+        } => Err(Error::TimedOut {
+            timeout_ms,
+            stdout,
+            stderr,
+        }),
+        response => Err(failure(response)),
+    }
+}
+
+// The answer of a command that only reports success, like a tap.
+pub(crate) fn done(response: BridgeResponse) -> Result<()> {
+    match response {
+        BridgeResponse::Success(_) => Ok(()),
+        response => Err(failure(response)),
+    }
+}
+
+// The chunk a stream response stands for; None for responses that are not part of one.
+pub(crate) fn chunk(response: BridgeResponse) -> Option<Chunk> {
+    Some(match response {
+        BridgeResponse::StdoutChunk(bytes) => Chunk::Stdout(bytes),
+        BridgeResponse::StderrChunk(bytes) => Chunk::Stderr(bytes),
+        // Older servers send every line, stderr included, as a StreamChunk.
+        #[allow(deprecated)]
+        BridgeResponse::StreamChunk(line) => Chunk::Stdout(format!("{}\n", line).into()),
+        BridgeResponse::Error(e) => Chunk::Error(e),
+        BridgeResponse::Failed(e) => Chunk::Error(e.message),
+        BridgeResponse::Unsupported { kind } => Chunk::Error(too_old(kind)),
+        BridgeResponse::Busy { running, limit } => Chunk::Error(busy(running, limit)),
+        BridgeResponse::FrameTooLarge { len, limit } => Chunk::Error(frame_too_large(len, limit)),
+        BridgeResponse::TimedOut { timeout_ms, .. } => Chunk::TimedOut { timeout_ms },
+        BridgeResponse::StreamEnd { code, signal } => Chunk::Exit { code, signal },
+        _ => return None,
+    })
+}
+
+/// The result of a finished [`BridgeClient::exec`]. A non-zero `code` is not an error; a
+/// process killed by a signal reports 128 + the signal number, like a shell would.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
+/// One piece of a [`BridgeClient::stream`], in the order the server sent it.
+#[derive(Debug, Clone)]
+pub enum Chunk {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// The server could not run the command, or gave up on it.
+    Error(String),
+    /// The command hit its timeout and was killed; an `Exit` follows.
+    TimedOut {
+        timeout_ms: u64,
+    },
+    /// The last chunk: the exit code, or the signal that killed the process. Both are None
+    /// when the server has no exit status.
+    Exit {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+}
+
+/// A connection to a NativeBridge server.
+pub struct BridgeClient {
+    session: Session,
+}
+
+impl BridgeClient {
+    /// Connects to the server's Unix socket, a path or an abstract `@name`, without a token.
+    ///
+    /// ```no_run
+    /// let mut client = bridge_client::BridgeClient::connect("/tmp/bridge.sock")?;
+    /// client.ping()?;
+    /// # Ok::<(), bridge_client::Error>(())
+    /// ```
+    pub fn connect(socket: impl AsRef<Path>) -> Result<BridgeClient> {
+        let server = Server::Unix(socket.as_ref().to_path_buf());
+        BridgeClient::connect_to(server, WireCodec::Bincode, "")
+    }
+
+    /// Connects to any server, presenting `token` to those that require one.
+    ///
+    /// ```no_run
+    /// use bridge_client::{BridgeClient, Server, WireCodec};
+    ///
+    /// let server = Server::Tcp("192.168.1.20:5555".to_string());
+    /// let token = std::fs::read_to_string("/etc/andro.token")?;
+    /// let mut client = BridgeClient::connect_to(server, WireCodec::Bincode, token.trim())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn connect_to(server: Server, codec: WireCodec, token: &str) -> Result<BridgeClient> {
+        let session = Session::open(server, codec, token.to_string(), Retry::default())?;
+        Ok(BridgeClient { session })
+    }
+
+    /// Sends any command with a single response and returns that response as it is, for
+    /// the commands without a method of their own. Error responses are not turned into
+    /// an `Err`.
+    pub fn request(&mut self, cmd: BridgeCommand) -> Result<BridgeResponse> {
+        Ok(self.session.request(cmd)?)
+    }
+
+    /// Checks that the server is alive, returning its greeting.
+    pub fn ping(&mut self) -> Result<String> {
+        match self.request(BridgeCommand::Ping)? {
+            BridgeResponse::Success(message) => Ok(message),
+            response => Err(failure(response)),
+        }
+    }
+
+    pub fn info(&mut self) -> Result<ServerInfo> {
+        match self.request(BridgeCommand::Info)? {
+            BridgeResponse::ServerInfo(info) => Ok(info),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// Describes the device: screen, density, rotation, Android version, model, battery and
+    /// uptime. Fields the server could not find out are `None`.
+    pub fn device_info(&mut self) -> Result<DeviceInfo> {
+        match self.request(BridgeCommand::DeviceInfo)? {
+            BridgeResponse::DeviceInfo(info) => Ok(*info),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// The value of a system property; a property that is not set fails with
+    /// [`ErrorKind::NotFound`].
+    pub fn get_prop(&mut self, name: &str) -> Result<String> {
+        let command = BridgeCommand::GetProp {
+            name: name.to_string(),
+        };
+        match self.request(command)? {
+            BridgeResponse::Property { value, .. } => Ok(value),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// The system properties whose name starts with `prefix`, or all of them.
+    pub fn props(&mut self, prefix: Option<&str>) -> Result<BTreeMap<String, String>> {
+        let command = BridgeCommand::ListProps {
+            prefix: prefix.map(str::to_string),
+        };
+        match self.request(command)? {
+            BridgeResponse::Properties(properties) => Ok(properties),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// Sets a system property. A value the device does not take, e.g. of a read-only
+    /// property, fails with [`ErrorKind::PermissionDenied`].
+    pub fn set_prop(&mut self, name: &str, value: &str) -> Result<()> {
+        done(self.request(BridgeCommand::SetProp {
+            name: name.to_string(),
+            value: value.to_string(),
+        })?)
+    }
+
+    /// Lists the processes the server is running, oldest first.
+    pub fn jobs(&mut self) -> Result<Vec<Job>> {
+        match self.request(BridgeCommand::ListJobs)? {
+            BridgeResponse::Jobs(jobs) => Ok(jobs),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// Starts `program` in the background on the server, where it keeps running after this
+    /// client is gone. Returns the job id.
+    pub fn start_job(&mut self, program: &str, args: &[&str]) -> Result<u64> {
+        let command = BridgeCommand::JobStart {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            options: ProcessOptions::default(),
+        };
+        match self.request(command)? {
+            BridgeResponse::JobStarted { id, .. } => Ok(id),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// Lists the background jobs, running and recently finished.
+    pub fn background_jobs(&mut self) -> Result<Vec<BackgroundJob>> {
+        match self.request(BridgeCommand::JobList)? {
+            BridgeResponse::BackgroundJobs(jobs) => Ok(jobs),
+            response => Err(failure(response)),
+        }
+    }
+
+    /// Sends `signal` (e.g. `libc::SIGTERM`) to a background job.
+    pub fn kill_job(&mut self, id: u64, signal: i32) -> Result<()> {
+        done(self.request(BridgeCommand::JobKill { id, signal })?)
+    }
+
+    /// Sends `signal` (e.g. `libc::SIGHUP`) to a background job, or to any process by pid
+    /// if the server allows it, without stopping it.
+    pub fn signal(&mut self, target: SignalTarget, signal: i32) -> Result<()> {
+        done(self.request(BridgeCommand::Signal { target, signal })?)
+    }
+
+    /// Runs `program` on the host and waits for it to finish.
+    ///
+    /// ```no_run
+    /// # let mut client = bridge_client::BridgeClient::connect("/tmp/bridge.sock")?;
+    /// let output = client.exec("getprop", &["ro.product.model"])?;
+    /// if output.code == 0 {
+    ///     println!("model: {}", output.stdout.trim());
+    /// }
+    /// # Ok::<(), bridge_client::Error>(())
+    /// ```
+    pub fn exec(&mut self, program: &str, args: &[&str]) -> Result<Output> {
+        self.exec_with(program, args, ProcessOptions::default())
+    }
+
+    /// Like [`exec`](Self::exec), with a timeout, environment, working directory or
+    /// credentials for the process.
+    pub fn exec_with(
+        &mut self,
+        program: &str,
+        args: &[&str],
+        options: ProcessOptions,
+    ) -> Result<Output> {
+        output(self.request(exec_command(program, args, options))?)
+    }
+
+    /// Taps the screen at (`x`, `y`) in pixels. Needs a server built with direct input.
+    pub fn tap(&mut self, x: i32, y: i32) -> Result<()> {
+        done(self.request(tap_command(x, y))?)
+    }
+
+    /// Runs `program` on the host and returns its output as it is written. The iterator ends
+    /// after [`Chunk::Exit`], or early if the connection breaks; dropping it before then
+    /// stops the process.
+    ///
+    /// ```no_run
+    /// use bridge_client::Chunk;
+    ///
+    /// # let mut client = bridge_client::BridgeClient::connect("/tmp/bridge.sock")?;
+    /// for chunk in client.stream("logcat", &["-d"])? {
+    ///     match chunk {
+    ///         Chunk::Stdout(bytes) => print!("{}", String::from_utf8_lossy(&bytes)),
+    ///         Chunk::Exit { code, .. } => println!("exited with {:?}", code),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # Ok::<(), bridge_client::Error>(())
+    /// ```
+    pub fn stream(&mut self, program: &str, args: &[&str]) -> Result<Chunks> {
+        self.stream_with(program, args, ProcessOptions::default())
+    }
+
+    pub fn stream_with(
+        &mut self,
+        program: &str,
+        args: &[&str],
+        options: ProcessOptions,
+    ) -> Result<Chunks> {
+        let cmd = stream_command(program, args, options);
+        if let Some(multiplexer) = self.session.multiplexer() {
+            let request = multiplexer.start(cmd)?;
+            return Ok(Chunks {
+                source: Source::Tagged(request.responses),
+                cancel: Some((multiplexer.clone(), request.id)),
+            });
+        }
+        // The stream would hold up the session, so it gets a connection of its own, which
+        // the server cancels when it closes.
+        let mut stream = self.session.connect()?;
+        send_command(&mut *stream, &cmd)?;
+        Ok(Chunks {
+            source: Source::Connection(Some(stream)),
+            cancel: None,
+        })
+    }
+}
+
+/// The output of a [`BridgeClient::stream`].
+pub struct Chunks {
+    source: Source,
+    // The request to cancel if the iterator is dropped early, on a multiplexed connection.
+    cancel: Option<(Multiplexer, u64)>,
+}
+
+enum Source {
+    Tagged(Receiver<BridgeResponse>),
+    // None once the stream is over.
+    Connection(Option<Box<dyn Transport>>),
+}
+
+impl Chunks {
+    fn next_response(&mut self) -> Option<BridgeResponse> {
+        match &mut self.source {
+            Source::Tagged(responses) => responses.recv().ok(),
+            Source::Connection(stream) => {
+                let response = read_response(&mut **stream.as_mut()?);
+                if response.is_err() {
+                    *stream = None;
+                }
+                response.ok()
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.cancel = None;
+        if let Source::Connection(stream) = &mut self.source {
+            *stream = None;
+        }
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Chunk> {
+        loop {
+            let Some(response) = self.next_response() else {
+                self.finish();
+                return None;
+            };
+            let Some(chunk) = chunk(response) else {
+                continue;
+            };
+            if let Chunk::Exit { .. } = chunk {
+                self.finish();
+            }
+            return Some(chunk);
+        }
+    }
+}
+
+impl Drop for Chunks {
+    fn drop(&mut self) {
+        // A dedicated connection is cancelled by closing it; a multiplexed one stays open.
+        if let Some((multiplexer, id)) = self.cancel.take() {
+            multiplexer.canceller(id)();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+
+    use bridge_core::{BridgeError, Codec, Load, PROTOCOL_VERSION};
+
+    // A server on a temp socket that speaks the protocol but runs nothing: Exec echoes its
+    // arguments, Stream writes one chunk per argument, GetProp finds no property.
+    fn test_server(name: &str) -> std::path::PathBuf {
+        let socket = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                thread::spawn(move || serve(stream));
+            }
+        });
+        socket
+    }
+
+    fn serve(mut stream: UnixStream) {
+        let codec = Codec::Bincode;
+        let send = |stream: &mut UnixStream, response: BridgeResponse| {
+            let payload = codec.encode_response(&response).unwrap();
+            codec.write_frame(stream, &payload)
+        };
+        let next = |stream: &mut UnixStream| codec.read_frame(stream, 1 << 20).ok().flatten();
+        if next(&mut stream).is_none() {
+            return;
+        }
+        codec
+            .write_hello(&mut stream, PROTOCOL_VERSION, b"")
+            .unwrap();
+        send(&mut stream, BridgeResponse::Success(String::new())).unwrap();
+        while let Some(payload) = next(&mut stream) {
+            let response = match codec.decode_command(&payload).unwrap() {
+                BridgeCommand::Info => BridgeResponse::ServerInfo(ServerInfo {
+                    version: "test".to_string(),
+                    protocol: PROTOCOL_VERSION,
+                    features: vec!["persistent".to_string()],
+                    touch_device: None,
+                    commands: Vec::new(),
+                    load: Load::default(),
+                }),
+                BridgeCommand::Exec { program, args, .. } => BridgeResponse::Completed {
+                    stdout: format!("{} {}\n", program, args.join(" ")),
+                    stderr: String::new(),
+                    code: 3,
+                },
+                BridgeCommand::DirectTap { .. } => BridgeResponse::Success(String::new()),
+                BridgeCommand::GetProp { name } => BridgeResponse::Failed(BridgeError {
+                    kind: ErrorKind::NotFound,
+                    message: format!("GetProp Failed: property {} is not set", name),
+                }),
+                BridgeCommand::Stream { args, .. } => {
+                    for arg in args {
+                        send(&mut stream, BridgeResponse::StdoutChunk(arg.into_bytes())).unwrap();
+                    }
+                    let end = BridgeResponse::StreamEnd {
+                        code: Some(0),
+                        signal: None,
+                    };
+                    let _ = send(&mut stream, end);
+                    return;
+                }
+                other => BridgeResponse::Error(format!("unexpected {:?}", other)),
+            };
+            if send(&mut stream, response).is_err() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn drives_a_server_through_the_library() {
+        let socket = test_server("client-library");
+        let mut client = BridgeClient::connect(&socket).unwrap();
+
+        let output = client.exec("echo", &["a", "b"]).unwrap();
+        assert_eq!((output.stdout.as_str(), output.code), ("echo a b\n", 3));
+        client.tap(10, 20).unwrap();
+        match client.get_prop("ro.missing") {
+            Err(Error::Failed { kind, message }) => {
+                assert_eq!(kind, ErrorKind::NotFound);
+                assert_eq!(message, "GetProp Failed: property ro.missing is not set");
+            }
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        // An Error response, here to a command the test server does not know.
+        assert!(matches!(client.ping(), Err(Error::Remote(e)) if e == "unexpected Ping"));
+
+        let chunks: Vec<_> = client.stream("cat", &["one", "two"]).unwrap().collect();
+        assert!(matches!(&chunks[..], [
+            Chunk::Stdout(one),
+            Chunk::Stdout(two),
+            Chunk::Exit { code: Some(0), signal: None },
+        ] if one == b"one" && two == b"two"));
+        // The stream had a connection of its own; the session carries on.
+        assert_eq!(client.exec("true", &[]).unwrap().stdout, "true \n");
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[test]
+    fn connecting_to_no_server_is_an_io_error() {
+        let socket = std::env::temp_dir().join(format!("client-none-{}.sock", std::process::id()));
+        match BridgeClient::connect(&socket) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected NotFound, got {:?}", other.err()),
+        }
+    }
+}
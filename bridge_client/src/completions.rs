@@ -0,0 +1,546 @@
+// `andro completions bash|zsh|fish`: a completion script generated from the clap definition
+// of the command line, so it follows new subcommands and flags by itself. Arguments with
+// fixed values (`--easing`, `--codec`) complete those values, KEY arguments the key names of
+// `bridge_core::keys`, and paths file names.
+
+use std::any::TypeId;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, Command, CommandFactory, ValueEnum};
+
+use bridge_core::keys::KEYS;
+
+use crate::Cli;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// A command of the tree: `andro`, `andro tap`, `andro rotation get`, ...
+struct Node {
+    // The names from the root down, e.g. ["andro", "rotation", "get"].
+    path: Vec<String>,
+    about: String,
+    aliases: Vec<String>,
+    // Accepted, like `repl` for `shell`, but not offered.
+    hidden_aliases: Vec<String>,
+    options: Vec<Opt>,
+    // What the first positional argument completes to: fixed values (possibly none), or
+    // files when None.
+    positionals: Option<Vec<String>>,
+    children: Vec<Node>,
+}
+
+struct Opt {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    value: Value,
+}
+
+enum Value {
+    Flag,
+    // A number, a duration, ...: nothing to offer.
+    Text,
+    Files,
+    Choices(Vec<String>),
+}
+
+pub fn print(shell: Shell) {
+    print!("{}", script(shell));
+}
+
+fn script(shell: Shell) -> String {
+    let root = tree();
+    match shell {
+        Shell::Bash => bash(&root),
+        Shell::Zsh => zsh(&root),
+        Shell::Fish => fish(&root),
+    }
+}
+
+fn tree() -> Node {
+    let mut command = Cli::command();
+    // Copies the global flags into every subcommand.
+    command.build();
+    node(&command, Vec::new())
+}
+
+fn node(command: &Command, parent: Vec<String>) -> Node {
+    let mut path = parent;
+    path.push(command.get_name().to_string());
+    let options = command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(|arg| Opt {
+            long: arg.get_long().map(str::to_string),
+            short: arg.get_short(),
+            help: summary(arg.get_help().map(|help| help.to_string())),
+            value: if arg.get_action().takes_values() {
+                match choices(arg) {
+                    Some(values) => Value::Choices(values),
+                    None if is_path(arg) => Value::Files,
+                    None => Value::Text,
+                }
+            } else {
+                Value::Flag
+            },
+        })
+        .collect();
+    let mut positional = command.get_positionals().filter(|arg| !arg.is_hide_set());
+    let positionals = match positional.next() {
+        Some(first) if is_path(first) => None,
+        Some(first) => Some(choices(first).unwrap_or_default()),
+        None => Some(Vec::new()),
+    };
+    // clap's own `help` subcommand repeats the whole tree; its name is enough.
+    let recurse = command.get_name() != "help";
+    let children = command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| match recurse {
+            true => node(sub, path.clone()),
+            false => leaf(sub, &path),
+        })
+        .collect();
+    Node {
+        about: summary(command.get_about().map(|about| about.to_string())),
+        aliases: command.get_visible_aliases().map(str::to_string).collect(),
+        hidden_aliases: command
+            .get_all_aliases()
+            .filter(|alias| {
+                !command
+                    .get_visible_aliases()
+                    .any(|visible| visible == *alias)
+            })
+            .map(str::to_string)
+            .collect(),
+        path,
+        options,
+        positionals,
+        children,
+    }
+}
+
+fn leaf(command: &Command, parent: &[String]) -> Node {
+    let mut path = parent.to_vec();
+    path.push(command.get_name().to_string());
+    Node {
+        path,
+        about: String::new(),
+        aliases: Vec::new(),
+        hidden_aliases: Vec::new(),
+        options: Vec::new(),
+        positionals: Some(Vec::new()),
+        children: Vec::new(),
+    }
+}
+
+// The values `arg` can take, if they are a fixed set.
+fn choices(arg: &Arg) -> Option<Vec<String>> {
+    if arg
+        .get_value_names()
+        .is_some_and(|names| names.iter().any(|name| name == "KEY"))
+    {
+        return Some(KEYS.iter().map(|key| key.name.to_string()).collect());
+    }
+    let values: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+fn is_path(arg: &Arg) -> bool {
+    arg.get_value_parser().type_id() == TypeId::of::<PathBuf>()
+}
+
+// The first line of a help text.
+fn summary(help: Option<String>) -> String {
+    help.and_then(|help| help.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+// Every node of the tree, parents first.
+fn walk<'a>(node: &'a Node, nodes: &mut Vec<&'a Node>) {
+    nodes.push(node);
+    for child in &node.children {
+        walk(child, nodes);
+    }
+}
+
+fn switches(opt: &Opt) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(long) = &opt.long {
+        names.push(format!("--{}", long));
+    }
+    if let Some(short) = opt.short {
+        names.push(format!("-{}", short));
+    }
+    names
+}
+
+// Every name a child is recognised by.
+fn all_names(child: &Node) -> Vec<&str> {
+    let mut all = names(child);
+    all.extend(child.hidden_aliases.iter().map(String::as_str));
+    all
+}
+
+// The names of a child to offer, canonical first.
+fn names(child: &Node) -> Vec<&str> {
+    let name = child.path.last().map(String::as_str).unwrap_or_default();
+    std::iter::once(name)
+        .chain(child.aliases.iter().map(String::as_str))
+        .collect()
+}
+
+fn bash(root: &Node) -> String {
+    let function = format!("_{}", root.path[0]);
+    let mut nodes = Vec::new();
+    walk(root, &mut nodes);
+    let mut out = String::new();
+    writeln!(out, "{}() {{", function).unwrap();
+    out.push_str(
+        "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+    );
+    writeln!(out, "    local cmdpath={} i", root.path[0]).unwrap();
+    out.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    out.push_str("        case \"$cmdpath/${COMP_WORDS[i]}\" in\n");
+    for node in &nodes {
+        for child in &node.children {
+            for name in all_names(child) {
+                writeln!(
+                    out,
+                    "            {}/{}) cmdpath={} ;;",
+                    node.path.join("/"),
+                    name,
+                    child.path.join("/")
+                )
+                .unwrap();
+            }
+        }
+    }
+    out.push_str("        esac\n    done\n");
+    out.push_str("    case \"$cmdpath\" in\n");
+    for node in &nodes {
+        writeln!(out, "        {})", node.path.join("/")).unwrap();
+        out.push_str("            case \"$prev\" in\n");
+        for opt in &node.options {
+            let reply = match &opt.value {
+                Value::Flag => continue,
+                Value::Text => "COMPREPLY=()".to_string(),
+                Value::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+                Value::Choices(values) => {
+                    format!(
+                        "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                        values.join(" ")
+                    )
+                }
+            };
+            writeln!(
+                out,
+                "                {}) {}; return ;;",
+                switches(opt).join("|"),
+                reply
+            )
+            .unwrap();
+        }
+        out.push_str("            esac\n");
+        let flags: Vec<String> = node.options.iter().flat_map(switches).collect();
+        out.push_str("            if [[ \"$cur\" == -* ]]; then\n");
+        writeln!(
+            out,
+            "                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+            flags.join(" ")
+        )
+        .unwrap();
+        out.push_str("            else\n");
+        let words: Vec<&str> = node.children.iter().flat_map(names).collect();
+        match (&node.positionals, words.is_empty()) {
+            (None, true) => out.push_str("                COMPREPLY=($(compgen -f -- \"$cur\"))\n"),
+            (values, _) => {
+                let mut words: Vec<&str> = words;
+                words.extend(values.iter().flatten().map(String::as_str));
+                writeln!(
+                    out,
+                    "                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    words.join(" ")
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("            fi\n            ;;\n");
+    }
+    out.push_str("    esac\n}\n\n");
+    writeln!(out, "complete -F {} {}", function, root.path[0]).unwrap();
+    out
+}
+
+// A word for a zsh single-quoted `name:description` item.
+fn zsh_item(name: &str, description: &str) -> String {
+    let mut item = name.replace(':', "\\:");
+    if !description.is_empty() {
+        write!(item, ":{}", description).unwrap();
+    }
+    format!("'{}'", item.replace('\'', "'\\''"))
+}
+
+fn zsh(root: &Node) -> String {
+    let name = &root.path[0];
+    let mut nodes = Vec::new();
+    walk(root, &mut nodes);
+    let mut out = String::new();
+    writeln!(out, "#compdef {}\n", name).unwrap();
+    writeln!(out, "_{}() {{", name).unwrap();
+    // Not `path`, which zsh ties to $PATH.
+    writeln!(out, "    local cmdpath={} i", name).unwrap();
+    out.push_str("    for ((i = 2; i < CURRENT; i++)); do\n");
+    out.push_str("        case \"$cmdpath/${words[i]}\" in\n");
+    for node in &nodes {
+        for child in &node.children {
+            for alias in all_names(child) {
+                writeln!(
+                    out,
+                    "            {}/{}) cmdpath={} ;;",
+                    node.path.join("/"),
+                    alias,
+                    child.path.join("/")
+                )
+                .unwrap();
+            }
+        }
+    }
+    out.push_str("        esac\n    done\n");
+    out.push_str("    local cur=${words[CURRENT]} prev=${words[CURRENT-1]}\n");
+    out.push_str("    local -a items\n");
+    out.push_str("    case $cmdpath in\n");
+    for node in &nodes {
+        writeln!(out, "        {})", node.path.join("/")).unwrap();
+        out.push_str("            case $prev in\n");
+        for opt in &node.options {
+            let reply = match &opt.value {
+                Value::Flag => continue,
+                Value::Text => "_message value".to_string(),
+                Value::Files => "_files".to_string(),
+                Value::Choices(values) => format!("compadd -- {}", values.join(" ")),
+            };
+            writeln!(
+                out,
+                "                {}) {}; return ;;",
+                switches(opt).join("|"),
+                reply
+            )
+            .unwrap();
+        }
+        out.push_str("            esac\n");
+        out.push_str("            if [[ $cur == -* ]]; then\n");
+        let flags: Vec<String> = node
+            .options
+            .iter()
+            .flat_map(|opt| {
+                switches(opt)
+                    .into_iter()
+                    .map(move |switch| zsh_item(&switch, &opt.help))
+            })
+            .collect();
+        writeln!(out, "                items=({})", flags.join(" ")).unwrap();
+        out.push_str("                _describe option items\n");
+        out.push_str("            else\n");
+        let mut words: Vec<String> = node
+            .children
+            .iter()
+            .flat_map(|child| {
+                names(child)
+                    .into_iter()
+                    .map(|alias| zsh_item(alias, &child.about))
+            })
+            .collect();
+        match &node.positionals {
+            None if words.is_empty() => out.push_str("                _files\n"),
+            values => {
+                words.extend(values.iter().flatten().map(|value| zsh_item(value, "")));
+                writeln!(out, "                items=({})", words.join(" ")).unwrap();
+                out.push_str("                _describe command items\n");
+            }
+        }
+        out.push_str("            fi\n            ;;\n");
+    }
+    out.push_str("    esac\n}\n\n");
+    writeln!(
+        out,
+        "if [ \"$funcstack[1]\" = \"_{0}\" ]; then\n    _{0} \"$@\"\nelse\n    compdef _{0} {0}\nfi",
+        name
+    )
+    .unwrap();
+    out
+}
+
+// A fish single-quoted string.
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(root: &Node) -> String {
+    let name = &root.path[0];
+    let mut nodes = Vec::new();
+    walk(root, &mut nodes);
+    let mut out = String::new();
+    // Files only where an argument takes them.
+    writeln!(out, "complete -c {} -f", name).unwrap();
+    for node in &nodes {
+        // Inside this node: its subcommands seen, none of its children yet.
+        let mut conditions: Vec<String> = match node.path.len() {
+            1 => vec!["__fish_use_subcommand".to_string()],
+            len => node.path[1..len - 1]
+                .iter()
+                .map(String::as_str)
+                .chain([all_names(node).join(" ").as_str()])
+                .map(|sub| format!("__fish_seen_subcommand_from {}", sub))
+                .collect(),
+        };
+        let children: Vec<&str> = node.children.iter().flat_map(all_names).collect();
+        if node.path.len() > 1 && !children.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                children.join(" ")
+            ));
+        }
+        let condition = fish_quote(&conditions.join("; and "));
+        for child in &node.children {
+            for alias in names(child) {
+                writeln!(
+                    out,
+                    "complete -c {} -n {} -a {} -d {}",
+                    name,
+                    condition,
+                    alias,
+                    fish_quote(&child.about)
+                )
+                .unwrap();
+            }
+        }
+        for opt in &node.options {
+            let mut line = format!("complete -c {} -n {}", name, condition);
+            if let Some(short) = opt.short {
+                write!(line, " -s {}", short).unwrap();
+            }
+            if let Some(long) = &opt.long {
+                write!(line, " -l {}", long).unwrap();
+            }
+            match &opt.value {
+                Value::Flag => {}
+                Value::Text => line.push_str(" -x"),
+                Value::Files => line.push_str(" -r -F"),
+                Value::Choices(values) => {
+                    write!(line, " -x -a {}", fish_quote(&values.join(" "))).unwrap()
+                }
+            }
+            if !opt.help.is_empty() {
+                write!(line, " -d {}", fish_quote(&opt.help)).unwrap();
+            }
+            writeln!(out, "{}", line).unwrap();
+        }
+        match &node.positionals {
+            Some(values) if !values.is_empty() => writeln!(
+                out,
+                "complete -c {} -n {} -a {}",
+                name,
+                condition,
+                fish_quote(&values.join(" "))
+            )
+            .unwrap(),
+            None if children.is_empty() => {
+                writeln!(out, "complete -c {} -n {} -F", name, condition).unwrap()
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    // What the bash script offers for the command line `words`, the last one being completed.
+    fn complete_bash(words: &[&str]) -> Vec<String> {
+        let file = std::env::temp_dir().join(format!("andro-completion-{}", std::process::id()));
+        std::fs::write(&file, script(Shell::Bash)).unwrap();
+        let words: Vec<String> = words.iter().map(|word| format!("'{}'", word)).collect();
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "source {}; COMP_WORDS=({}); COMP_CWORD={}; _andro; printf '%s\\n' \"${{COMPREPLY[@]}}\"",
+                file.display(),
+                words.join(" "),
+                words.len() - 1
+            ))
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn tree_has_the_subcommands_and_key_names() {
+        let root = tree();
+        let child = |name: &str| {
+            root.children
+                .iter()
+                .find(|child| child.path == ["andro", name])
+                .unwrap_or_else(|| panic!("no {} subcommand", name))
+        };
+        for name in ["tap", "swipe", "shell", "completions"] {
+            child(name);
+        }
+        let keys = child("key").positionals.as_ref().unwrap();
+        assert_eq!(keys.len(), KEYS.len());
+        assert!(keys.iter().any(|key| key == "BACK"));
+    }
+
+    #[test]
+    fn every_shell_names_the_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell);
+            for name in ["tap", "swipe", "key", "completions"] {
+                assert!(script.contains(name), "{} missing", name);
+            }
+        }
+        assert!(script(Shell::Zsh).starts_with("#compdef andro\n"));
+        assert!(script(Shell::Fish).starts_with("complete -c andro -f\n"));
+    }
+
+    #[test]
+    fn bash_completes_subcommands_flag_values_and_keys() {
+        assert_eq!(complete_bash(&["andro", "completi"]), ["completions"]);
+        assert_eq!(
+            complete_bash(&["andro", "completions", ""]),
+            ["bash", "zsh", "fish"]
+        );
+        assert_eq!(
+            complete_bash(&["andro", "swipe", "--easing", "o"]),
+            ["overshoot"]
+        );
+        assert_eq!(
+            complete_bash(&["andro", "key", "VOLUME_"]),
+            ["VOLUME_UP", "VOLUME_DOWN", "VOLUME_MUTE"]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+// Defaults for the client's flags, from ANDRO_* variables and ~/.config/andro/config.toml:
+//
+//     socket = "/data/local/tmp/bridge.sock"
+//     timeout = 5000        # ms, like --timeout
+//     easing = "ease-in-out"
+//     json = true
+//     token_file = "/etc/andro/token"
+//
+// A flag on the command line wins over the variable, which wins over the file, which wins
+// over the built-in default. The file is a small subset of TOML: `key = value` lines with
+// strings, integers and booleans, and `#` comments. A file (or a variable) that cannot be
+// read is a warning and otherwise ignored, so a broken config never keeps andro from
+// running.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, ValueEnum};
+use serde::Serialize;
+
+use bridge_core::json;
+
+use crate::{Cli, SOCKET_PATH, SwipeEasing, report};
+
+// Where a default comes from.
+#[derive(Clone)]
+pub enum Origin {
+    Env(&'static str),
+    File,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Env(name) => write!(f, "{}", name),
+            Origin::File => write!(f, "config file"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Setting {
+    pub value: String,
+    pub origin: Origin,
+}
+
+#[derive(Default)]
+pub struct Config {
+    // The file, when there is a home directory to look in.
+    pub path: Option<PathBuf>,
+    pub socket: Option<Setting>,
+    pub timeout: Option<Setting>,
+    pub easing: Option<Setting>,
+    pub json: Option<Setting>,
+    pub token_file: Option<Setting>,
+}
+
+// The settings, with the variable each can be set by and how its value is checked.
+const KEYS: [(&str, &str, Kind); 5] = [
+    ("socket", "ANDRO_SOCKET", Kind::Text),
+    ("timeout", "ANDRO_TIMEOUT", Kind::Integer),
+    ("easing", "ANDRO_EASING", Kind::Easing),
+    ("json", "ANDRO_JSON", Kind::Bool),
+    ("token_file", "ANDRO_TOKEN_FILE", Kind::Text),
+];
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Integer,
+    Bool,
+    Easing,
+}
+
+// Loaded on first use, so the warnings print once even when batch lines are parsed too.
+pub fn get() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(load)
+}
+
+// `command` with the defaults of the config in place of the built-in ones.
+pub fn apply(mut command: Command) -> Command {
+    let config = get();
+    for (id, setting) in [
+        ("socket", &config.socket),
+        ("timeout", &config.timeout),
+        ("json", &config.json),
+        ("token_file", &config.token_file),
+    ] {
+        if let Some(setting) = setting {
+            let value = setting.value.clone();
+            command = command.mut_arg(id, |arg| arg.default_value(value));
+        }
+    }
+    if let Some(setting) = &config.easing {
+        let value = setting.value.clone();
+        command = command.mut_subcommand("swipe", |swipe| {
+            swipe.mut_arg("easing", |arg| arg.default_value(value))
+        });
+    }
+    command
+}
+
+// One line of `andro config show`.
+#[derive(Serialize)]
+struct Row {
+    name: &'static str,
+    value: String,
+    source: String,
+}
+
+// `andro config show`: the value each setting has for this command line, and where it
+// comes from.
+pub fn show(cli: &Cli, matches: &ArgMatches) {
+    let config = get();
+    let flag = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let path = |path: &Option<PathBuf>| path.as_ref().map(|path| path.display().to_string());
+    // The value given on the command line, if any; only the global flags can be.
+    let given = [
+        flag("socket").then(|| ("--socket", cli.socket.display().to_string())),
+        None,
+        None,
+        if flag("no_json") {
+            Some(("--no-json", "false".to_string()))
+        } else {
+            flag("json").then(|| ("--json", "true".to_string()))
+        },
+        flag("token_file").then(|| ("--token-file", path(&cli.token_file).unwrap_or_default())),
+    ];
+    let settings = [
+        (&config.socket, SOCKET_PATH),
+        (&config.timeout, "none"),
+        (&config.easing, "linear"),
+        (&config.json, "false"),
+        (&config.token_file, "none"),
+    ];
+    let rows: Vec<Row> = KEYS
+        .iter()
+        .zip(given)
+        .zip(settings)
+        .map(|(((name, _, _), given), (setting, default))| {
+            let (value, source) = match (given, setting) {
+                (Some((flag, value)), _) => (value, flag.to_string()),
+                (None, Some(setting)) => (setting.value.clone(), setting.origin.to_string()),
+                (None, None) => (default.to_string(), "default".to_string()),
+            };
+            Row {
+                name,
+                value,
+                source,
+            }
+        })
+        .collect();
+    let file = path(&config.path);
+    if report::enabled() {
+        #[derive(Serialize)]
+        struct Shown<'a> {
+            file: Option<String>,
+            settings: &'a [Row],
+        }
+        let shown = Shown {
+            file,
+            settings: &rows,
+        };
+        println!(
+            "{}",
+            json::to_string(&shown).expect("output objects always encode")
+        );
+        return;
+    }
+    match (file, &config.path) {
+        (Some(file), Some(path)) if path.exists() => println!("Config file: {}", file),
+        (Some(file), _) => println!("Config file: {} (not found)", file),
+        _ => println!("Config file: none (HOME is not set)"),
+    }
+    let width = rows.iter().map(|row| row.value.len()).max().unwrap_or(0);
+    for row in &rows {
+        println!("{:<10} {:<width$}  ({})", row.name, row.value, row.source);
+    }
+}
+
+fn load() -> Config {
+    let path = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+    .map(|dir| dir.join("andro").join("config.toml"));
+    let mut config = Config {
+        path: path.clone(),
+        ..Config::default()
+    };
+    if let Some(path) = &path {
+        match fs::read_to_string(path) {
+            Ok(text) => match parse(&text) {
+                Ok(settings) => {
+                    for (key, value) in settings {
+                        *slot(&mut config, key) = Some(Setting {
+                            value,
+                            origin: Origin::File,
+                        });
+                    }
+                }
+                Err(e) => eprintln!("Warning: ignoring {}: {}", path.display(), e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Warning: cannot read {}: {}", path.display(), e),
+        }
+    }
+    for (key, var, kind) in KEYS {
+        let Ok(raw) = env::var(var) else {
+            continue;
+        };
+        match check(kind, &raw) {
+            Ok(value) => {
+                *slot(&mut config, key) = Some(Setting {
+                    value,
+                    origin: Origin::Env(var),
+                })
+            }
+            Err(e) => eprintln!("Warning: ignoring {}: {}", var, e),
+        }
+    }
+    // The socket's old variable, for setups that predate ANDRO_SOCKET.
+    if config
+        .socket
+        .as_ref()
+        .is_none_or(|setting| matches!(setting.origin, Origin::File))
+        && let Some(value) = env::var("BRIDGE_SOCKET")
+            .ok()
+            .filter(|value| !value.is_empty())
+    {
+        config.socket = Some(Setting {
+            value,
+            origin: Origin::Env("BRIDGE_SOCKET"),
+        });
+    }
+    config
+}
+
+fn slot<'a>(config: &'a mut Config, key: &str) -> &'a mut Option<Setting> {
+    match key {
+        "socket" => &mut config.socket,
+        "timeout" => &mut config.timeout,
+        "easing" => &mut config.easing,
+        "json" => &mut config.json,
+        _ => &mut config.token_file,
+    }
+}
+
+// The settings of a config file, each value checked and in the form its flag takes.
+fn parse(text: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let mut settings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at = |e: String| format!("line {}: {}", number + 1, e);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at("expected `key = value`".to_string()))?;
+        let key = key.trim();
+        let Some(&(key, _, kind)) = KEYS.iter().find(|(name, _, _)| *name == key) else {
+            let known: Vec<&str> = KEYS.iter().map(|(name, _, _)| *name).collect();
+            return Err(at(format!(
+                "unknown key `{}` (known: {})",
+                key,
+                known.join(", ")
+            )));
+        };
+        let value = toml_value(value.trim()).map_err(at)?;
+        settings.push((
+            key,
+            check(kind, &value).map_err(|e| at(format!("{}: {}", key, e)))?,
+        ));
+    }
+    Ok(settings)
+}
+
+// The text of a TOML string, integer or boolean, with a trailing comment removed.
+fn toml_value(value: &str) -> Result<String, String> {
+    let mut chars = value.chars();
+    let (text, rest) = match chars.next() {
+        Some('"') => {
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(c @ ('"' | '\\')) => text.push(c),
+                        _ => return Err("unsupported escape in string".to_string()),
+                    },
+                    Some(c) => text.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            (text, chars.as_str())
+        }
+        Some('\'') => {
+            let rest = chars.as_str();
+            let end = rest.find('\'').ok_or("unterminated string")?;
+            (rest[..end].to_string(), &rest[end + 1..])
+        }
+        _ => {
+            let end = value.find('#').unwrap_or(value.len());
+            (value[..end].trim().to_string(), "")
+        }
+    };
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected `{}` after the value", rest));
+    }
+    if text.is_empty() && !value.starts_with(['"', '\'']) {
+        return Err("missing value".to_string());
+    }
+    Ok(text)
+}
+
+// Checks a value the way the flag would, so that a bad one is a warning here rather than
+// an error on every command.
+fn check(kind: Kind, value: &str) -> Result<String, String> {
+    match kind {
+        Kind::Text if value.is_empty() => Err("empty value".to_string()),
+        Kind::Text => Ok(value.to_string()),
+        Kind::Integer => value
+            .parse::<u64>()
+            .map(|n| n.to_string())
+            .map_err(|_| format!("`{}` is not a whole number", value)),
+        Kind::Bool => match value {
+            "true" | "1" | "yes" => Ok("true".to_string()),
+            "false" | "0" | "no" => Ok("false".to_string()),
+            _ => Err(format!("`{}` is not true or false", value)),
+        },
+        Kind::Easing => SwipeEasing::from_str(value, false)
+            .map(|_| value.to_string())
+            .map_err(|_| {
+                let names: Vec<String> = SwipeEasing::value_variants()
+                    .iter()
+                    .filter_map(|easing| easing.to_possible_value())
+                    .map(|value| value.get_name().to_string())
+                    .collect();
+                format!("`{}` is not one of {}", value, names.join(", "))
+            }),
+    }
+}
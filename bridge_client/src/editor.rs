@@ -0,0 +1,251 @@
+// A small line editor for `andro shell` on a terminal: cursor movement, the usual Emacs
+// keys and a history kept in ~/.andro_history. Keys are read in raw mode only while a line
+// is being typed, so commands run with the terminal as the user left it.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use crate::terminal::RawMode;
+
+// Lines kept in the history file; older ones are dropped when it is loaded.
+const HISTORY_SIZE: usize = 1000;
+
+pub struct Editor {
+    history: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+// What a key asks the editor to do.
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    KillStart,
+    KillEnd,
+    KillWord,
+    Clear,
+    Interrupt,
+    Eof,
+    Other,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        let path = env::var_os("HOME").map(|home| PathBuf::from(home).join(".andro_history"));
+        let mut history: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        if history.len() > HISTORY_SIZE {
+            history.drain(..history.len() - HISTORY_SIZE);
+            if let Some(path) = &path {
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        Editor { history, path }
+    }
+
+    // Reads one line, or None once the user pressed Ctrl-D on an empty line. Ctrl-C throws
+    // the line away and returns an empty one.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let _raw = RawMode::keystrokes()?;
+        let mut input = io::stdin().lock();
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Position in the history while walking it with the arrows, and the line that was
+        // being typed before.
+        let mut entry = self.history.len();
+        let mut draft = Vec::new();
+        redraw(prompt, &line, cursor)?;
+        loop {
+            match read_key(&mut input)? {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    eprintln!();
+                    return Ok(Some(line.into_iter().collect()));
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left if cursor > 0 => cursor -= 1,
+                Key::Right if cursor < line.len() => cursor += 1,
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::Up if entry > 0 => {
+                    if entry == self.history.len() {
+                        draft = line;
+                    }
+                    entry -= 1;
+                    line = self.history[entry].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if entry < self.history.len() => {
+                    entry += 1;
+                    line = match self.history.get(entry) {
+                        Some(text) => text.chars().collect(),
+                        None => std::mem::take(&mut draft),
+                    };
+                    cursor = line.len();
+                }
+                Key::KillStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::KillEnd => line.truncate(cursor),
+                Key::KillWord => {
+                    let mut start = cursor;
+                    while start > 0 && line[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && line[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    line.drain(start..cursor);
+                    cursor = start;
+                }
+                Key::Clear => eprint!("\x1b[H\x1b[2J"),
+                Key::Interrupt => {
+                    eprintln!("^C");
+                    return Ok(Some(String::new()));
+                }
+                Key::Eof if line.is_empty() => {
+                    eprintln!();
+                    return Ok(None);
+                }
+                // Ctrl-D with text deletes under the cursor, like Delete.
+                Key::Eof if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                _ => continue,
+            }
+            redraw(prompt, &line, cursor)?;
+        }
+    }
+
+    // Remembers a line that was run, unless it repeats the one before.
+    pub fn add(&mut self, line: &str) {
+        if self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        // Appending keeps the lines of shells running side by side; the file is trimmed the
+        // next time it is loaded. Failing to save the history is not worth an error.
+        if let Some(path) = &self.path
+            && let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path)
+        {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+// Prints the prompt and the line over the current row and puts the cursor back in place.
+fn redraw(prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+    let text: String = line.iter().collect();
+    let mut out = format!("\r{}{}\x1b[K", prompt, text);
+    if cursor < line.len() {
+        out.push_str(&format!("\x1b[{}D", line.len() - cursor));
+    }
+    let mut stderr = io::stderr();
+    stderr.write_all(out.as_bytes())?;
+    stderr.flush()
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    if input.read(&mut byte)? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(byte[0])
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillEnd,
+        0x0c => Key::Clear,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Other,
+        byte => read_char(input, byte)?,
+    };
+    Ok(key)
+}
+
+// Cursor keys arrive as ESC [ or ESC O, parameters, and a final letter or `~`.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let introducer = read_byte(input)?;
+    if introducer != b'[' && introducer != b'O' {
+        return Ok(Key::Other);
+    }
+    let mut params = Vec::new();
+    let last = loop {
+        let byte = read_byte(input)?;
+        if (0x40..=0x7e).contains(&byte) {
+            break byte;
+        }
+        params.push(byte);
+    };
+    Ok(match (last, params.as_slice()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', b"1") | (b'~', b"7") => Key::Home,
+        (b'F', _) | (b'~', b"4") | (b'~', b"8") => Key::End,
+        (b'~', b"3") => Key::Delete,
+        _ => Key::Other,
+    })
+}
+
+// A character starting with `first`, reading the rest of its UTF-8 bytes.
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.push(read_byte(input)?);
+    }
+    Ok(
+        match std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+        {
+            Some(c) => Key::Char(c),
+            None => Key::Other,
+        },
+    )
+}
@@ -0,0 +1,120 @@
+// Gesture files for `andro path`: a JSON array of [x, y, t] triples, t in ms.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use bridge_core::Gesture;
+
+// Reads the gesture from `source`, or stdin for `-`.
+pub fn load(source: &Path) -> Result<Gesture, String> {
+    let text = if source.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("cannot read stdin: {}", e))?;
+        text
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| format!("cannot read {}: {}", source.display(), e))?
+    };
+    let gesture = Gesture {
+        points: parse(&text)?,
+    };
+    gesture.validate()?;
+    Ok(gesture)
+}
+
+// Parses `[[x, y, t], ...]`. Only this shape is accepted, values must be integers.
+fn parse(text: &str) -> Result<Vec<(i32, i32, u64)>, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let mut points = Vec::new();
+    parser.expect('[')?;
+    if !parser.eat(']') {
+        loop {
+            parser.expect('[')?;
+            let x = parser.number()?;
+            parser.expect(',')?;
+            let y = parser.number()?;
+            parser.expect(',')?;
+            let t = parser.number()?;
+            parser.expect(']')?;
+            match (i32::try_from(x), i32::try_from(y), u64::try_from(t)) {
+                (Ok(x), Ok(y), Ok(t)) => points.push((x, y, t)),
+                _ => {
+                    return Err(format!(
+                        "point {} is out of range: [{}, {}, {}]",
+                        points.len() + 1,
+                        x,
+                        y,
+                        t
+                    ));
+                }
+            }
+            if parser.eat(']') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.skip_whitespace();
+    if parser.pos != text.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(points)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.text[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+            .map_or(rest.len(), |(i, _)| i);
+        let number = rest[..len]
+            .parse()
+            .map_err(|_| self.error("expected an integer"))?;
+        self.pos += len;
+        Ok(number)
+    }
+
+    // Describes a syntax error at the current position, as line:column.
+    fn error(&self, message: &str) -> String {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        format!(
+            "invalid gesture at line {}, column {}: {}",
+            line, column, message
+        )
+    }
+}
@@ -0,0 +1,99 @@
+// --idle-timeout: gives up on a server that has gone quiet. The socket timeout applies to
+// every single read, so a stream only times out after that long without any output, not
+// after that long in total. Wherever the timeout hits (the response reader, the stdin
+// forwarder, a transfer), the client ends the same way, like coreutils `timeout`.
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, RawFd};
+use std::process;
+use std::time::Duration;
+
+use bridge_core::Codec;
+use bridge_core::transport::Transport;
+
+use crate::{TIMEOUT_EXIT_CODE, report};
+
+pub struct IdleTimeout {
+    inner: Box<dyn Transport>,
+    timeout: Duration,
+}
+
+impl IdleTimeout {
+    pub fn wrap(inner: Box<dyn Transport>, timeout: Duration) -> io::Result<Box<dyn Transport>> {
+        inner.set_timeouts(Some(timeout))?;
+        Ok(Box::new(IdleTimeout { inner, timeout }))
+    }
+
+    fn check<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result
+            && matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        {
+            let message = format!(
+                "the server stalled for {} s (--idle-timeout)",
+                self.timeout.as_secs()
+            );
+            if report::enabled() {
+                report::error("idle_timeout", &message, TIMEOUT_EXIT_CODE);
+            } else {
+                eprintln!("Error: {}", message);
+            }
+            let _ = io::stdout().flush();
+            process::exit(TIMEOUT_EXIT_CODE);
+        }
+        result
+    }
+}
+
+impl Read for IdleTimeout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.check(result)
+    }
+}
+
+impl Write for IdleTimeout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.check(result)
+    }
+}
+
+impl AsRawFd for IdleTimeout {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Transport for IdleTimeout {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(IdleTimeout {
+            inner: self.inner.try_clone_box()?,
+            timeout: self.timeout,
+        }))
+    }
+
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_timeouts(timeout)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn tcp_peer(&self) -> Option<SocketAddr> {
+        self.inner.tcp_peer()
+    }
+
+    fn codec(&self) -> Codec {
+        self.inner.codec()
+    }
+}
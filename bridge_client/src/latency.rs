@@ -0,0 +1,138 @@
+// `andro ping -c N`: round-trip times, to see what the bridge itself costs. Every round
+// times a Ping on a fresh connection (connect, handshake and reply, what a one-shot andro
+// command pays) and one on a connection that stays open (what a shell or a library client
+// pays per request).
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_client::session::Session;
+use bridge_client::{
+    Retry, Server, WireCodec, connect_retrying, handshake, read_response, send_command,
+};
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+pub struct Options {
+    pub count: u32,
+    pub interval: Duration,
+    // Print every round, not only the summary.
+    pub verbose: bool,
+}
+
+// Returns the exit code: 0 if every Ping got its answer.
+pub fn run(server: Server, codec: WireCodec, token: String, retry: Retry, options: Options) -> i32 {
+    let label = server.to_string();
+    let fresh = || -> Result<Duration, String> {
+        let started = Instant::now();
+        let mut stream = connect_retrying(&server, codec, &retry).map_err(|e| e.to_string())?;
+        handshake(&mut *stream, &token).map_err(|e| e.to_string())?;
+        send_command(&mut *stream, &BridgeCommand::Ping).map_err(|e| e.to_string())?;
+        pong(read_response(&mut *stream).map_err(|e| e.to_string())?)?;
+        Ok(started.elapsed())
+    };
+    let mut session = match Session::open(server.clone(), codec, token.clone(), retry) {
+        Ok(session) if session.persistent() => Some(session),
+        Ok(_) => {
+            eprintln!("The server closes connections after each reply; timing connect+ping only");
+            None
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+
+    println!(
+        "PING {}: {} pings, {} ms apart",
+        label,
+        options.count,
+        options.interval.as_millis()
+    );
+    let mut connect_times = Vec::new();
+    let mut reuse_times = Vec::new();
+    let mut failed = 0;
+    for round in 1..=options.count {
+        if round > 1 {
+            thread::sleep(options.interval);
+        }
+        let connected = fresh();
+        let reused = session.as_mut().map(|session| {
+            let started = Instant::now();
+            let response = session
+                .request(BridgeCommand::Ping)
+                .map_err(|e| e.to_string())?;
+            pong(response)?;
+            Ok(started.elapsed())
+        });
+        let mut line = format!("ping {}: connect+ping {}", round, show(&connected));
+        if let Some(reused) = &reused {
+            line.push_str(&format!(", ping only {}", show(reused)));
+        }
+        match (connected, reused.unwrap_or(Ok(Duration::ZERO))) {
+            (Ok(connected), Ok(reused)) => {
+                connect_times.push(connected);
+                if session.is_some() {
+                    reuse_times.push(reused);
+                }
+                if options.verbose {
+                    println!("{}", line);
+                }
+            }
+            _ => {
+                failed += 1;
+                println!("{}", line);
+            }
+        }
+    }
+
+    println!("--- {} ping statistics ---", label);
+    println!(
+        "{} rounds, {} answered, {} failed",
+        options.count,
+        options.count - failed,
+        failed
+    );
+    print_stats("connect+ping", &mut connect_times);
+    print_stats("ping only", &mut reuse_times);
+    if failed > 0 { 1 } else { 0 }
+}
+
+fn pong(response: BridgeResponse) -> Result<(), String> {
+    match response {
+        BridgeResponse::Success(_) => Ok(()),
+        BridgeResponse::Error(e) => Err(e),
+        BridgeResponse::Failed(e) => Err(e.message),
+        response => Err(format!("unexpected answer {:?}", response)),
+    }
+}
+
+fn show(result: &Result<Duration, String>) -> String {
+    match result {
+        Ok(time) => format!("{:.3} ms", millis(*time)),
+        Err(e) => format!("failed ({})", e),
+    }
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+fn print_stats(name: &str, times: &mut [Duration]) {
+    if times.is_empty() {
+        return;
+    }
+    times.sort();
+    let total: Duration = times.iter().sum();
+    let average = total / times.len() as u32;
+    // The nearest-rank percentile: the smallest time at least 95% of the rounds beat or
+    // matched.
+    let p95 = times[(times.len() * 95).div_ceil(100) - 1];
+    println!(
+        "{:<12} min/avg/max/p95 = {:.3}/{:.3}/{:.3}/{:.3} ms",
+        name,
+        millis(times[0]),
+        millis(average),
+        millis(times[times.len() - 1]),
+        millis(p95)
+    );
+}
@@ -0,0 +1,479 @@
+//! The client side of NativeBridge as a library, for tools that drive the bridge from Rust
+//! instead of running `andro`. [`BridgeClient`] covers the common requests; the functions
+//! below it speak the protocol on any [`Transport`] for everything else.
+//! `AsyncBridgeClient`, with the `tokio` feature, does the same for async code.
+//!
+//! ```no_run
+//! use bridge_client::BridgeClient;
+//!
+//! let mut client = BridgeClient::connect("/tmp/bridge.sock")?;
+//! let output = client.exec("ls", &["/sdcard"])?;
+//! print!("{}", output.stdout);
+//! client.tap(540, 1200)?;
+//! # Ok::<(), bridge_client::Error>(())
+//! ```
+
+use std::fmt;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::proto::MAX_FRAME_LEN;
+use bridge_core::transport::{JsonLines, Transport, connect_tcp};
+use bridge_core::{
+    BridgeCommand, BridgeResponse, COMMAND_NAMES, Codec, PRE_HANDSHAKE_VERSION, PROTOCOL_VERSION,
+    READS_COMPRESSED, SPILL_PROTOCOL_VERSION, abstract_name, negotiate, protocol_mismatch,
+};
+
+#[cfg(feature = "tokio")]
+mod async_client;
+mod client;
+pub mod multiplex;
+pub mod session;
+
+#[cfg(feature = "tokio")]
+pub use async_client::{AsyncBridgeClient, AsyncChunks, Reply};
+pub use client::{BridgeClient, Chunk, Chunks, Error, Output, Result};
+
+/// Where the server listens: its Unix socket (a path or an abstract `@name`) or a TCP address.
+#[derive(Clone)]
+pub enum Server {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Server::Unix(socket) => write!(f, "{}", socket.display()),
+            Server::Tcp(address) => write!(f, "tcp://{}", address),
+        }
+    }
+}
+
+/// How frames are encoded on the connection; the server accepts either.
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum WireCodec {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// Opens a connection to `server`. It still needs a [`handshake`] before any command.
+pub fn connect(server: &Server, codec: WireCodec) -> io::Result<Box<dyn Transport>> {
+    let stream: Box<dyn Transport> = match server {
+        Server::Tcp(address) => Box::new(connect_tcp(address)?),
+        Server::Unix(socket) => match abstract_name(socket) {
+            Some(name) => Box::new(UnixStream::connect_addr(&SocketAddr::from_abstract_name(
+                name,
+            )?)?),
+            None => Box::new(UnixStream::connect(socket)?),
+        },
+    };
+    Ok(match codec {
+        WireCodec::Bincode => stream,
+        WireCodec::Json => Box::new(JsonLines(stream)),
+    })
+}
+
+/// How long to keep trying to connect while the server is not up yet, e.g. during boot.
+/// The default tries once.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// The wait before the first retry; it doubles after each one, up to [`MAX_RETRY_DELAY`].
+    pub delay: Duration,
+    /// Give up once this much time has passed in total; None keeps going until `retries`
+    /// are used up.
+    pub deadline: Option<Duration>,
+}
+
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry {
+            retries: 0,
+            delay: Duration::from_millis(500),
+            deadline: None,
+        }
+    }
+}
+
+/// Like [`connect`], retrying as long as `retry` allows while the socket does not exist yet
+/// or nothing listens on it. Other errors fail right away. Giving up after retries makes
+/// one error that says how long it tried.
+pub fn connect_retrying(
+    server: &Server,
+    codec: WireCodec,
+    retry: &Retry,
+) -> io::Result<Box<dyn Transport>> {
+    let started = Instant::now();
+    let mut delay = retry.delay;
+    let mut attempts = 0u32;
+    loop {
+        attempts += 1;
+        let e = match connect(server, codec) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => e,
+        };
+        let waiting = matches!(
+            e.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+        );
+        let left = retry
+            .deadline
+            .map(|deadline| deadline.saturating_sub(started.elapsed()));
+        if !waiting || attempts > retry.retries || left == Some(Duration::ZERO) {
+            if attempts == 1 {
+                return Err(e);
+            }
+            return Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "{} (gave up after {} attempts in {:.1} s)",
+                    e,
+                    attempts,
+                    started.elapsed().as_secs_f64()
+                ),
+            ));
+        }
+        thread::sleep(left.map_or(delay, |left| delay.min(left)));
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// Exchanges hello frames with the server, failing with a readable message when the two
+/// have no protocol version in common or the server refuses the token.
+pub fn handshake(stream: &mut dyn Transport, token: &str) -> io::Result<()> {
+    let codec = stream.codec();
+    codec.write_hello(stream, hello_version(codec), token.as_bytes())?;
+    let frame = next_frame(stream)?;
+    server_hello(codec, &frame)?;
+    handshake_result(read_response(stream)?)
+}
+
+// The version our hello announces, with READS_COMPRESSED if we take compressed frames.
+fn hello_version(codec: Codec) -> u32 {
+    if codec == Codec::Bincode && COMPRESSION.load(Ordering::Relaxed) {
+        return PROTOCOL_VERSION | READS_COMPRESSED;
+    }
+    PROTOCOL_VERSION
+}
+
+// Agrees on a version with the server's hello `frame`, for the commands sent from now on.
+fn server_hello(codec: Codec, frame: &[u8]) -> io::Result<()> {
+    // Servers from before the handshake answer the hello with an Error instead.
+    let (server, _) = codec
+        .parse_hello(frame)
+        .unwrap_or((PRE_HANDSHAKE_VERSION, Vec::new()));
+    let Some(version) = negotiate(PROTOCOL_VERSION, server) else {
+        return Err(io::Error::other(protocol_mismatch(
+            server,
+            PROTOCOL_VERSION,
+        )));
+    };
+    SERVER_PROTOCOL.store(version, Ordering::Relaxed);
+    Ok(())
+}
+
+// The server's verdict on our hello: anything but an Error lets us in.
+fn handshake_result(response: BridgeResponse) -> io::Result<()> {
+    match response {
+        BridgeResponse::Error(err) if err.starts_with("Authentication Failed") => Err(
+            io::Error::other(format!("{} (set ANDRO_TOKEN or --token-file)", err)),
+        ),
+        BridgeResponse::Error(err) => Err(io::Error::other(err)),
+        _ => Ok(()),
+    }
+}
+
+/// Sends a command in the form the server's protocol version takes. A command using what
+/// the server is too old for, such as resource limits, fails with `InvalidInput` instead.
+pub fn send_command(stream: &mut dyn Transport, cmd: &BridgeCommand) -> io::Result<()> {
+    let codec = stream.codec();
+    let payload = codec.encode_command_for(cmd, server_protocol())?;
+    codec.write_frame(stream, &payload)
+}
+
+static SERVER_PROTOCOL: AtomicU32 = AtomicU32::new(PROTOCOL_VERSION);
+
+/// The protocol version agreed with the server in the last handshake of the process, the
+/// newest one before any.
+pub fn server_protocol() -> u32 {
+    SERVER_PROTOCOL.load(Ordering::Relaxed)
+}
+
+static MAX_FRAME: AtomicU64 = AtomicU64::new(MAX_FRAME_LEN);
+
+/// Sets the largest response frame read from now on, for every connection of the process
+/// (64 MiB by default). A longer one fails as `InvalidData` carrying a
+/// [`bridge_core::proto::FrameTooLarge`], before anything is allocated for it.
+pub fn set_max_frame_len(bytes: u64) {
+    MAX_FRAME.store(bytes, Ordering::Relaxed);
+}
+
+pub fn max_frame_len() -> u64 {
+    MAX_FRAME.load(Ordering::Relaxed)
+}
+
+static COMPRESSION: AtomicBool = AtomicBool::new(cfg!(feature = "compress"));
+
+/// Sets whether connections opened from now on let the server compress large responses, which
+/// the frame readers undo transparently. On by default in builds with the `compress` feature,
+/// the only ones that can read them.
+pub fn set_compression(enabled: bool) {
+    COMPRESSION.store(enabled && cfg!(feature = "compress"), Ordering::Relaxed);
+}
+
+// The next frame from the server. A server that has closed the connection fails the read
+// as UnexpectedEof, since every caller is waiting for an answer.
+fn next_frame(stream: &mut dyn Transport) -> io::Result<Vec<u8>> {
+    stream
+        .codec()
+        .read_frame(stream, max_frame_len())?
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the server closed the connection",
+            )
+        })
+}
+
+/// Reads the next response. A frame that does not decode fails as `InvalidData` carrying an
+/// [`Undecodable`], and one over [`max_frame_len`] as `InvalidData` too.
+pub fn read_response(stream: &mut dyn Transport) -> io::Result<BridgeResponse> {
+    let buffer = next_frame(stream)?;
+    decode_response(stream.codec(), &buffer)
+}
+
+/// Decodes a response frame read by other means, failing like [`read_response`].
+pub fn decode_response(codec: Codec, frame: &[u8]) -> io::Result<BridgeResponse> {
+    codec
+        .decode_response_for(frame, server_protocol())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, Undecodable::new(frame, e)))
+}
+
+/// Output the server sent ahead of a command's single response in StdoutChunk and
+/// StderrChunk frames, because there was more of it than the server holds (from
+/// [`SPILL_PROTOCOL_VERSION`] on).
+#[derive(Default)]
+pub struct Spilled {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Spilled {
+    /// Keeps `response` if it is such a chunk, and hands it back otherwise. Servers before
+    /// [`SPILL_PROTOCOL_VERSION`] send no such chunks, so every response is handed back.
+    pub fn take(&mut self, response: BridgeResponse) -> Option<BridgeResponse> {
+        if server_protocol() < SPILL_PROTOCOL_VERSION {
+            return Some(response);
+        }
+        match response {
+            BridgeResponse::StdoutChunk(bytes) => self.stdout.extend(bytes),
+            BridgeResponse::StderrChunk(bytes) => self.stderr.extend(bytes),
+            response => return Some(response),
+        }
+        None
+    }
+
+    /// Puts the output kept in front of that of `response`, the one that ends the command.
+    pub fn merge(self, response: BridgeResponse) -> BridgeResponse {
+        let text =
+            |kept: Vec<u8>, rest: String| String::from_utf8_lossy(&kept).into_owned() + &rest;
+        match response {
+            BridgeResponse::Completed {
+                stdout,
+                stderr,
+                code,
+            } => BridgeResponse::Completed {
+                stdout: text(self.stdout, stdout),
+                stderr: text(self.stderr, stderr),
+                code,
+            },
+            BridgeResponse::CompletedBytes {
+                stdout,
+                stderr,
+                code,
+            } => BridgeResponse::CompletedBytes {
+                stdout: [self.stdout, stdout].concat(),
+                stderr: text(self.stderr, stderr),
+                code,
+            },
+            BridgeResponse::TimedOut {
+                timeout_ms,
+                stdout,
+                stderr,
+            } => BridgeResponse::TimedOut {
+                timeout_ms,
+                stdout: text(self.stdout, stdout),
+                stderr: text(self.stderr, stderr),
+            },
+            response => response,
+        }
+    }
+}
+
+/// Reads the answer to a command with a single response, like [`read_response`], with the
+/// output that went ahead of it put back in.
+pub fn read_single_response(stream: &mut dyn Transport) -> io::Result<BridgeResponse> {
+    let mut spilled = Spilled::default();
+    loop {
+        if let Some(response) = spilled.take(read_response(stream)?) {
+            return Ok(spilled.merge(response));
+        }
+    }
+}
+
+/// A response frame that is not a `BridgeResponse`, most likely from a server built from
+/// another version of the protocol.
+#[derive(Debug)]
+pub struct Undecodable {
+    pub len: usize,
+    /// The first bytes of the frame, at most 16.
+    pub head: Vec<u8>,
+    pub error: String,
+}
+
+impl Undecodable {
+    fn new(frame: &[u8], error: impl fmt::Display) -> Undecodable {
+        Undecodable {
+            len: frame.len(),
+            head: frame[..frame.len().min(16)].to_vec(),
+            error: error.to_string(),
+        }
+    }
+
+    /// The Undecodable behind an error of [`read_response`].
+    pub fn of(e: &io::Error) -> Option<&Undecodable> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Undecodable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot decode a {} byte response", self.len)?;
+        if !self.head.is_empty() {
+            let head: Vec<String> = self.head.iter().map(|b| format!("{:02x}", b)).collect();
+            let more = if self.len > self.head.len() {
+                " ..."
+            } else {
+                ""
+            };
+            write!(f, " ({}{})", head.join(" "), more)?;
+        }
+        write!(
+            f,
+            ": {}; are andro and bridge_server built from the same version?",
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for Undecodable {}
+
+/// Explains a FrameTooLarge answer: the request was over the server's --max-frame-size.
+pub fn frame_too_large(len: u64, limit: u64) -> String {
+    format!(
+        "the request is {} bytes, more than the server accepts ({} bytes, its --max-frame-size)",
+        len, limit
+    )
+}
+
+/// Explains a Busy answer: the server runs its --max-jobs host processes already.
+pub fn busy(running: u32, limit: u32) -> String {
+    format!(
+        "server busy: {} of {} running, try again later",
+        running, limit
+    )
+}
+
+/// Explains an Unsupported answer: the command was added after the server was built.
+pub fn too_old(kind: u32) -> String {
+    let name = COMMAND_NAMES
+        .get(kind as usize)
+        .copied()
+        .unwrap_or("this command");
+    format!(
+        "the server is too old for {}, it needs bridge_server {} or newer",
+        name,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undecodable_frames_name_their_length_and_first_bytes() {
+        let frame: Vec<u8> = (0xf0..=0xff).chain([0, 1]).collect();
+        let e = decode_response(Codec::Bincode, &frame).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let undecodable = Undecodable::of(&e).unwrap();
+        assert_eq!(undecodable.len, 18);
+        assert_eq!(undecodable.head, frame[..16]);
+        let message = e.to_string();
+        assert!(message.starts_with(
+            "cannot decode a 18 byte response (f0 f1 f2 f3 f4 f5 f6 f7 f8 f9 fa fb fc fd fe ff ...): "
+        ));
+        assert!(message.ends_with("are andro and bridge_server built from the same version?"));
+
+        let e = decode_response(Codec::Json, b"[]").unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("cannot decode a 2 byte response (5b 5d): ")
+        );
+        // Other errors are not taken for one.
+        assert!(Undecodable::of(&io::Error::other("broken pipe")).is_none());
+    }
+
+    #[test]
+    fn spilled_output_goes_in_front_of_the_response() {
+        let mut wire = Vec::new();
+        for response in [
+            BridgeResponse::StdoutChunk(b"one ".to_vec()),
+            BridgeResponse::StderrChunk(b"warning\n".to_vec()),
+            BridgeResponse::StdoutChunk(b"two ".to_vec()),
+            BridgeResponse::Completed {
+                stdout: "three\n".to_string(),
+                stderr: String::new(),
+                code: 0,
+            },
+        ] {
+            let payload = Codec::Bincode.encode_response(&response).unwrap();
+            Codec::Bincode.write_frame(&mut wire, &payload).unwrap();
+        }
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        io::Write::write_all(&mut server, &wire).unwrap();
+        match read_single_response(&mut client).unwrap() {
+            BridgeResponse::Completed {
+                stdout,
+                stderr,
+                code,
+            } => assert_eq!(
+                (stdout.as_str(), stderr.as_str(), code),
+                ("one two three\n", "warning\n", 0)
+            ),
+            other => panic!("expected Completed, got {:?}", other),
+        }
+
+        // Responses that carry no output are handed back as they are.
+        let mut spilled = Spilled::default();
+        assert!(
+            spilled
+                .take(BridgeResponse::StdoutChunk(b"x".to_vec()))
+                .is_none()
+        );
+        assert!(matches!(
+            spilled.merge(BridgeResponse::Success(String::new())),
+            BridgeResponse::Success(_)
+        ));
+    }
+}
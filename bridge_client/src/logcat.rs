@@ -0,0 +1,184 @@
+// `andro logcat`: logcat with flags for what otherwise takes filterspecs and timestamps. It
+// runs as a Stream of logcat, which ends by itself with --dump or --clear. Only `--pid
+// PACKAGE` needs the server's Logcat command, which looks up the app's pid in the same
+// request. On a terminal each line is colored by its priority.
+
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bridge_client::read_response;
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse, ProcessOptions};
+
+use crate::{show_stream_response, write_stdout};
+
+pub struct Options {
+    pub tags: Vec<String>,
+    // The lowest priority shown, as logcat's letter.
+    pub priority: Option<char>,
+    // A pid, or the package name of a running app.
+    pub pid: Option<String>,
+    pub clear: bool,
+    pub dump: bool,
+    pub since: Option<Duration>,
+    // Passed to logcat as they are, ahead of the filterspecs.
+    pub args: Vec<String>,
+}
+
+// Parses --priority: logcat's letter or the name it stands for, e.g. W or warn.
+pub fn parse_priority(arg: &str) -> Result<char, String> {
+    match arg.to_ascii_lowercase().as_str() {
+        "v" | "verbose" => Ok('V'),
+        "d" | "debug" => Ok('D'),
+        "i" | "info" => Ok('I'),
+        "w" | "warn" | "warning" => Ok('W'),
+        "e" | "error" => Ok('E'),
+        "f" | "fatal" => Ok('F'),
+        _ => Err(format!("expected one of V, D, I, W, E or F, got '{}'", arg)),
+    }
+}
+
+pub fn command(options: Options, process: ProcessOptions) -> BridgeCommand {
+    let mut args = options.args;
+    if options.clear {
+        args.push("-c".to_string());
+        return stream(args, process);
+    }
+    match (options.since, options.dump) {
+        // Client and server share the device's clock, so the time can be taken here.
+        (Some(since), dump) => {
+            let start = SystemTime::now()
+                .checked_sub(since)
+                .unwrap_or(UNIX_EPOCH)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            // -t prints what is there and exits, -T goes on following.
+            args.push(if dump { "-t" } else { "-T" }.to_string());
+            args.push(format!("{}.{:03}", start.as_secs(), start.subsec_millis()));
+        }
+        (None, true) => args.push("-d".to_string()),
+        (None, false) => {}
+    }
+    let package = match options.pid {
+        Some(pid) if pid.parse::<u32>().is_ok() => {
+            args.push(format!("--pid={}", pid));
+            None
+        }
+        pid => pid,
+    };
+    args.extend(filterspecs(&options.tags, options.priority));
+    match package {
+        Some(package) => BridgeCommand::Logcat {
+            package,
+            args,
+            options: process,
+        },
+        None => stream(args, process),
+    }
+}
+
+fn stream(args: Vec<String>, options: ProcessOptions) -> BridgeCommand {
+    BridgeCommand::Stream {
+        program: "logcat".to_string(),
+        args,
+        stdin: false,
+        options,
+    }
+}
+
+// `TAG:P` for each tag and `*:S` to silence the rest, or just `*:P` without tags.
+fn filterspecs(tags: &[String], priority: Option<char>) -> Vec<String> {
+    let priority = priority.unwrap_or('V');
+    if tags.is_empty() {
+        return match priority {
+            'V' => Vec::new(),
+            _ => vec![format!("*:{}", priority)],
+        };
+    }
+    let mut specs: Vec<String> = tags
+        .iter()
+        .map(|tag| format!("{}:{}", tag, priority))
+        .collect();
+    specs.push("*:S".to_string());
+    specs
+}
+
+// Prints the stream with each complete line colored by its priority. A line split between
+// chunks waits for the rest of it.
+pub fn receive(stream: &mut dyn Transport) -> io::Result<i32> {
+    let mut partial = Vec::new();
+    loop {
+        let response = match read_response(stream) {
+            Ok(response) => response,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        match response {
+            BridgeResponse::StdoutChunk(bytes) => {
+                partial.extend_from_slice(&bytes);
+                let end = partial
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |i| i + 1);
+                write_stdout(&colorize(&partial[..end]))?;
+                partial.drain(..end);
+            }
+            BridgeResponse::StderrChunk(_) => {
+                show_stream_response(response)?;
+            }
+            response => {
+                write_stdout(&colorize(&partial))?;
+                partial.clear();
+                if let Some(code) = show_stream_response(response)? {
+                    return Ok(code);
+                }
+            }
+        }
+    }
+    // The connection closed before the stream was finished.
+    write_stdout(&colorize(&partial))?;
+    Ok(1)
+}
+
+fn colorize(text: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len() + text.len() / 8);
+    for line in text.split_inclusive(|&b| b == b'\n') {
+        let Some(color) = priority(line).and_then(color) else {
+            out.extend_from_slice(line);
+            continue;
+        };
+        let (line, newline) = match line.strip_suffix(b"\n") {
+            Some(line) => (line, &b"\n"[..]),
+            None => (line, &b""[..]),
+        };
+        out.extend_from_slice(format!("\x1b[{}m", color).as_bytes());
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\x1b[0m");
+        out.extend_from_slice(newline);
+    }
+    out
+}
+
+// The priority of a line of logcat output: a word of its own in the default `threadtime`
+// format (`date time pid tid P tag: ...`), and the start of `P/tag` in `brief` and `time`.
+fn priority(line: &[u8]) -> Option<u8> {
+    line.split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty())
+        .take(5)
+        .find_map(|word| match word {
+            [p] | [p, b'/', ..] if b"VDIWEFA".contains(p) => Some(*p),
+            _ => None,
+        })
+}
+
+fn color(priority: u8) -> Option<&'static str> {
+    match priority {
+        b'V' => Some("37"),
+        b'D' => Some("34"),
+        b'I' => Some("32"),
+        b'W' => Some("33"),
+        b'E' => Some("31"),
+        b'F' | b'A' => Some("1;31"),
+        _ => None,
+    }
+}
@@ -1,172 +1,2482 @@
-use clap::{Parser, Subcommand};
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use bridge_core::{BridgeCommand, BridgeResponse};
+use bridge_client::{
+    Retry, Server, Spilled, Undecodable, WireCodec, busy, connect_retrying, decode_response,
+    frame_too_large, handshake, max_frame_len, read_response, send_command, too_old,
+};
+use bridge_core::keys::Keycode;
+use bridge_core::proto::FrameTooLarge;
+use bridge_core::transport::{Transport, tcp_address};
+use bridge_core::{
+    BackgroundJob, BridgeCommand, BridgeResponse, COMMAND_NAMES, Coord, DIRECT_INPUT_COMMANDS,
+    DeviceInfo, Easing, ErrorKind, ImageFormat, InputSource, Job, JobState, Limits, MouseButton,
+    Point, ProcessOptions, ScreenSize, ServerInfo, SignalTarget,
+};
 
-// Socket location as seen from the Chroot side
+mod app;
+mod batch;
+mod completions;
+mod config;
+mod editor;
+mod gesture;
+mod idle;
+mod latency;
+mod logcat;
+mod pexec;
+mod progress;
+mod prop;
+mod recording;
+mod report;
+mod screen;
+mod script;
+mod shell;
+mod shell_session;
+mod taps;
+mod terminal;
+mod touch;
+mod transfer;
+mod watch;
+
+use transfer::TransferOptions;
+
+// Default socket location as seen from the Chroot side (--socket / ANDRO_SOCKET)
 const SOCKET_PATH: &str = "/tmp/bridge.sock";
 
+// Size of each StdinChunk/FileChunk frame; keeps memory bounded for large inputs and files.
+const CHUNK_SIZE: usize = 64 * 1024;
+
 #[derive(Parser)]
 #[command(name = "andro")]
 #[command(about = "NativeBridge Client for Android Chroot", long_about = None, arg_required_else_help = true)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    #[arg(short, long, value_name = "CMD", num_args=1.., conflicts_with_all=&["stream", "command"])]
+    #[arg(short, long, value_name = "CMD", num_args=1.., allow_hyphen_values = true, conflicts_with = "stream")]
     exec: Option<Vec<String>>,
 
-    #[arg(short, long, value_name = "CMD", num_args=1.., conflicts_with_all=&["exec", "command"])]
+    #[arg(short, long, value_name = "CMD", num_args=1.., allow_hyphen_values = true, conflicts_with = "exec")]
     stream: Option<Vec<String>>,
 
+    /// Do not forward stdin to the remote command, even when it is piped
+    #[arg(short, long)]
+    no_stdin: bool,
+
+    /// Pass the output of -e through byte for byte, for binary data like images
+    #[arg(long, requires = "exec", conflicts_with = "stream")]
+    binary: bool,
+
+    /// Kill the remote command if it runs longer than this (0 = no timeout)
+    #[arg(long, value_name = "MS")]
+    timeout: Option<u64>,
+
+    /// Set an environment variable for the remote command (repeatable)
+    #[arg(short = 'E', long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Copy an environment variable from the chroot to the remote command (repeatable)
+    #[arg(long, value_name = "KEY")]
+    pass_env: Vec<String>,
+
+    /// Working directory for the remote command, as a host path (e.g. /sdcard)
+    #[arg(long, value_name = "DIR")]
+    cwd: Option<String>,
+
+    /// Run the remote command as an Android user (e.g. shell, system) or numeric id
+    #[arg(long, value_name = "USER", value_parser = parse_android_user)]
+    as_user: Option<u32>,
+
+    /// Run the remote command with this uid (overrides --as-user)
+    #[arg(long)]
+    uid: Option<u32>,
+
+    /// Run the remote command with this gid (overrides --as-user)
+    #[arg(long)]
+    gid: Option<u32>,
+
+    /// Run the remote command at this niceness, from -20 (most favorable) to 19
+    #[arg(long, value_name = "N", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Only let the remote command run on these CPUs, e.g. 0-3 or 4,5
+    #[arg(long, value_name = "LIST", value_parser = parse_cpus)]
+    cpus: Option<u64>,
+
+    /// Cap the remote command's address space (e.g. 512M); allocations past it fail
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_mem: Option<u64>,
+
+    /// Cap the size of files the remote command writes (e.g. 1G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// File holding the server's auth token (see `bridge_server --auth-token-file`);
+    /// the ANDRO_TOKEN variable can hold the token itself instead
+    #[arg(long, value_name = "PATH", global = true)]
+    token_file: Option<PathBuf>,
+
+    /// Server socket, as seen from the chroot, or `@name` for an abstract socket (also
+    /// ANDRO_SOCKET, or BRIDGE_SOCKET)
+    #[arg(long, value_name = "PATH", global = true, default_value = SOCKET_PATH)]
+    socket: PathBuf,
+
+    /// Reach the server over TCP instead (see `bridge_server --listen`); needs the token
+    #[arg(long, value_name = "tcp://HOST:PORT", global = true, env = "ANDRO_ADDR",
+          value_parser = parse_tcp)]
+    connect: Option<String>,
+
+    /// Wire format to talk to the server in; json is meant for testing the JSON mode
+    #[arg(long, value_enum, global = true, default_value = "bincode")]
+    codec: WireCodec,
+
+    /// Retry connecting up to N times while the server is not up yet (no socket, or nothing
+    /// listening on it)
+    #[arg(long, value_name = "N", global = true)]
+    retry: Option<u32>,
+
+    /// Wait before the first retry (e.g. 500ms, 2s); it doubles after each one, up to 5s
+    #[arg(long, value_name = "DURATION", global = true, default_value = "500ms",
+          value_parser = parse_duration)]
+    retry_delay: Duration,
+
+    /// Keep retrying until the server is up, or for at most DURATION (`--wait=30s`)
+    #[arg(long, value_name = "DURATION", global = true, num_args = 0..=1, require_equals = true,
+          value_parser = parse_duration)]
+    wait: Option<Option<Duration>>,
+
+    /// Give up with exit code 124 once the server has sent nothing for SECS seconds; a
+    /// stream counts from its last output. Pty sessions are exempt. Waits forever by default
+    #[arg(long, value_name = "SECS", global = true)]
+    idle_timeout: Option<u64>,
+
+    /// Refuse responses larger than this, failing with an error instead of reading them
+    #[arg(long, value_name = "BYTES", global = true, default_value_t = bridge_core::proto::MAX_FRAME_LEN,
+          value_parser = clap::value_parser!(u64).range(1024 * 1024..))]
+    max_frame_size: u64,
+
+    /// Have the server send large responses uncompressed
+    #[arg(long, global = true)]
+    no_compress: bool,
+
+    /// Print each response as one JSON object per line, errors included, and nothing else
+    #[arg(long, global = true, overrides_with = "no_json")]
+    json: bool,
+
+    /// Print responses for people even if the config or ANDRO_JSON turns on --json
+    #[arg(long, global = true, overrides_with = "json")]
+    no_json: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Tap a point; coordinates are pixels or a percentage of the screen (e.g. `50% 90%`)
     Tap {
-        x: i32,
-        y: i32,
+        #[arg(value_parser = parse_coord)]
+        x: Coord,
+        #[arg(value_parser = parse_coord)]
+        y: Coord,
+        /// Print the events the server would write instead of tapping
+        #[arg(long)]
+        dry_run: bool,
     },
+    /// Swipe between two points, in pixels or percent like `tap`
     Swipe {
+        #[arg(value_parser = parse_coord)]
+        x1: Coord,
+        #[arg(value_parser = parse_coord)]
+        y1: Coord,
+        #[arg(value_parser = parse_coord)]
+        x2: Coord,
+        #[arg(value_parser = parse_coord)]
+        y2: Coord,
+        #[arg(default_value_t = 300)]
+        duration: u64,
+        /// How the speed changes along the swipe
+        #[arg(long, value_enum, default_value_t = SwipeEasing::Linear)]
+        easing: SwipeEasing,
+        /// Interval between move events in ms (lower is smoother, minimum 2)
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(2..))]
+        step_ms: u64,
+        /// Print the events the server would write instead of swiping
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Tap several points in one request: "x,y,delay_ms ..." with the delay before each tap
+    Taps {
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        taps: Option<String>,
+        /// Read the tap list from a file (`-` for stdin)
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// Two-finger swipe (e.g. to scroll or pull down the full notification shade)
+    Swipe2 {
         x1: i32,
         y1: i32,
         x2: i32,
         y2: i32,
         #[arg(default_value_t = 300)]
         duration: u64,
+        /// Distance between the fingers in pixels
+        #[arg(long, default_value_t = 100)]
+        spacing: i32,
+    },
+    /// Press a key by name (BACK, HOME, POWER, ...) or Android keycode (e.g. 26)
+    Key {
+        #[arg(value_name = "KEY", value_parser = parse_key)]
+        key: u16,
+        /// Hold the key down (duration set by the server's --long-press-ms)
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Type text on the device (`-` reads it from stdin)
+    Type {
+        text: String,
+        /// Pause between key presses in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 0)]
+        delay: u64,
+    },
+    /// Press and hold at a point (duration in ms)
+    Longpress {
+        x: i32,
+        y: i32,
+        #[arg(default_value_t = 800)]
+        duration: u64,
+    },
+    /// Drag from one point to another, pausing at both ends (e.g. to move icons)
+    Drag {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        /// How long to hold before moving and before releasing, in ms
+        #[arg(long, default_value_t = 500)]
+        hold: u64,
+        /// Hold before releasing, if different from --hold
+        #[arg(long, value_name = "MS")]
+        hold_after: Option<u64>,
+        /// Duration of the movement in ms
+        #[arg(long, default_value_t = 600)]
+        duration: u64,
+    },
+    /// Two-finger pinch around a center point; a growing distance zooms in
+    Pinch {
+        cx: i32,
+        cy: i32,
+        /// Distance between the fingers at the start, in pixels
+        start_dist: i32,
+        /// Distance between the fingers at the end, in pixels
+        end_dist: i32,
+        /// Duration in ms
+        #[arg(long, default_value_t = 500)]
+        duration: u64,
+    },
+    /// Check that the server is alive
+    Ping {
+        /// Also show the server version, features and supported commands, like `info`.
+        /// With --count, print every round instead
+        #[arg(short, long)]
+        verbose: bool,
+        /// Send this many Pings and report the round-trip times (min/avg/max/p95), both of
+        /// a fresh connection and of one kept open
+        #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        count: Option<u32>,
+        /// The wait between Pings with --count, e.g. 50ms or 1s
+        #[arg(short, long, value_name = "DURATION", default_value = "1s",
+              value_parser = parse_duration, requires = "count")]
+        interval: Duration,
+    },
+    /// Follow the Android log, with flags for what otherwise takes filterspecs:
+    /// `andro logcat --tag ActivityManager --priority W --pid com.example.app`
+    Logcat {
+        /// Only show this tag (repeatable)
+        #[arg(short, long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Only show this priority and above: V, D, I, W, E or F
+        #[arg(short, long, value_parser = logcat::parse_priority)]
+        priority: Option<char>,
+        /// Only show this pid, or the running app with this package name
+        #[arg(long, value_name = "PID|PACKAGE")]
+        pid: Option<String>,
+        /// Clear the log and exit
+        #[arg(short, long, conflicts_with_all = ["tags", "priority", "pid", "dump", "since"])]
+        clear: bool,
+        /// Print the log and exit instead of following it
+        #[arg(short, long)]
+        dump: bool,
+        /// Start with what was logged this long ago, e.g. 30s or 5m
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        since: Option<Duration>,
+        /// More logcat arguments, after `--`: `andro logcat -- -b crash`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Launch, stop, clear, install and list apps, with am's and pm's errors read back:
+    /// `andro app launch com.example.app`
+    App {
+        #[command(subcommand)]
+        action: app::AppAction,
+    },
+    /// Show the device: model, Android version, screen, density, rotation, battery and
+    /// uptime
+    Info {
+        /// Show the server instead: version, protocol, features, touch device, load and
+        /// supported commands (same as `ping -v`)
+        #[arg(long)]
+        server: bool,
+    },
+    /// List the processes the server is running: pid, connection, age and command line
+    Jobs,
+    /// Run host commands in the background, detached from this client, and check on them
+    /// later: `andro job start logcat -f /sdcard/log.txt`
+    Job {
+        #[command(subcommand)]
+        action: JobAction,
+    },
+    /// Send a signal to a background job (`%ID`) or, on servers started with
+    /// --allow-raw-pid-signals, any process by pid: `andro signal %3 HUP`
+    Signal {
+        /// `%ID` for a job from `andro job start`, or a pid
+        #[arg(value_parser = parse_signal_target)]
+        target: SignalTarget,
+        /// Signal name or number, e.g. HUP, USR1 or 10
+        #[arg(value_parser = parse_signal)]
+        signal: i32,
+    },
+    /// A shell on the host that keeps its working directory, variables and functions from
+    /// one line to the next. Without options, opens one and reads lines from you; Ctrl-D
+    /// ends it
+    Session {
+        /// Use the session with this id, from `andro session --open`, instead of a new one
+        #[arg(long, conflicts_with_all = ["open", "shell"])]
+        id: Option<u64>,
+        /// Run this line and exit with its exit code
+        #[arg(
+            short = 'c',
+            long = "command",
+            value_name = "LINE",
+            conflicts_with = "close"
+        )]
+        line: Option<String>,
+        /// Open a session, print its id and leave it open for `--id`
+        #[arg(long, conflicts_with_all = ["line", "close"])]
+        open: bool,
+        /// Close the session given by --id
+        #[arg(long, requires = "id")]
+        close: bool,
+        /// The shell to start
+        #[arg(long, default_value = "sh")]
+        shell: String,
+    },
+    /// Replay a gesture from a JSON file of [x, y, ms] points (`-` reads stdin)
+    Path { file: PathBuf },
+    /// Record raw events from the touch screen (default) or key device to stdout
+    Record {
+        /// Record the touch screen
+        #[arg(long, conflicts_with = "keys")]
+        touch: bool,
+        /// Record the key device instead of the touch screen
+        #[arg(long)]
+        keys: bool,
+    },
+    /// Play back a recording made with `andro record`
+    Replay {
+        file: PathBuf,
+        /// Playback speed multiplier (2.0 plays twice as fast)
+        #[arg(long, default_value_t = 1.0)]
+        speed: f32,
+    },
+    /// Press the Back button
+    Back {
+        /// Long press
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Press the Home button
+    Home {
+        /// Long press (usually opens the assistant)
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Press the Power button
+    Power {
+        /// Long press (usually opens the power menu)
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Press Volume Up or Down
+    Volume {
+        #[arg(value_enum)]
+        direction: VolumeDirection,
+        /// Long press
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Relative mouse input through a virtual mouse (desktop mode, pointer capture)
+    Mouse {
+        #[command(subcommand)]
+        action: MouseAction,
+    },
+    /// Turn the mouse wheel by a number of notches; negative scrolls down
+    Scroll {
+        #[arg(allow_negative_numbers = true)]
+        dy: i32,
+    },
+    /// Show or set the display rotation the server maps input coordinates with
+    Rotation {
+        #[command(subcommand)]
+        action: RotationAction,
+    },
+    /// Print the events of the touch screen (or --device) as they happen, like getevent
+    MonitorInput {
+        /// Event device to watch instead of the touch screen
+        #[arg(long, value_name = "PATH")]
+        device: Option<String>,
+        /// Output the raw input_event records, for piping into other tools
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Show which touch and key devices the server uses for direct input
+    InputInfo,
+    /// Raw touch contacts: `down SLOT X Y`, `move SLOT X Y`, `up SLOT` and `wait MS`
+    /// actions, played in order (`-` reads them from stdin). Contacts left down are lifted.
+    Touch {
+        #[arg(required = true, allow_hyphen_values = true, value_name = "ACTION")]
+        actions: Vec<String>,
+    },
+    /// Run a program on a remote pseudo-terminal (e.g. `andro pty sh`)
+    Pty {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Read, list, set and watch system properties: `andro prop get ro.build.version.sdk`
+    Prop {
+        #[command(subcommand)]
+        action: PropAction,
+    },
+    /// Read or set the host clipboard
+    Clip {
+        #[command(subcommand)]
+        action: ClipAction,
+    },
+    /// Capture the screen into an image file (`-` for stdout)
+    Screenshot {
+        output: PathBuf,
+        /// Image format; defaults to JPEG for .jpg/.jpeg names and PNG otherwise
+        #[arg(long, value_enum)]
+        format: Option<ScreenshotFormat>,
+        /// JPEG quality from 1 to 100; implies --format jpeg
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+    },
+    /// Capture frames continuously into a directory, or `-` for a framed stdout stream
+    ScreenStream {
+        output: PathBuf,
+        /// Frames per second to capture (0 = as fast as possible)
+        #[arg(long, default_value_t = 2)]
+        fps: u32,
+        /// Stop after this many frames (default: until interrupted)
+        #[arg(long, value_name = "N")]
+        frames: Option<u64>,
+        /// Image format of each frame
+        #[arg(long, value_enum)]
+        format: Option<ScreenshotFormat>,
+        /// JPEG quality from 1 to 100; implies --format jpeg
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+    },
+    /// Copy a file from the chroot to the host
+    Push {
+        source: PathBuf,
+        /// Host path; a trailing `/` keeps the source file name
+        dest: String,
+        /// Permission bits in octal (default: those of the source file)
+        #[arg(long, value_parser = parse_mode)]
+        mode: Option<u32>,
+        #[command(flatten)]
+        options: TransferOptions,
+    },
+    /// Copy a file from the host to the chroot
+    Pull {
+        source: String,
+        /// Local path, an existing directory, or `-` for stdout
+        dest: PathBuf,
+        /// Pull a whole directory (extracted into `dest`, or a tar stream with `-`)
+        #[arg(short, long, conflicts_with_all = ["resume", "verify"])]
+        recursive: bool,
+        #[command(flatten)]
+        options: TransferOptions,
+    },
+    /// Run the andro commands in FILE (one per line, `-` reads stdin) in a single request.
+    /// Only commands with a single reply work (no pty, push, screenshot, ...)
+    Batch {
+        file: PathBuf,
+        /// Run the remaining commands after one fails
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Wait on the server between the steps of a batch
+    Sleep { ms: u64 },
+    /// Run a host command once per line of an argument file, several at a time, like
+    /// `xargs -P`: `andro pexec --jobs 4 --arg-file apps.txt -- pm clear {}`
+    Pexec {
+        /// How many commands run at once
+        #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+        jobs: u32,
+        /// One argument per line (`-` reads stdin). `{}` in the command stands for it;
+        /// without one it is appended
+        #[arg(short, long, value_name = "FILE")]
+        arg_file: PathBuf,
+        /// The host command and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Run a script of andro commands (one per line, `-` reads stdin) one after another over
+    /// a single connection. `$VAR` takes the value of an environment variable, and
+    /// `sleep 500ms` waits on the client
+    Run {
+        file: PathBuf,
+        /// Stop at the first command that fails, reporting its line (the default)
+        #[arg(long, overrides_with = "keep_going")]
+        stop_on_error: bool,
+        /// Run every line and list the ones that failed at the end
+        #[arg(long = "continue", overrides_with = "stop_on_error")]
+        keep_going: bool,
+    },
+    /// Open a prompt that runs andro commands over a single connection, one per line
+    /// (`tap 100 200`, `exec ls /sdcard`, `stream logcat`); `exit` or Ctrl-D ends it
+    #[command(alias = "repl")]
+    Shell,
+    /// Wait until the server answers a Ping, e.g. in init scripts that start before it.
+    /// Exits 0 once it does, 1 when the time is up
+    WaitServer {
+        /// Give up after this many seconds (0 = wait forever)
+        #[arg(long, value_name = "SECS", default_value_t = 30)]
+        timeout: u64,
+    },
+    /// Run a command again and again over one connection, like watch(1):
+    /// `andro watch --interval 2s -- exec dumpsys battery`. Ctrl+C stops it
+    Watch {
+        /// The wait between runs, e.g. 500ms or 2s
+        #[arg(short = 'n', long, value_name = "DURATION", default_value = "2s",
+              value_parser = parse_duration)]
+        interval: Duration,
+        /// Stop after this many runs
+        #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        count: Option<u32>,
+        /// Print a timestamp header before each run instead of clearing the screen
+        #[arg(long)]
+        no_clear: bool,
+        /// Highlight the lines that changed since the previous run (with --no-clear, print
+        /// only those)
+        #[arg(short, long)]
+        diff: bool,
+        /// The command, as typed in `andro shell` (`exec ...`, `info`, `batch FILE`)
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Print a completion script for bash, zsh or fish:
+    /// `source <(andro completions bash)`
+    Completions { shell: completions::Shell },
+    /// Defaults for the flags come from ~/.config/andro/config.toml and ANDRO_* variables;
+    /// see the README
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum RotationAction {
+    /// Print the rotation: 0 natural, 1 90°, 2 180°, 3 270°
+    Get,
+    /// Set the rotation (overridden by the server's --auto-rotate)
+    Set {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=3))]
+        rotation: u8,
+    },
+}
+
+#[derive(Subcommand)]
+enum MouseAction {
+    /// Move the pointer by DX, DY pixels
+    Move {
+        #[arg(allow_negative_numbers = true)]
+        dx: i32,
+        #[arg(allow_negative_numbers = true)]
+        dy: i32,
+    },
+    /// Click a mouse button
+    Click {
+        #[arg(value_enum)]
+        button: ClickButton,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ClickButton {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum VolumeDirection {
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SwipeEasing {
+    Linear,
+    EaseInOut,
+    Overshoot,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ScreenshotFormat {
+    Png,
+    Jpeg,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective defaults and where each comes from
+    Show,
+}
+
+#[derive(Subcommand)]
+enum JobAction {
+    /// Start a program in the background and print its job id; --timeout, --cwd, --env and
+    /// --as-user apply as for -e
+    Start {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// List the background jobs: id, pid, state, runtime and command line
+    List,
+    /// Print the output of a job kept by the server; exits with the job's exit code once it
+    /// has finished
+    Logs {
+        id: u64,
+        /// Keep printing what the job writes until it exits
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Send a signal to a job's processes
+    Kill {
+        id: u64,
+        /// Signal name or number, e.g. TERM, KILL, INT or 9
+        #[arg(short, long, default_value = "TERM", value_parser = parse_signal)]
+        signal: i32,
+    },
+}
+
+#[derive(Subcommand)]
+enum PropAction {
+    /// Print the value of a property, or list them all as a sorted table with --all; an
+    /// unset property exits 66
+    Get {
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        /// List every property, or those whose name starts with --prefix
+        #[arg(short, long, conflicts_with = "name")]
+        all: bool,
+        #[arg(long, requires = "all")]
+        prefix: Option<String>,
+        /// Keep checking the property and print a line each time it changes, until Ctrl+C
+        #[arg(short, long, conflicts_with = "all")]
+        watch: bool,
+        /// How often --watch checks, e.g. 500ms or 2s
+        #[arg(short, long, value_name = "DURATION", default_value = "1s",
+              value_parser = parse_duration, requires = "watch")]
+        interval: Duration,
     },
-    Ping,
+    /// Set a property; fails with permission denied if the device keeps the value from
+    /// changing, as for read-only (ro.*) properties
+    Set { name: String, value: String },
+}
+
+#[derive(Subcommand)]
+enum ClipAction {
+    /// Print the clipboard text
+    Get,
+    /// Replace the clipboard text (`-` reads it from stdin)
+    Set { text: String },
+}
+
+// How the responses to a command are read and printed.
+enum ResponseMode {
+    Single,
+    Stream,
+    // A stream of logcat, colored by priority if `color`.
+    Logcat {
+        color: bool,
+    },
+    JobOutput,
+    Pty,
+    Screenshot {
+        target: PathBuf,
+    },
+    ScreenStream {
+        target: PathBuf,
+        format: ImageFormat,
+    },
+    Push {
+        file: File,
+        dest: String,
+        options: TransferOptions,
+    },
+    Pull {
+        target: PathBuf,
+        recursive: bool,
+        offset: u64,
+        options: TransferOptions,
+    },
+    Touch {
+        script: touch::Script,
+    },
+    Record {
+        source: InputSource,
+    },
+    // The script line each command of the batch came from.
+    Batch {
+        lines: Vec<usize>,
+    },
+}
+
+// What Ctrl+C (or SIGTERM, or SIGHUP) does while a command runs on the server: ask the
+// server to cancel it, so its final response ends the client as usual. Each hook says what
+// it does. With no hook, or on a second signal, the client exits right away.
+type Cancel = Box<dyn FnOnce() + Send>;
+static ON_CTRL_C: Mutex<Option<Cancel>> = Mutex::new(None);
+
+fn on_ctrl_c(cancel: Option<Cancel>) {
+    *ON_CTRL_C.lock().unwrap() = cancel;
+}
+
+// How a stream is cancelled, quietly, once nobody reads its output any more.
+static ON_STDOUT_CLOSED: Mutex<Option<Cancel>> = Mutex::new(None);
+
+// Ends the client once stdout is closed under it, as by `andro -s logcat | head`: the command
+// is cancelled rather than left running until the server notices the connection is gone,
+// and the client exits 0 like any program whose reader in a pipeline is done.
+fn stdout_closed() -> ! {
+    let cancel = ON_STDOUT_CLOSED.lock().unwrap().take();
+    if let Some(cancel) = cancel {
+        cancel();
+    }
+    process::exit(0);
+}
+
+// Writes output of the command to stdout; see `stdout_closed` for a reader that went away.
+fn write_stdout(bytes: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    match stdout.write_all(bytes).and_then(|()| stdout.flush()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => stdout_closed(),
+        result => result,
+    }
 }
 
 fn main() -> std::io::Result<()> {
     // Handle Ctrl+C gracefully to ensure the program exits cleanly
     // without panicking, especially during a streaming process.
+    // The message goes to stderr, because stdout may carry binary data (recordings, streams).
     ctrlc::set_handler(move || {
-        println!("\nExiting...");
-        process::exit(0);
+        let cancel = ON_CTRL_C.lock().unwrap().take();
+        match cancel {
+            Some(cancel) => cancel(),
+            None => {
+                eprintln!("\nExiting...");
+                process::exit(0);
+            }
+        }
     })
     .expect("Error setting Ctrl-C handler");
 
-    let cli = Cli::parse();
-    
-    let (bridge_cmd, is_streaming) = if let Some(mut cmd) = cli.exec {
+    let matches = config::apply(Cli::command()).get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.json &= !cli.no_json;
+    if cli.json {
+        report::enable();
+    }
+    if let Some(Commands::Config {
+        action: ConfigAction::Show,
+    }) = cli.command
+    {
+        config::show(&cli, &matches);
+        return Ok(());
+    }
+    if let Some(Commands::Completions { shell }) = cli.command {
+        completions::print(shell);
+        return Ok(());
+    }
+    let (server, source) = match &cli.connect {
+        Some(address) => (
+            Server::Tcp(address.clone()),
+            matches.value_source("connect"),
+        ),
+        None => (
+            Server::Unix(cli.socket.clone()),
+            matches.value_source("socket"),
+        ),
+    };
+    let source = match (&server, source) {
+        (Server::Tcp(_), Some(ValueSource::CommandLine)) => "--connect",
+        (Server::Tcp(_), _) => "ANDRO_ADDR",
+        (Server::Unix(_), Some(ValueSource::CommandLine)) => "--socket",
+        (Server::Unix(_), _) => match &config::get().socket {
+            Some(config::Setting {
+                origin: config::Origin::Env(var),
+                ..
+            }) => var,
+            Some(_) => "config file",
+            None => "default",
+        },
+    };
+    let token = match auth_token(cli.token_file.as_deref()) {
+        Ok(token) => token,
+        Err(e) => fail("usage", &e.to_string(), 2),
+    };
+    let codec = cli.codec;
+    let idle_timeout = cli.idle_timeout.filter(|&secs| secs > 0);
+    bridge_client::set_max_frame_len(cli.max_frame_size);
+    bridge_client::set_compression(!cli.no_compress);
+    let retry = Retry {
+        retries: cli
+            .retry
+            .unwrap_or(if cli.wait.is_some() { u32::MAX } else { 0 }),
+        delay: cli.retry_delay,
+        deadline: cli.wait.flatten(),
+    };
+    let own_loop = match &cli.command {
+        Some(Commands::Shell) => Some("shell"),
+        Some(Commands::WaitServer { .. }) => Some("wait-server"),
+        Some(Commands::Watch { .. }) => Some("watch"),
+        Some(Commands::Run { .. }) => Some("run"),
+        Some(Commands::Pexec { .. }) => Some("pexec"),
+        Some(Commands::Ping { count: Some(_), .. }) => Some("ping --count"),
+        Some(Commands::Prop {
+            action: PropAction::Get { watch: true, .. },
+        }) => Some("prop get --watch"),
+        Some(Commands::Session {
+            open: false,
+            close: false,
+            ..
+        }) => Some("session"),
+        _ => None,
+    };
+    if let Some(name) = own_loop
+        && cli.json
+    {
+        fail("usage", &format!("--json does not cover {}", name), 2);
+    }
+    match cli.command {
+        Some(Commands::Shell) => process::exit(shell::run(server, codec, token, retry)),
+        Some(Commands::WaitServer { timeout }) => {
+            process::exit(wait_server(&server, codec, &token, timeout, retry.delay))
+        }
+        Some(Commands::Pexec {
+            jobs,
+            arg_file,
+            command,
+        }) => process::exit(pexec::run(
+            server, codec, token, retry, jobs, &arg_file, command,
+        )),
+        Some(Commands::Run {
+            file, keep_going, ..
+        }) => process::exit(script::run(server, codec, token, retry, &file, keep_going)),
+        Some(Commands::Watch {
+            interval,
+            count,
+            no_clear,
+            diff,
+            command,
+        }) => {
+            let options = watch::Options {
+                interval,
+                count,
+                clear: !no_clear,
+                diff,
+            };
+            process::exit(watch::run(server, codec, token, retry, command, options))
+        }
+        Some(Commands::Ping {
+            verbose,
+            count: Some(count),
+            interval,
+        }) => {
+            let options = latency::Options {
+                count,
+                interval,
+                verbose,
+            };
+            process::exit(latency::run(server, codec, token, retry, options))
+        }
+        Some(Commands::Session {
+            id,
+            line,
+            open: false,
+            close: false,
+            shell,
+        }) => process::exit(shell_session::run(
+            server, codec, token, retry, id, shell, line,
+        )),
+        Some(Commands::App { action }) => {
+            process::exit(app::run(server, codec, token, retry, action))
+        }
+        Some(Commands::Prop {
+            action:
+                PropAction::Get {
+                    name: Some(name),
+                    watch: true,
+                    interval,
+                    ..
+                },
+        }) => process::exit(prop::watch(server, codec, token, retry, name, interval)),
+        _ => {}
+    }
+    let (bridge_cmd, mode) = build(cli)?;
+    // The other modes carry files, images or a terminal rather than text.
+    if report::enabled()
+        && !matches!(
+            mode,
+            ResponseMode::Single
+                | ResponseMode::Batch { .. }
+                | ResponseMode::Stream
+                | ResponseMode::Logcat { .. }
+                | ResponseMode::JobOutput
+        )
+    {
+        let message = format!("--json does not cover {}", bridge_cmd.name());
+        fail("usage", &message, 2);
+    }
+
+    if DIRECT_INPUT_COMMANDS.contains(&bridge_cmd.name())
+        && let Some(message) = unsupported(&bridge_cmd, &server, codec, &token, &retry)
+    {
+        fail("unsupported", &message, 1);
+    }
+
+    let mut connection = match connect_retrying(&server, codec, &retry) {
+        Ok(connection) => connection,
+        Err(e) if report::enabled() => {
+            let message = format!("cannot connect to {}: {}", server, e);
+            fail("connect", &message, 1);
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to connect to {} (from {}): {}. Is the server running?",
+                server, source, e
+            );
+            process::exit(1);
+        }
+    };
+    // A pty sits idle whenever its user does, and must not exit with the terminal raw.
+    if let Some(secs) = idle_timeout
+        && !matches!(mode, ResponseMode::Pty)
+    {
+        connection = idle::IdleTimeout::wrap(connection, Duration::from_secs(secs))?;
+    }
+    let stream = &mut *connection;
+    if let Err(e) = handshake(stream, &token) {
+        match Undecodable::of(&e) {
+            Some(_) => fail("protocol", &e.to_string(), PROTOCOL_EXIT_CODE),
+            None => fail("handshake", &e.to_string(), 1),
+        }
+    }
+
+    let forwards_stdin = matches!(
+        bridge_cmd,
+        BridgeCommand::Exec { stdin: true, .. }
+            | BridgeCommand::ExecBytes { stdin: true, .. }
+            | BridgeCommand::Stream { stdin: true, .. }
+    );
+    // The server reads the connection while these run, so a Cancel frame reaches them.
+    let cancellable = matches!(
+        bridge_cmd,
+        BridgeCommand::Exec { stdin: true, .. }
+            | BridgeCommand::ExecBytes { stdin: true, .. }
+            | BridgeCommand::Stream { .. }
+            | BridgeCommand::Logcat { .. }
+    );
+    match send_command(stream, &bridge_cmd) {
+        Ok(()) => {}
+        // Only encoding the command fails that way, before anything is written.
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            fail("protocol", &e.to_string(), PROTOCOL_EXIT_CODE)
+        }
+        Err(e) => return Err(e),
+    }
+
+    if cancellable {
+        // Shared, so that a Cancel never lands in the middle of a stdin frame.
+        let writer = Arc::new(Mutex::new(stream.try_clone_box()?));
+        let cancel_writer = Arc::clone(&writer);
+        let cancel = move || {
+            let cancel = BridgeCommand::Cancel { request_id: 0 };
+            let _ = send_command(&mut **cancel_writer.lock().unwrap(), &cancel);
+        };
+        *ON_STDOUT_CLOSED.lock().unwrap() = Some(Box::new(cancel.clone()));
+        on_ctrl_c(Some(Box::new(move || {
+            if !report::enabled() {
+                eprintln!("\nCancelling... (Ctrl+C again to quit)");
+            }
+            cancel();
+        })));
+        if forwards_stdin {
+            // Runs alongside the response reader; the process exits once the response
+            // arrives, even if this thread is still blocked reading our stdin.
+            thread::spawn(move || {
+                if let Err(e) = forward_stdin(&writer) {
+                    eprintln!("Failed to forward stdin: {}", e);
+                }
+            });
+        }
+    }
+
+    let code = match mode {
+        ResponseMode::Stream => handle_stream_response(stream, false),
+        ResponseMode::Logcat { color: true } => logcat::receive(stream),
+        ResponseMode::Logcat { color: false } => handle_stream_response(stream, false),
+        ResponseMode::JobOutput => handle_stream_response(stream, true),
+        ResponseMode::Pty => handle_pty_session(stream),
+        ResponseMode::Single => handle_single_response(stream),
+        ResponseMode::Batch { lines } => handle_batch_response(stream, &lines),
+        ResponseMode::Screenshot { target } => transfer::save_screenshot(stream, &target),
+        ResponseMode::ScreenStream { target, format } => {
+            screen::receive_stream(stream, &target, format)
+        }
+        ResponseMode::Push {
+            file,
+            dest,
+            options,
+        } => transfer::push(stream, file, &dest, options),
+        ResponseMode::Pull {
+            target,
+            recursive,
+            offset,
+            options,
+        } => transfer::pull(stream, &target, recursive, offset, options),
+        ResponseMode::Touch { script } => touch::run(stream, script),
+        ResponseMode::Record { source } => recording::receive(stream, source),
+    };
+    let code = match code {
+        Ok(code) => code,
+        Err(e) if Undecodable::of(&e).is_some() => {
+            fail("protocol", &e.to_string(), PROTOCOL_EXIT_CODE)
+        }
+        Err(e) => return Err(e),
+    };
+    // Propagate the remote exit code so scripts inside the chroot can branch on it.
+    io::stdout().flush()?;
+    process::exit(code);
+}
+
+// Well-known Android user ids (see android_filesystem_config.h).
+const ANDROID_USERS: &[(&str, u32)] = &[
+    ("root", 0),
+    ("system", 1000),
+    ("radio", 1001),
+    ("bluetooth", 1002),
+    ("graphics", 1003),
+    ("input", 1004),
+    ("audio", 1005),
+    ("camera", 1006),
+    ("log", 1007),
+    ("wifi", 1010),
+    ("media", 1013),
+    ("sdcard_rw", 1015),
+    ("shell", 2000),
+    ("cache", 2001),
+    ("nobody", 9999),
+];
+
+// Parses a duration like `500ms`, `2s` or `1m`; a bare number counts as milliseconds,
+// like --timeout.
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let invalid = || format!("expected a duration like 500ms, 2s or 1m, got '{}'", arg);
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let n: u64 = number.parse().map_err(|_| invalid())?;
+    match unit {
+        "" | "ms" => Ok(Duration::from_millis(n)),
+        "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        _ => Err(invalid()),
+    }
+}
+
+// Parses a signal name (`TERM`, `SIGTERM`) or number.
+fn parse_signal(arg: &str) -> Result<i32, String> {
+    if let Ok(number) = arg.parse() {
+        return Ok(number);
+    }
+    let name = arg.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|&(_, number)| number)
+        .ok_or_else(|| format!("unknown signal '{}'", arg))
+}
+
+// Parses the target of `andro signal`: `%ID` for a job, a pid otherwise.
+fn parse_signal_target(arg: &str) -> Result<SignalTarget, String> {
+    match arg.strip_prefix('%') {
+        Some(id) => id
+            .parse()
+            .map(SignalTarget::Job)
+            .map_err(|_| format!("expected a job id after %, got '{}'", arg)),
+        None => arg
+            .parse()
+            .ok()
+            .filter(|&pid| pid > 0)
+            .map(SignalTarget::Pid)
+            .ok_or_else(|| format!("expected %ID for a job or a pid, got '{}'", arg)),
+    }
+}
+
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+];
+
+// Parses the argument of --as-user: a name from ANDROID_USERS or a numeric id.
+fn parse_android_user(arg: &str) -> Result<u32, String> {
+    if let Ok(id) = arg.parse() {
+        return Ok(id);
+    }
+    ANDROID_USERS
+        .iter()
+        .find(|(name, _)| *name == arg)
+        .map(|(_, id)| *id)
+        .ok_or_else(|| {
+            let names: Vec<&str> = ANDROID_USERS.iter().map(|(name, _)| *name).collect();
+            format!(
+                "unknown user '{}', expected one of: {}",
+                arg,
+                names.join(", ")
+            )
+        })
+}
+
+// Picks the image format from the flags, falling back to the output file extension.
+fn image_format(
+    format: Option<ScreenshotFormat>,
+    quality: Option<u8>,
+    output: &Path,
+) -> ImageFormat {
+    let wants_jpeg = match (format, quality) {
+        (Some(ScreenshotFormat::Jpeg), _) | (None, Some(_)) => true,
+        (Some(ScreenshotFormat::Png), _) => false,
+        (None, None) => output
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")),
+    };
+    if wants_jpeg {
+        ImageFormat::Jpeg {
+            quality: quality.unwrap_or(90),
+        }
+    } else {
+        ImageFormat::Png
+    }
+}
+
+// Resolves a key name or Android keycode to the Linux input code the server injects.
+fn parse_key(arg: &str) -> Result<u16, String> {
+    let keycode: Keycode = arg.parse()?;
+    match keycode.key() {
+        Some(key) => Ok(key.linux),
+        None => Err(format!(
+            "keycode {} cannot be injected directly (use a name like BACK or HOME)",
+            keycode
+        )),
+    }
+}
+
+fn key_event(keycode: Keycode, long_press: bool) -> BridgeCommand {
+    BridgeCommand::KeyEvent {
+        keycode,
+        long_press,
+    }
+}
+
+// Parses a coordinate: a pixel position like `540` or a share of the screen like `50%`.
+fn parse_coord(arg: &str) -> Result<Coord, String> {
+    if let Some(percent) = arg.strip_suffix('%') {
+        return match percent.parse::<f32>() {
+            Ok(percent) if percent.is_finite() => Ok(Coord::Fraction(percent / 100.0)),
+            _ => Err(format!("expected a percentage like 50%, got '{}'", arg)),
+        };
+    }
+    arg.parse().map(Coord::Pixels).map_err(|_| {
+        format!(
+            "expected pixels like 540 or a percentage like 50%, got '{}'",
+            arg
+        )
+    })
+}
+
+// Parses an octal permission mode such as `644` or `0755`.
+fn parse_mode(arg: &str) -> Result<u32, String> {
+    match u32::from_str_radix(arg, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("expected an octal mode like 644, got '{}'", arg)),
+    }
+}
+
+// Parses a size like `512M`: a number of bytes, or of KiB, MiB or GiB with K, M or G.
+fn parse_size(arg: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a size like 4096, 512M or 2G, got '{}'", arg);
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let n: u64 = number.parse().map_err(|_| invalid())?;
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return Err(invalid()),
+    };
+    match n.checked_mul(1 << shift) {
+        Some(0) | None => Err(invalid()),
+        Some(bytes) => Ok(bytes),
+    }
+}
+
+// Parses a CPU list like `0-3,6` into a mask with bit N set for CPU N.
+fn parse_cpus(arg: &str) -> Result<u64, String> {
+    let invalid = || format!("expected CPUs from 0 to 63 like 0-3 or 4,5, got '{}'", arg);
+    let cpu = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n < 64);
+    let mut mask = 0u64;
+    for part in arg.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (cpu(first), cpu(last)),
+            None => (cpu(part), cpu(part)),
+        };
+        match (first, last) {
+            (Some(first), Some(last)) if first <= last => {
+                mask |= (u64::MAX >> (63 - last)) & (u64::MAX << first)
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(mask)
+}
+
+// Parses a `KEY=VALUE` argument of --env.
+fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", arg)),
+    }
+}
+
+// Exit code for commands killed by the server-side timeout, matching coreutils `timeout`.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+// Exit code for an Exec whose program never ran, as a shell would have it: 127 if it does
+// not exist, 126 if it cannot be run, 1 if the command was refused before the spawn.
+fn not_started_code(os_error: Option<i32>) -> i32 {
+    match os_error {
+        Some(libc::ENOENT) => 127,
+        Some(_) => 126,
+        None => 1,
+    }
+}
+
+// Exit code for a response that does not decode, EX_SOFTWARE from sysexits.h: the server
+// speaks another version of the protocol, or something other than the server answered.
+const PROTOCOL_EXIT_CODE: i32 = 70;
+
+// Exit code for an error the server reported with its kind, from sysexits.h where one
+// fits, so scripts can tell a missing file from a refused program without the message.
+fn failed_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::NotFound => 66,
+        ErrorKind::PermissionDenied => 77,
+        ErrorKind::PolicyRejected => 78,
+        ErrorKind::Timeout => TIMEOUT_EXIT_CODE,
+        ErrorKind::ProtocolError => PROTOCOL_EXIT_CODE,
+        ErrorKind::InputDeviceError => 69,
+        ErrorKind::Internal => 1,
+    }
+}
+
+// Maps the exit status carried by StreamEnd to our own exit code, shell style.
+fn stream_exit_code(code: Option<i32>, signal: Option<i32>) -> i32 {
+    match (code, signal) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+// Turns the parsed command line into the request and how to read its responses.
+fn build(cli: Cli) -> std::io::Result<(BridgeCommand, ResponseMode)> {
+    let mut env = cli.env;
+    for key in cli.pass_env {
+        match std::env::var(&key) {
+            Ok(value) => env.push((key, value)),
+            Err(_) => eprintln!(
+                "Warning: {} is not set in this environment, not passing it.",
+                key
+            ),
+        }
+    }
+    let options = ProcessOptions {
+        timeout_ms: cli.timeout,
+        env,
+        cwd: cli.cwd,
+        // Android users have matching uid and gid, so --as-user sets both.
+        uid: cli.uid.or(cli.as_user),
+        gid: cli.gid.or(cli.as_user),
+        limits: Limits {
+            nice: cli.nice,
+            cpus: cli.cpus,
+            max_mem: cli.max_mem,
+            max_file_size: cli.max_file_size,
+        },
+    };
+
+    Ok(if let Some(mut cmd) = cli.exec {
         let program = cmd.remove(0);
-        (BridgeCommand::Exec { program, args: cmd }, false)
+        // Piped input is forwarded to the remote process; an interactive terminal is not,
+        // since the command would otherwise wait for the user to type EOF.
+        let stdin = !cli.no_stdin && !io::stdin().is_terminal();
+        let exec = if cli.binary {
+            BridgeCommand::ExecBytes {
+                program,
+                args: cmd,
+                stdin,
+                options,
+            }
+        } else {
+            BridgeCommand::Exec {
+                program,
+                args: cmd,
+                stdin,
+                options,
+            }
+        };
+        (exec, ResponseMode::Single)
     } else if let Some(mut cmd) = cli.stream {
         let program = cmd.remove(0);
-        (BridgeCommand::Stream { program, args: cmd }, true)
+        // Streams are often interactive (e.g. `sh`), so terminal input is forwarded too.
+        (
+            BridgeCommand::Stream {
+                program,
+                args: cmd,
+                stdin: !cli.no_stdin,
+                options,
+            },
+            ResponseMode::Stream,
+        )
     } else if let Some(command) = cli.command {
+        let mut mode = ResponseMode::Single;
         let cmd = match command {
-            Commands::Tap { x, y } => BridgeCommand::DirectTap { x, y },
+            Commands::Tap { x, y, dry_run } => BridgeCommand::DirectTap {
+                at: Point { x, y },
+                dry_run,
+            },
+            Commands::Swipe2 {
+                x1,
+                y1,
+                x2,
+                y2,
+                duration,
+                spacing,
+            } => BridgeCommand::DirectMultiSwipe {
+                fingers: 2,
+                x1,
+                y1,
+                x2,
+                y2,
+                spacing,
+                duration_ms: duration,
+            },
+            Commands::Taps { taps, file } => {
+                let list = match (taps, file) {
+                    (Some(taps), _) => taps::parse(&taps),
+                    (None, Some(file)) => taps::load(&file),
+                    (None, None) => unreachable!("clap requires one of them"),
+                };
+                match list {
+                    Ok(taps) => BridgeCommand::DirectTapBatch { taps },
+                    Err(e) => fail("usage", &e.to_string(), 2),
+                }
+            }
             Commands::Swipe {
                 x1,
                 y1,
                 x2,
                 y2,
                 duration,
+                easing,
+                step_ms,
+                dry_run,
             } => BridgeCommand::DirectSwipe {
+                from: Point { x: x1, y: y1 },
+                to: Point { x: x2, y: y2 },
+                duration_ms: duration,
+                easing: match easing {
+                    SwipeEasing::Linear => Easing::Linear,
+                    SwipeEasing::EaseInOut => Easing::EaseInOut,
+                    SwipeEasing::Overshoot => Easing::Overshoot,
+                },
+                step_ms,
+                dry_run,
+            },
+            Commands::Key { key, long } => BridgeCommand::DirectKey {
+                keycode: key,
+                long_press: long,
+            },
+            Commands::Type { text, delay } => {
+                let text = if text == "-" {
+                    let mut text = String::new();
+                    io::stdin().read_to_string(&mut text)?;
+                    text
+                } else {
+                    text
+                };
+                BridgeCommand::DirectText {
+                    text,
+                    delay_ms: delay,
+                }
+            }
+            Commands::Longpress { x, y, duration } => BridgeCommand::DirectLongPress {
+                x,
+                y,
+                duration_ms: duration,
+            },
+            Commands::Drag {
                 x1,
                 y1,
                 x2,
                 y2,
+                hold,
+                hold_after,
+                duration,
+            } => BridgeCommand::DirectDrag {
+                x1,
+                y1,
+                x2,
+                y2,
+                hold_before_ms: hold,
+                move_duration_ms: duration,
+                hold_after_ms: hold_after.unwrap_or(hold),
+            },
+            Commands::Pinch {
+                cx,
+                cy,
+                start_dist,
+                end_dist,
+                duration,
+            } => BridgeCommand::DirectPinch {
+                cx,
+                cy,
+                start_dist,
+                end_dist,
                 duration_ms: duration,
             },
-            Commands::Ping => BridgeCommand::Ping,
+            Commands::Batch { file, keep_going } => {
+                let (commands, lines) =
+                    batch_commands(&file).unwrap_or_else(|e| fail("usage", &e.to_string(), 2));
+                mode = ResponseMode::Batch { lines };
+                BridgeCommand::Batch {
+                    commands,
+                    stop_on_error: !keep_going,
+                }
+            }
+            Commands::Sleep { ms } => BridgeCommand::Sleep { ms },
+            // These run their own loop in main.
+            Commands::Shell
+            | Commands::WaitServer { .. }
+            | Commands::Watch { .. }
+            | Commands::Run { .. }
+            | Commands::Pexec { .. }
+            | Commands::Completions { .. }
+            | Commands::Config { .. }
+            | Commands::App { .. }
+            | Commands::Prop {
+                action: PropAction::Get { watch: true, .. },
+            }
+            | Commands::Ping { count: Some(_), .. }
+            | Commands::Session {
+                open: false,
+                close: false,
+                ..
+            } => {
+                return Err(std::io::Error::other(
+                    "this command needs a connection of its own; run it as a separate andro command",
+                ));
+            }
+            Commands::Ping { verbose: false, .. } => BridgeCommand::Ping,
+            Commands::Ping { verbose: true, .. } | Commands::Info { server: true } => {
+                BridgeCommand::Info
+            }
+            Commands::Info { server: false } => BridgeCommand::DeviceInfo,
+            Commands::Jobs => BridgeCommand::ListJobs,
+            Commands::Job { action } => match action {
+                JobAction::Start { mut command } => {
+                    let program = command.remove(0);
+                    BridgeCommand::JobStart {
+                        program,
+                        args: command,
+                        options,
+                    }
+                }
+                JobAction::List => BridgeCommand::JobList,
+                JobAction::Logs { id, follow } => {
+                    mode = ResponseMode::JobOutput;
+                    BridgeCommand::JobOutput { id, follow }
+                }
+                JobAction::Kill { id, signal } => BridgeCommand::JobKill { id, signal },
+            },
+            Commands::Signal { target, signal } => BridgeCommand::Signal { target, signal },
+            Commands::Logcat {
+                tags,
+                priority,
+                pid,
+                clear,
+                dump,
+                since,
+                args,
+            } => {
+                mode = ResponseMode::Logcat {
+                    color: io::stdout().is_terminal() && !report::enabled(),
+                };
+                let filter = logcat::Options {
+                    tags,
+                    priority,
+                    pid,
+                    clear,
+                    dump,
+                    since,
+                    args,
+                };
+                logcat::command(filter, options)
+            }
+            Commands::Session {
+                open: true, shell, ..
+            } => BridgeCommand::SessionOpen { shell: Some(shell) },
+            Commands::Session { id, .. } => BridgeCommand::SessionClose {
+                id: id.expect("--close requires --id"),
+            },
+            Commands::Path { file } => match gesture::load(&file) {
+                Ok(gesture) => BridgeCommand::DirectPath { gesture },
+                Err(e) => fail("usage", &e.to_string(), 2),
+            },
+            Commands::Record { touch: _, keys } => {
+                if let Err(e) = recording::check_output() {
+                    fail("usage", &e.to_string(), 2)
+                }
+                let source = if keys {
+                    InputSource::Keys
+                } else {
+                    InputSource::Touch
+                };
+                mode = ResponseMode::Record { source };
+                BridgeCommand::RecordInput { source }
+            }
+            Commands::Replay { file, speed } => {
+                if !(speed.is_finite() && speed > 0.0) {
+                    eprintln!("Error: --speed must be a positive number");
+                    process::exit(2);
+                }
+                match recording::load(&file) {
+                    Ok((source, events)) => BridgeCommand::DirectReplay {
+                        source,
+                        events,
+                        speed,
+                    },
+                    Err(e) => fail("usage", &e.to_string(), 2),
+                }
+            }
+            // Android keycodes; the server picks direct injection or `input keyevent`.
+            Commands::Back { long } => key_event(Keycode::BACK, long),
+            Commands::Home { long } => key_event(Keycode::HOME, long),
+            Commands::Power { long } => key_event(Keycode::POWER, long),
+            Commands::Volume { direction, long } => match direction {
+                VolumeDirection::Up => key_event(Keycode::VOLUME_UP, long),
+                VolumeDirection::Down => key_event(Keycode::VOLUME_DOWN, long),
+            },
+            Commands::Rotation { action } => match action {
+                RotationAction::Get => BridgeCommand::GetRotation,
+                RotationAction::Set { rotation } => BridgeCommand::SetRotation(rotation),
+            },
+            Commands::Mouse { action } => match action {
+                MouseAction::Move { dx, dy } => BridgeCommand::DirectMouseMove { dx, dy },
+                MouseAction::Click { button } => BridgeCommand::DirectMouseClick {
+                    button: match button {
+                        ClickButton::Left => MouseButton::Left,
+                        ClickButton::Right => MouseButton::Right,
+                        ClickButton::Middle => MouseButton::Middle,
+                    },
+                },
+            },
+            Commands::Scroll { dy } => BridgeCommand::DirectScroll { dy },
+            Commands::MonitorInput { device, raw } => {
+                mode = ResponseMode::Stream;
+                BridgeCommand::MonitorInput { device, raw }
+            }
+            Commands::InputInfo => BridgeCommand::InputInfo,
+            Commands::Touch { actions } => {
+                let mut script = touch::script(actions);
+                let first = match touch::next_command(&mut script) {
+                    Ok(Some(command)) => command,
+                    Ok(None) => process::exit(0),
+                    Err(e) => fail("usage", &e.to_string(), 2),
+                };
+                mode = ResponseMode::Touch { script };
+                first
+            }
+            Commands::Push {
+                source,
+                dest,
+                mode: file_mode,
+                options: transfer_options,
+            } => {
+                // Open the source before connecting so a typo fails without touching the host.
+                let file = File::open(&source).inspect_err(|e| {
+                    eprintln!("Cannot open {}: {}", source.display(), e);
+                })?;
+                let file_mode = match file_mode {
+                    Some(file_mode) => file_mode,
+                    None => file.metadata()?.permissions().mode() & 0o7777,
+                };
+                let dest = match (dest.ends_with('/'), source.file_name()) {
+                    (true, Some(name)) => format!("{}{}", dest, name.to_string_lossy()),
+                    _ => dest,
+                };
+                let cmd = BridgeCommand::PushFile {
+                    dest: dest.clone(),
+                    mode: file_mode,
+                    resume: transfer_options.resume,
+                    verify: transfer_options.verify(),
+                };
+                mode = ResponseMode::Push {
+                    file,
+                    dest,
+                    options: transfer_options,
+                };
+                cmd
+            }
+            Commands::Prop { action } => match action {
+                PropAction::Get {
+                    all: true, prefix, ..
+                } => BridgeCommand::ListProps { prefix },
+                PropAction::Get { name, .. } => BridgeCommand::GetProp {
+                    name: name.unwrap_or_default(),
+                },
+                PropAction::Set { name, value } => BridgeCommand::SetProp { name, value },
+            },
+            Commands::Clip { action } => match action {
+                ClipAction::Get => BridgeCommand::ClipboardGet,
+                ClipAction::Set { text } if text == "-" => {
+                    let mut text = String::new();
+                    io::stdin().read_to_string(&mut text)?;
+                    BridgeCommand::ClipboardSet(text)
+                }
+                ClipAction::Set { text } => BridgeCommand::ClipboardSet(text),
+            },
+            Commands::Screenshot {
+                output,
+                format,
+                quality,
+            } => {
+                let format = image_format(format, quality, &output);
+                mode = ResponseMode::Screenshot { target: output };
+                BridgeCommand::Screenshot { format }
+            }
+            Commands::ScreenStream {
+                output,
+                fps,
+                frames,
+                format,
+                quality,
+            } => {
+                let format = image_format(format, quality, &output);
+                mode = ResponseMode::ScreenStream {
+                    target: output,
+                    format,
+                };
+                BridgeCommand::ScreenStream {
+                    format,
+                    fps,
+                    max_frames: frames,
+                }
+            }
+            Commands::Pull {
+                source,
+                dest,
+                recursive,
+                options: transfer_options,
+            } => {
+                let to_stdout = dest.as_os_str() == "-";
+                if to_stdout && (transfer_options.resume || transfer_options.verify()) {
+                    eprintln!("--resume and --verify need a file destination, not stdout.");
+                    process::exit(2);
+                }
+                let target = if recursive || to_stdout {
+                    dest
+                } else {
+                    transfer::local_path(&source, &dest)
+                };
+                let offset = if transfer_options.resume {
+                    transfer::resume_offset(&target)
+                } else {
+                    0
+                };
+                let cmd = BridgeCommand::PullFile {
+                    src: source,
+                    recursive,
+                    offset,
+                    verify: transfer_options.verify(),
+                };
+                mode = ResponseMode::Pull {
+                    target,
+                    recursive,
+                    offset,
+                    options: transfer_options,
+                };
+                cmd
+            }
+            Commands::Pty { mut command } => {
+                mode = ResponseMode::Pty;
+                let (cols, rows) = terminal::window_size();
+                BridgeCommand::ExecPty {
+                    program: command.remove(0),
+                    args: command,
+                    cols,
+                    rows,
+                }
+            }
         };
-        (cmd, false)
+        (cmd, mode)
     } else {
         // This branch is unreachable because of `arg_required_else_help = true`
         unreachable!();
+    })
+}
+
+// Parses every line of a batch script with the regular command line definitions.
+fn batch_commands(file: &Path) -> Result<(Vec<BridgeCommand>, Vec<usize>), String> {
+    let mut commands = Vec::new();
+    let mut lines = Vec::new();
+    for (line, words) in batch::load(file, false)? {
+        let cli = parse_words(words).map_err(|e| format!("line {}: {}", line, e))?;
+        match cli.command {
+            Some(Commands::Batch { .. }) => {
+                return Err(format!("line {}: batches cannot be nested", line));
+            }
+            Some(Commands::Shell) => {
+                return Err(format!("line {}: shell cannot run in a batch", line));
+            }
+            Some(Commands::WaitServer { .. }) => {
+                return Err(format!("line {}: wait-server cannot run in a batch", line));
+            }
+            _ => {}
+        }
+        let (cmd, mode) = build(cli).map_err(|e| format!("line {}: {}", line, e))?;
+        if !matches!(mode, ResponseMode::Single) {
+            return Err(format!(
+                "line {}: {} cannot run in a batch",
+                line,
+                cmd.name()
+            ));
+        }
+        commands.push(without_stdin(cmd));
+        lines.push(line);
+    }
+    Ok((commands, lines))
+}
+
+// Parses the words of a batch or shell line as if they followed `andro` on the command line.
+fn parse_words(words: Vec<String>) -> Result<Cli, String> {
+    let args = std::iter::once("andro".to_string()).chain(words);
+    let matches = config::apply(Cli::command()).try_get_matches_from(args);
+    matches
+        .and_then(|matches| Cli::from_arg_matches(&matches))
+        .map_err(|e| {
+            // Only the first line, without clap's usage text.
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })
+}
+
+// The script or the shell input, not our stdin, is what these commands read.
+fn without_stdin(mut cmd: BridgeCommand) -> BridgeCommand {
+    if let BridgeCommand::Exec { stdin, .. } | BridgeCommand::ExecBytes { stdin, .. } = &mut cmd {
+        *stdin = false;
+    }
+    cmd
+}
+
+// Asks the server whether it handles `cmd`, so that a build without direct input is
+// reported plainly instead of with a generic error. Servers predating Info, or any failure
+// to ask, let the command through.
+fn unsupported(
+    cmd: &BridgeCommand,
+    server: &Server,
+    codec: WireCodec,
+    token: &str,
+    retry: &Retry,
+) -> Option<String> {
+    let mut stream = connect_retrying(server, codec, retry).ok()?;
+    handshake(&mut *stream, token).ok()?;
+    send_command(&mut *stream, &BridgeCommand::Info).ok()?;
+    let BridgeResponse::ServerInfo(info) = read_response(&mut *stream).ok()? else {
+        return None;
     };
-    
-    let mut stream = UnixStream::connect(SOCKET_PATH).inspect_err(|_e| {
-        eprintln!(
-            "Failed to connect to {}. Is the server running?",
-            SOCKET_PATH
+    if info.commands.contains(&cmd.discriminant()) {
+        return None;
+    }
+    let mut message = format!(
+        "the server (version {}) does not support {}",
+        info.version,
+        cmd.name()
+    );
+    if !info
+        .features
+        .iter()
+        .any(|feature| feature == "direct_input")
+    {
+        message.push_str("; it was built without the direct_input feature");
+    }
+    Some(message)
+}
+
+// Pings the server until it answers or `timeout` seconds have passed, and returns the exit
+// code. Errors that waiting does not fix, like a refused token, end it right away.
+fn wait_server(
+    server: &Server,
+    codec: WireCodec,
+    token: &str,
+    timeout: u64,
+    delay: Duration,
+) -> i32 {
+    let started = Instant::now();
+    let deadline = (timeout > 0).then(|| Duration::from_secs(timeout));
+    loop {
+        let retry = Retry {
+            retries: u32::MAX,
+            delay,
+            deadline: deadline.map(|deadline| deadline.saturating_sub(started.elapsed())),
+        };
+        let e = match ping_once(server, codec, token, &retry) {
+            Ok(()) => return 0,
+            Err(e) => e,
+        };
+        let expired = deadline.is_some_and(|deadline| started.elapsed() >= deadline);
+        if expired || e.kind() == io::ErrorKind::Other {
+            eprintln!("Error: no answer from {}: {}", server, e);
+            return 1;
+        }
+        // Connected, but the server went away before answering, e.g. while starting up.
+        thread::sleep(delay);
+    }
+}
+
+fn ping_once(server: &Server, codec: WireCodec, token: &str, retry: &Retry) -> io::Result<()> {
+    let mut stream = connect_retrying(server, codec, retry)?;
+    handshake(&mut *stream, token)?;
+    send_command(&mut *stream, &BridgeCommand::Ping)?;
+    match read_response(&mut *stream)? {
+        BridgeResponse::Success(_) => Ok(()),
+        BridgeResponse::Error(e) => Err(io::Error::other(e)),
+        BridgeResponse::Failed(e) => Err(io::Error::other(e.message)),
+        response => Err(io::Error::other(format!(
+            "unexpected answer to Ping: {:?}",
+            response
+        ))),
+    }
+}
+
+fn print_jobs(jobs: &[Job]) {
+    if jobs.is_empty() {
+        println!("No jobs");
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    println!("{:>7}  {:>5}  {:>8}  COMMAND", "PID", "CONN", "AGE");
+    for job in jobs {
+        let connection = job.connection.map_or("-".to_string(), |id| id.to_string());
+        let age = format_age(now.saturating_sub(job.started_ms) / 1000);
+        let orphaned = if job.orphaned { "  (orphaned)" } else { "" };
+        println!(
+            "{:>7}  {:>5}  {:>8}  {}{}",
+            job.pid,
+            connection,
+            age,
+            job.command.join(" "),
+            orphaned
         );
-    })?;
+    }
+}
 
-    let bin_payload = bincode::serialize(&bridge_cmd).expect("Failed to serialize command");
-    stream.write_all(&bin_payload)?;
+fn print_background_jobs(jobs: &[BackgroundJob]) {
+    if jobs.is_empty() {
+        println!("No background jobs");
+        return;
+    }
+    println!(
+        "{:>4}  {:>7}  {:<11}  {:>8}  COMMAND",
+        "ID", "PID", "STATE", "RUNTIME"
+    );
+    for job in jobs {
+        let state = match job.state {
+            JobState::Running => "running".to_string(),
+            JobState::Exited { code } => format!("exited {}", code),
+            JobState::Killed { signal } => format!("signal {}", signal),
+        };
+        let dropped = if job.dropped > 0 {
+            format!("  ({} bytes of output dropped)", job.dropped)
+        } else {
+            String::new()
+        };
+        println!(
+            "{:>4}  {:>7}  {:<11}  {:>8}  {}{}",
+            job.id,
+            job.pid,
+            state,
+            format_age(job.runtime_ms / 1000),
+            job.command.join(" "),
+            dropped
+        );
+    }
+}
 
-    if is_streaming {
-        handle_stream_response(&mut stream)
-    } else {
-        handle_single_response(&mut stream)
+// Like ps: 42s, 3m05s, 2h07m.
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
     }
 }
 
-fn handle_stream_response(stream: &mut UnixStream) -> std::io::Result<()> {
-    loop {
-        // Read the first 8 bytes to determine the length of the incoming payload.
-        // This is part of the length-prefix protocol to ensure the message is received completely.
-        let mut len_bytes = [0u8; 8];
-        if stream.read_exact(&mut len_bytes).is_err() {
-            // If read fails, the server has likely closed the connection.
-            break;
+fn print_server_info(info: &ServerInfo) {
+    let or_none = |list: Vec<&str>| {
+        if list.is_empty() {
+            "none".to_string()
+        } else {
+            list.join(", ")
         }
-        let len = u64::from_be_bytes(len_bytes);
+    };
+    println!("Server version: {}", info.version);
+    println!("Protocol: {}", info.protocol);
+    println!(
+        "Features: {}",
+        or_none(info.features.iter().map(String::as_str).collect())
+    );
+    println!(
+        "Touch device: {}",
+        info.touch_device.as_deref().unwrap_or("none")
+    );
+    let limit = |max: u32| match max {
+        0 => "no limit".to_string(),
+        max => max.to_string(),
+    };
+    let load = &info.load;
+    println!(
+        "Connections: {} (max {})",
+        load.connections,
+        limit(load.max_connections)
+    );
+    println!(
+        "Processes: {} (max {}), {} waiting",
+        load.jobs,
+        limit(load.max_jobs),
+        load.queued
+    );
+    // Names are only known up to this client's own commands; newer ones show as numbers.
+    let commands: Vec<String> = info
+        .commands
+        .iter()
+        .map(|&d| match COMMAND_NAMES.get(d as usize) {
+            Some(name) => name.to_string(),
+            None => format!("#{}", d),
+        })
+        .collect();
+    println!(
+        "Commands: {}",
+        or_none(commands.iter().map(String::as_str).collect())
+    );
+}
 
-        // Allocate a buffer of the specified length and read the payload.
-        let mut buffer = vec![0u8; len as usize];
-        stream.read_exact(&mut buffer)?;
+// One `name: value` line per field, with "unknown" for probes that failed on the server.
+fn print_device_info(info: &DeviceInfo) {
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+    let model = match (&info.manufacturer, &info.model) {
+        (Some(manufacturer), Some(model)) if !model.starts_with(manufacturer.as_str()) => {
+            Some(format!("{} {}", manufacturer, model))
+        }
+        (_, Some(model)) => Some(model.clone()),
+        (manufacturer, None) => manufacturer.clone(),
+    };
+    let android = match (&info.android_version, info.sdk) {
+        (Some(version), Some(sdk)) => Some(format!("{} (SDK {})", version, sdk)),
+        (None, Some(sdk)) => Some(format!("SDK {}", sdk)),
+        (version, None) => version.clone(),
+    };
+    let size = |size: ScreenSize| format!("{}x{}", size.width, size.height);
+    let screen = info.screen.map(|screen| match info.screen_override {
+        Some(used) => format!("{} (override {})", size(screen), size(used)),
+        None => size(screen),
+    });
+    let density = info.density.map(|density| match info.density_override {
+        Some(used) => format!("{} dpi (override {})", density, used),
+        None => format!("{} dpi", density),
+    });
+    let rotation = info
+        .rotation
+        .map(|rotation| format!("{} ({}°)", rotation, u32::from(rotation) * 90));
+    let battery = info.battery.as_ref().map(|battery| {
+        let state = match (battery.charging, &battery.plugged) {
+            (true, Some(plugged)) => format!("charging, {}", plugged),
+            (true, None) => "charging".to_string(),
+            (false, Some(plugged)) => format!("not charging, {}", plugged),
+            (false, None) => "discharging".to_string(),
+        };
+        format!("{}% ({})", battery.level, state)
+    });
+    let uptime = info.uptime_secs.map(format_age);
+    println!("Model: {}", or_unknown(model));
+    println!("Android: {}", or_unknown(android));
+    println!("Screen: {}", or_unknown(screen));
+    println!("Density: {}", or_unknown(density));
+    println!("Rotation: {}", or_unknown(rotation));
+    println!("Battery: {}", battery.unwrap_or_else(|| "none".to_string()));
+    println!("Uptime: {}", or_unknown(uptime));
+}
 
-        let response: BridgeResponse =
-            bincode::deserialize(&buffer).expect("Failed to deserialize stream response");
+// Strips the `tcp://` scheme, leaving the address to connect to.
+fn parse_tcp(value: &str) -> Result<String, String> {
+    tcp_address(value)
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected tcp://HOST:PORT, got '{}'", value))
+}
 
-        match response {
-            BridgeResponse::StreamChunk(msg) => {
-                println!("{}", msg);
+// The auth token to present: ANDRO_TOKEN, or the contents of `token_file`; empty for
+// servers without authentication.
+fn auth_token(token_file: Option<&Path>) -> Result<String, String> {
+    if let Some(path) = token_file {
+        return std::fs::read_to_string(path)
+            .map(|text| text.trim().to_string())
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e));
+    }
+    Ok(std::env::var("ANDRO_TOKEN").unwrap_or_default())
+}
+
+// Ships our stdin to the server in bounded chunks, then signals EOF with StdinEnd.
+// The writer is behind a mutex so other threads (e.g. resize events) can share the connection
+// without interleaving frames.
+fn forward_stdin(writer: &Mutex<Box<dyn Transport>>) -> std::io::Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = match stdin.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let chunk = BridgeCommand::StdinChunk(buffer[..n].to_vec());
+        send_command(&mut **writer.lock().unwrap(), &chunk)?;
+    }
+    send_command(&mut **writer.lock().unwrap(), &BridgeCommand::StdinEnd)
+}
+
+// Runs an interactive ExecPty session: the local terminal is switched to raw mode,
+// keystrokes and window size changes go to the server, and raw output is copied back.
+fn handle_pty_session(stream: &mut dyn Transport) -> std::io::Result<i32> {
+    let raw_mode = if io::stdin().is_terminal() {
+        Some(terminal::RawMode::enable()?)
+    } else {
+        None
+    };
+
+    let writer = Arc::new(Mutex::new(stream.try_clone_box()?));
+    let input_writer = Arc::clone(&writer);
+    thread::spawn(move || forward_stdin(&input_writer));
+
+    terminal::watch_resize();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            if terminal::take_resize() {
+                let (cols, rows) = terminal::window_size();
+                let resize = BridgeCommand::Resize { cols, rows };
+                if send_command(&mut **writer.lock().unwrap(), &resize).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut stdout = io::stdout();
+    let mut remote_error = None;
+    let mut protocol_error = None;
+    let mut exit_code = 1;
+    loop {
+        let response = match read_response(stream) {
+            Ok(response) => response,
+            // Reported once the terminal is back to normal.
+            Err(e) if Undecodable::of(&e).is_some() => {
+                protocol_error = Some(e.to_string());
+                break;
             }
-            BridgeResponse::StreamEnd => {
-                break; // Signal from the server that streaming has ended.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                remote_error = Some(e.to_string());
+                break;
+            }
+            Err(_) => break,
+        };
+        match response {
+            BridgeResponse::PtyOutput(bytes) => {
+                stdout.write_all(&bytes)?;
+                stdout.flush()?;
             }
-            BridgeResponse::Error(err) => {
-                eprintln!("Remote Error: {}", err);
+            BridgeResponse::StreamEnd { code, signal } => {
+                exit_code = stream_exit_code(code, signal);
                 break;
             }
-            _ => {
-                eprintln!("Received unexpected response type during stream.");
+            BridgeResponse::Error(err) => remote_error = Some(err),
+            BridgeResponse::Failed(err) => remote_error = Some(err.message),
+            BridgeResponse::Busy { running, limit } => remote_error = Some(busy(running, limit)),
+            BridgeResponse::FrameTooLarge { len, limit } => {
+                remote_error = Some(frame_too_large(len, limit))
             }
+            _ => {}
+        }
+    }
+
+    // Restore the terminal before printing anything of our own.
+    drop(raw_mode);
+    if let Some(err) = remote_error {
+        eprintln!("Remote Error: {}", err);
+    }
+    if let Some(err) = protocol_error {
+        fail("protocol", &err, PROTOCOL_EXIT_CODE);
+    }
+    Ok(exit_code)
+}
+
+// Prints stream output as it arrives and returns the exit code of the remote child. With
+// `no_status_ok` a stream that ends without an exit status, the output of a background job
+// that is still running, exits 0.
+fn handle_stream_response(stream: &mut dyn Transport, no_status_ok: bool) -> std::io::Result<i32> {
+    // Each response is a length-prefixed frame, so the message is received completely.
+    // A server that closes the connection before StreamEnd ends the loop; a connection that
+    // breaks or carries garbage is an error.
+    loop {
+        let response = match read_response(stream) {
+            Ok(response) => response,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        let response = match response {
+            BridgeResponse::StreamEnd {
+                code: None,
+                signal: None,
+            } if no_status_ok => BridgeResponse::StreamEnd {
+                code: Some(0),
+                signal: None,
+            },
+            response => response,
+        };
+        if let Some(code) = show_stream_response(response)? {
+            return Ok(code);
+        }
+    }
+    // The connection closed before the stream was finished.
+    if report::enabled() {
+        report::error(
+            "protocol",
+            "the connection closed before the stream ended",
+            1,
+        );
+    }
+    Ok(1)
+}
+
+// Prints one response of a stream; returns the exit code once the stream is over.
+fn show_stream_response(response: BridgeResponse) -> std::io::Result<Option<i32>> {
+    if report::enabled() {
+        return Ok(report::stream_response(response));
+    }
+    match response {
+        BridgeResponse::StdoutChunk(bytes) => write_stdout(&bytes)?,
+        BridgeResponse::StderrChunk(bytes) => {
+            io::stderr().write_all(&bytes)?;
+        }
+        // Older servers send every line, stderr included, as a StreamChunk.
+        #[allow(deprecated)]
+        BridgeResponse::StreamChunk(msg) => write_stdout(format!("{}\n", msg).as_bytes())?,
+        BridgeResponse::StreamEnd { code, signal } => {
+            // Signal from the server that streaming has ended.
+            return Ok(Some(stream_exit_code(code, signal)));
         }
+        BridgeResponse::Error(err) => {
+            eprintln!("Remote Error: {}", err);
+            return Ok(Some(1));
+        }
+        BridgeResponse::Failed(err) => {
+            eprintln!("Remote Error: {}", err.message);
+            return Ok(Some(failed_code(err.kind)));
+        }
+        BridgeResponse::Unsupported { kind } => {
+            eprintln!("Error: {}", too_old(kind));
+            return Ok(Some(1));
+        }
+        BridgeResponse::Busy { running, limit } => {
+            eprintln!("Remote Error: {}", busy(running, limit));
+            return Ok(Some(1));
+        }
+        BridgeResponse::FrameTooLarge { len, limit } => {
+            eprintln!("Remote Error: {}", frame_too_large(len, limit));
+            return Ok(Some(1));
+        }
+        BridgeResponse::TimedOut { timeout_ms, .. } => {
+            eprintln!("Remote Error: timed out after {} ms", timeout_ms);
+            return Ok(Some(TIMEOUT_EXIT_CODE));
+        }
+        _ => {
+            eprintln!("Received unexpected response type during stream.");
+        }
+    }
+    Ok(None)
+}
+
+// Prints a single response and returns the exit code the client should finish with.
+fn handle_single_response(stream: &mut dyn Transport) -> std::io::Result<i32> {
+    // An Exec with more output than the server holds sends it ahead of the response, as
+    // chunks: shown as they come, or kept for the one reply of --json.
+    let mut spilled = Spilled::default();
+    loop {
+        let buffer = match stream.codec().read_frame(stream, max_frame_len()) {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => return Ok(no_response()),
+            Err(e) => return Ok(read_failed(&e.into())),
+        };
+
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let response = match decode_response(stream.codec(), &buffer) {
+            Ok(response) => response,
+            Err(e) => return Ok(read_failed(&e)),
+        };
+        if !report::enabled()
+            && let BridgeResponse::StdoutChunk(_) | BridgeResponse::StderrChunk(_) = response
+        {
+            show_stream_response(response)?;
+            continue;
+        }
+        if let Some(response) = spilled.take(response) {
+            return Ok(print_response(spilled.merge(response)));
+        }
+    }
+}
+
+// Why no response could be read: one that does not decode, one over --max-frame-size, or
+// none at all.
+fn read_failed(e: &io::Error) -> i32 {
+    if let Some(undecodable) = Undecodable::of(e) {
+        let message = undecodable.to_string();
+        if report::enabled() {
+            report::error("protocol", &message, PROTOCOL_EXIT_CODE);
+        } else {
+            eprintln!("Error: {}", message);
+        }
+        return PROTOCOL_EXIT_CODE;
+    }
+    let Some(too_large) = FrameTooLarge::of(e) else {
+        return no_response();
+    };
+    let message = format!(
+        "the response is {} bytes, more than --max-frame-size ({} bytes)",
+        too_large.len, too_large.limit
+    );
+    if report::enabled() {
+        report::error("frame_too_large", &message, 1);
+    } else {
+        eprintln!("Error: {}", message);
     }
-    Ok(())
+    1
 }
 
-fn handle_single_response(stream: &mut UnixStream) -> std::io::Result<()> {
-    let mut len_bytes = [0u8; 8];
-    if stream.read_exact(&mut len_bytes).is_err() {
+fn no_response() -> i32 {
+    if report::enabled() {
+        report::error("protocol", "the server did not provide a response", 1);
+    } else {
         eprintln!("Server did not provide a response.");
-        return Ok(());
     }
-    let len = u64::from_be_bytes(len_bytes);
+    1
+}
 
-    if len == 0 {
-        return Ok(());
+// Reports an error of the client's own and exits: a line on stderr, or with --json an
+// object of the given kind.
+fn fail(kind: &str, message: &str, code: i32) -> ! {
+    if report::enabled() {
+        report::error(kind, message, code);
+    } else {
+        eprintln!("Error: {}", message);
     }
+    process::exit(code)
+}
 
-    let mut buffer = vec![0u8; len as usize];
-    stream.read_exact(&mut buffer)?;
+// Prints the responses of a batch in order, as if its commands ran one after another.
+// The exit code is that of the first command that failed.
+fn handle_batch_response(stream: &mut dyn Transport, lines: &[usize]) -> std::io::Result<i32> {
+    match read_response(stream) {
+        Ok(response) => Ok(print_batch(response, lines)),
+        Err(e) => Ok(read_failed(&e)),
+    }
+}
 
-    let response: BridgeResponse =
-        bincode::deserialize(&buffer).expect("Failed to deserialize response");
+fn print_batch(response: BridgeResponse, lines: &[usize]) -> i32 {
+    let BridgeResponse::Batch(responses) = response else {
+        return print_response(response);
+    };
+    let run = responses.len();
+    let mut code = 0;
+    for (i, response) in responses.into_iter().enumerate() {
+        let status = if report::enabled() {
+            report::response(response, lines.get(i).copied())
+        } else {
+            print_response(response)
+        };
+        if code == 0 {
+            code = status;
+        }
+    }
+    if run < lines.len() {
+        let message = format!(
+            "Batch stopped at line {}, {} of {} commands ran",
+            lines[run.max(1) - 1],
+            run,
+            lines.len()
+        );
+        if report::enabled() {
+            report::error("stopped", &message, code);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+    code
+}
 
+// Prints a single-command response; returns the exit code to report.
+fn print_response(response: BridgeResponse) -> i32 {
+    if report::enabled() {
+        return report::response(response, None);
+    }
     match response {
         BridgeResponse::Success(msg) => {
-            if msg == "Pong!" {
+            if let Some(details) = msg.strip_prefix("Pong!") {
                 println!("Pong! Server is alive.");
+                print!("{}", details.trim_start_matches('\n'));
             } else if !msg.is_empty() {
                 print!("{}", msg);
             }
         }
+        BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        } => {
+            // Tools like `am` warn on stderr and still succeed, so it is shown either way.
+            print!("{}", stdout);
+            eprint!("{}", stderr);
+            return code;
+        }
+        BridgeResponse::CompletedBytes {
+            stdout,
+            stderr,
+            code,
+        } => {
+            let mut out = io::stdout();
+            if let Err(e) = out.write_all(&stdout).and_then(|()| out.flush()) {
+                eprintln!("Error: cannot write the output: {}", e);
+                return 1;
+            }
+            eprint!("{}", stderr);
+            return code;
+        }
+        BridgeResponse::SpawnFailed { os_error, message } => {
+            eprintln!("Remote Error: {}", message);
+            return not_started_code(os_error);
+        }
+        BridgeResponse::TimedOut {
+            timeout_ms,
+            stdout,
+            stderr,
+        } => {
+            // Whatever the command printed before it was killed is still useful.
+            print!("{}", stdout);
+            eprint!("{}", stderr);
+            eprintln!("Remote Error: timed out after {} ms", timeout_ms);
+            return TIMEOUT_EXIT_CODE;
+        }
+        BridgeResponse::ServerInfo(info) => print_server_info(&info),
+        BridgeResponse::DeviceInfo(info) => print_device_info(&info),
+        BridgeResponse::Property { value, .. } => println!("{}", value),
+        BridgeResponse::Properties(properties) => prop::print_table(&properties),
+        BridgeResponse::Jobs(jobs) => print_jobs(&jobs),
+        BridgeResponse::JobStarted { id, pid } => println!("Started job {} (pid {})", id, pid),
+        BridgeResponse::SessionOpened { id } => println!("{}", id),
+        BridgeResponse::BackgroundJobs(jobs) => print_background_jobs(&jobs),
         BridgeResponse::Error(err) => {
             eprintln!("Remote Error: {}", err);
+            return 1;
+        }
+        BridgeResponse::Failed(err) => {
+            eprintln!("Remote Error: {}", err.message);
+            return failed_code(err.kind);
+        }
+        BridgeResponse::Unsupported { kind } => {
+            eprintln!("Error: {}", too_old(kind));
+            return 1;
+        }
+        BridgeResponse::Busy { running, limit } => {
+            eprintln!("Remote Error: {}", busy(running, limit));
+            return 1;
+        }
+        BridgeResponse::FrameTooLarge { len, limit } => {
+            eprintln!("Remote Error: {}", frame_too_large(len, limit));
+            return 1;
         }
         _ => {
             eprintln!("Received unexpected response type for single command.");
+            return 1;
         }
     }
 
-    Ok(())
+    0
 }
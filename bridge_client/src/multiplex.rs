@@ -0,0 +1,192 @@
+// The client end of a multiplexed connection (see `BridgeCommand::Tagged`). Requests get
+// ids from a counter and are written under a lock; a reader thread hands every response to
+// the channel of the request it is tagged with, so any number of requests can be waited on
+// at once from different threads.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeError, BridgeResponse, SignalTarget};
+
+use crate::{Spilled, read_response, send_command};
+
+// Where the responses of a request go. Dropping it tells the request that no more are
+// coming, be it after the last one or because the connection went away.
+pub trait Deliver: Send {
+    fn deliver(&self, response: BridgeResponse);
+}
+
+impl Deliver for Sender<BridgeResponse> {
+    fn deliver(&self, response: BridgeResponse) {
+        // Nobody listening any more is fine, e.g. a foreground stream that was given up.
+        let _ = self.send(response);
+    }
+}
+
+// A request waiting for responses, and whether it is a Stream (which ends with StreamEnd)
+// rather than a command with a single response.
+type Pending = Mutex<HashMap<u64, (Box<dyn Deliver>, bool)>>;
+
+#[derive(Clone)]
+pub struct Multiplexer {
+    writer: Arc<Writer>,
+    next_id: Arc<AtomicU64>,
+    pending: Arc<Pending>,
+    // Set once the server has closed the connection.
+    closed: Arc<AtomicBool>,
+}
+
+// The connection as requests are written to it. The reader thread holds a clone of it, so
+// once the last Multiplexer is gone the connection is shut down, which ends that thread
+// too; otherwise the server would go on serving a client that has finished.
+struct Writer(Mutex<Box<dyn Transport>>);
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let fd = self.0.get_mut().unwrap().as_raw_fd();
+        unsafe {
+            libc::shutdown(fd, libc::SHUT_RDWR);
+        }
+    }
+}
+
+// A request in flight. `responses` disconnects after the last one, or when the connection
+// goes away.
+pub struct Request {
+    pub id: u64,
+    pub responses: Receiver<BridgeResponse>,
+}
+
+impl Request {
+    // The single response of a request that is not a Stream.
+    pub fn wait(self) -> io::Result<BridgeResponse> {
+        self.responses.recv().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the connection closed before the response arrived",
+            )
+        })
+    }
+}
+
+impl Multiplexer {
+    // Takes over `stream`, which has been through the handshake.
+    pub fn new(stream: Box<dyn Transport>) -> io::Result<Multiplexer> {
+        let reader = stream.try_clone_box()?;
+        let pending = Arc::new(Pending::default());
+        let closed = Arc::new(AtomicBool::new(false));
+        let (routes, ended) = (Arc::clone(&pending), Arc::clone(&closed));
+        thread::spawn(move || {
+            route(reader, &routes);
+            ended.store(true, Ordering::Relaxed);
+        });
+        Ok(Multiplexer {
+            writer: Arc::new(Writer(Mutex::new(stream))),
+            next_id: Arc::new(AtomicU64::new(1)),
+            pending,
+            closed,
+        })
+    }
+
+    // Whether the connection is gone, e.g. closed by the server for being idle.
+    pub fn closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn start(&self, cmd: BridgeCommand) -> io::Result<Request> {
+        let (sender, responses) = mpsc::channel();
+        let id = self.send(cmd, Box::new(sender))?;
+        Ok(Request { id, responses })
+    }
+
+    // Sends `cmd` as a new request whose responses go to `sink`, and returns its id.
+    pub fn send(&self, cmd: BridgeCommand, sink: Box<dyn Deliver>) -> io::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let streaming = matches!(
+            cmd,
+            BridgeCommand::Stream { .. } | BridgeCommand::Logcat { .. }
+        );
+        self.pending.lock().unwrap().insert(id, (sink, streaming));
+        let tagged = BridgeCommand::Tagged {
+            request_id: id,
+            command: Box::new(cmd),
+        };
+        if let Err(e) = send_command(&mut **self.writer.0.lock().unwrap(), &tagged) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    // Stops the process of request `id`; its own responses then end as usual.
+    pub fn cancel(&self, id: u64) -> io::Result<BridgeResponse> {
+        self.start(BridgeCommand::Cancel { request_id: id })?.wait()
+    }
+
+    // Sends `signal` to the process of request `id` and leaves it running.
+    pub fn signal(&self, id: u64, signal: i32) -> io::Result<BridgeResponse> {
+        self.start(BridgeCommand::Signal {
+            target: SignalTarget::Request(id),
+            signal,
+        })?
+        .wait()
+    }
+
+    // Cancels request `id` when called, without waiting for the answer, e.g. from a
+    // Ctrl+C handler.
+    pub fn canceller(&self, id: u64) -> impl FnOnce() + Send + 'static {
+        let multiplexer = self.clone();
+        move || {
+            let _ = multiplexer.start(BridgeCommand::Cancel { request_id: id });
+        }
+    }
+}
+
+// Hands out responses until the connection closes, which disconnects every request left.
+fn route(mut reader: Box<dyn Transport>, pending: &Pending) {
+    let mut spilled: HashMap<u64, Spilled> = HashMap::new();
+    while let Ok(response) = read_response(&mut *reader) {
+        let BridgeResponse::Tagged {
+            request_id,
+            response,
+        } = response
+        else {
+            // Something the server could not tie to a request, e.g. a garbled frame. With
+            // no request waiting it is the server closing an idle connection, which is left
+            // to the next request to notice.
+            if let BridgeResponse::Error(e) | BridgeResponse::Failed(BridgeError { message: e, .. }) =
+                response
+                && !pending.lock().unwrap().is_empty()
+            {
+                eprintln!("Remote Error: {}", e);
+            }
+            continue;
+        };
+        let mut pending = pending.lock().unwrap();
+        let Some((sink, streaming)) = pending.get(&request_id) else {
+            continue;
+        };
+        let response = match streaming {
+            true => *response,
+            // Output an Exec sent ahead of its response goes back into it.
+            false => match spilled.entry(request_id).or_default().take(*response) {
+                Some(response) => spilled
+                    .remove(&request_id)
+                    .unwrap_or_default()
+                    .merge(response),
+                None => continue,
+            },
+        };
+        let last = !streaming || matches!(response, BridgeResponse::StreamEnd { .. });
+        sink.deliver(response);
+        if last {
+            pending.remove(&request_id);
+        }
+    }
+    pending.lock().unwrap().clear();
+}
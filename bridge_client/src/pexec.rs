@@ -0,0 +1,239 @@
+// `andro pexec`: one Exec per line of an argument file, several at a time, like `xargs -P`.
+// On a server that multiplexes, the jobs are requests in flight on one connection; older
+// servers get a connection per job. Results print in the order of the file, each line
+// prefixed with its argument, as soon as everything before them has finished.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use bridge_client::session::Session;
+use bridge_client::{Retry, Server, WireCodec, busy, frame_too_large, too_old};
+use bridge_core::{BridgeCommand, BridgeResponse, ProcessOptions};
+
+use crate::TIMEOUT_EXIT_CODE;
+
+type Outcome = Result<BridgeResponse, String>;
+
+// The results so far, and how many of them have been printed.
+struct Results {
+    outcomes: Vec<Option<Outcome>>,
+    printed: usize,
+    failures: Vec<(usize, i32)>,
+}
+
+// Returns 0 if every command succeeded, 1 otherwise.
+pub fn run(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    jobs: u32,
+    arg_file: &Path,
+    template: Vec<String>,
+) -> i32 {
+    let items = match load(arg_file) {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 2;
+        }
+    };
+    let mut first = match Session::open(server.clone(), codec, token.clone(), retry) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let multiplexer = first.multiplexer().cloned();
+    let first = Mutex::new(Some(first));
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Results {
+        outcomes: items.iter().map(|_| None).collect(),
+        printed: 0,
+        failures: Vec::new(),
+    });
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len() as u32) {
+            scope.spawn(|| {
+                // Without multiplexing every job needs a connection of its own; the first
+                // one reuses the connection opened above.
+                let mut session = None;
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    let cmd = command(&template, item);
+                    let outcome = match &multiplexer {
+                        Some(multiplexer) => {
+                            multiplexer.start(cmd).and_then(|request| request.wait())
+                        }
+                        None => {
+                            if session.is_none() {
+                                session = match first.lock().unwrap().take() {
+                                    Some(session) => Some(Ok(session)),
+                                    None => Some(Session::open(
+                                        server.clone(),
+                                        codec,
+                                        token.clone(),
+                                        retry,
+                                    )),
+                                };
+                            }
+                            match session.as_mut().unwrap() {
+                                Ok(session) => session.request(cmd),
+                                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                            }
+                        }
+                    };
+                    let mut results = results.lock().unwrap();
+                    results.outcomes[index] = Some(outcome.map_err(|e| e.to_string()));
+                    print_ready(&mut results, &items);
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    if results.failures.is_empty() {
+        return 0;
+    }
+    let failed: Vec<String> = results
+        .failures
+        .iter()
+        .map(|&(index, code)| format!("{} (exit code {})", items[index], code))
+        .collect();
+    eprintln!(
+        "{} of {} failed: {}",
+        failed.len(),
+        items.len(),
+        failed.join(", ")
+    );
+    1
+}
+
+// The arguments, one per line (stdin for `-`); blank lines are skipped.
+fn load(source: &Path) -> Result<Vec<String>, String> {
+    let text = if source.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("cannot read stdin: {}", e))?;
+        text
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| format!("cannot read {}: {}", source.display(), e))?
+    };
+    let items: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if items.is_empty() {
+        return Err(format!("{} holds no arguments", source.display()));
+    }
+    Ok(items)
+}
+
+// The Exec for one argument: it replaces every `{}` of the template, or is appended if
+// there is none, like xargs.
+fn command(template: &[String], item: &str) -> BridgeCommand {
+    let mut words: Vec<String> = template
+        .iter()
+        .map(|word| word.replace("{}", item))
+        .collect();
+    if !template.iter().any(|word| word.contains("{}")) {
+        words.push(item.to_string());
+    }
+    BridgeCommand::Exec {
+        program: words.remove(0),
+        args: words,
+        stdin: false,
+        options: ProcessOptions::default(),
+    }
+}
+
+// Prints the finished results that every earlier one has been printed before.
+fn print_ready(results: &mut Results, items: &[String]) {
+    while let Some(Some(outcome)) = results.outcomes.get_mut(results.printed).map(Option::take) {
+        let index = results.printed;
+        let code = print_outcome(&items[index], outcome);
+        if code != 0 {
+            results.failures.push((index, code));
+        }
+        results.printed += 1;
+    }
+}
+
+// Prints one result like `andro -e` would, every line prefixed with `item`, and returns its
+// exit code.
+fn print_outcome(item: &str, outcome: Outcome) -> i32 {
+    let prefixed = |text: &str| {
+        text.lines()
+            .map(|line| format!("{}: {}\n", item, line))
+            .collect::<String>()
+    };
+    match outcome {
+        Ok(BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        }) => {
+            print!("{}", prefixed(&stdout));
+            eprint!("{}", prefixed(&stderr));
+            code
+        }
+        Ok(BridgeResponse::TimedOut {
+            timeout_ms,
+            stdout,
+            stderr,
+        }) => {
+            print!("{}", prefixed(&stdout));
+            eprint!("{}", prefixed(&stderr));
+            eprintln!("{}: Remote Error: timed out after {} ms", item, timeout_ms);
+            TIMEOUT_EXIT_CODE
+        }
+        Ok(BridgeResponse::Error(err)) => {
+            eprintln!("{}: Remote Error: {}", item, err);
+            1
+        }
+        Ok(BridgeResponse::Failed(err)) => {
+            eprintln!("{}: Remote Error: {}", item, err.message);
+            crate::failed_code(err.kind)
+        }
+        Ok(BridgeResponse::Unsupported { kind }) => {
+            eprintln!("{}: Error: {}", item, too_old(kind));
+            1
+        }
+        Ok(BridgeResponse::Busy { running, limit }) => {
+            eprintln!("{}: Remote Error: {}", item, busy(running, limit));
+            1
+        }
+        Ok(BridgeResponse::SpawnFailed { os_error, message }) => {
+            eprintln!("{}: Remote Error: {}", item, message);
+            crate::not_started_code(os_error)
+        }
+        Ok(BridgeResponse::FrameTooLarge { len, limit }) => {
+            eprintln!("{}: Remote Error: {}", item, frame_too_large(len, limit));
+            1
+        }
+        Ok(_) => {
+            eprintln!(
+                "{}: Received unexpected response type for single command.",
+                item
+            );
+            1
+        }
+        Err(e) => {
+            eprintln!("{}: Error: {}", item, e);
+            1
+        }
+    }
+}
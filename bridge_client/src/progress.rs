@@ -0,0 +1,131 @@
+// Progress bar for file transfers, drawn on stderr so it never mixes with pulled data.
+
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Progress {
+    total: Option<u64>,
+    done: u64,
+    // Bytes already present when a resumed transfer started; excluded from the rate.
+    resumed: u64,
+    started: Instant,
+    last_draw: Option<Instant>,
+    visible: bool,
+}
+
+impl Progress {
+    // The bar is only drawn when stderr is a terminal and `quiet` is not set.
+    pub fn new(total: Option<u64>, resumed: u64, quiet: bool) -> Progress {
+        Progress {
+            total,
+            done: resumed,
+            resumed,
+            started: Instant::now(),
+            last_draw: None,
+            visible: !quiet && io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    pub fn advance(&mut self, bytes: usize) {
+        self.done += bytes as u64;
+        if self
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL)
+        {
+            self.draw();
+        }
+    }
+
+    // Draws the final state and moves to a new line.
+    pub fn finish(&mut self) {
+        if self.visible {
+            self.draw();
+            eprintln!();
+            self.visible = false;
+        }
+    }
+
+    fn draw(&mut self) {
+        if !self.visible {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            (self.done - self.resumed) as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let line = match self.total {
+            Some(total) if total > 0 => {
+                let fraction = (self.done as f64 / total as f64).min(1.0);
+                let filled = (fraction * BAR_WIDTH as f64) as usize;
+                let eta = if rate > 0.0 {
+                    format_duration((total.saturating_sub(self.done)) as f64 / rate)
+                } else {
+                    "--:--".to_string()
+                };
+                format!(
+                    "[{}{}] {:>3}% {} / {}  {}/s  ETA {}",
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    (fraction * 100.0) as u32,
+                    format_bytes(self.done),
+                    format_bytes(total),
+                    format_bytes(rate as u64),
+                    eta
+                )
+            }
+            _ => format!(
+                "{}  {}/s",
+                format_bytes(self.done),
+                format_bytes(rate as u64)
+            ),
+        };
+        // `\x1b[K` clears leftovers of a longer previous line.
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
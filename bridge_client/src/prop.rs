@@ -0,0 +1,57 @@
+// `andro prop`: system properties as the server reads them with getprop, a sorted table for
+// --all, and `get --watch`, which asks again every interval over one connection and prints
+// a line each time the value changes.
+
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+use bridge_client::session::Session;
+use bridge_client::{Retry, Server, WireCodec};
+use bridge_core::{BridgeCommand, BridgeResponse, ErrorKind};
+
+use crate::{print_response, read_failed, watch};
+
+// `name  value` lines with the values lined up.
+pub fn print_table(properties: &BTreeMap<String, String>) {
+    let width = properties.keys().map(String::len).max().unwrap_or(0);
+    for (name, value) in properties {
+        println!("{:width$}  {}", name, value, width = width);
+    }
+}
+
+// Prints the value, then the time and new value on every change, until interrupted or a
+// request fails; returns the exit code of that failure.
+pub fn watch(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    name: String,
+    interval: Duration,
+) -> i32 {
+    let mut session = match Session::open(server, codec, token, retry) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    // None until the first answer; then the value, None again while it is not set.
+    let mut last: Option<Option<String>> = None;
+    loop {
+        let command = BridgeCommand::GetProp { name: name.clone() };
+        let value = match session.request(command) {
+            Ok(BridgeResponse::Property { value, .. }) => Some(value),
+            Ok(BridgeResponse::Failed(e)) if e.kind == ErrorKind::NotFound => None,
+            Ok(response) => return print_response(response),
+            Err(e) => return read_failed(&e),
+        };
+        if last.as_ref() != Some(&value) {
+            let shown = value.as_deref().unwrap_or("(not set)");
+            println!("{} {}: {}", watch::now(), name, shown);
+            last = Some(value);
+        }
+        thread::sleep(interval);
+    }
+}
@@ -0,0 +1,97 @@
+// Input recordings for `andro record` and `andro replay`.
+// File format: the 6-byte magic "NBREC\x01", a source byte (0 = touch, 1 = keys), a zero
+// byte, then 16-byte events: [u64 offset in µs][u16 type][u16 code][i32 value], big-endian.
+
+use bridge_core::transport::Transport;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+use bridge_core::{BridgeResponse, InputSource, RecordedEvent};
+
+use crate::read_response;
+
+const MAGIC: &[u8; 6] = b"NBREC\x01";
+const EVENT_SIZE: usize = 16;
+
+// Writes the recording to stdout until the server stops or the user interrupts.
+// Every frame is flushed as it arrives, so Ctrl+C leaves a complete file behind.
+pub fn receive(stream: &mut dyn Transport, source: InputSource) -> io::Result<i32> {
+    let mut stdout = io::stdout().lock();
+    let source_byte = match source {
+        InputSource::Touch => 0,
+        InputSource::Keys => 1,
+    };
+    stdout.write_all(MAGIC)?;
+    stdout.write_all(&[source_byte, 0])?;
+    stdout.flush()?;
+    eprintln!("Recording, press Ctrl+C to stop.");
+
+    let mut record = Vec::new();
+    loop {
+        match read_response(stream) {
+            Ok(BridgeResponse::InputEvents(events)) => {
+                record.clear();
+                for event in events {
+                    record.extend_from_slice(&event.offset_us.to_be_bytes());
+                    record.extend_from_slice(&event.type_.to_be_bytes());
+                    record.extend_from_slice(&event.code.to_be_bytes());
+                    record.extend_from_slice(&event.value.to_be_bytes());
+                }
+                stdout.write_all(&record)?;
+                stdout.flush()?;
+            }
+            Ok(BridgeResponse::Error(e)) => {
+                eprintln!("Remote Error: {}", e);
+                return Ok(1);
+            }
+            Ok(BridgeResponse::Failed(e)) => {
+                eprintln!("Remote Error: {}", e.message);
+                return Ok(1);
+            }
+            Ok(_) => {
+                eprintln!("Unexpected response while recording");
+                return Ok(1);
+            }
+            Err(_) => {
+                eprintln!("The server ended the recording.");
+                return Ok(1);
+            }
+        }
+    }
+}
+
+// Recordings are binary, so refuse to dump one onto a terminal.
+pub fn check_output() -> Result<(), String> {
+    if io::stdout().is_terminal() {
+        return Err("redirect the recording to a file, e.g. `andro record > gesture.bin`".into());
+    }
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<(InputSource, Vec<RecordedEvent>), String> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let invalid = || format!("{} is not an input recording", path.display());
+    if data.len() < 8 || &data[..6] != MAGIC {
+        return Err(invalid());
+    }
+    let source = match data[6] {
+        0 => InputSource::Touch,
+        1 => InputSource::Keys,
+        _ => return Err(invalid()),
+    };
+    let body = &data[8..];
+    if body.len() % EVENT_SIZE != 0 {
+        return Err(format!("{} is truncated", path.display()));
+    }
+    let events = body
+        .chunks_exact(EVENT_SIZE)
+        .map(|raw| RecordedEvent {
+            offset_us: u64::from_be_bytes(raw[0..8].try_into().unwrap()),
+            type_: u16::from_be_bytes([raw[8], raw[9]]),
+            code: u16::from_be_bytes([raw[10], raw[11]]),
+            value: i32::from_be_bytes(raw[12..16].try_into().unwrap()),
+        })
+        .collect();
+    Ok((source, events))
+}
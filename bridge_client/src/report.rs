@@ -0,0 +1,346 @@
+// --json: every response as one JSON object on a line of its own, for scripts. Errors the
+// client runs into itself (it cannot connect, the handshake fails) come out the same way,
+// with a `kind` to branch on, and nothing meant for people is printed. Exit codes are the
+// same as without --json.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+use bridge_client::{busy, frame_too_large, too_old};
+use bridge_core::{BackgroundJob, BridgeResponse, DeviceInfo, ErrorKind, Job, ServerInfo, json};
+
+use crate::{TIMEOUT_EXIT_CODE, failed_code, not_started_code, stream_exit_code, write_stdout};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// The answer to a request with a single reply: `status` is "success" or "error".
+#[derive(Serialize, Default)]
+struct Reply<'a> {
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    // The script line a reply in a batch belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout: Option<&'a str>,
+    // The stdout of --binary, which need not be text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<&'a ServerInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<&'a DeviceInfo>,
+    // Property and Properties, by name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    properties: Option<&'a BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jobs: Option<&'a [Job]>,
+    // JobStarted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    background_jobs: Option<&'a [BackgroundJob]>,
+    // SessionOpened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<u64>,
+    // SpawnFailed: the errno of the failed spawn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os_error: Option<i32>,
+    exit_code: i32,
+}
+
+// One line of a stream: `event` is "chunk", "end" or "error".
+#[derive(Serialize, Default)]
+struct Event<'a> {
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+}
+
+fn emit<T: Serialize>(value: &T) {
+    let mut line = json::to_string(value).expect("output objects always encode");
+    line.push('\n');
+    let _ = write_stdout(line.as_bytes());
+}
+
+// The result of an `andro app` action, which has fields of its own.
+pub fn app<T: Serialize>(value: &T) {
+    emit(value);
+}
+
+// An error of the client's own. Kinds: "connect", "handshake", "protocol" (a missing or
+// garbled response), "unsupported", "idle_timeout", "stopped" (a batch) and "usage".
+pub fn error(kind: &str, message: &str, exit_code: i32) {
+    emit(&Reply {
+        status: "error",
+        kind: Some(kind),
+        message: Some(message),
+        exit_code,
+        ..Reply::default()
+    });
+}
+
+// The `kind` of an error the server reported with one; Internal is the old "remote".
+fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::PolicyRejected => "policy_rejected",
+        ErrorKind::Timeout => "timeout",
+        ErrorKind::ProtocolError => "protocol",
+        ErrorKind::InputDeviceError => "input_device",
+        ErrorKind::Internal => "remote",
+    }
+}
+
+// Like `print_response`, including the exit code it returns. `line` is set in batches.
+pub fn response(response: BridgeResponse, line: Option<usize>) -> i32 {
+    let success = |exit_code| Reply {
+        status: "success",
+        line,
+        exit_code,
+        ..Reply::default()
+    };
+    let failure = |kind, message, exit_code| Reply {
+        status: "error",
+        kind: Some(kind),
+        message: Some(message),
+        line,
+        exit_code,
+        ..Reply::default()
+    };
+    match response {
+        BridgeResponse::Success(msg) => {
+            // Only the details of a Pong, e.g. the input backend, are worth a message.
+            let msg = match msg.strip_prefix("Pong!") {
+                Some(details) => details.trim(),
+                None => &msg,
+            };
+            emit(&Reply {
+                message: Some(msg).filter(|msg| !msg.is_empty()),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        } => {
+            emit(&Reply {
+                stdout: Some(&stdout),
+                stderr: Some(&stderr),
+                ..success(code)
+            });
+            code
+        }
+        BridgeResponse::CompletedBytes {
+            stdout,
+            stderr,
+            code,
+        } => {
+            emit(&Reply {
+                stdout_base64: Some(json::base64_encode(&stdout)),
+                stderr: Some(&stderr),
+                ..success(code)
+            });
+            code
+        }
+        BridgeResponse::TimedOut {
+            timeout_ms,
+            stdout,
+            stderr,
+        } => {
+            let message = format!("timed out after {} ms", timeout_ms);
+            emit(&Reply {
+                stdout: Some(&stdout),
+                stderr: Some(&stderr),
+                ..failure("timeout", &message, TIMEOUT_EXIT_CODE)
+            });
+            TIMEOUT_EXIT_CODE
+        }
+        BridgeResponse::ServerInfo(info) => {
+            emit(&Reply {
+                info: Some(&info),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::DeviceInfo(device) => {
+            emit(&Reply {
+                device: Some(&device),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::Property { name, value } => {
+            emit(&Reply {
+                properties: Some(&BTreeMap::from([(name, value)])),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::Properties(properties) => {
+            emit(&Reply {
+                properties: Some(&properties),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::Jobs(jobs) => {
+            emit(&Reply {
+                jobs: Some(&jobs),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::JobStarted { id, pid } => {
+            emit(&Reply {
+                job: Some(id),
+                pid: Some(pid),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::BackgroundJobs(jobs) => {
+            emit(&Reply {
+                background_jobs: Some(&jobs),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::SessionOpened { id } => {
+            emit(&Reply {
+                session: Some(id),
+                ..success(0)
+            });
+            0
+        }
+        BridgeResponse::Error(err) => {
+            emit(&failure("remote", &err, 1));
+            1
+        }
+        BridgeResponse::Failed(err) => {
+            let code = failed_code(err.kind);
+            emit(&failure(kind_name(err.kind), &err.message, code));
+            code
+        }
+        BridgeResponse::Unsupported { kind } => {
+            emit(&failure("unsupported", &too_old(kind), 1));
+            1
+        }
+        BridgeResponse::Busy { running, limit } => {
+            emit(&failure("busy", &busy(running, limit), 1));
+            1
+        }
+        BridgeResponse::FrameTooLarge { len, limit } => {
+            emit(&failure("frame_too_large", &frame_too_large(len, limit), 1));
+            1
+        }
+        BridgeResponse::SpawnFailed { os_error, message } => {
+            let code = not_started_code(os_error);
+            emit(&Reply {
+                os_error,
+                ..failure("spawn", &message, code)
+            });
+            code
+        }
+        _ => {
+            emit(&failure("protocol", "unexpected response type", 1));
+            1
+        }
+    }
+}
+
+// Like `show_stream_response`: Some(exit code) once the stream is over.
+pub fn stream_response(response: BridgeResponse) -> Option<i32> {
+    let chunk = |stream, data: &[u8]| Event {
+        event: "chunk",
+        stream: Some(stream),
+        data: Some(String::from_utf8_lossy(data).into_owned()),
+        ..Event::default()
+    };
+    let failure = |kind, message, exit_code| Event {
+        event: "error",
+        kind: Some(kind),
+        message: Some(message),
+        exit_code,
+        ..Event::default()
+    };
+    match response {
+        BridgeResponse::StdoutChunk(bytes) => emit(&chunk("stdout", &bytes)),
+        BridgeResponse::StderrChunk(bytes) => emit(&chunk("stderr", &bytes)),
+        #[allow(deprecated)]
+        BridgeResponse::StreamChunk(msg) => emit(&chunk("stdout", format!("{}\n", msg).as_bytes())),
+        BridgeResponse::StreamEnd { code, signal } => {
+            let exit_code = stream_exit_code(code, signal);
+            emit(&Event {
+                event: "end",
+                exit_code: Some(exit_code),
+                signal,
+                ..Event::default()
+            });
+            return Some(exit_code);
+        }
+        BridgeResponse::Error(err) => {
+            emit(&failure("remote", &err, Some(1)));
+            return Some(1);
+        }
+        BridgeResponse::Failed(err) => {
+            let code = failed_code(err.kind);
+            emit(&failure(kind_name(err.kind), &err.message, Some(code)));
+            return Some(code);
+        }
+        BridgeResponse::Unsupported { kind } => {
+            emit(&failure("unsupported", &too_old(kind), Some(1)));
+            return Some(1);
+        }
+        BridgeResponse::Busy { running, limit } => {
+            emit(&failure("busy", &busy(running, limit), Some(1)));
+            return Some(1);
+        }
+        BridgeResponse::FrameTooLarge { len, limit } => {
+            emit(&failure(
+                "frame_too_large",
+                &frame_too_large(len, limit),
+                Some(1),
+            ));
+            return Some(1);
+        }
+        BridgeResponse::TimedOut { timeout_ms, .. } => {
+            let message = format!("timed out after {} ms", timeout_ms);
+            emit(&failure("timeout", &message, Some(TIMEOUT_EXIT_CODE)));
+            return Some(TIMEOUT_EXIT_CODE);
+        }
+        // The stream goes on.
+        _ => emit(&failure("protocol", "unexpected response type", None)),
+    }
+    None
+}
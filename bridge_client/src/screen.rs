@@ -0,0 +1,69 @@
+// Receiving side of `andro screen-stream`.
+
+use bridge_core::transport::Transport;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bridge_core::{BridgeResponse, ImageFormat};
+
+use crate::read_response;
+
+// Writes every ScreenFrame either as a numbered file in `target`, or to stdout (`-`) as
+// [8-byte big-endian timestamp in ms][4-byte big-endian length][image] records.
+// Returns the exit code.
+pub fn receive_stream(
+    stream: &mut dyn Transport,
+    target: &Path,
+    format: ImageFormat,
+) -> io::Result<i32> {
+    let to_stdout = target.as_os_str() == "-";
+    if !to_stdout {
+        fs::create_dir_all(target)?;
+    }
+    let extension = match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg { .. } => "jpg",
+    };
+
+    let mut stdout = io::stdout().lock();
+    let mut code = 0;
+    loop {
+        match read_response(stream)? {
+            BridgeResponse::ScreenFrame {
+                index,
+                timestamp_ms,
+                data,
+            } => {
+                if to_stdout {
+                    stdout.write_all(&timestamp_ms.to_be_bytes())?;
+                    stdout.write_all(&(data.len() as u32).to_be_bytes())?;
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                } else {
+                    let path = target.join(format!("frame-{:06}.{}", index, extension));
+                    fs::write(&path, &data)?;
+                }
+            }
+            BridgeResponse::ScreenStreamEnd { frames, dropped } => {
+                eprintln!(
+                    "{} frames received, {} dropped by the server",
+                    frames, dropped
+                );
+                return Ok(code);
+            }
+            BridgeResponse::Error(err) => {
+                eprintln!("Remote Error: {}", err);
+                code = 1;
+            }
+            BridgeResponse::Failed(err) => {
+                eprintln!("Remote Error: {}", err.message);
+                code = 1;
+            }
+            _ => {
+                eprintln!("Received unexpected response type for screen-stream.");
+                return Ok(1);
+            }
+        }
+    }
+}
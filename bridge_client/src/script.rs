@@ -0,0 +1,109 @@
+// `andro run FILE`: a script of andro command lines, run one after another over a single
+// connection. Unlike a batch, which the server runs as one request, every line is its own
+// request, so streams and background jobs work, and the client decides what happens after
+// a failure. The whole file is checked before the first line runs.
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use bridge_client::session::Session;
+use bridge_client::{Retry, Server, WireCodec};
+
+use crate::{batch, parse_duration, shell};
+
+// What each line of the script does.
+enum Step {
+    // `sleep 500ms`, waited out on the client.
+    Sleep(Duration),
+    Command(Vec<String>),
+}
+
+// Returns the exit code of the last line that failed, or 0.
+pub fn run(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    file: &Path,
+    keep_going: bool,
+) -> i32 {
+    let steps = match load(file) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 2;
+        }
+    };
+    let mut session = match Session::open(server, codec, token, retry) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut failures = Vec::new();
+    for (line, step) in &steps {
+        let code = match step {
+            Step::Sleep(duration) => {
+                thread::sleep(*duration);
+                0
+            }
+            Step::Command(words) => {
+                shell::run_line(&mut session, words.clone()).unwrap_or_else(|e| {
+                    eprintln!("Error: line {}: {}", line, e);
+                    2
+                })
+            }
+        };
+        if code == 0 {
+            continue;
+        }
+        if !keep_going {
+            eprintln!("Error: line {} failed with exit code {}", line, code);
+            return code;
+        }
+        failures.push((*line, code));
+    }
+    let Some(&(_, last)) = failures.last() else {
+        return 0;
+    };
+    let lines: Vec<String> = failures
+        .iter()
+        .map(|(line, code)| format!("line {} (exit code {})", line, code))
+        .collect();
+    eprintln!(
+        "{} of {} lines failed: {}",
+        failures.len(),
+        steps.len(),
+        lines.join(", ")
+    );
+    last
+}
+
+// Reads the script, with environment variables substituted, and parses every line the way
+// it will run, so that a typo on the last line stops the script before its first.
+fn load(file: &Path) -> Result<Vec<(usize, Step)>, String> {
+    let mut steps = Vec::new();
+    for (line, words) in batch::load(file, true)? {
+        let step = match words.as_slice() {
+            [sleep, duration] if sleep == "sleep" => {
+                Step::Sleep(parse_duration(duration).map_err(|e| format!("line {}: {}", line, e))?)
+            }
+            [cancel, ..] if cancel == "cancel" => Step::Command(words),
+            _ => {
+                let mut command = words.clone();
+                if command.last().is_some_and(|word| word == "&") {
+                    command.pop();
+                }
+                if command.is_empty() {
+                    return Err(format!("line {}: nothing to run before &", line));
+                }
+                shell::command(command).map_err(|e| format!("line {}: {}", line, e))?;
+                Step::Command(words)
+            }
+        };
+        steps.push((line, step));
+    }
+    Ok(steps)
+}
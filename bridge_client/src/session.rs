@@ -0,0 +1,133 @@
+// A connection that carries one command after another, for callers issuing many requests
+// (`andro shell`). Each request waits for its response, so replies arrive in request order.
+// What the server can do is asked up front through Info: multiplexing servers get a
+// Multiplexer, which also runs requests side by side, and servers that close the connection
+// after every response simply get a fresh connection per request. A connection the server
+// closed in between, for sitting idle past its --idle-timeout, is replaced before the next
+// request goes out.
+
+use std::io;
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use crate::multiplex::Multiplexer;
+use crate::{
+    Retry, Server, WireCodec, connect_retrying, handshake, read_response, read_single_response,
+    send_command,
+};
+
+pub struct Session {
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    // None after a one-shot server hung up, or after a failed request.
+    stream: Option<Box<dyn Transport>>,
+    persistent: bool,
+    multiplexer: Option<Multiplexer>,
+}
+
+impl Session {
+    // `retry` covers every connection the session makes, the first one included.
+    pub fn open(
+        server: Server,
+        codec: WireCodec,
+        token: String,
+        retry: Retry,
+    ) -> io::Result<Session> {
+        let mut session = Session {
+            server,
+            codec,
+            token,
+            retry,
+            stream: None,
+            persistent: false,
+            multiplexer: None,
+        };
+        let mut stream = session.connect()?;
+        send_command(&mut *stream, &BridgeCommand::Info)?;
+        let features = features(read_response(&mut *stream)?);
+        let has = |name: &str| features.iter().any(|feature| feature == name);
+        session.persistent = has("persistent");
+        if has("multiplex") {
+            session.multiplexer = Some(Multiplexer::new(stream)?);
+        } else if session.persistent {
+            session.stream = Some(stream);
+        }
+        Ok(session)
+    }
+
+    // Whether requests share one connection; otherwise each gets a connection of its own.
+    pub fn persistent(&self) -> bool {
+        self.persistent
+    }
+
+    // Only servers that multiplex can run requests side by side, e.g. a Stream.
+    pub fn multiplexer(&mut self) -> Option<&Multiplexer> {
+        self.replace_closed();
+        self.multiplexer.as_ref()
+    }
+
+    fn replace_closed(&mut self) {
+        if let Some(multiplexer) = &self.multiplexer
+            && multiplexer.closed()
+            // If the server cannot be reached, the closed one fails the request instead.
+            && let Ok(multiplexer) = self.connect().and_then(Multiplexer::new)
+        {
+            self.multiplexer = Some(multiplexer);
+        }
+        // Between requests the server has nothing to say, so anything to read on the
+        // connection is it hanging up, or its Error telling why.
+        if let Some(stream) = &self.stream
+            && readable(&**stream)
+        {
+            self.stream = None;
+        }
+    }
+
+    pub fn request(&mut self, cmd: BridgeCommand) -> io::Result<BridgeResponse> {
+        self.replace_closed();
+        if let Some(multiplexer) = &self.multiplexer {
+            return multiplexer.start(cmd)?.wait();
+        }
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert(self.connect()?),
+        };
+        let result =
+            send_command(&mut **stream, &cmd).and_then(|()| read_single_response(&mut **stream));
+        if result.is_err() || !self.persistent {
+            self.stream = None;
+        }
+        result
+    }
+
+    pub(crate) fn connect(&self) -> io::Result<Box<dyn Transport>> {
+        let mut stream = connect_retrying(&self.server, self.codec, &self.retry).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to connect to {}: {}", self.server, e),
+            )
+        })?;
+        handshake(&mut *stream, &self.token)?;
+        Ok(stream)
+    }
+}
+
+// What the server can do, from its answer to Info. Servers predating Info are one-shot.
+pub(crate) fn features(response: BridgeResponse) -> Vec<String> {
+    match response {
+        BridgeResponse::ServerInfo(info) => info.features,
+        _ => Vec::new(),
+    }
+}
+
+fn readable(stream: &dyn Transport) -> bool {
+    let mut fd = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fd, 1, 0) > 0 }
+}
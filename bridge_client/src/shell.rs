@@ -0,0 +1,228 @@
+// `andro shell`: a prompt over one connection, where every line is an andro command line
+// without the `andro`. On a terminal the line editor (see `editor`) gives history and line
+// editing; piped input is read line by line, so scripts can feed it as well.
+
+use std::io::{self, IsTerminal};
+use std::thread;
+
+use clap::CommandFactory;
+
+use bridge_client::session::Session;
+use bridge_client::{Retry, Server, WireCodec};
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use crate::editor::Editor;
+use crate::{
+    Cli, Commands, ResponseMode, batch, build, on_ctrl_c, parse_words, print_batch, print_response,
+    show_stream_response, without_stdin,
+};
+
+const PROMPT: &str = "andro> ";
+
+// Runs the commands typed on stdin over one connection, printing each response before the
+// next line is read. Returns the exit code of the last command, like a shell script.
+pub fn run(server: Server, codec: WireCodec, token: String, retry: Retry) -> i32 {
+    let mut session = match Session::open(server, codec, token, retry) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut editor = io::stdin().is_terminal().then(Editor::new);
+    let mut code = 0;
+    loop {
+        let line = match &mut editor {
+            Some(editor) => match editor.read_line(PROMPT) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    break;
+                }
+            },
+            None => {
+                // Not `lines()`, which would keep stdin locked.
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line) {
+                    Ok(n) if n > 0 => line,
+                    _ => break,
+                }
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(editor) = &mut editor {
+            editor.add(line);
+        }
+        if line == "exit" || line == "quit" {
+            break;
+        }
+        if line == "help" {
+            eprintln!("{}", Cli::command().render_help());
+            continue;
+        }
+        code = batch::split(line)
+            .and_then(|words| run_line(&mut session, words))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                2
+            });
+    }
+    code
+}
+
+// Runs one line and returns its exit code. On a multiplexing server a line ending in `&`
+// runs in the background, printing its output as it arrives, and `cancel ID` stops it.
+pub fn run_line(session: &mut Session, mut words: Vec<String>) -> Result<i32, String> {
+    if words[0] == "cancel" {
+        let id = match words.as_slice() {
+            [_, id] => id
+                .parse()
+                .map_err(|_| format!("expected a job id, got '{}'", id))?,
+            _ => return Err("usage: cancel ID".to_string()),
+        };
+        let multiplexer = session
+            .multiplexer()
+            .ok_or("the server cannot cancel requests, it does not multiplex")?;
+        return Ok(match multiplexer.cancel(id) {
+            Ok(response) => print_response(response),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                1
+            }
+        });
+    }
+    let background = words.last().is_some_and(|word| word == "&");
+    if background {
+        words.pop();
+    }
+    let (cmd, mode) = command(words)?;
+    let streaming = matches!(mode, ResponseMode::Stream);
+    let batch = match mode {
+        ResponseMode::Batch { lines } => Some(lines),
+        _ => None,
+    };
+    if session.multiplexer().is_none() && !background && !streaming {
+        // Nothing to send a Cancel through; Ctrl+C says so instead of ending the shell.
+        on_ctrl_c(Some(Box::new(|| {
+            eprintln!(
+                "\nThe server cannot cancel requests, it does not multiplex (Ctrl+C again to quit)"
+            );
+        })));
+        let code = match session.request(cmd) {
+            Ok(response) => print_responses([response], false, batch.as_deref()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                1
+            }
+        };
+        on_ctrl_c(None);
+        return Ok(code);
+    }
+
+    let name = cmd.name();
+    let multiplexer = session.multiplexer().ok_or_else(|| {
+        format!(
+            "{} needs a server that multiplexes; update bridge_server or run it as a separate andro command",
+            if background { "running in the background" } else { name }
+        )
+    })?;
+    let request = match multiplexer.start(cmd) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(1);
+        }
+    };
+    if !background {
+        let cancel = multiplexer.canceller(request.id);
+        on_ctrl_c(Some(Box::new(move || {
+            eprintln!("\nCancelling... (Ctrl+C again to quit)");
+            cancel();
+        })));
+        let code = print_responses(request.responses, streaming, batch.as_deref());
+        on_ctrl_c(None);
+        return Ok(code);
+    }
+    let id = request.id;
+    eprintln!("[{}] {} started", id, name);
+    thread::spawn(move || {
+        let code = print_responses(request.responses, streaming, batch.as_deref());
+        eprintln!("[{}] {} done, exit code {}", id, name, code);
+    });
+    Ok(0)
+}
+
+// Turns a line into its request: anything a batch accepts, a whole batch, or a stream.
+// `exec` and `stream` stand for -e and -s, which read more naturally at a prompt.
+pub fn command(mut words: Vec<String>) -> Result<(BridgeCommand, ResponseMode), String> {
+    match words[0].as_str() {
+        "exec" => words[0] = "--exec".to_string(),
+        "stream" => words[0] = "--stream".to_string(),
+        _ => {}
+    }
+    let cli = parse_words(words)?;
+    if matches!(cli.command, Some(Commands::Shell)) {
+        return Err("already in a shell".to_string());
+    }
+    let (cmd, mode) = build(cli).map_err(|e| e.to_string())?;
+    match mode {
+        ResponseMode::Single | ResponseMode::Batch { .. } => Ok((without_stdin(cmd), mode)),
+        // Uncolored, like any stream at the prompt; it takes no stdin.
+        ResponseMode::Logcat { .. } => Ok((cmd, ResponseMode::Stream)),
+        ResponseMode::Stream => {
+            let BridgeCommand::Stream {
+                program,
+                args,
+                options,
+                ..
+            } = cmd
+            else {
+                unreachable!()
+            };
+            let cmd = BridgeCommand::Stream {
+                program,
+                args,
+                stdin: false,
+                options,
+            };
+            Ok((cmd, mode))
+        }
+        _ => Err(format!(
+            "{} needs a connection of its own; run it as a separate andro command",
+            cmd.name()
+        )),
+    }
+}
+
+// Prints the responses of one request and returns its exit code. `batch` holds the script
+// lines of a Batch.
+pub fn print_responses(
+    responses: impl IntoIterator<Item = BridgeResponse>,
+    streaming: bool,
+    batch: Option<&[usize]>,
+) -> i32 {
+    let mut responses = responses.into_iter();
+    if streaming {
+        for response in responses {
+            match show_stream_response(response) {
+                Ok(Some(code)) => return code,
+                Ok(None) => {}
+                Err(_) => return 1,
+            }
+        }
+        // The connection closed before the stream was finished.
+        return 1;
+    }
+    match (responses.next(), batch) {
+        (Some(response), Some(lines)) => print_batch(response, lines),
+        (Some(response), _) => print_response(response),
+        (None, _) => {
+            eprintln!("Server did not provide a response.");
+            1
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// `andro session`: a shell on the host that keeps its state from one line to the next. Lines
+// go over one connection as SessionExec requests; Ctrl+C sends SIGINT to what the line runs
+// through a connection of its own, leaving the shell and the session alone.
+
+use std::io::{self, IsTerminal, Write};
+
+use bridge_client::{
+    Retry, Server, WireCodec, busy, connect_retrying, frame_too_large, handshake, read_response,
+    send_command, too_old,
+};
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use crate::editor::Editor;
+use crate::{on_ctrl_c, stream_exit_code};
+
+// Where the session lives and how to reach its server again, for Ctrl+C.
+struct Target {
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+}
+
+// Runs `line` in session `id`, or without one in a session opened for it, or reads lines from
+// the user. Returns the exit code of the last line.
+pub fn run(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    id: Option<u64>,
+    shell: String,
+    line: Option<String>,
+) -> i32 {
+    let target = Target {
+        server,
+        codec,
+        token,
+        retry,
+    };
+    match session(&target, id, shell, line) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn session(
+    target: &Target,
+    id: Option<u64>,
+    shell: String,
+    line: Option<String>,
+) -> io::Result<i32> {
+    let mut stream = connect_retrying(&target.server, target.codec, &target.retry)?;
+    handshake(&mut *stream, &target.token)?;
+    let (id, opened) = match id {
+        Some(id) => (id, false),
+        None => (open(&mut *stream, shell)?, true),
+    };
+
+    if let Some(line) = line {
+        let (code, ended) = exec(&mut *stream, target, id, line)?;
+        if opened && !ended {
+            close(&mut *stream, id)?;
+        }
+        return Ok(code);
+    }
+
+    let terminal = io::stdin().is_terminal();
+    if terminal {
+        eprintln!(
+            "Session {} (Ctrl+D leaves it{})",
+            id,
+            if opened { " and closes it" } else { " open" }
+        );
+    }
+    let mut editor = terminal.then(Editor::new);
+    let prompt = format!("session {}$ ", id);
+    let mut code = 0;
+    loop {
+        let line = match &mut editor {
+            Some(editor) => match editor.read_line(&prompt)? {
+                Some(line) => line,
+                None => break,
+            },
+            None => {
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line)? == 0 {
+                    break;
+                }
+                line
+            }
+        };
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(editor) = &mut editor {
+            editor.add(line);
+        }
+        let ended;
+        (code, ended) = exec(&mut *stream, target, id, line.to_string())?;
+        if ended {
+            if terminal {
+                eprintln!("Session {} ended", id);
+            }
+            return Ok(code);
+        }
+    }
+    if opened {
+        close(&mut *stream, id)?;
+    } else if terminal {
+        eprintln!("Session {} left open", id);
+    }
+    Ok(code)
+}
+
+fn open(stream: &mut dyn Transport, shell: String) -> io::Result<u64> {
+    send_command(stream, &BridgeCommand::SessionOpen { shell: Some(shell) })?;
+    match read_response(stream)? {
+        BridgeResponse::SessionOpened { id } => Ok(id),
+        response => Err(failure(response)),
+    }
+}
+
+fn close(stream: &mut dyn Transport, id: u64) -> io::Result<()> {
+    send_command(stream, &BridgeCommand::SessionClose { id })?;
+    match read_response(stream)? {
+        BridgeResponse::Success(_) => Ok(()),
+        response => Err(failure(response)),
+    }
+}
+
+fn failure(response: BridgeResponse) -> io::Error {
+    match response {
+        BridgeResponse::Error(e) => io::Error::other(format!("Remote Error: {}", e)),
+        BridgeResponse::Failed(e) => io::Error::other(format!("Remote Error: {}", e.message)),
+        BridgeResponse::Unsupported { kind } => io::Error::other(too_old(kind)),
+        BridgeResponse::Busy { running, limit } => {
+            io::Error::other(format!("Remote Error: {}", busy(running, limit)))
+        }
+        BridgeResponse::FrameTooLarge { len, limit } => {
+            io::Error::other(format!("Remote Error: {}", frame_too_large(len, limit)))
+        }
+        _ => io::Error::other("unexpected response type"),
+    }
+}
+
+// Runs one line, printing its output as it comes. Returns its exit code, and whether the shell
+// exited with it, which ends the session.
+fn exec(
+    stream: &mut dyn Transport,
+    target: &Target,
+    id: u64,
+    line: String,
+) -> io::Result<(i32, bool)> {
+    send_command(stream, &BridgeCommand::SessionExec { id, line })?;
+    let (server, codec, token, retry) = (
+        target.server.clone(),
+        target.codec,
+        target.token.clone(),
+        target.retry,
+    );
+    on_ctrl_c(Some(Box::new(move || {
+        eprintln!("\nInterrupting... (Ctrl+C again to quit)");
+        let signal = BridgeCommand::SessionSignal {
+            id,
+            signal: libc::SIGINT,
+        };
+        let sent = connect_retrying(&server, codec, &retry).and_then(|mut stream| {
+            handshake(&mut *stream, &token)?;
+            send_command(&mut *stream, &signal)?;
+            read_response(&mut *stream)
+        });
+        if let Err(e) = sent {
+            eprintln!("Error: {}", e);
+        }
+    })));
+    let result = output(stream);
+    on_ctrl_c(None);
+    result
+}
+
+fn output(stream: &mut dyn Transport) -> io::Result<(i32, bool)> {
+    loop {
+        match read_response(stream)? {
+            BridgeResponse::StdoutChunk(bytes) => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&bytes)?;
+                stdout.flush()?;
+            }
+            BridgeResponse::StderrChunk(bytes) => io::stderr().write_all(&bytes)?,
+            BridgeResponse::StreamEnd { code, signal } => {
+                return Ok((stream_exit_code(code, signal), false));
+            }
+            BridgeResponse::SessionEnded { code, signal } => {
+                return Ok((stream_exit_code(code, signal), true));
+            }
+            response => return Err(failure(response)),
+        }
+    }
+}
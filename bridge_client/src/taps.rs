@@ -0,0 +1,55 @@
+// Tap lists for `andro taps`: whitespace separated `x,y,delay` entries, delay in ms before
+// the tap (optional, 0 if left out).
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+// Reads a tap list from `source`, or stdin for `-`.
+pub fn load(source: &Path) -> Result<Vec<(i32, i32, u64)>, String> {
+    let text = if source.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("cannot read stdin: {}", e))?;
+        text
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| format!("cannot read {}: {}", source.display(), e))?
+    };
+    parse(&text)
+}
+
+pub fn parse(text: &str) -> Result<Vec<(i32, i32, u64)>, String> {
+    let taps = text
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, entry)| {
+            parse_tap(entry).ok_or_else(|| {
+                format!(
+                    "tap {}: expected x,y or x,y,delay_ms, got '{}'",
+                    index + 1,
+                    entry
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if taps.is_empty() {
+        return Err("the tap list is empty".to_string());
+    }
+    Ok(taps)
+}
+
+fn parse_tap(entry: &str) -> Option<(i32, i32, u64)> {
+    let mut fields = entry.split(',');
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    let delay = match fields.next() {
+        Some(delay) => delay.parse().ok()?,
+        None => 0,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((x, y, delay))
+}
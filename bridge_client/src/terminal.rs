@@ -0,0 +1,84 @@
+// Local terminal handling for `andro pty`: raw mode and window size tracking.
+
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+// Puts stdin into raw mode so every keystroke (including Ctrl-C and Ctrl-D) is passed
+// through to the remote terminal. The previous settings are restored on drop.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+
+    // Raw input for the line editor of `andro shell`: keystrokes arrive one by one without
+    // echo, and Ctrl-C is a key rather than a signal. Output is still translated, so a
+    // plain `\n` moves to the start of the next line.
+    pub fn keystrokes() -> io::Result<RawMode> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+// Returns the current terminal size as (cols, rows), falling back to 80x24.
+pub fn window_size() -> (u16, u16) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == -1 || size.ws_col == 0 || size.ws_row == 0 {
+        return (80, 24);
+    }
+    (size.ws_col, size.ws_row)
+}
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Starts recording SIGWINCH deliveries; poll them with `take_resize`.
+pub fn watch_resize() {
+    unsafe {
+        libc::signal(
+            libc::SIGWINCH,
+            on_sigwinch as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+// Returns true once per batch of window size changes since the last call.
+pub fn take_resize() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
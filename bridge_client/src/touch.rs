@@ -0,0 +1,131 @@
+// `andro touch`: raw touch contacts, sent one after another on a single connection.
+
+use bridge_core::transport::Transport;
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::Duration;
+
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use crate::{parse_coord, read_response, send_command};
+
+// One step of a touch script.
+pub enum Action {
+    Send(BridgeCommand),
+    Wait(u64),
+}
+
+// The actions of a script, parsed as they are needed so stdin scripts can be driven live.
+pub type Script = Box<dyn Iterator<Item = Result<Action, String>>>;
+
+// Builds the script from the command line words, or from stdin lines for `-`.
+pub fn script(words: Vec<String>) -> Script {
+    if words.len() == 1 && words[0] == "-" {
+        let lines = io::stdin().lock().lines();
+        return Box::new(lines.flat_map(|line| {
+            let actions = line
+                .map_err(|e| e.to_string())
+                .and_then(|line| parse(line.split_whitespace().map(String::from).collect()));
+            split(actions)
+        }));
+    }
+    Box::new(split(parse(words)).into_iter())
+}
+
+// Turns a parse result into the script items, with a failure as the last one.
+fn split(actions: Result<Vec<Action>, String>) -> Vec<Result<Action, String>> {
+    match actions {
+        Ok(actions) => actions.into_iter().map(Ok).collect(),
+        Err(e) => vec![Err(e)],
+    }
+}
+
+// Parses words like `down 0 540 960 wait 200 move 0 50% 20% up 0`.
+fn parse(words: Vec<String>) -> Result<Vec<Action>, String> {
+    let mut actions = Vec::new();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let mut arg = |name: &str| {
+            words
+                .next()
+                .ok_or_else(|| format!("'{}' needs a {} argument", word, name))
+        };
+        let action = match word.as_str() {
+            "down" => BridgeCommand::DirectTouchDown {
+                slot: parse_slot(&arg("slot")?)?,
+                x: parse_coord(&arg("x")?)?,
+                y: parse_coord(&arg("y")?)?,
+            },
+            "move" => BridgeCommand::DirectTouchMove {
+                slot: parse_slot(&arg("slot")?)?,
+                x: parse_coord(&arg("x")?)?,
+                y: parse_coord(&arg("y")?)?,
+            },
+            "up" => BridgeCommand::DirectTouchUp {
+                slot: parse_slot(&arg("slot")?)?,
+            },
+            "wait" => {
+                let ms = arg("duration")?;
+                let ms = ms
+                    .parse()
+                    .map_err(|_| format!("invalid wait duration '{}'", ms))?;
+                actions.push(Action::Wait(ms));
+                continue;
+            }
+            _ => {
+                return Err(format!(
+                    "unknown touch action '{}' (expected down, move, up or wait)",
+                    word
+                ));
+            }
+        };
+        actions.push(Action::Send(action));
+    }
+    Ok(actions)
+}
+
+fn parse_slot(arg: &str) -> Result<u8, String> {
+    arg.parse()
+        .map_err(|_| format!("invalid slot '{}' (expected 0 to 255)", arg))
+}
+
+// Runs leading waits and returns the first command of the script.
+pub fn next_command(script: &mut Script) -> Result<Option<BridgeCommand>, String> {
+    for action in script {
+        match action? {
+            Action::Send(command) => return Ok(Some(command)),
+            Action::Wait(ms) => thread::sleep(Duration::from_millis(ms)),
+        }
+    }
+    Ok(None)
+}
+
+// Reads the answer to the command that opened the session, then plays the rest of the
+// script. Returns the exit code.
+pub fn run(stream: &mut dyn Transport, mut script: Script) -> io::Result<i32> {
+    loop {
+        match read_response(stream)? {
+            BridgeResponse::Success(message) => eprint!("{}", message),
+            BridgeResponse::Error(e) => {
+                eprintln!("Remote Error: {}", e);
+                return Ok(1);
+            }
+            BridgeResponse::Failed(e) => {
+                eprintln!("Remote Error: {}", e.message);
+                return Ok(1);
+            }
+            _ => {
+                eprintln!("Unexpected response to a touch command");
+                return Ok(1);
+            }
+        }
+        match next_command(&mut script) {
+            Ok(Some(command)) => send_command(stream, &command)?,
+            Ok(None) => return Ok(0),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Ok(2);
+            }
+        }
+    }
+}
@@ -0,0 +1,374 @@
+// Client side of file transfers (`andro push` / `andro pull`).
+
+use bridge_core::transport::Transport;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use bridge_core::sha256::digest_reader;
+use bridge_core::{BridgeCommand, BridgeResponse};
+use clap::Args;
+
+use crate::progress::Progress;
+use crate::{CHUNK_SIZE, failed_code, read_response, send_command};
+
+// Flags shared by push and pull.
+#[derive(Args)]
+pub struct TransferOptions {
+    /// Do not show a progress bar
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Continue an interrupted transfer from its partial file
+    #[arg(long)]
+    pub resume: bool,
+    /// Compare checksums of both ends after the transfer
+    #[arg(long, value_name = "ALGO", value_parser = ["sha256"])]
+    pub verify: Option<String>,
+}
+
+impl TransferOptions {
+    pub fn verify(&self) -> bool {
+        self.verify.is_some()
+    }
+}
+
+// Sends `file` after the PushFile command and reports the result; returns the exit code.
+pub fn push(
+    stream: &mut dyn Transport,
+    mut file: File,
+    dest: &str,
+    options: TransferOptions,
+) -> io::Result<i32> {
+    let offset = match read_response(stream)? {
+        BridgeResponse::TransferReady { offset } => offset,
+        BridgeResponse::Error(err) => {
+            eprintln!("Remote Error: {}", err);
+            return Ok(1);
+        }
+        BridgeResponse::Failed(err) => {
+            eprintln!("Remote Error: {}", err.message);
+            return Ok(failed_code(err.kind));
+        }
+        _ => {
+            eprintln!("Received unexpected response type for push.");
+            return Ok(1);
+        }
+    };
+    let size = file.metadata()?.len();
+    if offset > size {
+        eprintln!(
+            "The partial file on the host ({} bytes) is larger than the source; push without --resume.",
+            offset
+        );
+        return Ok(1);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut progress = Progress::new(Some(size), offset, options.quiet);
+    let sent = send_file(stream, &mut file, &mut progress);
+    progress.finish();
+    if let Err(e) = sent {
+        // The server stops reading when it hits an error (e.g. disk full) and reports why
+        // before closing; prefer that explanation over our broken pipe.
+        match read_response(stream) {
+            Ok(BridgeResponse::Error(err)) => {
+                eprintln!("Remote Error: {}", err);
+                return Ok(1);
+            }
+            Ok(BridgeResponse::Failed(err)) => {
+                eprintln!("Remote Error: {}", err.message);
+                return Ok(failed_code(err.kind));
+            }
+            _ => {}
+        }
+        return Err(e);
+    }
+
+    match read_response(stream)? {
+        BridgeResponse::Transferred { bytes, sha256 } => {
+            println!("{} bytes pushed to {}", bytes, dest);
+            if options.verify() {
+                file.seek(SeekFrom::Start(0))?;
+                let local = digest_reader(&mut file)?;
+                return Ok(compare_digests(&local, sha256.as_deref()));
+            }
+            Ok(0)
+        }
+        BridgeResponse::Error(err) => {
+            eprintln!("Remote Error: {}", err);
+            Ok(1)
+        }
+        BridgeResponse::Failed(err) => {
+            eprintln!("Remote Error: {}", err.message);
+            Ok(failed_code(err.kind))
+        }
+        _ => {
+            eprintln!("Received unexpected response type for push.");
+            Ok(1)
+        }
+    }
+}
+
+pub fn send_file(
+    stream: &mut dyn Transport,
+    file: &mut File,
+    progress: &mut Progress,
+) -> io::Result<()> {
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        send_command(stream, &BridgeCommand::FileChunk(buffer[..n].to_vec()))?;
+        progress.advance(n);
+    }
+    send_command(stream, &BridgeCommand::FileEnd)
+}
+
+// Where a pull of `source` into `dest` ends up; an existing directory keeps the remote
+// file name, like cp does.
+pub fn local_path(source: &str, dest: &Path) -> PathBuf {
+    match Path::new(source).file_name() {
+        Some(name) if dest.is_dir() => dest.join(name),
+        _ => dest.to_path_buf(),
+    }
+}
+
+// Length of the partial file an interrupted pull into `target` left behind.
+pub fn resume_offset(target: &Path) -> u64 {
+    fs::metadata(partial_path(target)).map_or(0, |meta| meta.len())
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(".nbpart");
+    PathBuf::from(partial)
+}
+
+// Receives the file requested by PullFile into `target` (`-` for stdout, a directory
+// for recursive pulls); returns the exit code.
+pub fn pull(
+    stream: &mut dyn Transport,
+    target: &Path,
+    recursive: bool,
+    offset: u64,
+    options: TransferOptions,
+) -> io::Result<i32> {
+    let size = match read_header(stream, "pull")? {
+        Ok(size) => size,
+        Err(code) => return Ok(code),
+    };
+
+    let to_stdout = target.as_os_str() == "-";
+    let mut progress = Progress::new(size, offset, options.quiet);
+    let result = if to_stdout {
+        receive_chunks(stream, &mut io::stdout().lock(), &mut progress)
+    } else if recursive {
+        extract_tree(stream, target, &mut progress)
+    } else {
+        receive_file(stream, target, offset, &mut progress)
+    };
+    progress.finish();
+
+    let remote_digest = match result {
+        Ok(digest) => digest,
+        Err(e) => {
+            report_failure("Pull", &e);
+            return Ok(1);
+        }
+    };
+    io::stdout().flush()?;
+    if to_stdout {
+        return Ok(0);
+    }
+    println!("{} bytes pulled to {}", progress.done(), target.display());
+    if options.verify() {
+        let local = File::open(target).and_then(digest_reader)?;
+        return Ok(compare_digests(&local, remote_digest.as_deref()));
+    }
+    Ok(0)
+}
+
+// Saves the image sent in answer to a Screenshot command into `target` (`-` for stdout).
+pub fn save_screenshot(stream: &mut dyn Transport, target: &Path) -> io::Result<i32> {
+    let size = match read_header(stream, "screenshot")? {
+        Ok(size) => size,
+        Err(code) => return Ok(code),
+    };
+    let mut progress = Progress::new(size, 0, true);
+    let to_stdout = target.as_os_str() == "-";
+    let result = if to_stdout {
+        receive_chunks(stream, &mut io::stdout().lock(), &mut progress)
+    } else {
+        receive_file(stream, target, 0, &mut progress)
+    };
+    match result {
+        Ok(_) if to_stdout => Ok(0),
+        Ok(_) => {
+            println!(
+                "Screenshot saved to {} ({} bytes)",
+                target.display(),
+                progress.done()
+            );
+            Ok(0)
+        }
+        Err(e) => {
+            report_failure("Screenshot", &e);
+            Ok(1)
+        }
+    }
+}
+
+// Waits for the FileHeader that starts a download and returns its size, or the exit code
+// after reporting an error.
+fn read_header(stream: &mut dyn Transport, what: &str) -> io::Result<Result<Option<u64>, i32>> {
+    match read_response(stream)? {
+        BridgeResponse::FileHeader { size } => Ok(Ok(size)),
+        BridgeResponse::Error(err) => {
+            eprintln!("Remote Error: {}", err);
+            Ok(Err(1))
+        }
+        BridgeResponse::Failed(err) => {
+            eprintln!("Remote Error: {}", err.message);
+            Ok(Err(failed_code(err.kind)))
+        }
+        _ => {
+            eprintln!("Received unexpected response type for {}.", what);
+            Ok(Err(1))
+        }
+    }
+}
+
+// Like the server side of push, data goes to a temporary file that is only renamed into
+// place once the transfer is complete. It is kept if the pull breaks, for --resume.
+fn receive_file(
+    stream: &mut dyn Transport,
+    target: &Path,
+    offset: u64,
+    progress: &mut Progress,
+) -> io::Result<Option<String>> {
+    let partial = partial_path(target);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
+        .open(&partial)?;
+    let result = receive_chunks(stream, &mut file, progress).and_then(|digest| {
+        file.sync_all()?;
+        fs::rename(&partial, target)?;
+        Ok(digest)
+    });
+    if result.is_err() && file.metadata().is_ok_and(|meta| meta.len() == 0) {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+// Unpacks the tar stream of a recursive pull with the local tar.
+fn extract_tree(
+    stream: &mut dyn Transport,
+    dest: &Path,
+    progress: &mut Progress,
+) -> io::Result<Option<String>> {
+    fs::create_dir_all(dest)?;
+    let mut tar = Command::new("tar")
+        .arg("-xf")
+        .arg("-")
+        .arg("-C")
+        .arg(dest)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut input = tar.stdin.take().unwrap();
+    let received = receive_chunks(stream, &mut input, progress);
+    drop(input);
+    let status = tar.wait()?;
+    let digest = received?;
+    if !status.success() {
+        return Err(io::Error::other(format!("local tar failed ({})", status)));
+    }
+    Ok(digest)
+}
+
+// Writes FileChunk frames to `out` until FileEnd, checking the total against the
+// announced size so a truncated transfer is never mistaken for a complete one.
+// Returns the digest the server sent along with FileEnd, if any.
+fn receive_chunks<W: Write>(
+    stream: &mut dyn Transport,
+    out: &mut W,
+    progress: &mut Progress,
+) -> io::Result<Option<String>> {
+    let digest = loop {
+        match read_response(stream)? {
+            BridgeResponse::FileChunk(data) => {
+                out.write_all(&data)?;
+                progress.advance(data.len());
+            }
+            BridgeResponse::FileEnd { sha256 } => break sha256,
+            BridgeResponse::Error(err) => return Err(io::Error::other(RemoteError(err))),
+            BridgeResponse::Failed(err) => {
+                return Err(io::Error::other(RemoteError(err.message)));
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected frame during file transfer",
+                ));
+            }
+        }
+    };
+    out.flush()?;
+    match progress.total() {
+        Some(size) if progress.done() != size => Err(io::Error::other(format!(
+            "transfer truncated ({} of {} bytes received)",
+            progress.done(),
+            size
+        ))),
+        _ => Ok(digest),
+    }
+}
+
+// An Error the server sent in the middle of a download.
+#[derive(Debug)]
+struct RemoteError(String);
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+fn report_failure(what: &str, e: &io::Error) {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<RemoteError>())
+    {
+        Some(remote) => eprintln!("Remote Error: {}", remote),
+        None => eprintln!("{} failed: {}", what, e),
+    }
+}
+
+// Reports the outcome of --verify; returns the exit code.
+fn compare_digests(local: &str, remote: Option<&str>) -> i32 {
+    match remote {
+        Some(remote) if remote == local => {
+            println!("sha256 verified: {}", local);
+            0
+        }
+        Some(remote) => {
+            eprintln!("Checksum mismatch! local {}, remote {}", local, remote);
+            1
+        }
+        None => {
+            eprintln!("The server did not report a checksum; cannot verify.");
+            1
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// `andro watch`: runs one command again and again over a single connection, like watch(1)
+// around andro but without a connect and handshake per tick. The command is anything the
+// shell accepts that finishes; a stream never does.
+
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use bridge_client::session::Session;
+use bridge_client::{Retry, Server, WireCodec};
+use bridge_core::BridgeResponse;
+
+use crate::ResponseMode;
+use crate::shell;
+
+pub struct Options {
+    pub interval: Duration,
+    // Stop after this many runs; None runs until Ctrl+C.
+    pub count: Option<u32>,
+    // Redraw the screen each time instead of printing a header between runs.
+    pub clear: bool,
+    pub diff: bool,
+}
+
+// Returns the exit code of the last run.
+pub fn run(
+    server: Server,
+    codec: WireCodec,
+    token: String,
+    retry: Retry,
+    words: Vec<String>,
+    options: Options,
+) -> i32 {
+    // Parsed up front so a typo fails before anything is sent; every run parses the words
+    // again, which also rereads the file of a `batch FILE`.
+    match shell::command(words.clone()) {
+        Ok((_, ResponseMode::Stream)) => {
+            eprintln!("Error: watch needs a command that finishes; a stream never does");
+            return 2;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 2;
+        }
+    }
+    let mut session = match Session::open(server, codec, token, retry) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let title = words
+        .iter()
+        .map(|word| {
+            if word.contains(char::is_whitespace) {
+                format!("'{}'", word)
+            } else {
+                word.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut previous: Option<Vec<String>> = None;
+    let mut runs = 0;
+    loop {
+        runs += 1;
+        let header = format!("Every {:?}: {}    {}", options.interval, title, now());
+        if options.clear {
+            print!("\x1b[H\x1b[2J{}\n\n", header);
+        } else {
+            println!("--- {} ---", header);
+        }
+        let (cmd, mode) = match shell::command(words.clone()) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return 2;
+            }
+        };
+        let batch = match mode {
+            ResponseMode::Batch { lines } => Some(lines),
+            _ => None,
+        };
+        let code = match session.request(cmd) {
+            Ok(response) => match (options.diff, text(&response)) {
+                (true, Some((text, code, stderr))) => {
+                    let lines: Vec<String> = text.lines().map(str::to_string).collect();
+                    print_diff(&lines, previous.as_deref(), !options.clear);
+                    previous = Some(lines);
+                    eprint!("{}", stderr);
+                    code
+                }
+                _ => shell::print_responses([response], false, batch.as_deref()),
+            },
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return 1;
+            }
+        };
+        let _ = io::stdout().flush();
+        if options.count.is_some_and(|count| runs >= count) {
+            return code;
+        }
+        thread::sleep(options.interval);
+    }
+}
+
+// The text a response prints, with its exit code and stderr.
+// Other responses (info, errors) print as usual even with --diff.
+fn text(response: &BridgeResponse) -> Option<(&str, i32, &str)> {
+    match response {
+        BridgeResponse::Success(msg) if !msg.starts_with("Pong!") => Some((msg, 0, "")),
+        BridgeResponse::Completed {
+            stdout,
+            stderr,
+            code,
+        } => Some((stdout, *code, stderr)),
+        _ => None,
+    }
+}
+
+// Prints `lines`, highlighting those that differ from the same line of the previous run.
+// `changed_only` leaves out the others, for --no-clear where the old output is still on
+// screen.
+fn print_diff(lines: &[String], previous: Option<&[String]>, changed_only: bool) {
+    let Some(previous) = previous else {
+        for line in lines {
+            println!("{}", line);
+        }
+        return;
+    };
+    // Reverse video like `watch -d` on a terminal, a marker where colours would end up in a
+    // file.
+    let (start, end) = if io::stdout().is_terminal() {
+        ("\x1b[7m", "\x1b[0m")
+    } else {
+        ("> ", "")
+    };
+    let mut changes = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if previous.get(i) != Some(line) {
+            changes += 1;
+            println!("{}{}{}", start, line, end);
+        } else if !changed_only {
+            println!("{}", line);
+        }
+    }
+    if previous.len() > lines.len() {
+        println!("({} lines fewer)", previous.len() - lines.len());
+    } else if changed_only && changes == 0 {
+        println!("(no changes)");
+    }
+}
+
+// The local time as YYYY-MM-DD HH:MM:SS.
+pub fn now() -> String {
+    let time = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
@@ -0,0 +1,134 @@
+// Runs `andro -s` against a server that streams until it is cancelled, and ends the client
+// the way pipelines do: by closing the read end of its stdout, as `andro -s yes | head` does,
+// which must cancel the command and exit 0, and by SIGTERM, which must cancel it and end
+// with the command's own status.
+
+use std::io::Read;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::proto::MAX_FRAME_LEN;
+use bridge_core::{BridgeCommand, BridgeResponse, Codec, PROTOCOL_VERSION};
+
+fn send(stream: &mut UnixStream, response: &BridgeResponse) -> std::io::Result<()> {
+    let payload = Codec::Bincode.encode_response(response).unwrap();
+    Codec::Bincode.write_frame(stream, &payload)
+}
+
+fn command(stream: &mut UnixStream) -> Option<BridgeCommand> {
+    let frame = Codec::Bincode
+        .read_frame(stream, MAX_FRAME_LEN)
+        .ok()
+        .flatten()?;
+    Codec::Bincode.decode_command(&frame).ok()
+}
+
+// A server for one connection that answers a Stream with a line every 10 ms until a Cancel
+// arrives, then ends it as killed by SIGTERM. Reports on the channel whether a Cancel came.
+fn stream_server(socket: &Path) -> mpsc::Receiver<bool> {
+    let listener = UnixListener::bind(socket).unwrap();
+    let (cancelled, report) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        command(&mut stream);
+        Codec::Bincode
+            .write_hello(&mut stream, PROTOCOL_VERSION, b"")
+            .unwrap();
+        send(&mut stream, &BridgeResponse::Success(String::new())).unwrap();
+        assert!(matches!(
+            command(&mut stream),
+            Some(BridgeCommand::Stream { .. })
+        ));
+        let mut writer = stream.try_clone().unwrap();
+        let lines = thread::spawn(move || {
+            let line = BridgeResponse::StdoutChunk(b"line\n".repeat(512));
+            while send(&mut writer, &line).is_ok() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        // The client forwards its (empty) stdin before anything else.
+        let cancel = loop {
+            match command(&mut stream) {
+                Some(BridgeCommand::StdinChunk(_) | BridgeCommand::StdinEnd) => continue,
+                next => break matches!(next, Some(BridgeCommand::Cancel { .. })),
+            }
+        };
+        let end = BridgeResponse::StreamEnd {
+            code: None,
+            signal: Some(libc::SIGTERM),
+        };
+        let _ = send(&mut stream, &end);
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        let _ = lines.join();
+        cancelled.send(cancel).unwrap();
+    });
+    report
+}
+
+fn socket(name: &str) -> PathBuf {
+    let socket = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    socket
+}
+
+fn andro_stream(socket: &Path) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_bridge_client"))
+        .arg("--socket")
+        .arg(socket)
+        .args(["-s", "yes"])
+        .env_remove("ANDRO_ADDR")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+// The client's exit code, or None if it is still running after 5 s.
+fn wait(child: &mut Child) -> Option<i32> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    None
+}
+
+#[test]
+fn closed_stdout_cancels_and_exits_0() {
+    let socket = socket("broken-pipe");
+    let cancelled = stream_server(&socket);
+    let mut child = andro_stream(&socket);
+    let mut stdout = child.stdout.take().unwrap();
+    let mut head = [0; 4096];
+    stdout.read_exact(&mut head).unwrap();
+    drop(stdout);
+    assert_eq!(wait(&mut child), Some(0));
+    assert!(cancelled.recv_timeout(Duration::from_secs(5)).unwrap());
+    std::fs::remove_file(&socket).unwrap();
+}
+
+#[test]
+fn sigterm_cancels_and_exits_with_the_commands_status() {
+    let socket = socket("sigterm");
+    let cancelled = stream_server(&socket);
+    let mut child = andro_stream(&socket);
+    let mut stdout = child.stdout.take().unwrap();
+    let mut head = [0; 4096];
+    stdout.read_exact(&mut head).unwrap();
+    // Drained, so only the signal can end the client.
+    thread::spawn(move || std::io::copy(&mut stdout, &mut std::io::sink()));
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    assert_eq!(wait(&mut child), Some(128 + libc::SIGTERM));
+    assert!(cancelled.recv_timeout(Duration::from_secs(5)).unwrap());
+    std::fs::remove_file(&socket).unwrap();
+}
@@ -0,0 +1,194 @@
+// Plays a server that answers with frames that are not responses, and checks that `andro`
+// fails on each with a one-line diagnostic and exit code 70 instead of a panic: garbage for a
+// single response, for the first and a later response of a stream, for the handshake, as a
+// JSON line, and with --json.
+
+use std::io::Write;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+use bridge_core::proto::{MAX_FRAME_LEN, write_frame};
+use bridge_core::{BridgeResponse, Codec, PROTOCOL_VERSION};
+
+const GARBAGE: &[u8] = &[
+    0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 0xff, 0x7f, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    0x09, 0x0a, 0x0b, 0x0c,
+];
+
+struct Case {
+    name: &'static str,
+    args: &'static [&'static str],
+    codec: Codec,
+    // Sent where the handshake's Success belongs.
+    ack: Option<&'static [u8]>,
+    // Sent after the command arrives.
+    frames: Vec<Vec<u8>>,
+    // Must all be in what andro prints on stderr (or stdout, with --json).
+    expect: &'static [&'static str],
+}
+
+fn chunk() -> Vec<u8> {
+    Codec::Bincode
+        .encode_response(&BridgeResponse::StdoutChunk(b"partial\n".to_vec()))
+        .unwrap()
+}
+
+// Runs `case` against a fake server on a socket of its own.
+fn check(case: Case) {
+    let socket = std::env::temp_dir().join(format!(
+        "garbage-{}-{}.sock",
+        std::process::id(),
+        case.name.replace(|c: char| !c.is_ascii_alphanumeric(), "-")
+    ));
+    let _ = std::fs::remove_file(&socket);
+    let andro = Path::new(env!("CARGO_BIN_EXE_bridge_client"));
+    let result = run(andro, &socket, &case);
+    let _ = std::fs::remove_file(&socket);
+    if let Err(e) = result {
+        panic!("{}: {}", case.name, e);
+    }
+}
+
+#[test]
+fn single_response() {
+    check(Case {
+        name: "single response",
+        args: &["-e", "true"],
+        codec: Codec::Bincode,
+        ack: None,
+        frames: vec![GARBAGE.to_vec()],
+        expect: &[
+            "cannot decode a 20 byte response (de ad be ef ff ff ff 7f 01 02 03 04 05 06 07 08 ...)",
+            "same version",
+        ],
+    });
+}
+
+#[test]
+fn first_stream_response() {
+    check(Case {
+        name: "first stream response",
+        args: &["-s", "true"],
+        codec: Codec::Bincode,
+        ack: None,
+        frames: vec![GARBAGE[..4].to_vec()],
+        expect: &["cannot decode a 4 byte response (de ad be ef)"],
+    });
+}
+
+#[test]
+fn stream_response_after_output() {
+    check(Case {
+        name: "stream response after output",
+        args: &["-s", "true"],
+        codec: Codec::Bincode,
+        ack: None,
+        frames: vec![chunk(), GARBAGE.to_vec()],
+        expect: &["cannot decode a 20 byte response"],
+    });
+}
+
+#[test]
+fn handshake() {
+    check(Case {
+        name: "handshake",
+        args: &["-e", "true"],
+        codec: Codec::Bincode,
+        ack: Some(GARBAGE),
+        frames: Vec::new(),
+        expect: &["cannot decode a 20 byte response"],
+    });
+}
+
+#[test]
+fn json_line() {
+    check(Case {
+        name: "json line",
+        args: &["--codec", "json", "-e", "true"],
+        codec: Codec::Json,
+        ack: None,
+        frames: vec![b"{\"NoSuchResponse\":1}".to_vec()],
+        expect: &[
+            "cannot decode a 20 byte response (7b 22 4e 6f",
+            "same version",
+        ],
+    });
+}
+
+#[test]
+fn json_report() {
+    check(Case {
+        name: "--json",
+        args: &["--json", "-e", "true"],
+        codec: Codec::Bincode,
+        ack: None,
+        frames: vec![GARBAGE.to_vec()],
+        expect: &["\"kind\":\"protocol\"", "\"exit_code\":70"],
+    });
+}
+
+fn run(andro: &Path, socket: &Path, case: &Case) -> Result<(), String> {
+    let listener = UnixListener::bind(socket).map_err(|e| e.to_string())?;
+    let codec = case.codec;
+    let (ack, frames) = (case.ack, case.frames.clone());
+    let server = thread::spawn(move || -> std::io::Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        read(codec, &mut stream)?;
+        codec.write_hello(&mut stream, PROTOCOL_VERSION, &[])?;
+        match ack {
+            Some(ack) => send(codec, &mut stream, ack),
+            None => {
+                let success = codec.encode_response(&BridgeResponse::Success(String::new()))?;
+                send(codec, &mut stream, &success)?;
+                read(codec, &mut stream)?;
+                frames
+                    .iter()
+                    .try_for_each(|frame| send(codec, &mut stream, frame))
+            }
+        }
+    });
+
+    let output = Command::new(andro)
+        .arg("--socket")
+        .arg(socket)
+        .args(case.args)
+        .env_remove("ANDRO_ADDR")
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", andro.display(), e))?;
+    // An andro that never connected leaves the fake server waiting for it.
+    if !server.is_finished() {
+        let _ = UnixStream::connect(socket);
+    }
+    let _ = server.join();
+
+    let printed = [output.stderr, output.stdout].concat();
+    let printed = String::from_utf8_lossy(&printed);
+    if printed.contains("panicked") {
+        return Err(format!("andro panicked:\n{}", printed));
+    }
+    if output.status.code() != Some(70) {
+        return Err(format!("exit status {}:\n{}", output.status, printed));
+    }
+    match case.expect.iter().find(|want| !printed.contains(*want)) {
+        Some(want) => Err(format!("no {:?} in:\n{}", want, printed)),
+        None => Ok(()),
+    }
+}
+
+fn read(codec: Codec, stream: &mut UnixStream) -> std::io::Result<()> {
+    codec.read_frame(stream, MAX_FRAME_LEN)?;
+    Ok(())
+}
+
+// Writes `frame` as is, however little it resembles a response.
+fn send(codec: Codec, stream: &mut UnixStream, frame: &[u8]) -> std::io::Result<()> {
+    match codec {
+        Codec::Bincode => write_frame(stream, frame),
+        Codec::Json => {
+            stream.write_all(frame)?;
+            stream.write_all(b"\n")
+        }
+    }
+}
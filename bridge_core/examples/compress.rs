@@ -0,0 +1,158 @@
+// Sends payloads through `write_compressed_frame` and the frame reader: random bytes, which
+// must go out as plain frames no bigger than before, and text, pixels and runs, which must
+// shrink and come back byte for byte. Corrupt compressed frames must fail as such, never panic
+// or decompress past the reader's limit. Exits 1 on the first failure.
+//
+//   cargo run -p bridge_core --example compress
+
+use std::io::Cursor;
+
+use bridge_core::proto::{
+    COMPRESS_FROM, COMPRESSED, FrameError, read_frame, write_compressed_frame,
+};
+
+const LIMIT: u64 = 16 * 1024 * 1024;
+
+// xorshift64*, so every run checks the same payloads.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+
+    for len in [
+        0,
+        1,
+        100,
+        COMPRESS_FROM - 1,
+        COMPRESS_FROM,
+        65536,
+        1024 * 1024,
+    ] {
+        let payload = rng.bytes(len);
+        let wire = round_trip(&format!("{} random bytes", len), &payload);
+        if wire.len() > payload.len() + 8 || wire[..4] == COMPRESSED {
+            fail(
+                &format!("{} random bytes", len),
+                &format!("sent {} bytes, compressed", wire.len()),
+            );
+        }
+    }
+
+    let mut logcat = String::new();
+    for i in 0..20000 {
+        logcat.push_str(&format!(
+            "10-15 12:00:{:02}.{:03}  1234  5678 I ActivityManager: Start proc {} for service\n",
+            i % 60,
+            i % 1000,
+            i
+        ));
+    }
+    // Rows of a screenshot: flat areas with a gradient running through.
+    let pixels: Vec<u8> = (0..1080 * 400 * 4)
+        .map(|i| {
+            if i % 4 == 3 {
+                255
+            } else {
+                (i / 4 % 1080 / 64) as u8
+            }
+        })
+        .collect();
+    let mut mixed = rng.bytes(4096);
+    mixed.extend_from_within(..);
+    mixed.extend(rng.bytes(100));
+    mixed.extend_from_within(1000..5000);
+    let compressible: [(&str, Vec<u8>); 6] = [
+        ("logcat", logcat.into_bytes()),
+        ("pixels", pixels),
+        ("zeros", vec![0; 1024 * 1024]),
+        ("short period", b"abc".repeat(10000)),
+        ("repeated random block", mixed),
+        ("just above the threshold", vec![b'x'; COMPRESS_FROM]),
+    ];
+    for (name, payload) in &compressible {
+        let wire = round_trip(name, payload);
+        if wire[..4] != COMPRESSED || wire.len() * 2 > payload.len() {
+            fail(
+                name,
+                &format!("{} bytes became {}", payload.len(), wire.len()),
+            );
+        }
+        println!("{}: {} bytes -> {}", name, payload.len(), wire.len());
+    }
+
+    // A frame claiming more than the limit once decompressed.
+    let mut wire = Vec::new();
+    write_compressed_frame(&mut wire, &vec![0; LIMIT as usize + 1]).unwrap();
+    match read_frame(&mut Cursor::new(&wire), LIMIT) {
+        Err(FrameError::TooLarge(e)) if e.len == LIMIT + 1 => {}
+        other => fail("over the limit", &format!("read {:?}", other.map(|_| ()))),
+    }
+
+    // Corrupt blocks: cut short, claiming the wrong length, and random bytes.
+    let (name, payload) = &compressible[0];
+    let mut wire = Vec::new();
+    write_compressed_frame(&mut wire, payload).unwrap();
+    let block = &wire[12..];
+    let original = payload.len() as u32;
+    expect_malformed("cut short", &frame(original, &block[..block.len() / 2]));
+    expect_malformed("length too short", &frame(original - 1, block));
+    expect_malformed("length too long", &frame(original + 1, block));
+    for _ in 0..2000 {
+        let len = (rng.next() % 4096) as usize;
+        let garbage = rng.bytes(len);
+        // Random blocks may happen to be valid; what matters is that none panics.
+        let _ = read_frame(
+            &mut Cursor::new(frame(rng.next() as u32 % 65536, &garbage)),
+            LIMIT,
+        );
+    }
+    println!("{}: corrupt frames rejected", name);
+
+    println!("compress: ok");
+}
+
+// Writes `payload` as a compressed frame, checks that it reads back unchanged, and returns
+// the frame.
+fn round_trip(name: &str, payload: &[u8]) -> Vec<u8> {
+    let mut wire = Vec::new();
+    write_compressed_frame(&mut wire, payload).unwrap();
+    match read_frame(&mut Cursor::new(&wire), LIMIT) {
+        Ok(Some(read)) if read == payload => wire,
+        Ok(Some(read)) => fail(name, &format!("read back {} different bytes", read.len())),
+        other => fail(name, &format!("read {:?}", other.map(|_| ()))),
+    }
+}
+
+// A compressed frame holding `block`, claiming `original` bytes once decompressed.
+fn frame(original: u32, block: &[u8]) -> Vec<u8> {
+    let mut wire = COMPRESSED.to_vec();
+    wire.extend_from_slice(&(block.len() as u32 + 4).to_be_bytes());
+    wire.extend_from_slice(&original.to_be_bytes());
+    wire.extend_from_slice(block);
+    wire
+}
+
+fn expect_malformed(name: &str, wire: &[u8]) {
+    match read_frame(&mut Cursor::new(wire), LIMIT) {
+        Err(FrameError::Malformed(_)) => {}
+        other => fail(name, &format!("read {:?}", other.map(|_| ()))),
+    }
+}
+
+fn fail(name: &str, e: &str) -> ! {
+    println!("{}: FAILED, {}", name, e);
+    std::process::exit(1);
+}
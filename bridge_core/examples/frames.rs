@@ -0,0 +1,151 @@
+// Runs the frame readers of `bridge_core::proto` over byte streams that arrive badly: frames
+// back to back, trickling in a byte at a time with reads interrupted by signals in between,
+// and cut off at every possible offset. Exits 1 on the first reader that gets it wrong.
+//
+//   cargo run -p bridge_core --example frames
+
+use std::io::{self, ErrorKind, Read};
+
+use bridge_core::Codec;
+use bridge_core::proto::{FrameError, write_frame};
+
+const LIMIT: u64 = 1024;
+
+// Hands out `data` one byte per read, failing every other read with Interrupted.
+struct Dribble<'a> {
+    data: &'a [u8],
+    interrupt: bool,
+}
+
+impl Read for Dribble<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(ErrorKind::Interrupted.into());
+        }
+        let Some((&byte, rest)) = self.data.split_first() else {
+            return Ok(0);
+        };
+        buf[0] = byte;
+        self.data = rest;
+        Ok(1)
+    }
+}
+
+// How reading a stream ended: the frames it held, then None for a clean end or the error.
+type Outcome = (Vec<Vec<u8>>, Result<(), String>);
+
+fn read_all(codec: Codec, data: &[u8]) -> Outcome {
+    let mut reader = Dribble {
+        data,
+        interrupt: false,
+    };
+    let mut frames = Vec::new();
+    loop {
+        match codec.read_frame(&mut reader, LIMIT) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => return (frames, Ok(())),
+            Err(FrameError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                return (frames, Err("truncated".to_string()));
+            }
+            Err(e) => return (frames, Err(e.to_string())),
+        }
+    }
+}
+
+fn check(name: &str, got: Outcome, want: Outcome) {
+    if got != want {
+        println!("{}: FAILED\n  got  {:?}\n  want {:?}", name, got, want);
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    // Lengths that cross the limit and the header halves: empty, tiny, exactly at the limit.
+    let payloads: Vec<Vec<u8>> = [0usize, 1, 3, 8, 200, LIMIT as usize]
+        .iter()
+        .map(|&len| (0..len).map(|i| i as u8).collect())
+        .collect();
+
+    let mut wire = Vec::new();
+    let mut ends = vec![0];
+    for payload in &payloads {
+        write_frame(&mut wire, payload).unwrap();
+        ends.push(wire.len());
+    }
+    check(
+        "bincode, back to back",
+        read_all(Codec::Bincode, &wire),
+        (payloads.clone(), Ok(())),
+    );
+    for cut in 0..wire.len() {
+        // Every frame that ends before the cut comes through; a cut inside one is truncation.
+        let complete = ends.iter().filter(|&&end| end <= cut).count() - 1;
+        let ending = match ends.contains(&cut) {
+            true => Ok(()),
+            false => Err("truncated".to_string()),
+        };
+        check(
+            &format!("bincode, cut at byte {}", cut),
+            read_all(Codec::Bincode, &wire[..cut]),
+            (payloads[..complete].to_vec(), ending),
+        );
+    }
+
+    let mut too_large = Vec::new();
+    write_frame(&mut too_large, &vec![0; LIMIT as usize + 1]).unwrap();
+    check(
+        "bincode, over the limit",
+        read_all(Codec::Bincode, &too_large),
+        (
+            Vec::new(),
+            Err(format!(
+                "frame of {} bytes exceeds the {} byte limit",
+                LIMIT + 1,
+                LIMIT
+            )),
+        ),
+    );
+    // A bincode enum tag where the length should be, as builds from before framing sent.
+    let (frames, ending) = read_all(Codec::Bincode, &[7, 0, 0, 0, 1, 2, 3, 4]);
+    if !frames.is_empty() || !ending.as_ref().is_err_and(|e| e.contains("length prefix")) {
+        println!(
+            "bincode, no length prefix: FAILED, read {:?} {:?}",
+            frames, ending
+        );
+        std::process::exit(1);
+    }
+
+    let lines = b"{\"Ping\":null}\n\n  \n{\"Info\":null}\n";
+    let want = vec![b"{\"Ping\":null}".to_vec(), b"{\"Info\":null}".to_vec()];
+    check(
+        "json, back to back",
+        read_all(Codec::Json, lines),
+        (want.clone(), Ok(())),
+    );
+    for cut in 0..lines.len() {
+        let complete = lines[..cut].iter().filter(|&&byte| byte == b'\n').count();
+        let complete = match complete {
+            0 => 0,
+            // The blank lines in between hold no frame.
+            1..=3 => 1,
+            _ => 2,
+        };
+        let rest = &lines[..cut];
+        let after = rest
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(rest, |i| &rest[i + 1..]);
+        let ending = match after.iter().all(u8::is_ascii_whitespace) {
+            true => Ok(()),
+            false => Err("truncated".to_string()),
+        };
+        check(
+            &format!("json, cut at byte {}", cut),
+            read_all(Codec::Json, &lines[..cut]),
+            (want[..complete].to_vec(), ending),
+        );
+    }
+
+    println!("frames: ok");
+}
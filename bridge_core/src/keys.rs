@@ -0,0 +1,255 @@
+// Key names shared by client and server.
+// Android keycodes (`KEYCODE_*`, as used by `input keyevent`) are what users know, but
+// injected events carry Linux input codes; this table maps one to the other following
+// Generic.kl, the default Android key layout.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+pub struct Key {
+    pub name: &'static str,
+    pub android: u16,
+    pub linux: u16,
+}
+
+const fn key(name: &'static str, android: u16, linux: u16) -> Key {
+    Key {
+        name,
+        android,
+        linux,
+    }
+}
+
+pub const KEYS: &[Key] = &[
+    key("HOME", 3, 172),
+    key("BACK", 4, 158),
+    key("CALL", 5, 169),
+    key("0", 7, 11),
+    key("1", 8, 2),
+    key("2", 9, 3),
+    key("3", 10, 4),
+    key("4", 11, 5),
+    key("5", 12, 6),
+    key("6", 13, 7),
+    key("7", 14, 8),
+    key("8", 15, 9),
+    key("9", 16, 10),
+    key("DPAD_UP", 19, 103),
+    key("DPAD_DOWN", 20, 108),
+    key("DPAD_LEFT", 21, 105),
+    key("DPAD_RIGHT", 22, 106),
+    key("DPAD_CENTER", 23, 353),
+    key("VOLUME_UP", 24, 115),
+    key("VOLUME_DOWN", 25, 114),
+    key("POWER", 26, 116),
+    key("CAMERA", 27, 212),
+    key("A", 29, 30),
+    key("B", 30, 48),
+    key("C", 31, 46),
+    key("D", 32, 32),
+    key("E", 33, 18),
+    key("F", 34, 33),
+    key("G", 35, 34),
+    key("H", 36, 35),
+    key("I", 37, 23),
+    key("J", 38, 36),
+    key("K", 39, 37),
+    key("L", 40, 38),
+    key("M", 41, 50),
+    key("N", 42, 49),
+    key("O", 43, 24),
+    key("P", 44, 25),
+    key("Q", 45, 16),
+    key("R", 46, 19),
+    key("S", 47, 31),
+    key("T", 48, 20),
+    key("U", 49, 22),
+    key("V", 50, 47),
+    key("W", 51, 17),
+    key("X", 52, 45),
+    key("Y", 53, 21),
+    key("Z", 54, 44),
+    key("COMMA", 55, 51),
+    key("PERIOD", 56, 52),
+    key("SHIFT_LEFT", 59, 42),
+    key("TAB", 61, 15),
+    key("SPACE", 62, 57),
+    key("ENTER", 66, 28),
+    key("DEL", 67, 14),
+    key("GRAVE", 68, 41),
+    key("MINUS", 69, 12),
+    key("EQUALS", 70, 13),
+    key("LEFT_BRACKET", 71, 26),
+    key("RIGHT_BRACKET", 72, 27),
+    key("BACKSLASH", 73, 43),
+    key("SEMICOLON", 74, 39),
+    key("APOSTROPHE", 75, 40),
+    key("SLASH", 76, 53),
+    key("MENU", 82, 139),
+    key("SEARCH", 84, 217),
+    key("MEDIA_PLAY_PAUSE", 85, 164),
+    key("MEDIA_STOP", 86, 166),
+    key("MEDIA_NEXT", 87, 163),
+    key("MEDIA_PREVIOUS", 88, 165),
+    key("ESCAPE", 111, 1),
+    key("VOLUME_MUTE", 164, 113),
+    key("APP_SWITCH", 187, 580),
+    key("BRIGHTNESS_DOWN", 220, 224),
+    key("BRIGHTNESS_UP", 221, 225),
+    key("SLEEP", 223, 142),
+    key("WAKEUP", 224, 143),
+];
+
+// Finds a key by name (`BACK`, `keycode_back`) or by Android keycode (`4`).
+// Numbers always mean keycodes, so the digit keys are spelled `KEYCODE_0` to `KEYCODE_9`.
+pub fn lookup(key: &str) -> Option<&'static Key> {
+    if let Ok(code) = key.parse::<u16>() {
+        return android(code);
+    }
+    let upper = key.to_ascii_uppercase();
+    let name = upper.strip_prefix("KEYCODE_").unwrap_or(&upper);
+    KEYS.iter().find(|k| k.name == name)
+}
+
+// The key with Android keycode `code`.
+pub fn android(code: u16) -> Option<&'static Key> {
+    KEYS.iter().find(|k| k.android == code)
+}
+
+// An Android keycode, as carried by KeyEvent. Parses from a key name like `lookup` does,
+// but accepts any number: `input keyevent` knows many keys that cannot be injected directly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keycode(pub u16);
+
+impl Keycode {
+    pub const HOME: Keycode = Keycode(3);
+    pub const BACK: Keycode = Keycode(4);
+    pub const VOLUME_UP: Keycode = Keycode(24);
+    pub const VOLUME_DOWN: Keycode = Keycode(25);
+    pub const POWER: Keycode = Keycode(26);
+
+    // The table entry, for keys the server can inject directly.
+    pub fn key(self) -> Option<&'static Key> {
+        android(self.0)
+    }
+}
+
+impl FromStr for Keycode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Keycode, String> {
+        if let Ok(code) = s.parse() {
+            return Ok(Keycode(code));
+        }
+        match lookup(s) {
+            Some(key) => Ok(Keycode(key.android)),
+            None => Err(format!(
+                "unknown key '{}' (use a name like BACK or HOME, or an Android keycode)",
+                s
+            )),
+        }
+    }
+}
+
+// The key name, or the number for keys missing from the table. Digit keys get their
+// KEYCODE_ prefix back, since a bare digit parses as a keycode.
+impl fmt::Display for Keycode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key() {
+            Some(key) if key.name.starts_with(|c: char| c.is_ascii_digit()) => {
+                write!(f, "KEYCODE_{}", key.name)
+            }
+            Some(key) => f.write_str(key.name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+// Unshifted and shifted symbols of a US keyboard layout, by key name.
+const SYMBOLS: &[(char, char, &str)] = &[
+    ('`', '~', "GRAVE"),
+    ('1', '!', "1"),
+    ('2', '@', "2"),
+    ('3', '#', "3"),
+    ('4', '$', "4"),
+    ('5', '%', "5"),
+    ('6', '^', "6"),
+    ('7', '&', "7"),
+    ('8', '*', "8"),
+    ('9', '(', "9"),
+    ('0', ')', "0"),
+    ('-', '_', "MINUS"),
+    ('=', '+', "EQUALS"),
+    ('[', '{', "LEFT_BRACKET"),
+    (']', '}', "RIGHT_BRACKET"),
+    ('\\', '|', "BACKSLASH"),
+    (';', ':', "SEMICOLON"),
+    ('\'', '"', "APOSTROPHE"),
+    (',', '<', "COMMA"),
+    ('.', '>', "PERIOD"),
+    ('/', '?', "SLASH"),
+    (' ', ' ', "SPACE"),
+    ('\t', '\t', "TAB"),
+    ('\n', '\n', "ENTER"),
+];
+
+// Maps a character to the key that types it on a US layout, and whether Shift is needed.
+// Returns None for characters without a key (anything outside printable ASCII).
+pub fn char_key(c: char) -> Option<(&'static Key, bool)> {
+    let by_name = |name: &str| KEYS.iter().find(|k| k.name == name);
+    if c.is_ascii_alphabetic() {
+        let name = c.to_ascii_uppercase().to_string();
+        return by_name(&name).map(|key| (key, c.is_ascii_uppercase()));
+    }
+    SYMBOLS.iter().find_map(|&(plain, shifted, name)| {
+        if c == plain {
+            by_name(name).map(|key| (key, false))
+        } else if c == shifted {
+            by_name(name).map(|key| (key, true))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keycode_parses_names_and_numbers() {
+        assert_eq!("BACK".parse(), Ok(Keycode::BACK));
+        assert_eq!("keycode_home".parse(), Ok(Keycode::HOME));
+        assert_eq!("KEYCODE_0".parse(), Ok(Keycode(7)));
+        // Numbers are keycodes, including ones the table does not know.
+        assert_eq!("7".parse(), Ok(Keycode(7)));
+        assert_eq!("999".parse(), Ok(Keycode(999)));
+        assert!("NOT_A_KEY".parse::<Keycode>().is_err());
+        assert!("-1".parse::<Keycode>().is_err());
+    }
+
+    #[test]
+    fn every_key_displays_as_something_that_parses_back() {
+        for key in KEYS {
+            let keycode = Keycode(key.android);
+            assert_eq!(keycode.to_string().parse(), Ok(keycode), "{}", key.name);
+        }
+        assert_eq!(Keycode(999).to_string(), "999");
+        assert_eq!(Keycode(7).to_string(), "KEYCODE_0");
+    }
+
+    #[test]
+    fn table_has_no_duplicates() {
+        for (i, key) in KEYS.iter().enumerate() {
+            assert!(
+                KEYS[i + 1..]
+                    .iter()
+                    .all(|other| other.name != key.name && other.android != key.android),
+                "{} is listed twice",
+                key.name
+            );
+        }
+    }
+}
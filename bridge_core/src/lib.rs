@@ -1,41 +1,1482 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::{self, IoSlice, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use proto::{FrameError, read_frame, read_line, write_all_vectored, write_frame};
+
+pub mod json;
+pub mod keys;
+#[cfg(feature = "compress")]
+mod lz4;
+pub mod proto;
+pub mod sha256;
+pub mod transport;
+
+// Version of the wire protocol, exchanged in the hello frames and reported by Info.
+// Bump it in the same change as any edit to existing BridgeCommand or BridgeResponse
+// variants or the types they carry: peers built from different versions can silently
+// misread each other's enums. Appending a new command needs no bump, see `Envelope`; a new
+// response does, since older clients cannot decode it.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+// The oldest version still spoken. A connection uses the older of the two peers' versions,
+// and a server talking to an older client answers with the responses that client knows
+// (an Error instead of Busy, FrameTooLarge or SpawnFailed for protocol 4, instead of Failed
+// for 4 and 5, and an Exec's output only in its response before 7). Commands and responses
+// are encoded and decoded for the connection's version, see `Codec::encode_command_for`.
+pub const OLDEST_PROTOCOL_VERSION: u32 = 4;
+
+// Busy, FrameTooLarge and ServerInfo::load came in while the protocol was still 4, so only
+// peers speaking 5 or later are sure to know them.
+pub const BUSY_PROTOCOL_VERSION: u32 = 5;
+
+// Failed, which carries an ErrorKind with the message, came with protocol 6; older peers
+// get an Error with the message alone.
+pub const ERROR_KIND_PROTOCOL_VERSION: u32 = 6;
+
+// From protocol 7, an Exec with more output than the server holds sends the excess ahead of
+// its response in StdoutChunk and StderrChunk frames instead of failing.
+pub const SPILL_PROTOCOL_VERSION: u32 = 7;
+
+// The version a connection speaks once `ours` has received `theirs`, or None if no version
+// both peers speak exists.
+pub fn negotiate(ours: u32, theirs: u32) -> Option<u32> {
+    let version = ours.min(theirs);
+    (version >= OLDEST_PROTOCOL_VERSION).then_some(version)
+}
+
+// Every connection opens with a hello frame from each side, the client's first:
+// HELLO_MAGIC, the sender's PROTOCOL_VERSION (big-endian u32), and for the client the
+// server's auth token if it has one (empty otherwise). The server's hello carries the
+// version the connection will speak instead, see `negotiate`. The server then answers
+// Success("") to accept the connection, or Error to refuse it.
+// Builds from before the handshake send no hello; they speak protocol 1.
+pub const HELLO_MAGIC: [u8; 4] = *b"NBrg";
+pub const PRE_HANDSHAKE_VERSION: u32 = 1;
+
+// Capabilities a client announces in the upper half of its hello's version (bincode only).
+// Servers that know none of them still negotiate the right version, since they take the
+// smaller of the two. Split off with VERSION_MASK before negotiating.
+pub const VERSION_MASK: u32 = 0xffff;
+// The client reads compressed frames (`proto::COMPRESSED`), so the server may send them.
+pub const READS_COMPRESSED: u32 = 1 << 16;
+
+pub fn write_hello<W: Write + ?Sized>(
+    writer: &mut W,
+    version: u32,
+    token: &[u8],
+) -> io::Result<()> {
+    let mut hello = HELLO_MAGIC.to_vec();
+    hello.extend_from_slice(&version.to_be_bytes());
+    hello.extend_from_slice(token);
+    write_frame(writer, &hello)
+}
+
+// The protocol version and token of a hello frame, or None if `frame` is not one.
+pub fn parse_hello(frame: &[u8]) -> Option<(u32, &[u8])> {
+    let rest = frame.strip_prefix(&HELLO_MAGIC)?;
+    let (version, token) = rest.split_first_chunk::<4>()?;
+    Some((u32::from_be_bytes(*version), token))
+}
+
+// Explains a version mismatch, naming the side that needs updating.
+pub fn protocol_mismatch(server: u32, client: u32) -> String {
+    let outdated = if server < client {
+        "bridge_server"
+    } else {
+        "bridge_client"
+    };
+    format!(
+        "server speaks protocol {}, client speaks {}, please update {}",
+        server, client, outdated
+    )
+}
+
+// A socket given as `@name` lives in the Linux abstract namespace instead of the filesystem.
+// Returns the name without the `@`, or None for a regular path.
+pub fn abstract_name(socket: &Path) -> Option<&[u8]> {
+    socket.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+// Commands travel in an envelope naming their kind (the BridgeCommand discriminant) next to
+// the encoded fields, so a server can tell a command newer than itself from a corrupt
+// payload and answer Unsupported instead.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    pub kind: u32,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    // A command this build does not know, from a newer peer.
+    Unsupported(u32),
+    Invalid(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::Unsupported(kind) => write!(f, "unknown command kind {}", kind),
+            DecodeError::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+// The payload of a command frame.
+pub fn encode_command(cmd: &BridgeCommand) -> io::Result<Vec<u8>> {
+    let bytes = bincode::serialize(cmd).map_err(unencodable)?;
+    // bincode leads with the variant index as a little-endian u32.
+    let (tag, body) = bytes.split_at(4);
+    let envelope = Envelope {
+        kind: u32::from_le_bytes(tag.try_into().unwrap()),
+        body: body.to_vec(),
+    };
+    bincode::serialize(&envelope).map_err(unencodable)
+}
+
+// Encoding only fails on values the wire format cannot carry, a bug rather than bad input;
+// it is still reported like a failed write rather than a panic.
+fn unencodable(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("cannot encode the frame: {}", e),
+    )
+}
+
+pub fn decode_command(payload: &[u8]) -> Result<BridgeCommand, DecodeError> {
+    let invalid = |e: bincode::Error| DecodeError::Invalid(e.to_string());
+    let envelope: Envelope = bincode::deserialize(payload).map_err(invalid)?;
+    if envelope.kind as usize >= COMMAND_NAMES.len() {
+        return Err(DecodeError::Unsupported(envelope.kind));
+    }
+    // The command inside a Tagged one (after its u64 id) may be the newer of the two.
+    if COMMAND_NAMES[envelope.kind as usize] == "Tagged"
+        && let Some(inner) = envelope.body.get(8..12)
+    {
+        let inner = u32::from_le_bytes(inner.try_into().unwrap());
+        if inner as usize >= COMMAND_NAMES.len() {
+            return Err(DecodeError::Unsupported(inner));
+        }
+    }
+    let mut bytes = envelope.kind.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&envelope.body);
+    bincode::deserialize(&bytes).map_err(invalid)
+}
+
+thread_local! {
+    // The protocol version messages are encoded and decoded for on this thread, while one of
+    // the `Codec::*_for` methods runs. Fields added to an existing type only go on the wire
+    // from the version that added them, see `ProcessOptions::limits` and `ServerInfo::load`.
+    static PEER_VERSION: Cell<u32> = const { Cell::new(PROTOCOL_VERSION) };
+}
+
+fn for_version<T>(version: u32, f: impl FnOnce() -> T) -> T {
+    let previous = PEER_VERSION.replace(version);
+    let result = f();
+    PEER_VERSION.set(previous);
+    result
+}
+
+// How messages are encoded on a connection. Bincode frames are the native format. A JSON
+// connection carries one JSON value per line instead (see `json`), so scripts can talk to the
+// server without a bincode implementation; the server recognizes one by its opening `{`.
+// JSON commands name their variant and need no Envelope; an unknown one is just invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Bincode,
+    Json,
+}
+
+// The hello of a JSON connection, e.g. `{"protocol":4,"token":"..."}`.
+#[derive(Serialize, Deserialize)]
+struct JsonHello {
+    protocol: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    token: String,
+}
+
+impl Codec {
+    pub fn write_frame<W: Write + ?Sized>(self, writer: &mut W, payload: &[u8]) -> io::Result<()> {
+        match self {
+            Codec::Bincode => write_frame(writer, payload),
+            Codec::Json => {
+                write_all_vectored(writer, &mut [IoSlice::new(payload), IoSlice::new(b"\n")])?;
+                writer.flush()
+            }
+        }
+    }
+
+    // None once the peer has closed the connection between frames; see `proto`.
+    pub fn read_frame<R: Read + ?Sized>(
+        self,
+        reader: &mut R,
+        max_len: u64,
+    ) -> Result<Option<Vec<u8>>, FrameError> {
+        match self {
+            Codec::Bincode => read_frame(reader, max_len),
+            Codec::Json => read_line(reader, max_len),
+        }
+    }
+
+    pub fn write_hello<W: Write + ?Sized>(
+        self,
+        writer: &mut W,
+        version: u32,
+        token: &[u8],
+    ) -> io::Result<()> {
+        match self {
+            Codec::Bincode => write_hello(writer, version, token),
+            Codec::Json => {
+                let hello = JsonHello {
+                    protocol: version,
+                    token: String::from_utf8_lossy(token).into_owned(),
+                };
+                self.write_frame(
+                    writer,
+                    json::to_string(&hello).map_err(unencodable)?.as_bytes(),
+                )
+            }
+        }
+    }
+
+    // Like `parse_hello`, for either codec.
+    pub fn parse_hello(self, frame: &[u8]) -> Option<(u32, Vec<u8>)> {
+        match self {
+            Codec::Bincode => parse_hello(frame).map(|(version, token)| (version, token.to_vec())),
+            Codec::Json => json::from_slice::<JsonHello>(frame)
+                .ok()
+                .map(|hello| (hello.protocol, hello.token.into_bytes())),
+        }
+    }
+
+    pub fn encode_command(self, cmd: &BridgeCommand) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Bincode => encode_command(cmd),
+            Codec::Json => Ok(json::to_string(cmd).map_err(unencodable)?.into_bytes()),
+        }
+    }
+
+    pub fn decode_command(self, payload: &[u8]) -> Result<BridgeCommand, DecodeError> {
+        match self {
+            Codec::Bincode => decode_command(payload),
+            Codec::Json => {
+                json::from_slice(payload).map_err(|e| DecodeError::Invalid(e.to_string()))
+            }
+        }
+    }
+
+    // Like `encode_command`, for a peer speaking protocol `version`: fields it does not know
+    // are left off, and a command that sets one fails to encode rather than lose it.
+    pub fn encode_command_for(self, cmd: &BridgeCommand, version: u32) -> io::Result<Vec<u8>> {
+        for_version(version, || self.encode_command(cmd))
+    }
+
+    // Like `decode_command`, for a peer speaking protocol `version`. JSON needs no version:
+    // fields a peer leaves out read as their default.
+    pub fn decode_command_for(
+        self,
+        payload: &[u8],
+        version: u32,
+    ) -> Result<BridgeCommand, DecodeError> {
+        match self {
+            Codec::Bincode => for_version(version, || decode_command(payload)),
+            Codec::Json => self.decode_command(payload),
+        }
+    }
+
+    pub fn encode_response(self, response: &BridgeResponse) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Bincode => bincode::serialize(response).map_err(unencodable),
+            Codec::Json => Ok(json::to_string(response).map_err(unencodable)?.into_bytes()),
+        }
+    }
+
+    pub fn decode_response(self, payload: &[u8]) -> Result<BridgeResponse, String> {
+        match self {
+            Codec::Bincode => bincode::deserialize(payload).map_err(|e| e.to_string()),
+            Codec::Json => json::from_slice(payload).map_err(|e| e.to_string()),
+        }
+    }
+
+    // Like `encode_response`, for a peer speaking protocol `version`: fields it does not
+    // know are left off. Responses it does not know at all are the caller's to replace.
+    pub fn encode_response_for(
+        self,
+        response: &BridgeResponse,
+        version: u32,
+    ) -> io::Result<Vec<u8>> {
+        for_version(version, || self.encode_response(response))
+    }
+
+    // Like `decode_response`, for a peer speaking protocol `version`; as for commands, JSON
+    // needs no version.
+    pub fn decode_response_for(
+        self,
+        payload: &[u8],
+        version: u32,
+    ) -> Result<BridgeResponse, String> {
+        match self {
+            Codec::Bincode => for_version(version, || self.decode_response(payload)),
+            Codec::Json => self.decode_response(payload),
+        }
+    }
+}
+
+// Settings shared by every command that spawns a host process.
+// The default value keeps the server's previous behavior.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ProcessOptions {
+    // Kill the process (group) if it is still running after this many milliseconds.
+    // None or zero means no timeout.
+    pub timeout_ms: Option<u64>,
+    // Extra environment variables, applied on top of the server's environment.
+    pub env: Vec<(String, String)>,
+    // Working directory on the host; None runs in the server's own directory.
+    pub cwd: Option<String>,
+    // Credentials to switch to before exec; None keeps the server's (normally root).
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    // Resource limits for the process (protocol 8). Peers that speak an older protocol are
+    // sent no limits field, and a command with limits fails to encode for them.
+    #[serde(
+        default,
+        skip_serializing_if = "Limits::left_off",
+        serialize_with = "Limits::serialize_for_peer",
+        deserialize_with = "Limits::deserialize_for_peer"
+    )]
+    pub limits: Limits,
+}
+
+// The protocol version that added ProcessOptions::limits.
+pub const LIMITS_PROTOCOL_VERSION: u32 = 8;
+
+// Resource limits the server applies in the child before it runs the program, so a runaway
+// command cannot starve the phone. None leaves a limit as the server's own. A limit that is
+// invalid or cannot be applied fails the request before anything is spawned.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    // Niceness to run at, from -20 (most favorable) to 19; below zero needs root.
+    pub nice: Option<i32>,
+    // CPUs the process may run on, bit N for CPU N.
+    pub cpus: Option<u64>,
+    // Largest address space in bytes (RLIMIT_AS); allocations past it fail.
+    pub max_mem: Option<u64>,
+    // Largest file the process may write in bytes (RLIMIT_FSIZE).
+    pub max_file_size: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    fn left_off(&self) -> bool {
+        PEER_VERSION.get() < LIMITS_PROTOCOL_VERSION && self.is_empty()
+    }
+
+    fn serialize_for_peer<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let version = PEER_VERSION.get();
+        if version < LIMITS_PROTOCOL_VERSION {
+            return Err(serde::ser::Error::custom(format!(
+                "resource limits need protocol {}, the server speaks {}, please update bridge_server",
+                LIMITS_PROTOCOL_VERSION, version
+            )));
+        }
+        self.serialize(serializer)
+    }
+
+    fn deserialize_for_peer<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Limits, D::Error> {
+        if PEER_VERSION.get() < LIMITS_PROTOCOL_VERSION {
+            return Ok(Limits::default());
+        }
+        Limits::deserialize(deserializer)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum BridgeCommand {
     // Generic command to execute any Android Host binary program
     // program: binary name (e.g., "input", "am", "pm", "ls")
     // args: list of arguments
+    // stdin: when true, the client follows up with StdinChunk frames and a final StdinEnd
+    // options: how the process is spawned on the host
     Exec {
         program: String,
         args: Vec<String>,
+        stdin: bool,
+        options: ProcessOptions,
     },
 
     // Command to run a long-running process and stream its output
+    // stdin: same as for Exec; input frames may keep arriving while output is streamed
     Stream {
         program: String,
         args: Vec<String>,
+        stdin: bool,
+        options: ProcessOptions,
     },
 
     Ping,
     // Direct input commands for low-latency interaction with kernel events.
     // This feature requires the "direct_input" flag during compilation.
+    // dry_run: write nothing, answer with the events that would have been written
     DirectTap {
+        at: Point,
+        dry_run: bool,
+    },
+    DirectSwipe {
+        from: Point,
+        to: Point,
+        duration_ms: u64,
+        easing: Easing,
+        // Time between move events; the server enforces a 2 ms minimum.
+        step_ms: u64,
+        dry_run: bool,
+    },
+
+    // Input for a running command, sent on the same connection after the command itself.
+    // StdinEnd closes the remote stdin so the child sees EOF.
+    StdinChunk(#[serde(with = "json::bytes")] Vec<u8>),
+    StdinEnd,
+
+    // Run a program attached to a pseudo-terminal of the given size.
+    // Input is sent as StdinChunk frames, raw output comes back as PtyOutput.
+    ExecPty {
+        program: String,
+        args: Vec<String>,
+        cols: u16,
+        rows: u16,
+    },
+    // Window size change for a running ExecPty session
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+
+    // Upload a file to the host. Once the server answers TransferReady, the client sends
+    // the contents (from the returned offset on) as FileChunk frames followed by FileEnd.
+    // mode: permission bits of the created file (e.g. 0o644)
+    // resume: append to the partial file left by an interrupted push instead of restarting
+    // verify: have the server report the SHA-256 of the finished file
+    PushFile {
+        dest: String,
+        mode: u32,
+        resume: bool,
+        verify: bool,
+    },
+    FileChunk(#[serde(with = "json::bytes")] Vec<u8>),
+    FileEnd,
+
+    // Download a file from the host. The server answers FileHeader, then FileChunk frames
+    // and FileEnd, or Error at any point.
+    // recursive: pull a directory as a tar stream instead of a single file
+    // offset: skip this many bytes, to resume an interrupted pull (ignored with recursive)
+    // verify: include the SHA-256 of the whole file in FileEnd
+    PullFile {
+        src: String,
+        recursive: bool,
+        offset: u64,
+        verify: bool,
+    },
+
+    // Read or replace the text on the host clipboard. ClipboardGet answers Success(text).
+    ClipboardGet,
+    ClipboardSet(String),
+
+    // Capture the screen. The image comes back like a PullFile: FileHeader, FileChunk
+    // frames and FileEnd, or Error.
+    Screenshot {
+        format: ImageFormat,
+    },
+    // Capture frames continuously, answered by ScreenFrame messages and ScreenStreamEnd.
+    // fps: target capture rate, 0 captures as fast as possible
+    // max_frames: stop after this many delivered frames; None runs until the client leaves
+    ScreenStream {
+        format: ImageFormat,
+        fps: u32,
+        max_frames: Option<u64>,
+    },
+
+    // Press and release a key. keycode is a Linux input code, see `keys::KEYS`.
+    // long_press: hold the key for the server's configured long press duration
+    DirectKey {
+        keycode: u16,
+        long_press: bool,
+    },
+    // Type text as key events, pausing delay_ms between keys for apps that drop fast input.
+    DirectText {
+        text: String,
+        delay_ms: u64,
+    },
+    DirectLongPress {
         x: i32,
         y: i32,
+        duration_ms: u64,
     },
-    DirectSwipe {
+    // Touch down, hold, move slowly, hold again, release; unlike a swipe this never flings.
+    DirectDrag {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        hold_before_ms: u64,
+        move_duration_ms: u64,
+        hold_after_ms: u64,
+    },
+    // Two contacts moving symmetrically around (cx, cy), from start_dist to end_dist pixels
+    // apart. end_dist > start_dist zooms in, end_dist < start_dist zooms out.
+    DirectPinch {
+        cx: i32,
+        cy: i32,
+        start_dist: i32,
+        end_dist: i32,
+        duration_ms: u64,
+    },
+    // Describe the input devices the server injects into; answered by Success(text).
+    InputInfo,
+
+    // Raw touch contacts, for gestures the commands above cannot express. Each is answered
+    // like a regular request, and the connection stays open for further touch commands.
+    // Contacts still down when the connection closes are lifted by the server.
+    DirectTouchDown {
+        slot: u8,
+        x: Coord,
+        y: Coord,
+    },
+    DirectTouchMove {
+        slot: u8,
+        x: Coord,
+        y: Coord,
+    },
+    DirectTouchUp {
+        slot: u8,
+    },
+
+    // Play a recorded gesture: touch down at the first point, move through the others and
+    // lift at the last one.
+    DirectPath {
+        gesture: Gesture,
+    },
+
+    // Stream the raw events of an input device as InputEvents responses, one per
+    // SYN_REPORT frame, until the client disconnects.
+    RecordInput {
+        source: InputSource,
+    },
+    // Write recorded events back to the device with their original timing, sped up by
+    // `speed` (2.0 plays twice as fast). Contacts and keys still down at the end are released.
+    DirectReplay {
+        source: InputSource,
+        events: Vec<RecordedEvent>,
+        speed: f32,
+    },
+
+    // Press a key by Android keycode (e.g. 4 for BACK). Servers built with direct input
+    // inject it into the key device; others, or when that device is unusable, run
+    // `input keyevent`. Works with every server build, unlike DirectKey.
+    KeyEvent {
+        keycode: keys::Keycode,
+        long_press: bool,
+    },
+
+    // Relative pointer input through a virtual mouse the server creates on first use.
+    // DirectScroll turns the wheel by dy notches, positive scrolls up.
+    DirectMouseMove {
+        dx: i32,
+        dy: i32,
+    },
+    DirectMouseClick {
+        button: MouseButton,
+    },
+    DirectScroll {
+        dy: i32,
+    },
+
+    // Several taps on one device handle, each (x, y, ms to wait before it). If a tap fails,
+    // the Error says how many were delivered, so the caller can resume after them.
+    DirectTapBatch {
+        taps: Vec<(i32, i32, u64)>,
+    },
+
+    // A swipe with several fingers moving in lockstep, `spacing` pixels apart on a line at
+    // a right angle to the motion. Limited to the contacts the touch device supports.
+    DirectMultiSwipe {
+        fingers: u8,
         x1: i32,
         y1: i32,
         x2: i32,
         y2: i32,
+        spacing: i32,
         duration_ms: u64,
     },
+
+    // Display rotation used to map input coordinates onto the panel: 0 natural, 1 90°,
+    // 2 180°, 3 270° (Android's Surface.ROTATION_*). GetRotation answers Success("N\n").
+    SetRotation(u8),
+    GetRotation,
+
+    // Stream the events of an input device (the touch screen unless `device` names one) as
+    // StdoutChunk frames until the client disconnects: one decoded line per event plus the
+    // contact positions after each SYN_REPORT, or the raw input_event records with `raw`.
+    MonitorInput {
+        device: Option<String>,
+        raw: bool,
+    },
+
+    // Describe the server: version, features and the commands it handles. Answered by
+    // ServerInfo; servers predating it answer with an Error.
+    Info,
+
+    // Run several single-response commands in order on one connection, answered by a Batch
+    // holding one response each. With `stop_on_error` the first failing command ends the
+    // batch. Commands that stream (Stream, PushFile, ...) and nested batches are refused.
+    Batch {
+        commands: Vec<BridgeCommand>,
+        stop_on_error: bool,
+    },
+    // Wait on the server, so the timing between the steps of a batch is kept exactly.
+    Sleep {
+        ms: u64,
+    },
+    // A command with a client-assigned id. The first Tagged command turns the connection
+    // into a multiplexed one: from then on every frame in both directions is Tagged, several
+    // requests run at once, and each response carries the id of its request. Follow-up
+    // frames (StdinChunk, StdinEnd) are Tagged with the id of the request they belong to.
+    // Only Exec, Stream and commands with a single response can be multiplexed; an id that
+    // is still in flight is refused.
+    Tagged {
+        request_id: u64,
+        command: Box<BridgeCommand>,
+    },
+    // Stop the process of an Exec or Stream: SIGTERM, then SIGKILL if it is still running
+    // after a grace period. On a multiplexed connection it names the request; sent as a
+    // follow-up frame of a Stream or an Exec with stdin, it stops that command and
+    // `request_id` is ignored. A cancelled Stream ends with StreamEnd carrying the signal,
+    // a cancelled Exec with an Error. Clients that disconnect are cancelled the same way.
+    Cancel {
+        request_id: u64,
+    },
+
+    // Exec that answers with CompletedBytes: stdout exactly as the program wrote it, for
+    // binary output (`cat image.png`). Completed would have to make it valid UTF-8.
+    ExecBytes {
+        program: String,
+        args: Vec<String>,
+        stdin: bool,
+        options: ProcessOptions,
+    },
+
+    // List the processes the server has started and not yet reaped, answered by Jobs.
+    ListJobs,
+
+    // Start a program in the background, answered by JobStarted. It is not tied to the
+    // connection: it keeps running after the client goes away, with its output kept on the
+    // server (the last --job-output-limit bytes of it) for JobOutput.
+    JobStart {
+        program: String,
+        args: Vec<String>,
+        options: ProcessOptions,
+    },
+    // The background jobs, running and recently finished, answered by BackgroundJobs.
+    JobList,
+    // A job's buffered output as StdoutChunk and StderrChunk frames, ending with StreamEnd:
+    // the job's exit status once it has finished, no status if it is still running. With
+    // `follow` the output goes on as the job writes it, until it exits.
+    JobOutput {
+        id: u64,
+        follow: bool,
+    },
+    // Send `signal` to the process group of a running job, answered by Success.
+    JobKill {
+        id: u64,
+        signal: i32,
+    },
+
+    // Start a persistent shell on the host (`shell`, or sh), answered by SessionOpened. Like a
+    // job it belongs to no connection; a session left idle for --session-ttl is closed.
+    SessionOpen {
+        shell: Option<String>,
+    },
+    // Run a line in a session as if it was typed at its prompt: cd, variables and functions
+    // carry over to the next line. Output comes as StdoutChunk and StderrChunk frames, then
+    // StreamEnd with the line's exit status, after which the connection takes more commands.
+    // If the shell exits instead, `exit` in the line for instance, SessionEnded carries its
+    // status and the session is gone. A client that disconnects while its line runs closes
+    // the session.
+    SessionExec {
+        id: u64,
+        line: String,
+    },
+    // Send `signal` to the processes a session runs, except the shell itself: Ctrl+C at an
+    // `andro session` prompt. Answered by Success.
+    SessionSignal {
+        id: u64,
+        signal: i32,
+    },
+    // End a session and stop what runs in it, answered by Success.
+    SessionClose {
+        id: u64,
+    },
+
+    // Send `signal` to a process the server runs without stopping it, e.g. SIGHUP to have a
+    // daemon reload, answered by Success. Job and request targets get it for their whole
+    // process group; a raw pid needs a server started with --allow-raw-pid-signals.
+    Signal {
+        target: SignalTarget,
+        signal: i32,
+    },
+
+    // A Stream of `logcat` with `args`, limited to the running app `package`: the server
+    // looks up its pid and adds `--pid`, answering NotFound if the app is not running.
+    Logcat {
+        package: String,
+        args: Vec<String>,
+        options: ProcessOptions,
+    },
+    // What the device is: screen, density, rotation, Android version, model, battery and
+    // uptime, answered by DeviceInfo.
+    DeviceInfo,
+
+    // System properties. GetProp answers Property, or NotFound if it is not set; ListProps
+    // answers Properties with every property whose name starts with `prefix`.
+    GetProp {
+        name: String,
+    },
+    ListProps {
+        prefix: Option<String>,
+    },
+    // Answered by Success once the property reads back as `value`; a read-only property, or
+    // one SELinux keeps this server from setting, fails with PermissionDenied.
+    SetProp {
+        name: String,
+        value: String,
+    },
+}
+
+// Names of the BridgeCommand variants, indexed by discriminant (their bincode tag).
+// New variants are appended to both.
+pub const COMMAND_NAMES: &[&str] = &[
+    "Exec",
+    "Stream",
+    "Ping",
+    "DirectTap",
+    "DirectSwipe",
+    "StdinChunk",
+    "StdinEnd",
+    "ExecPty",
+    "Resize",
+    "PushFile",
+    "FileChunk",
+    "FileEnd",
+    "PullFile",
+    "ClipboardGet",
+    "ClipboardSet",
+    "Screenshot",
+    "ScreenStream",
+    "DirectKey",
+    "DirectText",
+    "DirectLongPress",
+    "DirectDrag",
+    "DirectPinch",
+    "InputInfo",
+    "DirectTouchDown",
+    "DirectTouchMove",
+    "DirectTouchUp",
+    "DirectPath",
+    "RecordInput",
+    "DirectReplay",
+    "KeyEvent",
+    "DirectMouseMove",
+    "DirectMouseClick",
+    "DirectScroll",
+    "DirectTapBatch",
+    "DirectMultiSwipe",
+    "SetRotation",
+    "GetRotation",
+    "MonitorInput",
+    "Info",
+    "Batch",
+    "Sleep",
+    "Tagged",
+    "Cancel",
+    "ExecBytes",
+    "ListJobs",
+    "JobStart",
+    "JobList",
+    "JobOutput",
+    "JobKill",
+    "SessionOpen",
+    "SessionExec",
+    "SessionSignal",
+    "SessionClose",
+    "Signal",
+    "Logcat",
+    "DeviceInfo",
+    "GetProp",
+    "ListProps",
+    "SetProp",
+];
+
+// Commands only servers built with the "direct_input" feature handle.
+pub const DIRECT_INPUT_COMMANDS: &[&str] = &[
+    "DirectTap",
+    "DirectSwipe",
+    "DirectKey",
+    "DirectText",
+    "DirectLongPress",
+    "DirectDrag",
+    "DirectPinch",
+    "InputInfo",
+    "DirectTouchDown",
+    "DirectTouchMove",
+    "DirectTouchUp",
+    "DirectPath",
+    "RecordInput",
+    "DirectReplay",
+    "DirectMouseMove",
+    "DirectMouseClick",
+    "DirectScroll",
+    "DirectTapBatch",
+    "DirectMultiSwipe",
+    "SetRotation",
+    "GetRotation",
+    "MonitorInput",
+];
+
+impl BridgeCommand {
+    // The variant's bincode tag, which leads every serialized command.
+    pub fn discriminant(&self) -> u32 {
+        let bytes = bincode::serialize(self).expect("commands always serialize");
+        u32::from_le_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    pub fn name(&self) -> &'static str {
+        COMMAND_NAMES
+            .get(self.discriminant() as usize)
+            .copied()
+            .unwrap_or("unknown")
+    }
+}
+
+// What a server reports about itself in answer to Info.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerInfo {
+    pub version: String,
+    pub protocol: u32,
+    // Enabled cargo features, e.g. "direct_input", and protocol capabilities such as
+    // "persistent" (a connection carries one command after another until it is closed).
+    pub features: Vec<String>,
+    // The touch device input is injected into, if any.
+    pub touch_device: Option<String>,
+    // Discriminants of the commands the server handles as requests.
+    pub commands: Vec<u32>,
+    // Left off for peers speaking protocol 4, see BUSY_PROTOCOL_VERSION. A protocol 4 server
+    // may still send it; bincode ignores the bytes left over after a response.
+    #[serde(
+        default,
+        skip_serializing_if = "Load::left_off",
+        deserialize_with = "Load::deserialize_for_peer"
+    )]
+    pub load: Load,
+}
+
+// The answer to DeviceInfo. Each field comes from a probe of its own (getprop, wm, dumpsys,
+// /proc/uptime) and is None if that probe failed, e.g. battery on a device without one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DeviceInfo {
+    // ro.product.manufacturer and ro.product.model.
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    // ro.build.version.release, e.g. "14".
+    pub android_version: Option<String>,
+    pub sdk: Option<u32>,
+    // The panel's size in pixels in its natural orientation, and the size set with
+    // `wm size` if it differs.
+    pub screen: Option<ScreenSize>,
+    pub screen_override: Option<ScreenSize>,
+    // Dots per inch, and the one set with `wm density` if it differs.
+    pub density: Option<u32>,
+    pub density_override: Option<u32>,
+    // 0 natural, 1 90°, 2 180°, 3 270°.
+    pub rotation: Option<u8>,
+    pub battery: Option<Battery>,
+    pub uptime_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ScreenSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Battery {
+    // Percent.
+    pub level: u8,
+    pub charging: bool,
+    // What it is plugged into: "ac", "usb", "wireless" or "dock"; None on battery.
+    pub plugged: Option<String>,
+}
+
+// How busy the server is, against its limits; a limit of 0 means there is none.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Load {
+    pub connections: u32,
+    pub max_connections: u32,
+    // Host processes running, and commands waiting for one to exit before they can start.
+    pub jobs: u32,
+    pub max_jobs: u32,
+    pub queued: u32,
+}
+
+impl Load {
+    fn left_off(&self) -> bool {
+        PEER_VERSION.get() < BUSY_PROTOCOL_VERSION
+    }
+
+    fn deserialize_for_peer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Load, D::Error> {
+        if PEER_VERSION.get() < BUSY_PROTOCOL_VERSION {
+            return Ok(Load::default());
+        }
+        Load::deserialize(deserializer)
+    }
+}
+
+// A process started by the server for a client (Exec, Stream, ExecPty, a file transfer).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub pid: u32,
+    // Program and arguments, redacted to the program with --log-redact-args.
+    pub command: Vec<String>,
+    // Unix epoch.
+    pub started_ms: u64,
+    // The server's id of the connection it was started for, as in its log.
+    pub connection: Option<u64>,
+    // The connection has closed and the process is being stopped.
+    pub orphaned: bool,
+}
+
+// A job started with JobStart.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackgroundJob {
+    pub id: u64,
+    pub pid: u32,
+    pub command: Vec<String>,
+    pub state: JobState,
+    // Unix epoch.
+    pub started_ms: u64,
+    // Until now, or until it exited.
+    pub runtime_ms: u64,
+    // Output bytes that no longer fit the buffer and were discarded, oldest first.
+    pub dropped: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Exited { code: i32 },
+    Killed { signal: i32 },
+}
+
+// What a Signal is sent to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SignalTarget {
+    // A background job, by the id JobStart answered with.
+    Job(u64),
+    // A Tagged request in flight on the same multiplexed connection.
+    Request(u64),
+    // Any process on the host, by pid.
+    Pid(u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+// The device an input recording is taken from and replayed to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InputSource {
+    Touch,
+    Keys,
+}
+
+// One raw evdev event of a recording; offset_us is the time since the recording started.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RecordedEvent {
+    pub offset_us: u64,
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+// A screen coordinate for tap and swipe, either absolute or relative to the screen size.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Coord {
+    Pixels(i32),
+    // 0.0 is the first pixel, 1.0 the last one; the server resolves it against the screen.
+    Fraction(f32),
+}
+
+// A point on the screen, in pixels or shares of the screen size per axis.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Point {
+    pub x: Coord,
+    pub y: Coord,
+}
+
+// A touch path in pixels. Each point is (x, y, ms since the start of the gesture).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Gesture {
+    pub points: Vec<(i32, i32, u64)>,
+}
+
+impl Gesture {
+    // Checks that the gesture can be played: at least one point, strictly increasing times.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.points.is_empty() {
+            return Err("the path has no points".to_string());
+        }
+        match self.points.windows(2).find(|pair| pair[1].2 <= pair[0].2) {
+            Some(pair) => Err(format!(
+                "timestamps must increase, but {} ms is followed by {} ms",
+                pair[0].2, pair[1].2
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// How a swipe's position progresses over its duration.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Easing {
+    Linear,    // Constant speed
+    EaseInOut, // Accelerates, then slows down before the end, like a finger
+    Overshoot, // Passes the target slightly and settles back onto it
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ImageFormat {
+    Png,
+    // quality: 1 (smallest) to 100 (best)
+    Jpeg { quality: u8 },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum BridgeResponse {
-    Success(String),     // Contains stdout for non-streaming commands
-    Error(String),       // Contains stderr or general error messages
-    StreamChunk(String), // One chunk of the stream output
-    StreamEnd,           // Signals that streaming has finished
+    Success(String), // Contains stdout for non-streaming commands
+    Error(String),   // Contains stderr or general error messages
+    // One line of stream output from servers predating StdoutChunk/StderrChunk, with stderr
+    // lines prefixed by "[STDERR] ". Clients still accept it; it will be removed next release.
+    #[deprecated(note = "servers send StdoutChunk/StderrChunk instead")]
+    StreamChunk(String),
+    // Signals that streaming has finished, with the child's exit code or, if it was
+    // killed, the terminating signal. Both are None when no exit status is available.
+    StreamEnd {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    PtyOutput(#[serde(with = "json::bytes")] Vec<u8>), // Raw terminal output of an ExecPty session
+    // Result of a finished Exec: the output plus the exit code. From protocol 7, output that
+    // outgrows the server's --exec-buffer all comes ahead of it in StdoutChunk and
+    // StderrChunk frames instead, leaving these fields empty (CompletedBytes and TimedOut too).
+    // A child killed by a signal reports 128 + signal number, like a shell would.
+    Completed {
+        stdout: String,
+        stderr: String,
+        code: i32,
+    },
+    StdoutChunk(#[serde(with = "json::bytes")] Vec<u8>), // Output of a streamed command, tagged by the pipe it came from
+    StderrChunk(#[serde(with = "json::bytes")] Vec<u8>),
+    // The command hit its timeout and was killed. Carries the output captured up to then;
+    // for streams that output has already been sent, so both fields are empty.
+    TimedOut {
+        timeout_ms: u64,
+        stdout: String,
+        stderr: String,
+    },
+    // The server is ready to receive the data of a PushFile, starting at `offset`
+    // (non-zero only when resuming).
+    TransferReady {
+        offset: u64,
+    },
+    // A push completed; `bytes` is the final file size, `sha256` is set if verify was asked for.
+    Transferred {
+        bytes: u64,
+        sha256: Option<String>,
+    },
+    // Start of a PullFile; size is the full file size, or None when it is not known up front
+    // (e.g. tar streams).
+    FileHeader {
+        size: Option<u64>,
+    },
+    FileChunk(#[serde(with = "json::bytes")] Vec<u8>),
+    FileEnd {
+        sha256: Option<String>,
+    },
+    // One captured image of a ScreenStream; timestamp_ms is the capture time (Unix epoch).
+    ScreenFrame {
+        index: u64,
+        timestamp_ms: u64,
+        #[serde(with = "json::bytes")]
+        data: Vec<u8>,
+    },
+    // A ScreenStream finished. Frames the client was too slow to receive are dropped
+    // rather than queued, and counted in `dropped`.
+    ScreenStreamEnd {
+        frames: u64,
+        dropped: u64,
+    },
+    // Events captured by RecordInput, ending with a SYN_REPORT.
+    InputEvents(Vec<RecordedEvent>),
+    ServerInfo(ServerInfo),
+    // The request is a command this server is too old to know; `kind` is its discriminant.
+    Unsupported {
+        kind: u32,
+    },
+    // The responses to a Batch, in order.
+    Batch(Vec<BridgeResponse>),
+    // A response to the Tagged request with this id. A Stream request ends with StreamEnd,
+    // any other with its single response.
+    Tagged {
+        request_id: u64,
+        response: Box<BridgeResponse>,
+    },
+    // Result of a finished ExecBytes, like Completed with stdout left as bytes. On a timeout
+    // it is TimedOut as usual, without the stdout that text could not carry intact.
+    CompletedBytes {
+        #[serde(with = "json::bytes")]
+        stdout: Vec<u8>,
+        stderr: String,
+        code: i32,
+    },
+    // The answer to ListJobs, oldest first.
+    Jobs(Vec<Job>),
+    JobStarted {
+        id: u64,
+        pid: u32,
+    },
+    // The answer to JobList, oldest first.
+    BackgroundJobs(Vec<BackgroundJob>),
+    SessionOpened {
+        id: u64,
+    },
+    // Ends a SessionExec whose shell exited, like StreamEnd.
+    SessionEnded {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    // The command would start a host process while `limit` (the server's --max-jobs) are
+    // running, and the server was told not to wait or has too many waiting already. On a
+    // multiplexed connection also a Tagged request while `limit` (--max-in-flight) of the
+    // connection's are running. Nothing was started; a stream still ends with StreamEnd.
+    Busy {
+        running: u32,
+        limit: u32,
+    },
+    // The request frame was `len` bytes, more than the server's --max-frame-size (`limit`).
+    // The rest of it is never read, so the server closes the connection after this.
+    FrameTooLarge {
+        len: u64,
+        limit: u64,
+    },
+    // An Exec or ExecBytes whose program never ran: it does not exist, is not executable,
+    // or its working directory or credentials could not be set up. `os_error` is the errno
+    // when the OS refused the spawn. A program that ran and failed answers Completed with
+    // its exit code instead. Protocol 4 clients get an Error with the same message.
+    SpawnFailed {
+        os_error: Option<i32>,
+        message: String,
+    },
+    // A command that failed, with what kind of failure it was. Clients before
+    // ERROR_KIND_PROTOCOL_VERSION get an Error with the message instead; refusals during the handshake are always an Error.
+    Failed(BridgeError),
+    // Boxed, as it would otherwise be the largest response by far.
+    DeviceInfo(Box<DeviceInfo>),
+    // The answer to GetProp.
+    Property {
+        name: String,
+        value: String,
+    },
+    // The answer to ListProps, by name.
+    Properties(BTreeMap<String, String>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BridgeError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    // The program, file, job, session or request named does not exist.
+    NotFound,
+    // The OS refused, e.g. file permissions or switching credentials.
+    PermissionDenied,
+    // The server's rules (--allow-program, logcat outside a stream) refuse the command.
+    PolicyRejected,
+    // A deadline ran out, e.g. --idle-timeout.
+    Timeout,
+    // The request does not decode or does not fit the connection it came on.
+    ProtocolError,
+    // Injecting or reading input through the event devices failed.
+    InputDeviceError,
+    Internal,
+}
+
+impl ErrorKind {
+    // The kind of a failed system call.
+    pub fn of(e: &io::Error) -> ErrorKind {
+        match e.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            _ => ErrorKind::Internal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::Keycode;
+
+    fn server_info() -> BridgeResponse {
+        BridgeResponse::ServerInfo(ServerInfo {
+            version: "1.2.0".into(),
+            protocol: PROTOCOL_VERSION,
+            features: vec!["persistent".into()],
+            touch_device: None,
+            commands: vec![0, 1],
+            load: Load {
+                connections: 3,
+                max_connections: 8,
+                jobs: 1,
+                max_jobs: 4,
+                queued: 0,
+            },
+        })
+    }
+
+    fn load(response: BridgeResponse) -> Load {
+        match response {
+            BridgeResponse::ServerInfo(info) => info.load,
+            other => panic!("expected ServerInfo, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negotiate_takes_the_older_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION, 6), Some(6));
+        assert_eq!(negotiate(6, PROTOCOL_VERSION + 3), Some(6));
+        assert_eq!(
+            negotiate(PROTOCOL_VERSION, OLDEST_PROTOCOL_VERSION),
+            Some(OLDEST_PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate(PROTOCOL_VERSION, OLDEST_PROTOCOL_VERSION - 1),
+            None
+        );
+        assert_eq!(negotiate(PROTOCOL_VERSION, PRE_HANDSHAKE_VERSION), None);
+    }
+
+    #[test]
+    fn server_info_keeps_load_for_current_peers() {
+        for codec in [Codec::Bincode, Codec::Json] {
+            let payload = codec
+                .encode_response_for(&server_info(), BUSY_PROTOCOL_VERSION)
+                .unwrap();
+            let decoded = codec
+                .decode_response_for(&payload, BUSY_PROTOCOL_VERSION)
+                .unwrap();
+            assert_eq!(load(decoded).connections, 3);
+        }
+    }
+
+    #[test]
+    fn server_info_leaves_load_off_for_protocol_4() {
+        let old = Codec::Bincode
+            .encode_response_for(&server_info(), OLDEST_PROTOCOL_VERSION)
+            .unwrap();
+        let new = Codec::Bincode
+            .encode_response_for(&server_info(), PROTOCOL_VERSION)
+            .unwrap();
+        // Five u32 fields fewer.
+        assert_eq!(old.len() + 20, new.len());
+        let decoded = Codec::Bincode
+            .decode_response_for(&old, OLDEST_PROTOCOL_VERSION)
+            .unwrap();
+        assert_eq!(load(decoded).max_jobs, 0);
+
+        let json = Codec::Json
+            .encode_response_for(&server_info(), OLDEST_PROTOCOL_VERSION)
+            .unwrap();
+        assert!(!String::from_utf8(json).unwrap().contains("load"));
+    }
+
+    #[test]
+    fn protocol_4_server_info_with_load_still_decodes() {
+        // Servers built while Load was new sent it at protocol 4 as well.
+        let payload = Codec::Bincode
+            .encode_response_for(&server_info(), PROTOCOL_VERSION)
+            .unwrap();
+        let decoded = Codec::Bincode
+            .decode_response_for(&payload, OLDEST_PROTOCOL_VERSION)
+            .unwrap();
+        assert!(matches!(decoded, BridgeResponse::ServerInfo(_)));
+    }
+
+    // The envelope body of `cmd`: its fields as bincode encodes them, without the kind.
+    fn body(cmd: &BridgeCommand) -> Vec<u8> {
+        let envelope: Envelope = bincode::deserialize(&encode_command(cmd).unwrap()).unwrap();
+        envelope.body
+    }
+
+    #[test]
+    fn point_and_gesture_encode_like_the_fields_they_replaced() {
+        let tap = BridgeCommand::DirectTap {
+            at: Point {
+                x: Coord::Pixels(100),
+                y: Coord::Fraction(0.5),
+            },
+            dry_run: false,
+        };
+        let old = (Coord::Pixels(100), Coord::Fraction(0.5), false);
+        assert_eq!(body(&tap), bincode::serialize(&old).unwrap());
+
+        let swipe = BridgeCommand::DirectSwipe {
+            from: Point {
+                x: Coord::Pixels(1),
+                y: Coord::Pixels(2),
+            },
+            to: Point {
+                x: Coord::Pixels(3),
+                y: Coord::Pixels(4),
+            },
+            duration_ms: 300,
+            easing: Easing::Overshoot,
+            step_ms: 8,
+            dry_run: true,
+        };
+        let old = (
+            Coord::Pixels(1),
+            Coord::Pixels(2),
+            Coord::Pixels(3),
+            Coord::Pixels(4),
+            300u64,
+            Easing::Overshoot,
+            8u64,
+            true,
+        );
+        assert_eq!(body(&swipe), bincode::serialize(&old).unwrap());
+
+        let points = vec![(10, 20, 0), (30, 40, 16)];
+        let path = BridgeCommand::DirectPath {
+            gesture: Gesture {
+                points: points.clone(),
+            },
+        };
+        assert_eq!(body(&path), bincode::serialize(&points).unwrap());
+
+        let key = BridgeCommand::KeyEvent {
+            keycode: Keycode::BACK,
+            long_press: true,
+        };
+        assert_eq!(body(&key), bincode::serialize(&(4u16, true)).unwrap());
+    }
+
+    #[test]
+    fn shared_types_survive_both_codecs() {
+        let commands = [
+            BridgeCommand::DirectTap {
+                at: Point {
+                    x: Coord::Pixels(-5),
+                    y: Coord::Fraction(1.0),
+                },
+                dry_run: true,
+            },
+            BridgeCommand::DirectPath {
+                gesture: Gesture {
+                    points: vec![(0, 0, 0), (i32::MAX, i32::MIN, u64::MAX)],
+                },
+            },
+            BridgeCommand::KeyEvent {
+                keycode: Keycode(u16::MAX),
+                long_press: false,
+            },
+        ];
+        for codec in [Codec::Bincode, Codec::Json] {
+            for cmd in &commands {
+                let payload = codec.encode_command(cmd).unwrap();
+                let decoded = codec.decode_command(&payload).unwrap();
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", cmd));
+            }
+        }
+    }
+
+    #[test]
+    fn gesture_needs_points_with_increasing_times() {
+        let gesture = |points: &[(i32, i32, u64)]| Gesture {
+            points: points.to_vec(),
+        };
+        assert!(gesture(&[(0, 0, 0)]).validate().is_ok());
+        assert!(gesture(&[(0, 0, 0), (1, 1, 5)]).validate().is_ok());
+        assert_eq!(
+            gesture(&[]).validate().unwrap_err(),
+            "the path has no points"
+        );
+        assert_eq!(
+            gesture(&[(0, 0, 5), (1, 1, 5)]).validate().unwrap_err(),
+            "timestamps must increase, but 5 ms is followed by 5 ms"
+        );
+    }
+
+    #[test]
+    fn hello_round_trips_in_both_codecs() {
+        for codec in [Codec::Bincode, Codec::Json] {
+            let mut frame = Vec::new();
+            codec
+                .write_hello(&mut frame, PROTOCOL_VERSION, b"secret")
+                .unwrap();
+            let payload = codec
+                .read_frame(&mut &frame[..], proto::MAX_REQUEST_LEN)
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                codec.parse_hello(&payload),
+                Some((PROTOCOL_VERSION, b"secret".to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_not_taken_for_a_hello() {
+        let request = encode_command(&BridgeCommand::Ping).unwrap();
+        assert_eq!(parse_hello(&request), None);
+        // The magic without a whole version after it.
+        assert_eq!(parse_hello(b"NBrg\0\0"), None);
+        assert_eq!(parse_hello(b"NBrg\0\0\0\x09"), Some((9, &b""[..])));
+        assert_eq!(Codec::Json.parse_hello(br#""Ping""#), None);
+    }
+
+    #[test]
+    fn protocol_mismatch_names_the_outdated_side() {
+        assert_eq!(
+            protocol_mismatch(9, 3),
+            "server speaks protocol 9, client speaks 3, please update bridge_client"
+        );
+        assert_eq!(
+            protocol_mismatch(3, 9),
+            "server speaks protocol 3, client speaks 9, please update bridge_server"
+        );
+    }
 }
@@ -0,0 +1,264 @@
+// Minimal LZ4 block compression (lz4_Block_format.md of the reference implementation), used
+// for large frames between peers built with the `compress` feature. Greedy and single pass:
+// fast enough to stay out of the way of a stream, while the text and raw pixels that make up
+// big responses still shrink several times over.
+// LZ4 rather than zstd: zstd would bring its C library into a crate that otherwise needs only
+// serde and bincode, and it would have to cross-build for every Android target, while LZ4 is
+// small enough to keep here and decodes several times faster on a phone. Blocks written here
+// decode with the reference `lz4`, and the decoder takes blocks from the peer as untrusted.
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = 65535;
+const HASH_BITS: u32 = 14;
+// The format ends every block with literals: no match starts within the last 12 bytes, and
+// none reaches into the last 5.
+const MATCH_START_LIMIT: usize = 12;
+const END_LITERALS: usize = 5;
+// After this many positions without a match the search steps further, so data that does not
+// compress costs little time.
+const SKIP_TRIGGER: u32 = 6;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    // Where each hashed 4-byte sequence was last seen, plus one; zero is empty.
+    let mut table = vec![0u32; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MATCH_START_LIMIT < input.len() {
+        let word = read_u32(input, i);
+        let slot = (word.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[slot] as usize;
+        table[slot] = i as u32 + 1;
+        let start = candidate.wrapping_sub(1);
+        if candidate == 0 || i - start > MAX_OFFSET || read_u32(input, start) != word {
+            i += 1 + ((i - anchor) >> SKIP_TRIGGER);
+            continue;
+        }
+        let end = input.len() - END_LITERALS;
+        let mut len = MIN_MATCH;
+        while i + len < end && input[start + len] == input[i + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &input[anchor..i], Some((i - start, len)));
+        i += len;
+        anchor = i;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// Decompresses a block, or None if it is corrupt or holds more than `limit` bytes. The output
+// grows with what the block actually produces, never past `limit`.
+pub fn decompress(input: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    loop {
+        let token = *input.get(i)?;
+        i += 1;
+        let literals = read_length(input, &mut i, token >> 4)?;
+        if literals > limit - out.len() {
+            return None;
+        }
+        out.extend_from_slice(input.get(i..i + literals)?);
+        i += literals;
+        // The last sequence has no match.
+        if i == input.len() {
+            return Some(out);
+        }
+        let offset = u16::from_le_bytes(*input.get(i..)?.first_chunk::<2>()?) as usize;
+        i += 2;
+        let len = read_length(input, &mut i, token & 0x0f)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || len > limit - out.len() {
+            return None;
+        }
+        let start = out.len() - offset;
+        if offset >= len {
+            out.extend_from_within(start..start + len);
+        } else {
+            // The match overlaps what it produces, repeating the last `offset` bytes.
+            for k in start..start + len {
+                out.push(out[k]);
+            }
+        }
+    }
+}
+
+// A token, the literals, and the match as offset and length unless it is the last sequence.
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let extra = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) as u8) << 4 | extra.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if extra >= 15 {
+            write_length(out, extra - 15);
+        }
+    }
+}
+
+// Lengths that do not fit their 4 bits continue in bytes of 255, ended by one below it.
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize, nibble: u8) -> Option<usize> {
+    let mut len = nibble as usize;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*i)?;
+            *i += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+fn read_u32(input: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(input[at..at + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift64*, so every run checks the same data.
+    fn random(len: usize) -> Vec<u8> {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        (0..len)
+            .map(|_| {
+                state ^= state >> 12;
+                state ^= state << 25;
+                state ^= state >> 27;
+                (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn dumpsys(lines: u32) -> Vec<u8> {
+        (0..lines)
+            .flat_map(|i| {
+                format!(
+                    "  mLastInputTime={} uid={} pkg=com.example.app{}\n",
+                    i * 7,
+                    10000 + i % 3,
+                    i % 5
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let block = compress(input);
+        let output = decompress(&block, input.len()).expect("decompresses");
+        assert_eq!(output, input);
+        block
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(b"");
+        round_trip(b"short");
+        round_trip(b"exactly twelve bytes");
+        round_trip(&random(100_000));
+        // Runs longer than the 15 and 255 the lengths spill over at.
+        round_trip(&[0u8; 70_000]);
+        round_trip(&b"ab".repeat(5000));
+        let text = dumpsys(5000);
+        assert!(round_trip(&text).len() < text.len() / 3);
+    }
+
+    #[test]
+    fn matches_reach_back_no_further_than_64_kib() {
+        let chunk = random(40_000);
+        let mut input = chunk.clone();
+        input.extend_from_slice(&random(30_000));
+        input.extend_from_slice(&chunk);
+        round_trip(&input);
+    }
+
+    // A block written by the reference `lz4 -9` for dumpsys(8).
+    #[test]
+    fn decodes_the_reference_implementation() {
+        const BLOCK: [u8; 131] = [
+            0xfd, 0x23, 0x20, 0x20, 0x6d, 0x4c, 0x61, 0x73, 0x74, 0x49, 0x6e, 0x70, 0x75, 0x74,
+            0x54, 0x69, 0x6d, 0x65, 0x3d, 0x30, 0x20, 0x75, 0x69, 0x64, 0x3d, 0x31, 0x30, 0x30,
+            0x30, 0x30, 0x20, 0x70, 0x6b, 0x67, 0x3d, 0x63, 0x6f, 0x6d, 0x2e, 0x65, 0x78, 0x61,
+            0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x61, 0x70, 0x70, 0x30, 0x0a, 0x32, 0x00, 0x15, 0x37,
+            0x32, 0x00, 0x1f, 0x31, 0x32, 0x00, 0x01, 0x1e, 0x31, 0x32, 0x00, 0x25, 0x31, 0x34,
+            0x33, 0x00, 0x1f, 0x32, 0x33, 0x00, 0x01, 0x1e, 0x32, 0x33, 0x00, 0x2f, 0x32, 0x31,
+            0x98, 0x00, 0x0b, 0x1f, 0x33, 0x33, 0x00, 0x00, 0x1f, 0x38, 0x99, 0x00, 0x0b, 0x1e,
+            0x34, 0x33, 0x00, 0x2e, 0x33, 0x35, 0x99, 0x00, 0x0f, 0xfe, 0x00, 0x0c, 0x2e, 0x34,
+            0x32, 0x99, 0x00, 0x0f, 0xff, 0x00, 0x0c, 0x2f, 0x34, 0x39, 0x99, 0x00, 0x08, 0x50,
+            0x61, 0x70, 0x70, 0x32, 0x0a,
+        ];
+        assert_eq!(decompress(&BLOCK, 406).unwrap(), dumpsys(8));
+    }
+
+    #[test]
+    fn decodes_overlapping_matches() {
+        // "abcd", a match of 8 at offset 4, then "xyzzy".
+        let block = [
+            0x44, b'a', b'b', b'c', b'd', 4, 0, 0x50, b'x', b'y', b'z', b'z', b'y',
+        ];
+        assert_eq!(decompress(&block, 64).unwrap(), b"abcdabcdabcdxyzzy");
+    }
+
+    #[test]
+    fn refuses_output_past_the_limit() {
+        let input = vec![7u8; 10_000];
+        let block = compress(&input);
+        assert!(decompress(&block, 9_999).is_none());
+        assert!(decompress(&block, 10_000).is_some());
+        // 15 + 255 * 100 literals claimed with only a few present.
+        let mut claim = vec![0xf0];
+        claim.extend(std::iter::repeat_n(255, 100));
+        claim.push(0);
+        assert!(decompress(&claim, 1000).is_none());
+        assert!(decompress(&claim, 1 << 20).is_none());
+    }
+
+    #[test]
+    fn refuses_corrupt_blocks() {
+        assert!(decompress(&[], 64).is_none());
+        // A match at offset 0, and one before the start of the output.
+        assert!(decompress(&[0x10, b'a', 0, 0], 64).is_none());
+        assert!(decompress(&[0x10, b'a', 2, 0], 64).is_none());
+        // A length that never ends.
+        assert!(decompress(&[0xf0, 255, 255], 64).is_none());
+        // The offset cut short.
+        assert!(decompress(&[0x10, b'a', 1], 64).is_none());
+    }
+
+    #[test]
+    fn truncated_and_damaged_blocks_never_panic() {
+        let input = dumpsys(200);
+        let block = compress(&input);
+        for cut in 0..block.len() {
+            if let Some(output) = decompress(&block[..cut], input.len()) {
+                assert!(output.len() <= input.len());
+            }
+        }
+        let noise = random(block.len());
+        for (i, flip) in noise.iter().enumerate() {
+            let mut damaged = block.clone();
+            damaged[i] ^= flip | 1;
+            if let Some(output) = decompress(&damaged, input.len()) {
+                assert!(output.len() <= input.len());
+            }
+        }
+        for len in [1, 2, 5, 16, 300, 4096] {
+            let _ = decompress(&random(len), 1 << 16);
+        }
+    }
+}
@@ -0,0 +1,539 @@
+// Framing shared by client and server. A bincode frame is [8-byte big-endian length][payload],
+// a JSON frame is one line. Readers tell three endings apart: the peer closing the connection
+// between frames (Ok(None), the normal end of a connection), the connection failing or closing
+// in the middle of one (Io), and bytes that are not a frame at all (TooLarge, Malformed).
+
+use std::fmt;
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+
+// Default upper bound for a single frame, in either direction (--max-frame-size). Readers
+// check a frame's length against it before taking in any of the payload, so a corrupt or
+// hostile length cannot make them allocate more. Everything big, transfers and output,
+// travels in chunks far below it.
+pub const MAX_FRAME_LEN: u64 = 64 * 1024 * 1024;
+
+// Default upper bound for a request frame (the server's --max-frame-size), which may not
+// reach LEGACY_FRAME_LEN. A legacy (unframed) payload misread as a length header always
+// lands above this, so the server can reject it instead of waiting for bytes that never
+// arrive.
+pub const MAX_REQUEST_LEN: u64 = 8 * 1024 * 1024;
+
+// The smallest length a legacy request reads as: its bincode variant tag fills the high half
+// of the header and the program's length follows, little-endian, so any program name
+// shorter than 256 bytes reads as a multiple of this.
+pub const LEGACY_FRAME_LEN: u64 = 16 * 1024 * 1024;
+const _: () = assert!(MAX_REQUEST_LEN < LEGACY_FRAME_LEN);
+
+// The upper half of the length header of a compressed frame, whose payload is the length of
+// the original (big-endian u32) followed by its LZ4 block. Only sent to a peer whose hello
+// announced READS_COMPRESSED; a reader built without the `compress` feature refuses it.
+pub const COMPRESSED: [u8; 4] = [0x80, 0, 0, 0];
+
+// Smallest payload worth compressing; below it the work outweighs the few bytes saved.
+#[cfg(feature = "compress")]
+pub const COMPRESS_FROM: usize = 4096;
+
+// A frame above the reader's limit. For a JSON line, `len` is how much of it was read before
+// the limit was passed.
+#[derive(Debug)]
+pub struct FrameTooLarge {
+    pub len: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame of {} bytes exceeds the {} byte limit",
+            self.len, self.limit
+        )
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+impl FrameTooLarge {
+    // The FrameTooLarge behind `e`, once a FrameError has become an io::Error.
+    pub fn of(e: &io::Error) -> Option<&FrameTooLarge> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    // The connection failed, timed out, or closed partway through a frame (UnexpectedEof).
+    Io(io::Error),
+    TooLarge(FrameTooLarge),
+    // Not a frame, e.g. a build from before framing sending its payload bare.
+    Malformed(String),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => e.fmt(f),
+            FrameError::TooLarge(e) => e.fmt(f),
+            FrameError::Malformed(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> FrameError {
+        FrameError::Io(e)
+    }
+}
+
+// For callers that only deal in io::Error: TooLarge and Malformed become InvalidData, the
+// former still to be found with `FrameTooLarge::of`.
+impl From<FrameError> for io::Error {
+    fn from(e: FrameError) -> io::Error {
+        match e {
+            FrameError::Io(e) => e,
+            FrameError::TooLarge(e) => io::Error::new(ErrorKind::InvalidData, e),
+            FrameError::Malformed(e) => io::Error::new(ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+pub fn write_frame<W: Write + ?Sized>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let header = (payload.len() as u64).to_be_bytes();
+    write_all_vectored(writer, &mut [IoSlice::new(&header), IoSlice::new(payload)])?;
+    writer.flush()
+}
+
+// Like `write_frame`, compressing a payload of COMPRESS_FROM bytes or more. One that does not
+// shrink goes out as a plain frame, so a compressed frame is never the bigger of the two.
+#[cfg(feature = "compress")]
+pub fn write_compressed_frame<W: Write + ?Sized>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() < COMPRESS_FROM {
+        return write_frame(writer, payload);
+    }
+    let block = crate::lz4::compress(payload);
+    let len = block.len() + 4;
+    if len >= payload.len() {
+        return write_frame(writer, payload);
+    }
+    let mut header = [0u8; 12];
+    header[..4].copy_from_slice(&COMPRESSED);
+    header[4..8].copy_from_slice(&(len as u32).to_be_bytes());
+    header[8..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    write_all_vectored(writer, &mut [IoSlice::new(&header), IoSlice::new(&block)])?;
+    writer.flush()
+}
+
+// Writes every part in order, handing the writer all that is left at once, so the pieces of a
+// frame usually leave in a single syscall instead of one each.
+pub fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut parts: &mut [IoSlice],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut parts, 0);
+    while !parts.is_empty() {
+        match writer.write_vectored(parts) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::WriteZero,
+                    "failed to write the whole frame",
+                ));
+            }
+            Ok(n) => IoSlice::advance_slices(&mut parts, n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Reads one frame written by `write_frame` or `write_compressed_frame`, rejecting anything
+// longer than `max_len`, before or after decompression. None if the peer closed the
+// connection before the frame began.
+pub fn read_frame<R: Read + ?Sized>(
+    reader: &mut R,
+    max_len: u64,
+) -> Result<Option<Vec<u8>>, FrameError> {
+    // The header is read in two halves: valid lengths always fit in 32 bits, so a
+    // non-zero upper half means the peer is not speaking the framed protocol
+    // (an old client sends the bincode enum tag first). Checking it early avoids
+    // blocking on short legacy payloads such as a bare `Ping`.
+    let mut high = [0u8; 4];
+    match fill(reader, &mut high)? {
+        0 => return Ok(None),
+        4 => {}
+        _ => return Err(truncated()),
+    }
+    let compressed = high == COMPRESSED;
+    if high != [0; 4] && !compressed {
+        return Err(FrameError::Malformed(
+            "missing length prefix (is the peer an outdated build?)".to_string(),
+        ));
+    }
+    let mut low = [0u8; 4];
+    if fill(reader, &mut low)? < 4 {
+        return Err(truncated());
+    }
+    let len = u32::from_be_bytes(low) as u64;
+    if len > max_len {
+        return Err(FrameError::TooLarge(FrameTooLarge {
+            len,
+            limit: max_len,
+        }));
+    }
+
+    // The buffer grows with what actually arrives rather than with what the header claims.
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    while (buffer.len() as u64) < len {
+        let want = chunk.len().min((len - buffer.len() as u64) as usize);
+        match fill(reader, &mut chunk[..want])? {
+            0 => return Err(truncated()),
+            n => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+    if compressed {
+        return decompress(&buffer, max_len).map(Some);
+    }
+    Ok(Some(buffer))
+}
+
+// The original payload of a compressed frame.
+#[cfg(feature = "compress")]
+fn decompress(frame: &[u8], max_len: u64) -> Result<Vec<u8>, FrameError> {
+    let corrupt = || FrameError::Malformed("corrupt compressed frame".to_string());
+    let (len, block) = frame.split_first_chunk::<4>().ok_or_else(corrupt)?;
+    let len = u32::from_be_bytes(*len) as u64;
+    if len > max_len {
+        return Err(FrameError::TooLarge(FrameTooLarge {
+            len,
+            limit: max_len,
+        }));
+    }
+    crate::lz4::decompress(block, len as usize)
+        .filter(|payload| payload.len() as u64 == len)
+        .ok_or_else(corrupt)
+}
+
+#[cfg(not(feature = "compress"))]
+fn decompress(_: &[u8], _: u64) -> Result<Vec<u8>, FrameError> {
+    Err(FrameError::Malformed(
+        "compressed frame, but this build has no compression".to_string(),
+    ))
+}
+
+// Reads one line, without its newline, skipping blank lines. A byte at a time, so nothing past
+// the newline is consumed: the rest of the connection may be read through another handle.
+pub fn read_line<R: Read + ?Sized>(
+    reader: &mut R,
+    max_len: u64,
+) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        if fill(reader, &mut byte)? == 0 {
+            // Whitespace left after the last line is no frame of its own.
+            if line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(None);
+            }
+            return Err(truncated());
+        }
+        match byte[0] {
+            b'\n' if line.iter().all(u8::is_ascii_whitespace) => line.clear(),
+            b'\n' => return Ok(Some(line)),
+            c => line.push(c),
+        }
+        if line.len() as u64 > max_len {
+            return Err(FrameError::TooLarge(FrameTooLarge {
+                len: line.len() as u64,
+                limit: max_len,
+            }));
+        }
+    }
+}
+
+// Reads until `buf` is full or the peer closes, however the bytes trickle in, and returns how
+// many arrived. Reads interrupted by a signal are retried.
+fn fill<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated() -> FrameError {
+    FrameError::Io(io::Error::new(
+        ErrorKind::UnexpectedEof,
+        "the connection closed in the middle of a frame",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8], max_len: u64) -> Result<Option<Vec<u8>>, FrameError> {
+        read_frame(&mut &bytes[..], max_len)
+    }
+
+    // An Exec as builds from before framing sent it: the bincode variant tag, then the
+    // program as a u64 length and its bytes.
+    fn legacy_exec(program: &str) -> Vec<u8> {
+        let mut bytes = 0u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(program.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(program.as_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes
+    }
+
+    // Hands out one byte per call, failing with EINTR before every other one.
+    struct Dribble<'a> {
+        bytes: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl<'a> Dribble<'a> {
+        fn new(bytes: &'a [u8]) -> Self {
+            Dribble {
+                bytes,
+                interrupt: true,
+            }
+        }
+    }
+
+    impl Read for Dribble<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if !self.interrupt {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            if self.bytes.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.bytes[0];
+            self.bytes = &self.bytes[1..];
+            Ok(1)
+        }
+    }
+
+    // Takes at most one byte per call, failing with EINTR before every other one.
+    #[derive(Default)]
+    struct Choppy {
+        written: Vec<u8>,
+        interrupt: bool,
+    }
+
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            self.written.extend_from_slice(&buf[..buf.len().min(1)]);
+            Ok(buf.len().min(1))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"hello").unwrap();
+        write_frame(&mut wire, b"").unwrap();
+        let mut reader = &wire[..];
+        assert_eq!(read_frame(&mut reader, 16).unwrap().unwrap(), b"hello");
+        assert_eq!(read_frame(&mut reader, 16).unwrap().unwrap(), b"");
+        assert!(read_frame(&mut reader, 16).unwrap().is_none());
+    }
+
+    #[test]
+    fn request_limit_stays_below_legacy_requests() {
+        for program in ["a", "ls", "cat", "echo", "input", "settings"] {
+            match read(&legacy_exec(program), MAX_REQUEST_LEN) {
+                Err(FrameError::TooLarge(e)) => {
+                    assert!(e.len >= LEGACY_FRAME_LEN, "{} read as {}", program, e.len)
+                }
+                other => panic!("{} was not refused: {:?}", program, other),
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_ping_is_malformed() {
+        // Ping is variant 2, which lands in the upper half of the header.
+        let ping = 2u32.to_le_bytes();
+        assert!(matches!(
+            read(&ping, MAX_REQUEST_LEN),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn oversize_is_refused_before_the_payload() {
+        let mut wire = 1000u64.to_be_bytes().to_vec();
+        wire.extend_from_slice(&[0; 10]);
+        match read(&wire, 999) {
+            Err(FrameError::TooLarge(e)) => {
+                assert_eq!((e.len, e.limit), (1000, 999));
+            }
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+        let mut exact = 4u64.to_be_bytes().to_vec();
+        exact.extend_from_slice(b"four");
+        assert_eq!(read(&exact, 4).unwrap().unwrap(), b"four");
+    }
+
+    #[test]
+    fn truncated_frames_are_unexpected_eof() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"payload").unwrap();
+        for cut in [1, 4, 7, 8, 12, wire.len() - 1] {
+            match read(&wire[..cut], 64) {
+                Err(FrameError::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+                other => panic!("cut at {}: {:?}", cut, other),
+            }
+        }
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compressed_round_trip() {
+        let text = b"uid=10000 pkg=com.example.app\n".repeat(1000);
+        let mut wire = Vec::new();
+        write_compressed_frame(&mut wire, &text).unwrap();
+        assert_eq!(wire[..4], COMPRESSED);
+        assert!(wire.len() < text.len() / 4);
+        assert_eq!(read(&wire, text.len() as u64).unwrap().unwrap(), text);
+
+        // Small or incompressible payloads go out plain.
+        let mut plain = Vec::new();
+        write_compressed_frame(&mut plain, b"tiny").unwrap();
+        assert_eq!(plain[..4], [0; 4]);
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compressed_frames_are_checked_against_the_limit() {
+        let text = vec![b'x'; 100_000];
+        let mut wire = Vec::new();
+        write_compressed_frame(&mut wire, &text).unwrap();
+        // The frame itself is small; what it decompresses to is not.
+        match read(&wire, 99_999) {
+            Err(FrameError::TooLarge(e)) => assert_eq!(e.len, 100_000),
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn corrupt_compressed_frames_are_malformed() {
+        let text = b"uid=10000 pkg=com.example.app\n".repeat(1000);
+        let mut wire = Vec::new();
+        write_compressed_frame(&mut wire, &text).unwrap();
+        // Claim one byte more than the block holds.
+        let claimed = u32::from_be_bytes(wire[8..12].try_into().unwrap()) + 1;
+        wire[8..12].copy_from_slice(&claimed.to_be_bytes());
+        assert!(matches!(
+            read(&wire, 1 << 20),
+            Err(FrameError::Malformed(_))
+        ));
+
+        let mut header_only = COMPRESSED.to_vec();
+        header_only.extend_from_slice(&2u32.to_be_bytes());
+        header_only.extend_from_slice(&[0, 1]);
+        assert!(matches!(
+            read(&header_only, 64),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+
+    #[cfg(not(feature = "compress"))]
+    #[test]
+    fn compressed_frames_need_the_feature() {
+        let mut wire = COMPRESSED.to_vec();
+        wire.extend_from_slice(&1u32.to_be_bytes());
+        wire.push(0);
+        assert!(matches!(read(&wire, 64), Err(FrameError::Malformed(_))));
+    }
+
+    #[test]
+    fn lines_skip_blanks_and_respect_the_limit() {
+        let mut reader = &b"\n  \n{\"a\":1}\nlast"[..];
+        assert_eq!(read_line(&mut reader, 64).unwrap().unwrap(), b"{\"a\":1}");
+        assert!(matches!(
+            read_line(&mut reader, 64),
+            Err(FrameError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert!(read_line(&mut &b"\n \n"[..], 64).unwrap().is_none());
+        assert!(matches!(
+            read_line(&mut &b"0123456789\n"[..], 4),
+            Err(FrameError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn dribbled_and_interrupted_reads_make_whole_frames() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"first").unwrap();
+        write_frame(&mut wire, &[7; 10_000]).unwrap();
+        write_frame(&mut wire, b"").unwrap();
+        let mut reader = Dribble::new(&wire);
+        assert_eq!(read_frame(&mut reader, 1 << 20).unwrap().unwrap(), b"first");
+        assert_eq!(
+            read_frame(&mut reader, 1 << 20).unwrap().unwrap(),
+            [7; 10_000]
+        );
+        assert_eq!(read_frame(&mut reader, 1 << 20).unwrap().unwrap(), b"");
+        assert!(read_frame(&mut reader, 1 << 20).unwrap().is_none());
+
+        let mut reader = Dribble::new(b"{\"a\":1}\n{\"b\":2}\n");
+        assert_eq!(read_line(&mut reader, 64).unwrap().unwrap(), b"{\"a\":1}");
+        assert_eq!(read_line(&mut reader, 64).unwrap().unwrap(), b"{\"b\":2}");
+        assert!(read_line(&mut reader, 64).unwrap().is_none());
+    }
+
+    #[test]
+    fn short_and_interrupted_writes_still_send_the_whole_frame() {
+        let mut writer = Choppy::default();
+        write_frame(&mut writer, b"payload").unwrap();
+        let mut expected = 7u64.to_be_bytes().to_vec();
+        expected.extend_from_slice(b"payload");
+        assert_eq!(writer.written, expected);
+
+        let mut writer = Choppy::default();
+        let mut parts = [
+            IoSlice::new(b"head"),
+            IoSlice::new(b""),
+            IoSlice::new(b"tail"),
+        ];
+        write_all_vectored(&mut writer, &mut parts).unwrap();
+        assert_eq!(writer.written, b"headtail");
+    }
+
+    #[test]
+    fn reader_stops_at_the_frame_boundary() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"one").unwrap();
+        wire.extend_from_slice(b"trailing");
+        let mut reader = &wire[..];
+        assert_eq!(read_frame(&mut reader, 16).unwrap().unwrap(), b"one");
+        assert_eq!(reader, b"trailing");
+    }
+}
@@ -0,0 +1,133 @@
+// The byte streams the protocol runs over: Unix sockets (a path or an abstract name) and TCP.
+// Everything above the framing only needs Read + Write, so both sides hold connections as
+// `dyn Transport` and never care which one they got.
+
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use crate::Codec;
+
+pub trait Transport: Read + Write + AsRawFd + Send {
+    // A second handle to the same connection, for a thread that reads while another writes.
+    fn try_clone_box(&self) -> io::Result<Box<dyn Transport>>;
+
+    // Makes reads and writes that wait longer than `timeout` fail with WouldBlock or
+    // TimedOut; None waits forever. Applies to every handle of the connection.
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    // Like `set_timeouts` for reads alone, so writes from other threads are not cut short.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    // The remote address of a TCP connection, None for Unix sockets.
+    fn tcp_peer(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    // How messages are encoded on this connection.
+    fn codec(&self) -> Codec {
+        Codec::Bincode
+    }
+}
+
+// A connection speaking JSON lines instead of bincode frames.
+pub struct JsonLines(pub Box<dyn Transport>);
+
+impl Read for JsonLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for JsonLines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsRawFd for JsonLines {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Transport for JsonLines {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(JsonLines(self.0.try_clone_box()?)))
+    }
+
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_timeouts(timeout)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+
+    fn tcp_peer(&self) -> Option<SocketAddr> {
+        self.0.tcp_peer()
+    }
+
+    fn codec(&self) -> Codec {
+        Codec::Json
+    }
+}
+
+impl Transport for UnixStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Transport for TcpStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn tcp_peer(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+// The `host:port` of a `tcp://host:port` address, or None if `address` is not one.
+pub fn tcp_address(address: &str) -> Option<&str> {
+    address
+        .strip_prefix("tcp://")
+        .filter(|rest| !rest.is_empty())
+}
+
+// Opens a TCP connection with Nagle disabled: requests are small and latency matters
+// more than packet count for taps and key events.
+pub fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
@@ -0,0 +1,140 @@
+// Client access control: a shared secret (`--auth-token-file`) sent in the hello frame,
+// and a uid allowlist (`--allow-uid`) checked against the socket peer. Without either,
+// anything that can reach the socket runs commands as root.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use bridge_core::transport::Transport;
+
+use crate::log;
+
+// Reads the token from `path`, or creates the file with a new random token if it does not
+// exist. The file is only readable by its owner, clients need their own copy.
+pub fn load_token(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => {
+            let token = text.trim().to_string();
+            if token.is_empty() {
+                return Err(io::Error::other(format!(
+                    "{} does not contain a token",
+                    path.display()
+                )));
+            }
+            Ok(token)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let token = generate_token()?;
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+            writeln!(file, "{}", token)?;
+            log::info!("Generated auth token {} in {}", token, path.display());
+            Ok(token)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// 128 random bits as hex.
+pub fn generate_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// The uid of the process on the other end of `socket`, as the kernel recorded it at connect.
+pub fn peer_uid(socket: &dyn Transport) -> io::Result<u32> {
+    // TCP carries no credentials, the other end may not even be on this device.
+    if let Some(addr) = socket.tcp_peer() {
+        return Err(io::Error::other(format!(
+            "connected over TCP from {}",
+            addr
+        )));
+    }
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+// Compares in constant time, so response timing does not reveal how much of a guess matched.
+pub fn token_matches(expected: &str, given: &[u8]) -> bool {
+    let expected = expected.as_bytes();
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn peer_uid_is_that_of_the_connecting_process() {
+        let (server, _client) = UnixStream::pair().unwrap();
+        assert_eq!(peer_uid(&server).unwrap(), unsafe { libc::getuid() });
+    }
+
+    #[test]
+    fn tcp_peers_have_no_uid() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let e = peer_uid(&server).unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("connected over TCP from 127.0.0.1:")
+        );
+    }
+
+    #[test]
+    fn token_matches_only_the_whole_token() {
+        assert!(token_matches("abc123", b"abc123"));
+        assert!(!token_matches("abc123", b"abc124"));
+        assert!(!token_matches("abc123", b"abc12"));
+        assert!(!token_matches("abc123", b"abc1234"));
+        assert!(!token_matches("abc123", b""));
+    }
+
+    #[test]
+    fn load_token_creates_a_private_file_and_reads_it_back() {
+        let path = std::env::temp_dir().join(format!("auth-token-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let token = load_token(&path).unwrap();
+        assert_eq!(token.len(), 32);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_token(&path).unwrap(), token);
+
+        fs::write(&path, "\n").unwrap();
+        assert!(load_token(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+// Background jobs (`andro job start|list|logs|kill`): programs that run detached from the
+// connection that started them, with their output kept in a buffer on the server for
+// whoever asks for it later. Each buffer holds the last --job-output-limit bytes, so a chatty
+// job costs a bounded amount of memory; finished jobs are kept for their output until
+// MAX_FINISHED newer ones have finished.
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bridge_core::transport::Transport;
+use bridge_core::{BackgroundJob, BridgeResponse, ErrorKind, JobState, ProcessOptions};
+
+use crate::config::Config;
+use crate::log;
+use crate::process::{self, Deadline, ProcessGroup};
+use crate::{failure, stream_end, write_response};
+
+const MAX_FINISHED: usize = 32;
+
+// How often a following client is checked for having gone away while a job is quiet.
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+static JOBS: Mutex<Vec<Arc<Job>>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+struct Job {
+    id: u64,
+    pid: u32,
+    started_ms: u64,
+    started: Instant,
+    group: Arc<ProcessGroup>,
+    output: Mutex<Output>,
+    // Notified when output arrives and when the job exits.
+    changed: Condvar,
+}
+
+struct Output {
+    // Chunks as read from the pipes, true for stderr.
+    chunks: VecDeque<(bool, Vec<u8>)>,
+    size: usize,
+    limit: usize,
+    // The number of chunks dropped from the front, which is also the position of the first
+    // one left: readers remember positions, not indices.
+    first: u64,
+    dropped: u64,
+    exit: Option<(ExitStatus, Duration)>,
+}
+
+impl Output {
+    fn push(&mut self, stderr: bool, mut data: Vec<u8>) {
+        if data.len() > self.limit {
+            self.dropped += (data.len() - self.limit) as u64;
+            data.drain(..data.len() - self.limit);
+        }
+        self.size += data.len();
+        self.chunks.push_back((stderr, data));
+        while self.size > self.limit {
+            let (_, old) = self.chunks.pop_front().unwrap();
+            self.size -= old.len();
+            self.dropped += old.len() as u64;
+            self.first += 1;
+        }
+    }
+
+    // The chunks from position `next` on; positions already dropped are skipped.
+    fn since(&self, next: u64) -> (Vec<(bool, Vec<u8>)>, u64) {
+        let skip = next.saturating_sub(self.first) as usize;
+        let chunks: Vec<_> = self.chunks.iter().skip(skip).cloned().collect();
+        let end = self.first + self.chunks.len() as u64;
+        (chunks, end)
+    }
+}
+
+// JobStart: spawns the program with its output piped into a new job's buffer.
+pub fn start(
+    config: &Config,
+    program: String,
+    args: Vec<String>,
+    options: ProcessOptions,
+) -> BridgeResponse {
+    log::info!("Job: {} {}", program, log::args(&args));
+    let spawned = crate::host_command(&program, args, &options)
+        .map_err(|e| failure(e.kind, format!("JobStart Failed: {}", e.message)))
+        .and_then(|mut command| {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            ProcessGroup::spawn_detached(&mut command, true)
+                .map_err(|e| process::spawn_failed("JobStart Failed: ", &e))
+        });
+    let (mut child, group) = match spawned {
+        Ok(spawned) => spawned,
+        Err(response) => return response,
+    };
+    let job = Arc::new(Job {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        pid: child.id(),
+        started_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        started: Instant::now(),
+        group,
+        output: Mutex::new(Output {
+            chunks: VecDeque::new(),
+            size: 0,
+            limit: config.job_output_limit as usize,
+            first: 0,
+            dropped: 0,
+            exit: None,
+        }),
+        changed: Condvar::new(),
+    });
+    JOBS.lock().unwrap().push(Arc::clone(&job));
+    log::info!("Started job {} (pid {})", job.id, job.pid);
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let deadline = Deadline::start(&job.group, options.timeout_ms);
+    let supervised = Arc::clone(&job);
+    thread::spawn(move || {
+        let job = supervised;
+        let stderr_job = Arc::clone(&job);
+        let stderr_thread = thread::spawn(move || collect(stderr, &stderr_job, true));
+        collect(stdout, &job, false);
+        let _ = stderr_thread.join();
+        let status = child.wait();
+        job.group.mark_reaped();
+        drop(deadline);
+        let Ok(status) = status else { return };
+        log::info!(
+            "Job {} finished with status {}",
+            job.id,
+            crate::exit_code(status)
+        );
+        job.output.lock().unwrap().exit = Some((status, job.started.elapsed()));
+        job.changed.notify_all();
+        forget_old();
+    });
+
+    BridgeResponse::JobStarted {
+        id: job.id,
+        pid: job.pid,
+    }
+}
+
+fn collect(mut pipe: impl Read, job: &Job, stderr: bool) {
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        job.output
+            .lock()
+            .unwrap()
+            .push(stderr, buffer[..n].to_vec());
+        job.changed.notify_all();
+    }
+}
+
+// Drops the oldest finished jobs beyond MAX_FINISHED.
+fn forget_old() {
+    let mut jobs = JOBS.lock().unwrap();
+    let finished = |job: &Arc<Job>| job.output.lock().unwrap().exit.is_some();
+    let mut excess = jobs.iter().filter(|job| finished(job)).count();
+    jobs.retain(|job| {
+        if excess > MAX_FINISHED && finished(job) {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+fn find(id: u64) -> Option<Arc<Job>> {
+    JOBS.lock()
+        .unwrap()
+        .iter()
+        .find(|job| job.id == id)
+        .cloned()
+}
+
+// JobList.
+pub fn list() -> BridgeResponse {
+    let jobs = JOBS.lock().unwrap().clone();
+    BridgeResponse::BackgroundJobs(
+        jobs.iter()
+            .map(|job| {
+                let output = job.output.lock().unwrap();
+                let (state, runtime) = match output.exit {
+                    Some((status, runtime)) => (state(status), runtime),
+                    None => (JobState::Running, job.started.elapsed()),
+                };
+                BackgroundJob {
+                    id: job.id,
+                    pid: job.pid,
+                    command: job.group.command().to_vec(),
+                    state,
+                    started_ms: job.started_ms,
+                    runtime_ms: runtime.as_millis() as u64,
+                    dropped: output.dropped,
+                }
+            })
+            .collect(),
+    )
+}
+
+fn state(status: ExitStatus) -> JobState {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => JobState::Exited { code },
+        (None, Some(signal)) => JobState::Killed { signal },
+        (None, None) => JobState::Exited { code: 1 },
+    }
+}
+
+// JobKill.
+pub fn kill(id: u64, signal: i32) -> BridgeResponse {
+    let Some(job) = find(id) else {
+        return failure(
+            ErrorKind::NotFound,
+            format!("JobKill Failed: no job {}", id),
+        );
+    };
+    if !job.group.signal(signal) {
+        return failure(
+            ErrorKind::NotFound,
+            format!("JobKill Failed: job {} has already exited", id),
+        );
+    }
+    log::info!("Sent signal {} to job {}", signal, id);
+    BridgeResponse::Success(String::new())
+}
+
+// The process group of job `id`, for Signal.
+pub fn group(id: u64) -> Option<Arc<ProcessGroup>> {
+    find(id).map(|job| Arc::clone(&job.group))
+}
+
+// JobOutput: what is buffered, then with `follow` whatever comes next until the job exits
+// or the client goes away.
+pub fn handle_output(socket: &mut dyn Transport, id: u64, follow: bool) {
+    let Some(job) = find(id) else {
+        let message = format!("JobOutput Failed: no job {}", id);
+        let _ = write_response(socket, &failure(ErrorKind::NotFound, message));
+        return;
+    };
+    let client = socket.as_raw_fd();
+    let mut next = 0;
+    loop {
+        let (chunks, end, exit) = {
+            let mut output = job.output.lock().unwrap();
+            if follow && next >= output.first + output.chunks.len() as u64 && output.exit.is_none()
+            {
+                output = job.changed.wait_timeout(output, FOLLOW_POLL).unwrap().0;
+            }
+            let (chunks, end) = output.since(next);
+            (chunks, end, output.exit.map(|(status, _)| status))
+        };
+        next = end;
+        for (stderr, data) in chunks {
+            let response = if stderr {
+                BridgeResponse::StderrChunk(data)
+            } else {
+                BridgeResponse::StdoutChunk(data)
+            };
+            if write_response(socket, &response).is_err() {
+                return;
+            }
+        }
+        if exit.is_some() || !follow {
+            let _ = write_response(socket, &stream_end(exit));
+            return;
+        }
+        if process::hung_up(client) {
+            return;
+        }
+    }
+}
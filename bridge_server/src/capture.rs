@@ -0,0 +1,140 @@
+// Collects the output of an Exec for its single response. Up to --exec-buffer bytes are held
+// back; past that, a client that takes them (protocol 7) gets the output ahead of the response
+// in StdoutChunk and StderrChunk frames, so the server holds little however much the child
+// writes. Anyone else, batches included, gets the Exec stopped once its output no longer
+// fits a frame.
+
+use std::io::Read;
+use std::mem;
+use std::process::{ChildStderr, ChildStdout};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use bridge_core::BridgeResponse;
+
+use crate::Reply;
+use crate::config::Config;
+use crate::process::ProcessGroup;
+
+// --exec-buffer and --chunk-size.
+static LIMITS: OnceLock<(usize, usize)> = OnceLock::new();
+
+pub fn init(config: &Config) {
+    let _ = LIMITS.set((config.exec_buffer as usize, config.chunk_size as usize));
+}
+
+// What is left for the response once the pipes are closed. With `overflowed` the group was
+// killed for writing more than a response can carry, and the output is gone.
+pub struct Captured {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub overflowed: bool,
+}
+
+// Shared by the readers of both pipes, which count against the same limit.
+struct State<'a> {
+    group: &'a Arc<ProcessGroup>,
+    // Where output goes once there is too much to hold, if the client takes chunks.
+    spill: Option<&'a Reply>,
+    limit: usize,
+    chunk_size: usize,
+    held: AtomicUsize,
+    spilled: AtomicBool,
+    overflowed: AtomicBool,
+}
+
+// Reads both pipes until they close. `spill` is where output goes ahead of the response.
+pub fn capture(
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    group: &Arc<ProcessGroup>,
+    spill: Option<&Reply>,
+) -> Captured {
+    let (exec_buffer, chunk_size) = LIMITS.get().copied().unwrap_or((1024 * 1024, 64 * 1024));
+    let state = State {
+        group,
+        spill,
+        // Without chunks everything has to fit the one response.
+        limit: match spill {
+            Some(_) => exec_buffer,
+            None => crate::max_frame() as usize,
+        },
+        chunk_size,
+        held: AtomicUsize::new(0),
+        spilled: AtomicBool::new(false),
+        overflowed: AtomicBool::new(false),
+    };
+    let connection = crate::log::connection();
+    let compression = crate::compression();
+    let (stdout, stderr) = thread::scope(|scope| {
+        let state = &state;
+        let stderr = scope.spawn(move || {
+            crate::log::set_connection(connection);
+            crate::set_compression(compression);
+            collect(stderr, state, BridgeResponse::StderrChunk)
+        });
+        let stdout = collect(stdout, state, BridgeResponse::StdoutChunk);
+        (stdout, stderr.join().unwrap())
+    });
+    Captured {
+        stdout,
+        stderr,
+        overflowed: state.overflowed.load(Ordering::SeqCst),
+    }
+}
+
+// Reads one pipe, holding its output until there is too much of it. Returns what is still
+// held at the end, which is nothing once the output has gone out in chunks.
+fn collect<R: Read>(mut pipe: R, state: &State, wrap: fn(Vec<u8>) -> BridgeResponse) -> Vec<u8> {
+    let mut held = Vec::new();
+    let mut buffer = vec![0u8; state.chunk_size];
+    loop {
+        let n = match pipe.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        if state.overflowed.load(Ordering::SeqCst) {
+            // Drained until the killed group closes the pipe.
+            continue;
+        }
+        held.extend_from_slice(&buffer[..n]);
+        if !state.spilled.load(Ordering::SeqCst) {
+            if state.held.fetch_add(n, Ordering::SeqCst) + n <= state.limit {
+                continue;
+            }
+            if state.spill.is_none() {
+                state.overflowed.store(true, Ordering::SeqCst);
+                state.group.kill();
+                held = Vec::new();
+                continue;
+            }
+            state.spilled.store(true, Ordering::SeqCst);
+        }
+        if !send(&mut held, state, wrap) {
+            break;
+        }
+    }
+    // The other pipe may have spilled while this one was waiting for output.
+    if state.spilled.load(Ordering::SeqCst) {
+        send(&mut held, state, wrap);
+    }
+    held
+}
+
+// Sends what `held` holds as chunks. Returns false, with the group cancelled, if the client
+// is gone.
+fn send(held: &mut Vec<u8>, state: &State, wrap: fn(Vec<u8>) -> BridgeResponse) -> bool {
+    let Some(spill) = state.spill else {
+        return true;
+    };
+    for piece in mem::take(held).chunks(state.chunk_size) {
+        if spill(wrap(piece.to_vec())).is_err() {
+            state.group.cancel();
+            return false;
+        }
+    }
+    true
+}
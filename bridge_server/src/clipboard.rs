@@ -0,0 +1,216 @@
+// Clipboard access through the host's clipboard service.
+// Android 13 added `cmd clipboard`; older releases only expose the raw binder interface
+// through `service call`, whose transaction codes and reply layout vary per release.
+
+use std::process::Command;
+
+use bridge_core::{BridgeResponse, ErrorKind};
+
+use crate::failure;
+
+// First SDK level whose clipboard service implements shell commands.
+const CMD_CLIPBOARD_SDK: u32 = 33;
+
+// Package reported to the clipboard service as the caller.
+const CALLING_PACKAGE: &str = "com.android.shell";
+
+pub fn get() -> BridgeResponse {
+    let sdk = sdk_version();
+    let result = if sdk >= CMD_CLIPBOARD_SDK {
+        run(Command::new("cmd").args(["clipboard", "get-primary-clip"])).and_then(parse_cmd_clip)
+    } else {
+        service_call_get(sdk)
+    };
+    match result {
+        Ok(text) => BridgeResponse::Success(text),
+        Err(e) => failure(ErrorKind::Internal, format!("Clipboard read failed: {}", e)),
+    }
+}
+
+pub fn set(text: String) -> BridgeResponse {
+    let sdk = sdk_version();
+    if sdk < CMD_CLIPBOARD_SDK {
+        return failure(
+            ErrorKind::Internal,
+            format!(
+                "Setting the clipboard needs Android 13 (SDK {}) or newer; this device is SDK {}",
+                CMD_CLIPBOARD_SDK, sdk
+            ),
+        );
+    }
+    // Passed as a single argument, so the text never goes through a shell.
+    match run(Command::new("cmd").args(["clipboard", "set-primary-clip-text", &text])) {
+        Ok(_) => BridgeResponse::Success(String::new()),
+        Err(e) => failure(
+            ErrorKind::Internal,
+            format!("Clipboard write failed: {}", e),
+        ),
+    }
+}
+
+fn sdk_version() -> u32 {
+    Command::new("getprop")
+        .arg("ro.build.version.sdk")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+        .unwrap_or(0)
+}
+
+// Runs a host tool and returns its stdout, turning a failed exit into its stderr.
+fn run(command: &mut Command) -> Result<String, String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = if stderr.trim().is_empty() {
+            stdout.trim()
+        } else {
+            stderr.trim()
+        };
+        return Err(message.to_string());
+    }
+    Ok(stdout)
+}
+
+// `cmd clipboard get-primary-clip` prints the clip description and its items, e.g.
+// `ClipData { text/plain "label" {T:hello} }`. Only text items (`T:`) are returned.
+fn parse_cmd_clip(output: String) -> Result<String, String> {
+    let output = output.trim_end_matches('\n');
+    if output.is_empty() || output == "null" {
+        return Ok(String::new());
+    }
+    match (output.find("{T:"), output.rfind("} }")) {
+        (Some(start), Some(end)) if start + 3 <= end => Ok(output[start + 3..end].to_string()),
+        _ => Err(format!(
+            "clipboard holds non-text content ({})",
+            clip_kind(output)
+        )),
+    }
+}
+
+// Best-effort description of a non-text clip, such as `U:content://...` or `I:Intent`.
+fn clip_kind(clip: &str) -> &str {
+    for (marker, kind) in [("{U:", "a URI"), ("{I:", "an intent"), ("{H:", "HTML")] {
+        if clip.contains(marker) {
+            return kind;
+        }
+    }
+    "unknown type"
+}
+
+// Reads the clip with `service call clipboard <getPrimaryClip> ...` and decodes the
+// reply parcel. Codes are the position of getPrimaryClip in IClipboard.aidl.
+fn service_call_get(sdk: u32) -> Result<String, String> {
+    let mut command = Command::new("service");
+    command.args(["call", "clipboard"]);
+    if sdk >= 29 {
+        // Android 10 added clearPrimaryClip before it and a userId parameter.
+        command.args(["3", "s16", CALLING_PACKAGE, "i32", "0"]);
+    } else {
+        command.args(["2", "s16", CALLING_PACKAGE]);
+    }
+    let parcel = parse_parcel_dump(&run(&mut command)?)?;
+    clip_text(&parcel)
+}
+
+// Turns `service call` output (`Result: Parcel(0x00000000: 00000000 00000001 '........')`)
+// into the raw parcel bytes. Each column is a little endian 32-bit word.
+fn parse_parcel_dump(output: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for line in output.lines() {
+        // Words sit between the `0x...:` offset and the quoted ASCII preview.
+        let line = line.split('\'').next().unwrap_or("");
+        let words = match line
+            .find("0x")
+            .and_then(|at| line[at..].find(": ").map(|n| at + n))
+        {
+            Some(at) => &line[at + 2..],
+            None => continue,
+        };
+        for word in words.split_whitespace() {
+            let value = u32::from_str_radix(word, 16)
+                .map_err(|_| format!("unexpected service output: {}", output.trim()))?;
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    if bytes.is_empty() {
+        return Err(format!("unexpected service output: {}", output.trim()));
+    }
+    Ok(bytes)
+}
+
+// Extracts the text of a ClipData reply. The exact layout differs between releases, so
+// rather than decoding every field the parcel is scanned for its strings: the MIME types
+// come first, followed by the item text.
+fn clip_text(parcel: &[u8]) -> Result<String, String> {
+    let word = |at: usize| {
+        parcel
+            .get(at..at + 4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    // Exception code, then whether a clip is present at all.
+    if word(0) != Some(0) {
+        return Err("the clipboard service rejected the request".to_string());
+    }
+    if word(4) == Some(0) {
+        return Ok(String::new());
+    }
+
+    let strings = parcel_strings(parcel);
+    let mime_types: Vec<&String> = strings.iter().filter(|s| is_mime_type(s)).collect();
+    if !mime_types.iter().any(|mime| mime.starts_with("text/plain")) {
+        let kinds: Vec<&str> = mime_types.iter().map(|s| s.as_str()).collect();
+        return Err(format!(
+            "clipboard holds non-text content ({})",
+            kinds.join(", ")
+        ));
+    }
+    let last_mime = strings.iter().rposition(|s| is_mime_type(s)).unwrap();
+    Ok(strings.get(last_mime + 1).cloned().unwrap_or_default())
+}
+
+fn is_mime_type(s: &str) -> bool {
+    ["text/", "image/", "application/", "video/", "audio/"]
+        .iter()
+        .any(|prefix| s.starts_with(prefix))
+        && !s.contains(char::is_whitespace)
+}
+
+// Small integers also look like short strings (a count of 1 followed by a length word
+// reads as a one character string), so control characters and blank strings are skipped.
+fn is_plausible_text(s: &str) -> bool {
+    !s.trim().is_empty()
+        && !s
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+}
+
+// Finds every String16 (32-bit length, UTF-16 code units, NUL, padding to 4 bytes) in the
+// parcel, in order.
+fn parcel_strings(parcel: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut at = 0;
+    while at + 4 <= parcel.len() {
+        let len = u32::from_le_bytes([parcel[at], parcel[at + 1], parcel[at + 2], parcel[at + 3]])
+            as usize;
+        let end = at + 4 + (len + 1) * 2;
+        if len > 0 && end <= parcel.len() {
+            let units: Vec<u16> = parcel[at + 4..end]
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            if units[len] == 0
+                && !units[..len].contains(&0)
+                && let Ok(s) = String::from_utf16(&units[..len])
+                && is_plausible_text(&s)
+            {
+                strings.push(s);
+                at = (end + 3) & !3;
+                continue;
+            }
+        }
+        at += 4;
+    }
+    strings
+}
@@ -0,0 +1,276 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::log::Level;
+
+// Runtime settings for the bridge server, parsed from the command line.
+#[derive(Parser, Debug)]
+#[command(name = "bridge_server")]
+#[command(about = "NativeBridge Server for the Android Host", long_about = None)]
+pub struct Config {
+    /// Unix socket to listen on; must be reachable from inside the chroot. `@name` uses an
+    /// abstract socket instead, reachable from any process without a file
+    #[arg(long, value_name = "PATH", env = "BRIDGE_SOCKET", default_value = crate::SOCKET_PATH)]
+    pub socket: PathBuf,
+
+    /// Also accept clients over TCP, e.g. `tcp://127.0.0.1:5959`; requires --auth-token-file.
+    /// The Unix socket stays available
+    #[arg(long, value_name = "tcp://HOST:PORT", value_parser = parse_listen)]
+    pub listen: Option<String>,
+
+    /// Largest request frame accepted; a client sending a bigger one gets a FrameTooLarge
+    /// error and is disconnected. Transfers and stdin arrive in chunks well below it. It
+    /// stays under 16 MiB so requests from pre-framing clients are still refused at once
+    #[arg(long, value_name = "BYTES", default_value_t = bridge_core::proto::MAX_REQUEST_LEN,
+          value_parser = clap::value_parser!(u64).range(1024 * 1024..bridge_core::proto::LEGACY_FRAME_LEN))]
+    pub max_frame_size: u64,
+
+    /// Maximum number of bytes sent per stream output chunk
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024,
+          value_parser = clap::value_parser!(u32).range(1..=16 * 1024 * 1024))]
+    pub chunk_size: u32,
+
+    /// Wait this long for more output before sending a stream chunk that is not yet full, so
+    /// very chatty commands take far fewer frames at the cost of that much latency. 0 sends
+    /// output as soon as it is read
+    #[arg(long, value_name = "MS", default_value_t = 0,
+          value_parser = clap::value_parser!(u64).range(..=1000))]
+    pub stream_coalesce_ms: u64,
+
+    /// How much output of an Exec is held for its single response; more is sent ahead of it
+    /// in stream chunks, so huge outputs never sit in the server's memory whole
+    #[arg(long, value_name = "BYTES", default_value_t = 1024 * 1024,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub exec_buffer: u32,
+
+    /// How long a long-pressed key is held down
+    #[arg(long, value_name = "MS", default_value_t = 800)]
+    pub long_press_ms: u64,
+
+    /// Touch screen event device (e.g. /dev/input/event4); detected automatically if unset
+    #[arg(long, value_name = "PATH", env = "BRIDGE_TOUCH_DEVICE")]
+    pub touch_device: Option<PathBuf>,
+
+    /// Event device keys are injected into (e.g. /dev/input/event2); if unset, the device
+    /// declaring the most of the keys `andro key` knows
+    #[arg(long, value_name = "PATH", env = "BRIDGE_KEY_DEVICE")]
+    pub key_device: Option<PathBuf>,
+
+    /// Pass tap/swipe coordinates to the touch device unchanged instead of scaling
+    /// screen pixels to the device's coordinate range
+    #[arg(long)]
+    pub no_scale: bool,
+
+    /// Screen size in pixels used for scaling (e.g. 1080x2400); read from `wm size` if unset
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    pub screen_size: Option<(u32, u32)>,
+
+    /// Mirror the touch X axis, for panels mounted the other way around
+    #[arg(long)]
+    pub invert_x: bool,
+
+    /// Mirror the touch Y axis
+    #[arg(long)]
+    pub invert_y: bool,
+
+    /// Look up the display rotation (from `dumpsys input`) before input commands, so
+    /// coordinates follow the screen in landscape
+    #[arg(long)]
+    pub auto_rotate: bool,
+
+    /// Do not inject anything: input commands answer with the events they would write
+    #[arg(long)]
+    pub input_dry_run: bool,
+
+    /// Pause after each tap before answering, for devices that drop taps sent back to back
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub tap_settle_ms: u64,
+
+    /// Where direct input goes: the real event devices, virtual devices created through
+    /// /dev/uinput, or auto to use uinput only when the event devices cannot be opened
+    #[arg(long, value_enum, default_value_t = InputBackend::Auto)]
+    pub input_backend: InputBackend,
+
+    /// Permission bits of the socket (octal); 777 lets every user in the chroot connect
+    #[arg(long, value_name = "MODE", default_value = "777", value_parser = parse_mode)]
+    pub socket_mode: u32,
+
+    /// Group owning the socket (numeric gid), e.g. with --socket-mode 660 to only admit a
+    /// chroot user in that group
+    #[arg(long, value_name = "GID")]
+    pub socket_group: Option<u32>,
+
+    /// Only accept clients presenting the token stored in this file (ANDRO_TOKEN or
+    /// --token-file on the client); a new random token is written to it if it does not exist
+    #[arg(long, value_name = "PATH")]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Only accept clients running as this uid (repeatable); rejected uids are logged
+    #[arg(long, value_name = "UID")]
+    pub allow_uid: Vec<u32>,
+
+    /// Only run these host programs (repeatable), e.g. `input` or `am start` to also require
+    /// the leading arguments; names must match exactly. Everything is allowed if unset
+    #[arg(long, value_name = "PROGRAM [ARGS]")]
+    pub allow_program: Vec<String>,
+
+    /// Only push and pull files under these directories (repeatable). While any policy is set
+    /// (this or --allow-program), transfers anywhere else are refused
+    #[arg(long, value_name = "DIR")]
+    pub allow_path: Vec<PathBuf>,
+
+    /// Let clients send signals to any process by pid (`andro signal PID SIG`), not just to
+    /// their jobs and requests. With a server running as root that is root's `kill`
+    #[arg(long)]
+    pub allow_raw_pid_signals: bool,
+
+    /// Run in the background, detached from the terminal; the log goes to --log-file.
+    /// Exits once the server is listening, or with 1 if it cannot start
+    #[arg(long, conflicts_with_all = ["stop", "status"])]
+    pub daemon: bool,
+
+    /// Write the server's pid to this file and keep it locked while running; a second
+    /// server with the same pidfile refuses to start
+    #[arg(long, value_name = "PATH")]
+    pub pidfile: Option<PathBuf>,
+
+    /// Stop the server holding --pidfile (SIGTERM) and wait for it to exit
+    #[arg(long, requires = "pidfile", conflicts_with = "status")]
+    pub stop: bool,
+
+    /// Tell whether a server holds --pidfile: exit code 0 if one does, 1 if not
+    #[arg(long, requires = "pidfile")]
+    pub status: bool,
+
+    /// On SIGTERM or SIGINT, how long the running commands (sent SIGTERM) and their
+    /// connections get to finish before the server exits anyway
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    pub shutdown_grace_ms: u64,
+
+    /// How much output of each background job (`andro job start`) is kept for `andro job
+    /// logs`; older output is dropped once a job has written more
+    #[arg(long, value_name = "BYTES", default_value_t = 1024 * 1024,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub job_output_limit: u32,
+
+    /// Leave background jobs running when the server shuts down instead of stopping them
+    /// with the other commands. Their output is lost from then on
+    #[arg(long)]
+    pub keep_jobs: bool,
+
+    /// Close a shell session (`andro session`) that has not run a line for this many
+    /// seconds; 0 keeps sessions until they are closed
+    #[arg(long, value_name = "SECS", default_value_t = 900)]
+    pub session_ttl: u64,
+
+    /// How many clients are served at once; further connections wait until one closes
+    #[arg(long, value_name = "N", default_value_t = 32,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_connections: u32,
+
+    /// How many Tagged requests one multiplexed connection may have running at once; more
+    /// get a Busy error until some of them finish
+    #[arg(long, value_name = "N", default_value_t = 64,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_in_flight: u32,
+
+    /// How many host processes may run at once, for all clients together (0 = no limit).
+    /// Background jobs and shell sessions count while they run
+    #[arg(long, value_name = "N", default_value_t = 32)]
+    pub max_jobs: u32,
+
+    /// What a command that would exceed --max-jobs does: wait for a process to exit, or
+    /// fail right away with a Busy error
+    #[arg(long, value_enum, default_value_t = WhenBusy::Queue)]
+    pub when_busy: WhenBusy,
+
+    /// How many commands may wait for a process slot with `--when-busy queue`; more get
+    /// the Busy error
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    pub job_queue: u32,
+
+    /// The longest Sleep a client may ask for; longer ones are refused so a client cannot
+    /// hold a connection slot with a sleep that never ends
+    #[arg(long, value_name = "MS", default_value_t = 60_000)]
+    pub max_sleep_ms: u64,
+
+    /// Close a connection that has not sent a complete request for this many seconds
+    /// (0 = never). Running commands, streams included, keep their connection open
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub idle_timeout: u64,
+
+    /// How much to log: warn leaves out the line per command, debug adds connections
+    /// opening and closing, trace every request
+    #[arg(long, value_enum, default_value_t = Level::Info)]
+    pub log_level: Level,
+
+    /// Append the log to this file instead of printing it
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Log how many arguments a command has instead of what they are, for commands that
+    /// carry secrets
+    #[arg(long)]
+    pub log_redact_args: bool,
+
+    /// The token loaded from --auth-token-file
+    #[arg(skip)]
+    pub auth_token: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum InputBackend {
+    Auto,
+    Evdev,
+    Uinput,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum WhenBusy {
+    Queue,
+    Reject,
+}
+
+// Parses an octal permission mode such as `660` or `0777`.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("expected an octal mode like 660, got '{}'", value)),
+    }
+}
+
+// Strips the `tcp://` scheme, leaving the address to bind.
+fn parse_listen(value: &str) -> Result<String, String> {
+    bridge_core::transport::tcp_address(value)
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected tcp://HOST:PORT, got '{}'", value))
+}
+
+// Parses "1080x2400" into (width, height).
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got '{}'", value))?;
+    let parse = |n: &str| match n.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid dimension '{}'", n)),
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_takes_a_tcp_address() {
+        assert_eq!(
+            parse_listen("tcp://127.0.0.1:5959"),
+            Ok("127.0.0.1:5959".to_string())
+        );
+        assert!(parse_listen("127.0.0.1:5959").is_err());
+        assert!(parse_listen("tcp://").is_err());
+        assert!(parse_listen("unix:///tmp/bridge.sock").is_err());
+    }
+}
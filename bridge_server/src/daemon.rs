@@ -0,0 +1,184 @@
+// --daemon, --pidfile, --stop and --status. The pidfile is flock()ed for as long as the
+// server runs, so a live instance is recognised by its lock rather than by the pid in the
+// file: a pidfile left behind by a crashed server is unlocked and simply taken over, and a
+// recycled pid is never mistaken for the server.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The locked pidfile of the running server.
+pub struct Pidfile {
+    file: File,
+}
+
+impl Pidfile {
+    // Locks `path` for this instance, or fails if another server holds it.
+    pub fn lock(path: &Path) -> io::Result<Pidfile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        if !try_lock(&file, libc::LOCK_EX)? {
+            let pid = read_pid(&mut file).map_or(String::new(), |pid| format!(" (pid {})", pid));
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("another server{} holds {}", pid, path.display()),
+            ));
+        }
+        Ok(Pidfile { file })
+    }
+
+    // Records the pid of this process, which after --daemon is the detached child.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", process::id())
+    }
+}
+
+// Takes `lock` without waiting; false if another process holds it.
+fn try_lock(file: &File, lock: libc::c_int) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), lock | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<libc::pid_t> {
+    let mut text = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+// The pid of the server holding `path`, or None if no server does.
+fn running(path: &Path) -> io::Result<Option<libc::pid_t>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if try_lock(&file, libc::LOCK_SH)? {
+        return Ok(None);
+    }
+    Ok(Some(read_pid(&mut file).ok_or_else(|| {
+        io::Error::other(format!("{} is locked but holds no pid", path.display()))
+    })?))
+}
+
+// --status: exit code 0 if a server holds the pidfile, 1 if not.
+pub fn status(path: &Path) -> io::Result<i32> {
+    match running(path)? {
+        Some(pid) => {
+            println!("Running (pid {})", pid);
+            Ok(0)
+        }
+        None if path.exists() => {
+            println!("Not running (stale pidfile {})", path.display());
+            Ok(1)
+        }
+        None => {
+            println!("Not running");
+            Ok(1)
+        }
+    }
+}
+
+// --stop: sends SIGTERM to the server holding the pidfile and waits until it has let go
+// of it, for at most `wait`.
+pub fn stop(path: &Path, wait: Duration) -> io::Result<i32> {
+    let Some(pid) = running(path)? else {
+        println!("Not running");
+        return Ok(1);
+    };
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let deadline = Instant::now() + wait;
+    while running(path)?.is_some() {
+        if Instant::now() >= deadline {
+            println!("Server (pid {}) still running after {:?}", pid, wait);
+            return Ok(1);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    println!("Stopped server (pid {})", pid);
+    Ok(0)
+}
+
+// The parent's side of --daemon: it waits until the child says it is serving, so that a
+// server that cannot start still fails the command that started it.
+pub struct Ready(OwnedFd);
+
+// Forks into the background. Only the child returns; the parent exits 0 once the child
+// calls `Ready::done`, or 1 if it exits before. Must be called before any thread starts.
+pub fn detach() -> io::Result<Ready> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // pipe2 returned two fresh descriptors.
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(read);
+            // A session of its own: no controlling terminal, and no SIGHUP when the shell
+            // that started it goes away.
+            unsafe { libc::setsid() };
+            Ok(Ready(write))
+        }
+        child => {
+            drop(write);
+            let mut byte = [0u8];
+            let mut read = File::from(read);
+            match read.read(&mut byte) {
+                Ok(1) => {
+                    println!("Started server (pid {})", child);
+                    process::exit(0)
+                }
+                _ => {
+                    eprintln!("Server failed to start");
+                    process::exit(1)
+                }
+            }
+        }
+    }
+}
+
+impl Ready {
+    // Tells the parent the server is up, and moves stdin, stdout and stderr away from the
+    // terminal: to /dev/null, or to `log_file` for output that bypasses the log.
+    pub fn done(self, log_file: Option<&Path>) -> io::Result<()> {
+        let null = File::open("/dev/null")?;
+        let output = match log_file {
+            Some(path) => OpenOptions::new().append(true).open(path)?,
+            None => OpenOptions::new().write(true).open("/dev/null")?,
+        };
+        unsafe {
+            libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+            libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO);
+            libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO);
+        }
+        let mut pipe = File::from(self.0);
+        pipe.write_all(&[1])
+    }
+}
+
+// Removes the pidfile on the way out; the lock goes with the process either way.
+pub fn remove(pidfile: Pidfile, path: &Path) {
+    let _ = fs::remove_file(path);
+    drop(pidfile);
+}
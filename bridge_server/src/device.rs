@@ -0,0 +1,169 @@
+// DeviceInfo: what scripts otherwise get by parsing `wm size`, `wm density`, `getprop` and
+// `dumpsys battery` themselves. Each probe runs once per request, side by side, and one that
+// fails leaves its fields None instead of failing the rest. What does not change while the
+// device runs (properties, screen size and density) is reused for a few seconds, so scripts
+// asking in a loop only pay for rotation, battery and uptime.
+
+use std::collections::HashMap;
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::{Battery, DeviceInfo, ScreenSize};
+
+use crate::prop;
+
+const STATIC_TTL: Duration = Duration::from_secs(5);
+
+// The parts of DeviceInfo from getprop and wm, with when they were read.
+static STATIC: Mutex<Option<(Instant, DeviceInfo)>> = Mutex::new(None);
+
+pub fn info() -> DeviceInfo {
+    let (info, rotation, battery) = thread::scope(|scope| {
+        let rotation = scope.spawn(|| display_rotation().ok());
+        let battery = scope.spawn(battery);
+        (static_info(), rotation.join(), battery.join())
+    });
+    DeviceInfo {
+        rotation: rotation.ok().flatten(),
+        battery: battery.ok().flatten(),
+        uptime_secs: uptime(),
+        ..info
+    }
+}
+
+fn static_info() -> DeviceInfo {
+    let mut cached = STATIC.lock().unwrap();
+    if let Some((at, info)) = &*cached
+        && at.elapsed() < STATIC_TTL
+    {
+        return info.clone();
+    }
+    let props = prop::dump().unwrap_or_default();
+    let prop = |name: &str| props.get(name).filter(|value| !value.is_empty()).cloned();
+    let (screen, screen_override) = wm("size", |value| {
+        let (width, height) = value.split_once('x')?;
+        Some(ScreenSize {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        })
+    });
+    let (density, density_override) = wm("density", |value| value.parse().ok());
+    let info = DeviceInfo {
+        manufacturer: prop("ro.product.manufacturer"),
+        model: prop("ro.product.model"),
+        android_version: prop("ro.build.version.release"),
+        sdk: prop("ro.build.version.sdk").and_then(|sdk| sdk.parse().ok()),
+        screen,
+        screen_override: screen_override.filter(|&size| Some(size) != screen),
+        density,
+        density_override: density_override.filter(|&dpi| Some(dpi) != density),
+        ..DeviceInfo::default()
+    };
+    *cached = Some((Instant::now(), info.clone()));
+    info
+}
+
+// The physical and override values of `wm size` or `wm density`, which print
+// "Physical size: 1080x2400" and, once changed, "Override size: 720x1600".
+fn wm<T>(what: &str, parse: impl Fn(&str) -> Option<T>) -> (Option<T>, Option<T>) {
+    let Some(stdout) = output(Command::new("wm").arg(what)) else {
+        return (None, None);
+    };
+    let value = |prefix: String| {
+        stdout
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(&prefix))
+            .and_then(|value| parse(value.trim()))
+    };
+    (
+        value(format!("Physical {}:", what)),
+        value(format!("Override {}:", what)),
+    )
+}
+
+// Reads the rotation of the built-in display from `dumpsys input`: "SurfaceOrientation: 1"
+// up to Android 11, the "orientation=1" field of the INTERNAL viewport on later versions.
+pub fn display_rotation() -> io::Result<u8> {
+    let output = Command::new("dumpsys").arg("input").output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let legacy = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("SurfaceOrientation:"));
+    let viewport = || {
+        stdout
+            .lines()
+            .filter(|line| line.contains("INTERNAL"))
+            .find_map(|line| line.split_once("orientation=").map(|(_, rest)| rest))
+    };
+    let value = legacy.or_else(viewport).ok_or_else(|| {
+        io::Error::other("no display orientation in `dumpsys input` (use `andro rotation set`)")
+    })?;
+    let digits: String = value
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    match digits.parse() {
+        Ok(rotation) if rotation <= 3 => Ok(rotation),
+        _ => Err(io::Error::other(format!(
+            "unexpected display orientation '{}'",
+            value.trim()
+        ))),
+    }
+}
+
+// From `dumpsys battery`, whose "Current Battery Service state" lists `name: value` fields.
+// None without a battery ("present: false"), as on some emulators and TV boxes.
+fn battery() -> Option<Battery> {
+    let stdout = output(Command::new("dumpsys").arg("battery"))?;
+    let fields: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.trim().split_once(": "))
+        .collect();
+    if fields.get("present") == Some(&"false") {
+        return None;
+    }
+    let level: u32 = fields.get("level")?.parse().ok()?;
+    let scale: u32 = match fields.get("scale").and_then(|scale| scale.parse().ok()) {
+        Some(0) | None => 100,
+        Some(scale) => scale,
+    };
+    let plugged = [
+        ("AC powered", "ac"),
+        ("USB powered", "usb"),
+        ("Wireless powered", "wireless"),
+        ("Dock powered", "dock"),
+    ]
+    .into_iter()
+    .find(|(field, _)| fields.get(field) == Some(&"true"))
+    .map(|(_, source)| source.to_string());
+    Some(Battery {
+        level: (level * 100 / scale).min(100) as u8,
+        // BatteryManager.BATTERY_STATUS_CHARGING.
+        charging: fields.get("status") == Some(&"2"),
+        plugged,
+    })
+}
+
+// Seconds since boot, the first field of /proc/uptime.
+fn uptime() -> Option<u64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let secs: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+// The stdout of a host tool that exited successfully.
+fn output(command: &mut Command) -> Option<String> {
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,232 @@
+// Queries on Linux event devices (/dev/input/event*), through the evdev ioctls.
+#![cfg(feature = "direct_input")]
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const EV_KEY: usize = 1;
+const EV_ABS: usize = 3;
+const KEY_CNT: usize = 0x300;
+const ABS_CNT: usize = 0x40;
+pub const ABS_MT_SLOT: u16 = 47;
+pub const ABS_MT_POSITION_X: u16 = 53;
+pub const ABS_MT_POSITION_Y: u16 = 54;
+
+// Mirrors the kernel's `struct input_absinfo`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct AbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+// Whether the device reports the absolute axis `code`. False if the ioctl fails,
+// e.g. because `file` is not an event device.
+pub fn has_abs(file: &File, code: u16) -> bool {
+    let mut bits = [0u8; ABS_CNT / 8];
+    // EVIOCGBIT(EV_ABS, len): bitmask of the absolute axes the device supports.
+    let request = ioc_read(0x20 + EV_ABS, bits.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, bits.as_mut_ptr()) } < 0 {
+        return false;
+    }
+    let code = code as usize;
+    bits[code / 8] & (1 << (code % 8)) != 0
+}
+
+// How many of the key `codes` the device declares; 0 if the ioctl fails.
+pub fn count_keys(file: &File, codes: &[u16]) -> usize {
+    let mut bits = [0u8; KEY_CNT / 8];
+    // EVIOCGBIT(EV_KEY, len): bitmask of the keys the device supports.
+    let request = ioc_read(0x20 + EV_KEY, bits.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, bits.as_mut_ptr()) } < 0 {
+        return 0;
+    }
+    codes
+        .iter()
+        .map(|&code| code as usize)
+        .filter(|&code| code < KEY_CNT && bits[code / 8] & (1 << (code % 8)) != 0)
+        .count()
+}
+
+// Range and current value of the absolute axis `code` (EVIOCGABS).
+pub fn abs_info(file: &File, code: u16) -> Option<AbsInfo> {
+    let mut info = AbsInfo::default();
+    let request = ioc_read(0x40 + code as usize, mem::size_of::<AbsInfo>());
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, &mut info as *mut AbsInfo) };
+    (result >= 0).then_some(info)
+}
+
+// The device name reported by the driver (EVIOCGNAME), e.g. "fts_ts".
+pub fn name(file: &File) -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let request = ioc_read(0x06, buffer.len());
+    let len = unsafe { libc::ioctl(file.as_raw_fd(), request as _, buffer.as_mut_ptr()) };
+    if len < 0 {
+        return None;
+    }
+    let name = &buffer[..(len as usize).min(buffer.len())];
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+// Every /dev/input/event* node that can be opened, with its name, in numeric order.
+pub fn list_devices() -> io::Result<Vec<(PathBuf, File, String)>> {
+    let mut devices = Vec::new();
+    let entries = fs::read_dir("/dev/input")
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot list /dev/input: {}", e)))?;
+    for entry in entries {
+        let path = entry?.path();
+        let is_event = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+        if !is_event {
+            continue;
+        }
+        if let Ok(file) = File::open(&path) {
+            let name = name(&file).unwrap_or_default();
+            devices.push((path, file, name));
+        }
+    }
+    devices.sort_by_key(|(path, _, _)| event_number(path));
+    Ok(devices)
+}
+
+// Finds the touch screen: a device reporting ABS_MT_POSITION_X and Y. When there are
+// several, one whose name suggests a touch screen ("touch", "ts") wins, then the first.
+pub fn find_touchscreen() -> io::Result<PathBuf> {
+    let devices = list_devices()?;
+    let mut candidates: Vec<_> = devices
+        .iter()
+        .filter(|(_, file, _)| has_abs(file, ABS_MT_POSITION_X) && has_abs(file, ABS_MT_POSITION_Y))
+        .collect();
+    candidates.sort_by_key(|(_, _, name)| !looks_like_touchscreen(name));
+    if let Some((path, _, _)) = candidates.first() {
+        return Ok(path.clone());
+    }
+
+    let listing: Vec<String> = devices
+        .iter()
+        .map(|(path, _, name)| format!("{} ({})", path.display(), name))
+        .collect();
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "no touch screen found among the input devices [{}]; pass the right one with \
+             --touch-device (see `getevent -pl`)",
+            listing.join(", ")
+        ),
+    ))
+}
+
+// Finds the device keys go to: the one declaring the most of `codes`, the first of them on a
+// tie. Power and volume usually sit on `gpio-keys` or the PMIC's device, not on event0.
+pub fn find_key_device(codes: &[u16]) -> io::Result<PathBuf> {
+    let devices = list_devices()?;
+    let best = devices
+        .iter()
+        .map(|(path, file, _)| (count_keys(file, codes), path))
+        .filter(|(count, _)| *count > 0)
+        // max_by_key keeps the last of equals, so the first device wins once reversed.
+        .rev()
+        .max_by_key(|(count, _)| *count);
+    if let Some((_, path)) = best {
+        return Ok(path.clone());
+    }
+
+    let listing: Vec<String> = devices
+        .iter()
+        .map(|(path, _, name)| format!("{} ({})", path.display(), name))
+        .collect();
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "no key device found among the input devices [{}]; pass the right one with \
+             --key-device (see `getevent -pl`)",
+            listing.join(", ")
+        ),
+    ))
+}
+
+// Checks that `path` can be used to inject touches, describing the problem if not.
+pub fn check_touchscreen(path: &Path) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("cannot open {} for writing: {}", path.display(), e))?;
+    if !has_abs(&file, ABS_MT_POSITION_X) || !has_abs(&file, ABS_MT_POSITION_Y) {
+        return Err(format!(
+            "{} does not report ABS_MT_POSITION_X/Y, it is probably not a touch screen",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+fn looks_like_touchscreen(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("touch")
+        || name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == "ts")
+}
+
+fn event_number(path: &Path) -> u32 {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("event"))
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(u32::MAX)
+}
+
+// The kernel's _IOR('E', nr, size) for the evdev ioctls.
+fn ioc_read(nr: usize, size: usize) -> u64 {
+    const IOC_READ: u64 = 2;
+    (IOC_READ << 30) | ((size as u64) << 16) | ((b'E' as u64) << 8) | nr as u64
+}
+
+// Describes an event the way `getevent -l` does, e.g. "EV_ABS ABS_MT_POSITION_X 540".
+// Types and codes without a name here are shown as hex numbers.
+pub fn describe(type_: u16, code: u16, value: i32) -> String {
+    let type_name = match type_ {
+        0 => "EV_SYN".to_string(),
+        1 => "EV_KEY".to_string(),
+        2 => "EV_REL".to_string(),
+        3 => "EV_ABS".to_string(),
+        _ => format!("{:04x}", type_),
+    };
+    let code_name = match (type_, code) {
+        (0, 0) => "SYN_REPORT",
+        (0, 2) => "SYN_MT_REPORT",
+        (1, 0x110) => "BTN_LEFT",
+        (1, 0x111) => "BTN_RIGHT",
+        (1, 0x112) => "BTN_MIDDLE",
+        (1, 0x14a) => "BTN_TOUCH",
+        (2, 0) => "REL_X",
+        (2, 1) => "REL_Y",
+        (2, 8) => "REL_WHEEL",
+        (3, 0) => "ABS_X",
+        (3, 1) => "ABS_Y",
+        (3, 0x2f) => "ABS_MT_SLOT",
+        (3, 0x30) => "ABS_MT_TOUCH_MAJOR",
+        (3, 0x35) => "ABS_MT_POSITION_X",
+        (3, 0x36) => "ABS_MT_POSITION_Y",
+        (3, 0x39) => "ABS_MT_TRACKING_ID",
+        (3, 0x3a) => "ABS_MT_PRESSURE",
+        _ => "",
+    };
+    // Keys are named after the Android key they map to, if any.
+    let code_name = match bridge_core::keys::KEYS.iter().find(|key| key.linux == code) {
+        Some(key) if type_ == 1 && code_name.is_empty() => format!("KEY {} ({})", code, key.name),
+        _ if code_name.is_empty() => format!("{:04x}", code),
+        _ => code_name.to_string(),
+    };
+    format!("{} {} {}", type_name, code_name, value)
+}
@@ -0,0 +1,156 @@
+// --idle-timeout: how long a connection may go without delivering a complete request, be it
+// a client that connects and sends nothing or one that stops halfway through a frame. A read
+// timeout on the socket only bounds a single read, so each frame gets a deadline instead, and
+// every read waits for what is left of it. The timeout is lifted again once the frame is in,
+// which leaves streams, uploads and stdin to run as long as they need.
+
+use std::io::{self, ErrorKind, Read};
+use std::time::{Duration, Instant};
+
+use bridge_core::transport::Transport;
+
+use crate::config::Config;
+
+pub fn limit(config: &Config) -> Option<Duration> {
+    (config.idle_timeout > 0).then(|| Duration::from_secs(config.idle_timeout))
+}
+
+// Reads one frame from `socket` within `limit`. While `busy` says the connection still has
+// requests running (a multiplexed Stream, say), the deadline is pushed back instead, so only
+// a connection that really has nothing going on is closed.
+pub struct FrameReader<'a> {
+    socket: &'a mut dyn Transport,
+    limit: Duration,
+    deadline: Instant,
+    busy: &'a dyn Fn() -> bool,
+}
+
+impl<'a> FrameReader<'a> {
+    pub fn new(socket: &'a mut dyn Transport, limit: Duration, busy: &'a dyn Fn() -> bool) -> Self {
+        FrameReader {
+            socket,
+            limit,
+            deadline: Instant::now() + limit,
+            busy,
+        }
+    }
+}
+
+impl Read for FrameReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let left = self.deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                if (self.busy)() {
+                    self.deadline = Instant::now() + self.limit;
+                    continue;
+                }
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(left))?;
+            match self.socket.read(buf) {
+                // The read timeout ran out: check the deadline again. Anything else, an
+                // error included, is the caller's.
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Drop for FrameReader<'_> {
+    fn drop(&mut self) {
+        let _ = self.socket.set_read_timeout(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use bridge_core::proto::{FrameError, MAX_REQUEST_LEN, read_frame, write_frame};
+
+    const LIMIT: Duration = Duration::from_millis(100);
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, payload).unwrap();
+        frame
+    }
+
+    fn timed_out(result: Result<Option<Vec<u8>>, FrameError>) -> bool {
+        matches!(result, Err(FrameError::Io(e)) if e.kind() == ErrorKind::TimedOut)
+    }
+
+    #[test]
+    fn silent_client_times_out() {
+        let (mut server, _client) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+        let result = read_frame(&mut FrameReader::new(&mut server, LIMIT, &|| false), 1024);
+        assert!(timed_out(result));
+        assert!(started.elapsed() >= LIMIT);
+        // Lifted again for whatever uses the connection next.
+        assert_eq!(server.read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn half_a_frame_times_out() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(&frame(b"half a frame")[..12]).unwrap();
+        let result = read_frame(&mut FrameReader::new(&mut server, LIMIT, &|| false), 1024);
+        assert!(timed_out(result));
+    }
+
+    #[test]
+    fn deadline_covers_the_whole_frame_not_each_read() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        // Every byte well within the limit, the frame as a whole not.
+        let trickle = thread::spawn(move || {
+            for byte in frame(b"slow") {
+                thread::sleep(LIMIT / 4);
+                if client.write_all(&[byte]).is_err() {
+                    break;
+                }
+            }
+        });
+        let result = read_frame(&mut FrameReader::new(&mut server, LIMIT, &|| false), 1024);
+        assert!(timed_out(result));
+        drop(server);
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn a_frame_in_time_is_read() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(LIMIT / 2);
+            client.write_all(&frame(b"ok")).unwrap();
+        });
+        let mut reader = FrameReader::new(&mut server, LIMIT, &|| false);
+        assert_eq!(
+            read_frame(&mut reader, MAX_REQUEST_LEN).unwrap(),
+            Some(b"ok".to_vec())
+        );
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn busy_connections_are_given_more_time() {
+        let (mut server, _client) = UnixStream::pair().unwrap();
+        let checks = Cell::new(0);
+        let busy = || {
+            checks.set(checks.get() + 1);
+            checks.get() <= 2
+        };
+        let started = Instant::now();
+        let result = read_frame(&mut FrameReader::new(&mut server, LIMIT, &busy), 1024);
+        assert!(timed_out(result));
+        // Twice pushed back, then closed on the third deadline.
+        assert_eq!(checks.get(), 3);
+        assert!(started.elapsed() >= LIMIT * 3);
+    }
+}
@@ -1,98 +1,1957 @@
 // This module will only be compiled if the `--features "direct_input"` flag is used.
 #![cfg(feature = "direct_input")]
 
-use std::fs::OpenOptions;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::mem;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, TryLockError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-// Input device configuration.
-// This path is vital and must match the event device for the touchscreen on the target system.
-// To find it, run `getevent -pl` in the Android shell and look for the device
-// that has `ABS_MT_POSITION_X` and `ABS_MT_POSITION_Y` events.
-const TOUCH_DEVICE: &str = "/dev/input/event1";
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse, Coord, Easing, ErrorKind, Gesture, Point, keys};
+
+use crate::config::{Config, InputBackend};
+use crate::evdev::{self, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT, AbsInfo};
+use crate::log;
+use crate::rotation;
+use crate::uinput::{Capabilities, VirtualDevice};
+use crate::{failure, write_response};
+
+// The touch screen is normally found automatically (see `evdev::find_touchscreen`).
+// Running the server with `--touch-device` (or BRIDGE_TOUCH_DEVICE) pins it instead;
+// `getevent -pl` in the Android shell lists the devices, the touch screen is the one that
+// has `ABS_MT_POSITION_X` and `ABS_MT_POSITION_Y` events.
+static TOUCH_DEVICE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// Screen size in pixels, from `wm size` unless configured; looked up once.
+static SCREEN_SIZE: Mutex<Option<(i32, i32)>> = Mutex::new(None);
+
+// Coordinate handling options, set once at startup by `init`.
+#[derive(Default)]
+struct Options {
+    no_scale: bool,
+    invert_x: bool,
+    invert_y: bool,
+    // Pause after each tap, for devices that drop taps sent back to back.
+    tap_settle_ms: u64,
+    // Whether the touch device was given with --touch-device rather than detected.
+    touch_device_configured: bool,
+    // --input-dry-run: every input command is planned, never written.
+    dry_run: bool,
+}
+static OPTIONS: OnceLock<Options> = OnceLock::new();
+
+// The event plan of the dry run in progress on this thread, with its start time.
+// Each client is served on its own thread, so a dry run never captures another client's
+// input.
+thread_local! {
+    static PLAN: RefCell<Option<(Instant, String)>> = const { RefCell::new(None) };
+}
+
+// Where touch and key events are written, chosen once by `init`.
+enum Backend {
+    Evdev,
+    Uinput(VirtualInput),
+}
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+// Virtual touch screen and keyboard for devices whose event nodes cannot be written
+// (SELinux) or whose driver ignores injected events. They live as long as the server:
+// the kernel removes them once the process exits and their descriptors are closed.
+struct VirtualInput {
+    touch: VirtualDevice,
+    keys: VirtualDevice,
+    // Ranges of the virtual touch axes, copied from the real panel where possible.
+    x: AbsInfo,
+    y: AbsInfo,
+}
+
+const VIRTUAL_SLOTS: i32 = 10;
+const INPUT_PROP_DIRECT: u16 = 1;
+
+// Event device for key injection, from --key-device (or BRIDGE_KEY_DEVICE) or detected
+// (see `evdev::find_key_device`). Keys usually live on a different node than the touch
+// screen (e.g. `gpio-keys` for power and volume); `getevent -pl` lists the `KEY_*` codes
+// each device supports. A key the device does not declare is silently ignored by the kernel.
+static KEY_DEVICE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+const KEY_LEFTSHIFT: u16 = 42;
+
+// Interval between move events of a gesture, and the shortest one a swipe may ask for.
+const STEP_MS: u64 = 10;
+const MIN_STEP_MS: u64 = 2;
+
+// Event codes from linux/input-event-codes.h.
+pub const SYN_REPORT: u16 = 0;
+const SYN_MT_REPORT: u16 = 2;
+pub const BTN_TOUCH: u16 = 330;
+pub const ABS_MT_TRACKING_ID: u16 = 57;
+
+// Tracking IDs only have to be unique among active contacts; counting up from a high
+// value keeps them apart from the IDs the driver assigns to real fingers.
+static NEXT_TRACKING_ID: AtomicI32 = AtomicI32::new(10000);
 
 // Represents the `input_event` structure from the Linux kernel.
 // This is a Rust representation of the C struct used by the kernel
 // to report input events, allowing us to write these events
 // directly to the device file.
+// The time fields are `__kernel_ulong_t` in the kernel headers, so their width follows the
+// pointer size. `libc::timeval` would be wrong on 32-bit targets whose libc uses a 64-bit
+// time_t (e.g. musl on armv7), which is why they are spelled out here.
 #[repr(C)]
-struct InputEvent {
-    time_sec: usize,  // Seconds
-    time_usec: usize, // Microseconds
-    type_: u16,       // Event type (e.g., EV_ABS for absolute axis)
-    code: u16,        // Event code (e.g., ABS_MT_POSITION_X)
-    value: i32,       // Event value
+pub struct InputEvent {
+    time_sec: KernelULong,  // Seconds
+    time_usec: KernelULong, // Microseconds
+    type_: u16,             // Event type (e.g., EV_ABS for absolute axis)
+    code: u16,              // Event code (e.g., ABS_MT_POSITION_X)
+    value: i32,             // Event value
 }
 
+#[cfg(target_pointer_width = "64")]
+type KernelULong = u64;
+#[cfg(target_pointer_width = "64")]
+pub const INPUT_EVENT_SIZE: usize = 24;
+
+#[cfg(target_pointer_width = "32")]
+type KernelULong = u32;
+#[cfg(target_pointer_width = "32")]
+pub const INPUT_EVENT_SIZE: usize = 16;
+
+impl InputEvent {
+    // An event stamped with the current time.
+    pub fn new(type_: u16, code: u16, value: i32) -> InputEvent {
+        let time = event_time();
+        InputEvent {
+            time_sec: time.as_secs() as KernelULong,
+            time_usec: time.subsec_micros() as KernelULong,
+            type_,
+            code,
+            value,
+        }
+    }
+}
+
+// The kernel rejects writes that are not a multiple of its event size, so a layout mistake
+// would make every injected event fail; catch it at compile time instead.
+const _: () = assert!(mem::size_of::<InputEvent>() == INPUT_EVENT_SIZE);
+
 pub fn tap(x: i32, y: i32) -> std::io::Result<()> {
-    let mut file = OpenOptions::new().write(true).open(TOUCH_DEVICE)?;
+    let mut screen = TouchScreen::open()?;
 
-    send_touch_event(&mut file, x, y, 1)?; // "touch down" event
-    send_touch_event(&mut file, x, y, 0)?; // "touch up" event
-    thread::sleep(Duration::from_millis(50)); // Short delay for stability
+    screen.down(&[(x, y)])?; // "touch down" event
+    screen.up()?; // "touch up" event
+    let settle_ms = OPTIONS.get_or_init(Options::default).tap_settle_ms;
+    if settle_ms > 0 {
+        thread::sleep(Duration::from_millis(settle_ms));
+    }
 
     Ok(())
 }
 
-pub fn swipe(x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u64) -> std::io::Result<()> {
-    let mut file = OpenOptions::new().write(true).open(TOUCH_DEVICE)?;
+// Plays `taps` in order on one device handle. On failure, returns the number of taps
+// delivered before it together with the error.
+pub fn tap_batch(taps: &[(i32, i32, u64)]) -> Result<(), (usize, std::io::Error)> {
+    let mut screen = TouchScreen::open().map_err(|e| (0, e))?;
+    for (delivered, &(x, y, delay_ms)) in taps.iter().enumerate() {
+        thread::sleep(Duration::from_millis(delay_ms));
+        screen
+            .down(&[(x, y)])
+            .and_then(|_| screen.up())
+            .map_err(|e| (delivered, e))?;
+    }
+    Ok(())
+}
 
-    let step_delay = 10;
-    let steps = (duration_ms / step_delay).max(1);
-    let dx = (x2 - x1) as f32 / steps as f32;
-    let dy = (y2 - y1) as f32 / steps as f32;
+pub fn swipe(
+    (x1, y1): (i32, i32),
+    (x2, y2): (i32, i32),
+    duration_ms: u64,
+    easing: Easing,
+    step_ms: u64,
+) -> std::io::Result<()> {
+    let mut screen = TouchScreen::open()?;
+    let step_ms = step_ms.max(MIN_STEP_MS);
 
     // Start the swipe gesture with a "touch down" event
-    send_touch_event(&mut file, x1, y1, 1)?;
+    screen.down(&[(x1, y1)])?;
+    move_contacts(
+        &mut screen,
+        &[((x1, y1), (x2, y2))],
+        duration_ms,
+        step_ms,
+        curve(easing),
+    )?;
+    // End the swipe gesture with a "touch up" event
+    screen.up()?;
+    Ok(())
+}
 
-    // Perform linear interpolation for a smooth movement
-    let mut current_x = x1 as f32;
-    let mut current_y = y1 as f32;
-    for _ in 0..steps {
-        current_x += dx;
-        current_y += dy;
-        send_move_event(&mut file, current_x as i32, current_y as i32)?;
-        thread::sleep(Duration::from_millis(step_delay));
+// A swipe with `fingers` parallel contacts, `spacing` pixels apart on a line perpendicular
+// to the motion and centered on it.
+pub fn multi_swipe(
+    fingers: u8,
+    (x1, y1): (i32, i32),
+    (x2, y2): (i32, i32),
+    spacing: i32,
+    duration_ms: u64,
+) -> std::io::Result<()> {
+    let mut screen = TouchScreen::open()?;
+    let max = screen.max_contacts();
+    if fingers == 0 || fingers as i32 > max {
+        return Err(std::io::Error::other(format!(
+            "cannot swipe with {} fingers, the touch device supports 1 to {} contacts",
+            fingers, max
+        )));
     }
 
-    // End the swipe gesture with a "touch up" event
-    send_touch_event(&mut file, x2, y2, 0)?;
+    // Unit vector at a right angle to the motion; a swipe that does not move
+    // spreads the fingers horizontally.
+    let (dx, dy) = ((x2 - x1) as f32, (y2 - y1) as f32);
+    let length = dx.hypot(dy);
+    let (px, py) = if length > 0.0 {
+        (-dy / length, dx / length)
+    } else {
+        (1.0, 0.0)
+    };
+    let paths: Vec<_> = (0..fingers)
+        .map(|finger| {
+            let offset = (finger as f32 - (fingers - 1) as f32 / 2.0) * spacing as f32;
+            let (ox, oy) = ((px * offset).round() as i32, (py * offset).round() as i32);
+            ((x1 + ox, y1 + oy), (x2 + ox, y2 + oy))
+        })
+        .collect();
+
+    let starts: Vec<_> = paths.iter().map(|&(start, _)| start).collect();
+    screen.down(&starts)?;
+    move_contacts(&mut screen, &paths, duration_ms, STEP_MS, linear)?;
+    screen.up()
+}
+
+// Like a swipe, but pauses at both ends so the framework sees a drag rather than a fling.
+pub fn drag(
+    (x1, y1): (i32, i32),
+    (x2, y2): (i32, i32),
+    hold_before_ms: u64,
+    move_duration_ms: u64,
+    hold_after_ms: u64,
+) -> std::io::Result<()> {
+    let mut screen = TouchScreen::open()?;
+
+    screen.down(&[(x1, y1)])?;
+    hold(&mut screen, hold_before_ms)?; // Long enough to pick the item up
+    move_contacts(
+        &mut screen,
+        &[((x1, y1), (x2, y2))],
+        move_duration_ms,
+        STEP_MS,
+        linear,
+    )?;
+    hold(&mut screen, hold_after_ms)?; // Let the velocity settle before releasing
+    screen.up()?;
+    Ok(())
+}
+
+// Start and end point of one contact's movement.
+type Stroke = ((i32, i32), (i32, i32));
+
+// Moves the contacts put down by `down` from the first to the second point of each pair,
+// in lockstep over `duration_ms` with frames `step_ms` apart. `ease` maps the elapsed share
+// of the duration (0.0 to 1.0) to the share of the distance. A duration shorter than one
+// step still produces a single move frame.
+fn move_contacts(
+    screen: &mut TouchScreen,
+    paths: &[Stroke],
+    duration_ms: u64,
+    step_ms: u64,
+    ease: fn(f32) -> f32,
+) -> std::io::Result<()> {
+    let steps = (duration_ms / step_ms).max(1);
+
+    // Each step has a fixed deadline relative to the start, so time spent writing events
+    // does not add up over long swipes.
+    let start = Instant::now();
+    let mut points = Vec::with_capacity(paths.len());
+    for step in 1..=steps {
+        let progress = ease(step as f32 / steps as f32);
+        points.clear();
+        for &((x1, y1), (x2, y2)) in paths {
+            let x = x1 as f32 + (x2 - x1) as f32 * progress;
+            let y = y1 as f32 + (y2 - y1) as f32 * progress;
+            points.push((x.round() as i32, y.round() as i32));
+        }
+        screen.move_to(&points)?;
+        let deadline = start + Duration::from_millis(step * step_ms);
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+    Ok(())
+}
+
+fn curve(easing: Easing) -> fn(f32) -> f32 {
+    match easing {
+        Easing::Linear => linear,
+        Easing::EaseInOut => ease_in_out,
+        Easing::Overshoot => overshoot,
+    }
+}
+
+fn linear(t: f32) -> f32 {
+    t
+}
+
+// Cubic ease-in-out.
+fn ease_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
+
+// "Back" ease-out: about 10% past the target near the end, then back onto it.
+fn overshoot(t: f32) -> f32 {
+    const C1: f32 = 1.70158;
+    const C3: f32 = C1 + 1.0;
+    1.0 + C3 * (t - 1.0).powi(3) + C1 * (t - 1.0).powi(2)
+}
+
+// Touches (x, y) and keeps the contact down for `duration_ms` before lifting it.
+pub fn long_press(x: i32, y: i32, duration_ms: u64) -> std::io::Result<()> {
+    let mut screen = TouchScreen::open()?;
+
+    screen.down(&[(x, y)])?; // "touch down" event
+    hold(&mut screen, duration_ms)?;
+    screen.up()?; // "touch up" event
+
+    Ok(())
+}
+
+// Keeps the active contacts still for `duration_ms`. The positions are repeated while
+// holding, so drivers and the framework that expect a steady stream of reports keep
+// treating the fingers as present.
+fn hold(screen: &mut TouchScreen, duration_ms: u64) -> std::io::Result<()> {
+    let keepalive = Duration::from_millis(50);
+    let hold = Duration::from_millis(duration_ms);
+    let start = Instant::now();
+    while start.elapsed() + keepalive < hold {
+        thread::sleep(keepalive);
+        screen.report()?;
+    }
+    thread::sleep(hold.saturating_sub(start.elapsed()));
+    Ok(())
+}
+
+// Plays `points` (x, y, ms since start) as one contact. Moves between points are
+// interpolated every STEP_MS, and every point is reached at its own time.
+pub fn path(gesture: &Gesture) -> std::io::Result<()> {
+    gesture.validate().map_err(std::io::Error::other)?;
+    let points = &gesture.points;
+    let (x0, y0, t0) = points[0];
+
+    let mut screen = TouchScreen::open()?;
+    let start = Instant::now();
+    screen.down(&[(x0, y0)])?;
+    for pair in points.windows(2) {
+        let ((x1, y1, t1), (x2, y2, t2)) = (pair[0], pair[1]);
+        let steps = ((t2 - t1) / STEP_MS).max(1);
+        for step in 1..=steps {
+            let progress = step as f32 / steps as f32;
+            let deadline = start + Duration::from_millis(t1 - t0 + (t2 - t1) * step / steps);
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            let x = x1 as f32 + (x2 - x1) as f32 * progress;
+            let y = y1 as f32 + (y2 - y1) as f32 * progress;
+            screen.move_to(&[(x.round() as i32, y.round() as i32)])?;
+        }
+    }
+    screen.up()
+}
+
+// Two fingers on a horizontal line through (cx, cy), moving from `start_dist` to
+// `end_dist` apart. A growing distance zooms in, a shrinking one zooms out.
+pub fn pinch(
+    cx: i32,
+    cy: i32,
+    start_dist: i32,
+    end_dist: i32,
+    duration_ms: u64,
+) -> std::io::Result<()> {
+    let mut screen = TouchScreen::open()?;
+    let fingers = |dist: f32| {
+        [
+            ((cx as f32 - dist / 2.0) as i32, cy),
+            ((cx as f32 + dist / 2.0) as i32, cy),
+        ]
+    };
+
+    let (start, end) = (fingers(start_dist as f32), fingers(end_dist as f32));
+
+    // Both contacts go down in the same report, so the framework sees a single gesture.
+    screen.down(&start)?;
+    let paths = [(start[0], end[0]), (start[1], end[1])];
+    move_contacts(&mut screen, &paths, duration_ms, STEP_MS, linear)?;
+    screen.up()
+}
+
+// Runs the raw touch commands of one connection on a single device handle. The client
+// may keep sending touch commands on the connection, each answered like a regular
+// request. Contacts still down when the connection closes are lifted, so a crashed
+// script cannot leave the screen pressed, and so are those of a client that sends nothing
+// for --idle-timeout while holding them.
+pub fn handle_touch_session(socket: &mut dyn Transport, config: &Config, first: BridgeCommand) {
+    if dry_run_forced() {
+        dry_run(|| touch_session(socket, config, first));
+    } else {
+        touch_session(socket, config, first);
+    }
+}
+
+fn touch_session(socket: &mut dyn Transport, config: &Config, first: BridgeCommand) {
+    let mut screen = match TouchScreen::open() {
+        Ok(screen) => screen,
+        Err(e) => {
+            let _ = write_response(
+                socket,
+                &failure(ErrorKind::InputDeviceError, format!("Touch Failed: {}", e)),
+            );
+            return;
+        }
+    };
+
+    let mut command = first;
+    loop {
+        let mut warnings = Vec::new();
+        let response = match touch_command(&mut screen, command, &mut warnings) {
+            Ok(()) => crate::input_success(warnings),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Touch Failed: {}", e)),
+        };
+        // In a dry run, each answer carries the events of its own command.
+        let response = with_plan(response, take_plan());
+        if write_response(socket, &response).is_err() {
+            break;
+        }
+        command = match crate::read_request(socket, config, &|| false)
+            .and_then(|payload| socket.codec().decode_command(&payload).ok())
+        {
+            Some(command) => command,
+            None => break,
+        };
+    }
+
+    if !screen.contacts.is_empty() {
+        log::info!(
+            "Touch session ended, lifting {} contact(s)",
+            screen.contacts.len()
+        );
+        if let Err(e) = screen.up() {
+            log::warning!("Failed to lift touch contacts: {}", e);
+        }
+    }
+}
+
+fn touch_command(
+    screen: &mut TouchScreen,
+    command: BridgeCommand,
+    warnings: &mut Vec<String>,
+) -> std::io::Result<()> {
+    match command {
+        BridgeCommand::DirectTouchDown { slot, x, y } => {
+            let point = point(Point { x, y }, warnings)?;
+            screen.contact_down(slot as i32, point)?;
+        }
+        BridgeCommand::DirectTouchMove { slot, x, y } => {
+            let point = point(Point { x, y }, warnings)?;
+            screen.contact_move(slot as i32, point)?;
+        }
+        BridgeCommand::DirectTouchUp { slot } => screen.contact_up(slot as i32)?,
+        _ => {
+            return Err(std::io::Error::other(
+                "only touch commands can follow a touch command on the same connection",
+            ));
+        }
+    }
+    screen.sync()
+}
+
+// Presses and releases a key (Linux input code), holding it for `hold_ms` in between.
+pub fn key(keycode: u16, hold_ms: u64) -> std::io::Result<()> {
+    let mut file = key_writer()?;
+
+    write_event(&mut file, 1, keycode, 1)?; // EV_KEY, key down
+    write_event(&mut file, 0, 0, 0)?; // EV_SYN, SYN_REPORT
+    thread::sleep(Duration::from_millis(hold_ms.max(20)));
+    write_event(&mut file, 1, keycode, 0)?; // EV_KEY, key up
+    write_event(&mut file, 0, 0, 0)?; // EV_SYN, SYN_REPORT
+
+    Ok(())
+}
+
+// Whether keys can be injected, i.e. the key device can be opened for writing.
+pub fn key_device_available() -> bool {
+    match backend() {
+        Backend::Evdev if dry_running() => key_device().and_then(File::open).is_ok(),
+        Backend::Evdev => key_device()
+            .and_then(|path| OpenOptions::new().write(true).open(path))
+            .is_ok(),
+        Backend::Uinput(_) => true,
+    }
+}
+
+// A handle on the device keys are injected into, as chosen by the backend.
+fn key_writer() -> std::io::Result<File> {
+    match backend() {
+        Backend::Evdev if dry_running() => {
+            let path = key_device()?;
+            let file = File::open(&path)?;
+            plan_note(format!("Key device: {}", path.display()));
+            Ok(file)
+        }
+        Backend::Evdev => OpenOptions::new().write(true).open(key_device()?),
+        Backend::Uinput(input) => {
+            plan_note("Key device: virtual (uinput)".to_string());
+            input.keys.try_clone()
+        }
+    }
+}
+
+// Types `text` as key events on the key device, pausing `delay_ms` between keys.
+// Characters the US layout map cannot produce are passed to `input text` instead.
+pub fn type_text(text: &str, delay_ms: u64) -> std::io::Result<()> {
+    let mut file = key_writer()?;
+    let delay = Duration::from_millis(delay_ms);
+
+    let mut unmapped = String::new();
+    for c in text.chars() {
+        let Some((key, shift)) = keys::char_key(c) else {
+            unmapped.push(c);
+            continue;
+        };
+        if !unmapped.is_empty() {
+            input_text(&unmapped)?;
+            unmapped.clear();
+        }
+        if shift {
+            write_event(&mut file, 1, KEY_LEFTSHIFT, 1)?;
+        }
+        write_event(&mut file, 1, key.linux, 1)?; // EV_KEY, key down
+        write_event(&mut file, 0, 0, 0)?;
+        write_event(&mut file, 1, key.linux, 0)?; // EV_KEY, key up
+        if shift {
+            write_event(&mut file, 1, KEY_LEFTSHIFT, 0)?;
+        }
+        write_event(&mut file, 0, 0, 0)?;
+        thread::sleep(delay);
+    }
+    if !unmapped.is_empty() {
+        input_text(&unmapped)?;
+    }
+    Ok(())
+}
+
+// Falls back to the framework's (slow) `input text`, which handles any character the
+// focused IME accepts.
+fn input_text(text: &str) -> std::io::Result<()> {
+    if dry_running() {
+        plan_note(format!("Would run: input text {:?}", text));
+        return Ok(());
+    }
+    let output = Command::new("input").arg("text").arg(text).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "input text failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
     Ok(())
 }
 
 // Internal helper function to write a raw `InputEvent` to the device file.
-fn write_event(file: &mut std::fs::File, type_: u16, code: u16, value: i32) -> std::io::Result<()> {
-    let ev = InputEvent {
-        time_sec: 0,
-        time_usec: 0,
-        type_,
-        code,
-        value,
-    };
-    // Convert the struct to a raw byte slice to be written to the file.
+pub fn write_event(
+    file: &mut impl Write,
+    type_: u16,
+    code: u16,
+    value: i32,
+) -> std::io::Result<()> {
+    write_frame(file, &[InputEvent::new(type_, code, value)])
+}
+
+// Writes `events` with a single write. Event devices and uinput accept any number of
+// whole events per write, and some drivers only handle a frame that arrives at once.
+pub fn write_frame(file: &mut impl Write, events: &[InputEvent]) -> std::io::Result<()> {
+    if plan(|plan, elapsed| {
+        for event in events {
+            let line = evdev::describe(event.type_, event.code, event.value);
+            plan.push_str(&format!("{:>6} ms  {}\n", elapsed.as_millis(), line));
+        }
+    }) {
+        return Ok(());
+    }
+    // Convert the structs to a raw byte slice to be written to the file.
     // This is an `unsafe` operation because Rust cannot guarantee memory layout,
-    // but it is safe here because `#[repr(C)]` ensures a C-like layout.
+    // but it is safe here because `#[repr(C)]` ensures a C-like layout, and the size
+    // assertion above rules out padding between consecutive events.
     let bytes: &[u8] = unsafe {
-        std::slice::from_raw_parts(&ev as *const _ as *const u8, mem::size_of::<InputEvent>())
+        std::slice::from_raw_parts(
+            events.as_ptr() as *const u8,
+            events.len() * INPUT_EVENT_SIZE,
+        )
     };
     file.write_all(bytes)
 }
 
-// Sends a complete touch event (X/Y position and touch state).
-fn send_touch_event(file: &mut std::fs::File, x: i32, y: i32, state: i32) -> std::io::Result<()> {
-    write_event(file, 3, 53, x)?; // EV_ABS, ABS_MT_POSITION_X
-    write_event(file, 3, 54, y)?; // EV_ABS, ABS_MT_POSITION_Y
-    write_event(file, 1, 330, state)?; // EV_KEY, BTN_TOUCH (1=Down, 0=Up)
-    write_event(file, 0, 0, 0)?; // EV_SYN, SYN_REPORT (synchronize event)
-    Ok(())
+// Runs `f` as a dry run: events it writes are described in the returned plan instead of
+// reaching a device, and devices are only opened for reading (to learn their protocol
+// and ranges). The timing is real, gestures take as long as they would otherwise.
+pub fn dry_run<T>(f: impl FnOnce() -> T) -> (T, String) {
+    PLAN.with(|plan| *plan.borrow_mut() = Some((Instant::now(), String::new())));
+    let result = f();
+    let plan = PLAN.with(|plan| plan.borrow_mut().take());
+    (result, plan.map(|(_, plan)| plan).unwrap_or_default())
+}
+
+// Appends the plan of a dry run to a successful response.
+pub fn with_plan(response: BridgeResponse, plan: String) -> BridgeResponse {
+    match response {
+        BridgeResponse::Success(message) => BridgeResponse::Success(message + &plan),
+        other => other,
+    }
+}
+
+// Takes what the current dry run planned so far, leaving it running.
+fn take_plan() -> String {
+    let mut taken = String::new();
+    plan(|plan, _| taken = mem::take(plan));
+    taken
+}
+
+// Whether the server runs with --input-dry-run.
+pub fn dry_run_forced() -> bool {
+    OPTIONS.get_or_init(Options::default).dry_run
+}
+
+pub fn dry_running() -> bool {
+    PLAN.with(|plan| plan.borrow().is_some())
+}
+
+// Hands the plan of the current dry run to `f`, together with the time since it started.
+// Returns false, without calling `f`, outside of a dry run.
+fn plan(f: impl FnOnce(&mut String, Duration)) -> bool {
+    PLAN.with(|plan| match plan.borrow_mut().as_mut() {
+        Some((start, plan)) => {
+            f(plan, start.elapsed());
+            true
+        }
+        None => false,
+    })
+}
+
+// Adds a line to the plan of the current dry run, if any.
+pub fn plan_note(note: String) {
+    plan(|plan, _| {
+        plan.push_str(&note);
+        plan.push('\n');
+    });
+}
+
+// Decodes one `input_event` read from a device: (timestamp, type, code, value).
+pub fn read_event(bytes: &[u8; INPUT_EVENT_SIZE]) -> (Duration, u16, u16, i32) {
+    let word = mem::size_of::<KernelULong>();
+    let ulong = |at: usize| {
+        let mut raw = [0u8; mem::size_of::<KernelULong>()];
+        raw.copy_from_slice(&bytes[at..at + word]);
+        KernelULong::from_ne_bytes(raw)
+    };
+    let rest = &bytes[2 * word..];
+    // A no-op on 64-bit targets, where KernelULong already is u64.
+    #[allow(clippy::unnecessary_cast)]
+    let time = Duration::from_secs(ulong(0) as u64) + Duration::from_micros(ulong(word) as u64);
+    (
+        time,
+        u16::from_ne_bytes([rest[0], rest[1]]),
+        u16::from_ne_bytes([rest[2], rest[3]]),
+        i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]),
+    )
+}
+
+// A tracking ID for a new contact.
+pub fn next_tracking_id() -> i32 {
+    NEXT_TRACKING_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Timestamp for an outgoing event, on the CLOCK_MONOTONIC time base evdev clients use by
+// default. The kernel stamps events injected through an event device itself, so this only
+// matters to consumers that read our stream directly (uinput, capture files); the spacing
+// the driver sees comes from pacing the writes.
+fn event_time() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+// One finger on the touch screen: the slot it occupies, the tracking ID that identifies it
+// to the kernel, and its position in device coordinates.
+#[derive(Clone, Copy)]
+struct TouchContact {
+    slot: i32,
+    tracking_id: i32,
+    x: i32,
+    y: i32,
+    // Protocol B only sends what changed since the last frame.
+    new: bool,
+    moved: bool,
+}
+
+// Linux multitouch comes in two flavours (see Documentation/input/multi-touch-protocol.rst):
+// protocol A devices report every contact anonymously in each frame, protocol B devices
+// keep per-slot state and need ABS_MT_SLOT and ABS_MT_TRACKING_ID to tell contacts apart.
+#[derive(Clone, Copy, PartialEq)]
+enum Protocol {
+    A,
+    // Carries the number of slots the device advertises.
+    B { slots: i32 },
+}
+
+// Slots available on protocol A devices, which do not advertise a limit.
+const PROTOCOL_A_SLOTS: i32 = 10;
+
+// An open touch device and what was detected about it, shared by all gestures so they do
+// not pay for the open and the capability ioctls every time.
+#[derive(Clone)]
+struct TouchHandle {
+    file: Arc<File>,
+    protocol: Protocol,
+    scale: Option<[Axis; 2]>,
 }
+static TOUCH_HANDLE: Mutex<Option<TouchHandle>> = Mutex::new(None);
 
-// Sends a move event (only X/Y position).
-fn send_move_event(file: &mut std::fs::File, x: i32, y: i32) -> std::io::Result<()> {
-    write_event(file, 3, 53, x)?; // EV_ABS, ABS_MT_POSITION_X
-    write_event(file, 3, 54, y)?; // EV_ABS, ABS_MT_POSITION_Y
-    write_event(file, 0, 0, 0)?; // EV_SYN, SYN_REPORT
+// Held from the first contact of a gesture until its last is lifted. Every connection writes
+// to the same device, and the kernel would merge two gestures' frames into one touch.
+static GESTURE: Mutex<()> = Mutex::new(());
+
+// How long a gesture waits for the one holding GESTURE before giving up on the device.
+const GESTURE_WAIT: Duration = Duration::from_secs(5);
+
+// Takes `gesture` (GESTURE), polling for at most `wait`, so a touch session that stalls with
+// its contacts down costs the other connections an error instead of a parked thread.
+fn lock_gesture(
+    gesture: &'static Mutex<()>,
+    wait: Duration,
+) -> std::io::Result<MutexGuard<'static, ()>> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match gesture.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(poisoned)) => return Ok(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(std::io::Error::other(format!(
+                    "touch device busy, another gesture has held it for over {:.1} s",
+                    wait.as_secs_f32()
+                )));
+            }
+        }
+    }
+}
+
+// The shared touch device handle, opened on first use.
+fn touch_handle() -> std::io::Result<TouchHandle> {
+    let mut cached = TOUCH_HANDLE.lock().unwrap();
+    if let Some(handle) = cached.as_ref() {
+        return Ok(handle.clone());
+    }
+    let handle = match backend() {
+        Backend::Evdev => {
+            let file = OpenOptions::new().write(true).open(touch_device()?)?;
+            TouchHandle {
+                protocol: detect_protocol(&file),
+                scale: device_scale(&file),
+                file: Arc::new(file),
+            }
+        }
+        Backend::Uinput(input) => TouchHandle {
+            file: Arc::new(input.touch.try_clone()?),
+            protocol: Protocol::B {
+                slots: VIRTUAL_SLOTS,
+            },
+            scale: axis_scale(input.x, input.y),
+        },
+    };
+    *cached = Some(handle.clone());
+    Ok(handle)
+}
+
+// A read-only handle on the touch device for dry runs, described in the plan.
+fn plan_handle() -> std::io::Result<TouchHandle> {
+    let (name, handle) = match backend() {
+        Backend::Evdev => {
+            let path = touch_device()?;
+            let file = File::open(&path)?;
+            let handle = TouchHandle {
+                protocol: detect_protocol(&file),
+                scale: device_scale(&file),
+                file: Arc::new(file),
+            };
+            (path.display().to_string(), handle)
+        }
+        Backend::Uinput(_) => ("virtual (uinput)".to_string(), touch_handle()?),
+    };
+    let protocol = match handle.protocol {
+        Protocol::A => "protocol A".to_string(),
+        Protocol::B { slots } => format!("protocol B, {} slots", slots),
+    };
+    plan_note(format!("Touch device: {} ({})", name, protocol));
+    match handle.scale {
+        Some([x, y]) => plan_note(format!(
+            "Scaling: {}x{} pixels to X {}..{}, Y {}..{}",
+            x.pixels, y.pixels, x.min, x.max, y.min, y.max
+        )),
+        None => plan_note("Scaling: off".to_string()),
+    }
+    Ok(handle)
+}
+
+// The touch device together with the contacts currently held down on it.
+// Contacts are changed with `contact_down`/`contact_move`/`contact_up` and the changes are
+// sent to the kernel as one frame by `sync`.
+struct TouchScreen {
+    file: Arc<File>,
+    protocol: Protocol,
+    contacts: Vec<TouchContact>,
+    // Contacts lifted since the last frame.
+    lifted: Vec<TouchContact>,
+    // Whether BTN_TOUCH was last reported as down.
+    touching: bool,
+    // Protocol A: whether the current gesture ever had several contacts, which makes its
+    // frames use SYN_MT_REPORT separators.
+    multi: bool,
+    // Maps screen pixels to device coordinates; None passes coordinates through.
+    scale: Option<[Axis; 2]>,
+    // Display rotation and natural screen size, if the display is rotated.
+    rotation: Option<(u8, (i32, i32))>,
+    // GESTURE, while contacts are down; dry runs write nothing and go without.
+    gesture: Option<MutexGuard<'static, ()>>,
+}
+
+impl TouchScreen {
+    fn open() -> std::io::Result<TouchScreen> {
+        let device = if dry_running() {
+            plan_handle()?
+        } else {
+            touch_handle()?
+        };
+        let rotation = match rotation::current() {
+            0 => None,
+            rotation => Some((rotation, screen_size()?)),
+        };
+        if let Some((rotation, _)) = rotation {
+            plan_note(format!("Rotation: {}", rotation));
+        }
+        Ok(TouchScreen {
+            file: device.file,
+            protocol: device.protocol,
+            contacts: Vec::new(),
+            lifted: Vec::new(),
+            touching: false,
+            multi: false,
+            scale: device.scale,
+            rotation,
+            gesture: None,
+        })
+    }
+
+    // Converts a point in screen pixels to device coordinates.
+    fn to_device(&self, point: (i32, i32)) -> (i32, i32) {
+        let (x, y) = match self.rotation {
+            Some((rotation, natural)) => rotation::to_panel(point, rotation, natural),
+            None => point,
+        };
+        match &self.scale {
+            Some([x_axis, y_axis]) => (x_axis.scale(x), y_axis.scale(y)),
+            None => (x, y),
+        }
+    }
+
+    fn position(&self, slot: i32) -> std::io::Result<usize> {
+        self.contacts
+            .iter()
+            .position(|contact| contact.slot == slot)
+            .ok_or_else(|| std::io::Error::other(format!("slot {} is not down", slot)))
+    }
+
+    // How many contacts the device can track at once.
+    fn max_contacts(&self) -> i32 {
+        match self.protocol {
+            Protocol::A => PROTOCOL_A_SLOTS,
+            Protocol::B { slots } => slots,
+        }
+    }
+
+    // Puts a finger down in `slot` at `point` (screen pixels).
+    fn contact_down(&mut self, slot: i32, point: (i32, i32)) -> std::io::Result<()> {
+        let slots = self.max_contacts();
+        if !(0..slots).contains(&slot) {
+            return Err(std::io::Error::other(format!(
+                "slot {} is out of range, the touch device supports {} contacts",
+                slot, slots
+            )));
+        }
+        if self.contacts.iter().any(|contact| contact.slot == slot) {
+            return Err(std::io::Error::other(format!(
+                "slot {} is already down",
+                slot
+            )));
+        }
+        if self.gesture.is_none() && !dry_running() {
+            self.gesture = Some(lock_gesture(&GESTURE, GESTURE_WAIT)?);
+        }
+        let (x, y) = self.to_device(point);
+        self.contacts.push(TouchContact {
+            slot,
+            tracking_id: next_tracking_id(),
+            x,
+            y,
+            new: true,
+            moved: true,
+        });
+        self.multi |= self.contacts.len() > 1;
+        Ok(())
+    }
+
+    // Moves the finger in `slot` to `point` (screen pixels).
+    fn contact_move(&mut self, slot: i32, point: (i32, i32)) -> std::io::Result<()> {
+        let (x, y) = self.to_device(point);
+        let index = self.position(slot)?;
+        let contact = &mut self.contacts[index];
+        contact.x = x;
+        contact.y = y;
+        contact.moved = true;
+        Ok(())
+    }
+
+    // Lifts the finger in `slot`.
+    fn contact_up(&mut self, slot: i32) -> std::io::Result<()> {
+        let index = self.position(slot)?;
+        let contact = self.contacts.remove(index);
+        self.lifted.push(contact);
+        Ok(())
+    }
+
+    // Sends the pending changes as one frame. The frame goes out in a single write, so the
+    // driver never sees half of it, and the changes only count as sent once it succeeded.
+    fn sync(&mut self) -> std::io::Result<()> {
+        let frame = self.frame();
+        match write_frame(&mut &*self.file, &frame) {
+            Err(e) if device_lost(&e) => self.reacquire(e)?,
+            result => result?,
+        }
+
+        for contact in &mut self.contacts {
+            contact.new = false;
+            contact.moved = false;
+        }
+        self.lifted.clear();
+        self.touching = !self.contacts.is_empty();
+        if !self.touching {
+            self.multi = false;
+            self.gesture = None;
+        }
+        Ok(())
+    }
+
+    // Reopens the touch device after writes failed with `error` because it went away
+    // (driver reload, unplugged USB panel), and sends the pending frame again. The new
+    // device knows nothing of the contacts held down, so they are reported as new.
+    fn reacquire(&mut self, error: std::io::Error) -> std::io::Result<()> {
+        log::warning!("Touch device lost ({}), reopening it", error);
+        *TOUCH_HANDLE.lock().unwrap() = None;
+        forget_touch_device();
+        let lost = |e: std::io::Error| {
+            std::io::Error::other(format!(
+                "input device lost and could not be reacquired: {}",
+                e
+            ))
+        };
+        let handle = touch_handle().map_err(lost)?;
+        self.file = handle.file;
+        self.protocol = handle.protocol;
+        self.scale = handle.scale;
+        for contact in &mut self.contacts {
+            contact.new = true;
+            contact.moved = true;
+        }
+        self.touching = false;
+        write_frame(&mut &*self.file, &self.frame()).map_err(lost)?;
+        log::info!("Reopened touch device");
+        Ok(())
+    }
+
+    // The events reporting the pending changes, ending with SYN_REPORT.
+    fn frame(&self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        if self.protocol == Protocol::A {
+            self.frame_a(&mut events);
+        } else {
+            for contact in &self.contacts {
+                if !contact.moved {
+                    continue;
+                }
+                events.push(InputEvent::new(3, ABS_MT_SLOT, contact.slot));
+                if contact.new {
+                    events.push(InputEvent::new(3, ABS_MT_TRACKING_ID, contact.tracking_id));
+                }
+                events.push(InputEvent::new(3, ABS_MT_POSITION_X, contact.x));
+                events.push(InputEvent::new(3, ABS_MT_POSITION_Y, contact.y));
+            }
+            for contact in &self.lifted {
+                events.push(InputEvent::new(3, ABS_MT_SLOT, contact.slot));
+                events.push(InputEvent::new(3, ABS_MT_TRACKING_ID, -1)); // Contact lifted
+            }
+        }
+
+        let touching = !self.contacts.is_empty();
+        if touching != self.touching {
+            events.push(InputEvent::new(1, BTN_TOUCH, touching as i32)); // EV_KEY, BTN_TOUCH
+        }
+        events.push(InputEvent::new(0, SYN_REPORT, 0));
+        events
+    }
+
+    // Protocol A frames list every active contact. Contacts of a multi-finger gesture are
+    // separated by SYN_MT_REPORT, and an empty report means no contacts are left. A gesture
+    // with a single contact is sent without them, like the single-touch sequence existing
+    // devices were tested with, including the position on release.
+    fn frame_a(&self, events: &mut Vec<InputEvent>) {
+        if !self.multi {
+            if let Some(contact) = self.contacts.first().or(self.lifted.first()) {
+                events.push(InputEvent::new(3, ABS_MT_POSITION_X, contact.x));
+                events.push(InputEvent::new(3, ABS_MT_POSITION_Y, contact.y));
+            }
+            return;
+        }
+        for contact in &self.contacts {
+            events.push(InputEvent::new(3, ABS_MT_POSITION_X, contact.x));
+            events.push(InputEvent::new(3, ABS_MT_POSITION_Y, contact.y));
+            events.push(InputEvent::new(0, SYN_MT_REPORT, 0));
+        }
+        if self.contacts.is_empty() {
+            events.push(InputEvent::new(0, SYN_MT_REPORT, 0));
+        }
+    }
+
+    // Puts one finger down at each of `points`, in slots 0, 1, ... and a single report.
+    fn down(&mut self, points: &[(i32, i32)]) -> std::io::Result<()> {
+        for (slot, &point) in points.iter().enumerate() {
+            self.contact_down(slot as i32, point)?;
+        }
+        self.sync()
+    }
+
+    // Moves the contacts put down by `down` to `points`, given in the same order.
+    fn move_to(&mut self, points: &[(i32, i32)]) -> std::io::Result<()> {
+        for (slot, &point) in points.iter().enumerate() {
+            self.contact_move(slot as i32, point)?;
+        }
+        self.sync()
+    }
+
+    // Repeats the current positions of all contacts.
+    fn report(&mut self) -> std::io::Result<()> {
+        for contact in &mut self.contacts {
+            contact.moved = true;
+        }
+        self.sync()
+    }
+
+    // Lifts every contact.
+    fn up(&mut self) -> std::io::Result<()> {
+        while let Some(contact) = self.contacts.first() {
+            self.contact_up(contact.slot)?;
+        }
+        self.sync()
+    }
+}
+
+// One touch axis: `pixels` screen pixels spread over the device range min..=max.
+#[derive(Clone, Copy)]
+struct Axis {
+    pixels: i32,
+    min: i32,
+    max: i32,
+    invert: bool,
+}
+
+impl Axis {
+    fn scale(&self, pixel: i32) -> i32 {
+        scale_axis(pixel, self.pixels, self.min, self.max, self.invert)
+    }
+
+    // The pixel a device coordinate corresponds to, the inverse of `scale`.
+    fn unscale(&self, value: i32) -> i32 {
+        let last = (self.pixels - 1).max(1) as i64;
+        let span = ((self.max - self.min) as i64).max(1);
+        let offset = (value as i64 - self.min as i64).clamp(0, span);
+        let pixel = (offset * last + span / 2) / span;
+        (if self.invert { last - pixel } else { pixel }) as i32
+    }
+}
+
+// Maps device coordinates of the touch screen `file` back to screen pixels as displayed,
+// undoing the scaling and rotation gestures apply. None when neither is in effect, since
+// the coordinates then already are pixels.
+pub fn screen_mapping(file: &File) -> Option<impl Fn((i32, i32)) -> (i32, i32) + use<>> {
+    let scale = device_scale(file);
+    let rotation = match rotation::current() {
+        0 => None,
+        rotation => Some((rotation, screen_size().ok()?)),
+    };
+    if scale.is_none() && rotation.is_none() {
+        return None;
+    }
+    Some(move |(x, y)| {
+        let point = match &scale {
+            Some([x_axis, y_axis]) => (x_axis.unscale(x), y_axis.unscale(y)),
+            None => (x, y),
+        };
+        match rotation {
+            Some((rotation, natural)) => rotation::to_display(point, rotation, natural),
+            None => point,
+        }
+    })
+}
+
+// Maps pixel 0..pixels-1 linearly onto min..=max (reversed if `invert`), rounding to the
+// nearest device unit. Positions outside the screen are clamped to the device range.
+fn scale_axis(pixel: i32, pixels: i32, min: i32, max: i32, invert: bool) -> i32 {
+    let last = (pixels - 1).max(1) as i64;
+    let pixel = (pixel as i64).clamp(0, last);
+    let pixel = if invert { last - pixel } else { pixel };
+    let span = (max - min) as i64;
+    (min as i64 + (pixel * span + last / 2) / last) as i32
+}
+
+// The pixel to device mapping for `file`, or None when scaling is disabled, the device does
+// not report its ranges (e.g. a plain file used for debugging) or the screen size is unknown.
+fn device_scale(file: &std::fs::File) -> Option<[Axis; 2]> {
+    axis_scale(
+        evdev::abs_info(file, ABS_MT_POSITION_X)?,
+        evdev::abs_info(file, ABS_MT_POSITION_Y)?,
+    )
+}
+
+// The pixel to device mapping for touch axes with the ranges `x` and `y`.
+fn axis_scale(x: AbsInfo, y: AbsInfo) -> Option<[Axis; 2]> {
+    let options = OPTIONS.get_or_init(Options::default);
+    if options.no_scale {
+        return None;
+    }
+    let (width, height) = match screen_size() {
+        Ok(size) => size,
+        Err(e) => {
+            log::warning!("not scaling touch coordinates: {}", e);
+            return None;
+        }
+    };
+    Some([
+        Axis {
+            pixels: width,
+            min: x.minimum,
+            max: x.maximum,
+            invert: options.invert_x,
+        },
+        Axis {
+            pixels: height,
+            min: y.minimum,
+            max: y.maximum,
+            invert: options.invert_y,
+        },
+    ])
+}
+
+// Resolves a point to screen pixels. Fractions outside the screen are clamped to its edge
+// and reported in `warnings`.
+pub fn point(point: Point, warnings: &mut Vec<String>) -> std::io::Result<(i32, i32)> {
+    Ok((
+        resolve(point.x, 0, warnings)?,
+        resolve(point.y, 1, warnings)?,
+    ))
+}
+
+fn resolve(coord: Coord, axis: usize, warnings: &mut Vec<String>) -> std::io::Result<i32> {
+    let fraction = match coord {
+        Coord::Pixels(pixels) => return Ok(pixels),
+        Coord::Fraction(fraction) => fraction,
+    };
+    // Fractions are relative to the display as it is shown, which is rotated by 90° or 270°
+    // against the natural screen size.
+    let (width, height) = screen_size()?;
+    let (width, height) = if rotation::current() % 2 == 1 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let pixels = if axis == 0 { width } else { height };
+    let clamped = fraction.clamp(0.0, 1.0);
+    if clamped != fraction {
+        warnings.push(format!(
+            "{:.1}% is outside the screen, clamped to {}%",
+            fraction * 100.0,
+            clamped * 100.0
+        ));
+    }
+    Ok((clamped * (pixels - 1) as f32).round() as i32)
+}
+
+// The physical screen size in pixels, as configured or reported by `wm size`.
+fn screen_size() -> std::io::Result<(i32, i32)> {
+    let mut cached = SCREEN_SIZE.lock().unwrap();
+    if let Some(size) = *cached {
+        return Ok(size);
+    }
+    let output = Command::new("wm").arg("size").output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // "Physical size: 1080x2400", possibly followed by an "Override size:" line. Touch
+    // coordinates follow the panel, so the physical size is the one that matters.
+    let size = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Physical size:"))
+        .and_then(|size| size.trim().split_once('x'))
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .ok_or_else(|| {
+            std::io::Error::other(format!(
+                "unexpected `wm size` output '{}' (set --screen-size)",
+                stdout.trim()
+            ))
+        })?;
+    *cached = Some(size);
+    Ok(size)
+}
+
+// Applies the input related command line options. A configured touch device that looks
+// unusable only produces a warning, so a misconfigured input setup does not keep the server
+// from running everything else.
+pub fn init(config: &Config) {
+    if let Some(path) = &config.touch_device {
+        match evdev::check_touchscreen(path) {
+            Ok(()) => log::info!("Using touch device {}", path.display()),
+            Err(e) => log::warning!("{}", e),
+        }
+        *TOUCH_DEVICE.lock().unwrap() = Some(path.clone());
+    }
+    if let Some(path) = &config.key_device {
+        log::info!("Using key device {}", path.display());
+        *KEY_DEVICE.lock().unwrap() = Some(path.clone());
+    }
+    if let Some((width, height)) = config.screen_size {
+        *SCREEN_SIZE.lock().unwrap() = Some((width as i32, height as i32));
+    }
+    let _ = OPTIONS.set(Options {
+        no_scale: config.no_scale,
+        invert_x: config.invert_x,
+        invert_y: config.invert_y,
+        tap_settle_ms: config.tap_settle_ms,
+        touch_device_configured: config.touch_device.is_some(),
+        dry_run: config.input_dry_run,
+    });
+    rotation::init(config.auto_rotate);
+
+    let use_uinput = match config.input_backend {
+        InputBackend::Evdev => false,
+        InputBackend::Uinput => true,
+        InputBackend::Auto => match evdev_writable() {
+            Ok(()) => false,
+            Err(e) => {
+                log::info!("Event devices are not usable ({}), trying uinput", e);
+                true
+            }
+        },
+    };
+    let backend = if use_uinput {
+        match VirtualInput::create() {
+            Ok(input) => Backend::Uinput(input),
+            Err(e) => {
+                log::warning!("cannot create uinput devices: {}", e);
+                Backend::Evdev
+            }
+        }
+    } else {
+        Backend::Evdev
+    };
+    let _ = BACKEND.set(backend);
+    log::info!("Input backend: {}", backend_name());
+}
+
+fn backend() -> &'static Backend {
+    BACKEND.get_or_init(|| Backend::Evdev)
+}
+
+// The active backend, as shown by Ping and `andro input-info`.
+pub fn backend_name() -> &'static str {
+    match backend() {
+        Backend::Evdev => "evdev",
+        Backend::Uinput(_) => "uinput",
+    }
+}
+
+// The touch device input goes to, for Info; None if no touch screen was found.
+pub fn touch_device_name() -> Option<String> {
+    match backend() {
+        Backend::Evdev => touch_device().ok().map(|path| path.display().to_string()),
+        Backend::Uinput(_) => Some("virtual (uinput)".to_string()),
+    }
+}
+
+// Checks that both the touch screen and the key device can be opened for writing.
+fn evdev_writable() -> std::io::Result<()> {
+    let path = touch_device()?;
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let path = key_device()?;
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
     Ok(())
 }
+
+impl VirtualInput {
+    fn create() -> std::io::Result<VirtualInput> {
+        let (x, y) = panel_ranges()?;
+        let slot = AbsInfo {
+            maximum: VIRTUAL_SLOTS - 1,
+            ..AbsInfo::default()
+        };
+        let tracking_id = AbsInfo {
+            maximum: i32::MAX,
+            ..AbsInfo::default()
+        };
+        let touch = VirtualDevice::create(
+            "NativeBridge Touch",
+            &Capabilities {
+                keys: &[BTN_TOUCH],
+                abs: &[
+                    (ABS_MT_SLOT, slot),
+                    (ABS_MT_TRACKING_ID, tracking_id),
+                    (ABS_MT_POSITION_X, x),
+                    (ABS_MT_POSITION_Y, y),
+                ],
+                props: &[INPUT_PROP_DIRECT],
+                ..Default::default()
+            },
+        )?;
+
+        let mut codes: Vec<u16> = keys::KEYS.iter().map(|key| key.linux).collect();
+        codes.push(KEY_LEFTSHIFT);
+        let keys = VirtualDevice::create(
+            "NativeBridge Keys",
+            &Capabilities {
+                keys: &codes,
+                ..Default::default()
+            },
+        )?;
+        Ok(VirtualInput { touch, keys, x, y })
+    }
+}
+
+// Touch ranges for the virtual screen: those of the real panel if it can at least be read,
+// so recordings and raw coordinates mean the same on both, otherwise the screen size.
+fn panel_ranges() -> std::io::Result<(AbsInfo, AbsInfo)> {
+    let panel = touch_device()
+        .ok()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| {
+            Some((
+                evdev::abs_info(&file, ABS_MT_POSITION_X)?,
+                evdev::abs_info(&file, ABS_MT_POSITION_Y)?,
+            ))
+        });
+    if let Some(ranges) = panel {
+        return Ok(ranges);
+    }
+    let (width, height) = screen_size()?;
+    let axis = |pixels: i32| AbsInfo {
+        maximum: pixels - 1,
+        ..AbsInfo::default()
+    };
+    Ok((axis(width), axis(height)))
+}
+
+// Errors that mean the device node is gone, as opposed to e.g. missing permissions.
+fn device_lost(error: &std::io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::ENODEV) | Some(libc::EIO))
+}
+
+// Drops a detected touch screen so the next use detects it again; a configured one stays.
+fn forget_touch_device() {
+    if !OPTIONS
+        .get_or_init(Options::default)
+        .touch_device_configured
+    {
+        *TOUCH_DEVICE.lock().unwrap() = None;
+    }
+}
+
+// The configured touch screen, or the detected one.
+pub fn touch_device() -> std::io::Result<PathBuf> {
+    let mut cached = TOUCH_DEVICE.lock().unwrap();
+    if let Some(path) = cached.as_ref() {
+        return Ok(path.clone());
+    }
+    // Not cached on failure, so plugging in a device or fixing permissions needs no restart.
+    let path = evdev::find_touchscreen()?;
+    log::info!("Using touch device {}", path.display());
+    *cached = Some(path.clone());
+    Ok(path)
+}
+
+// The configured key device, or the detected one.
+pub fn key_device() -> std::io::Result<PathBuf> {
+    let mut cached = KEY_DEVICE.lock().unwrap();
+    if let Some(path) = cached.as_ref() {
+        return Ok(path.clone());
+    }
+    let codes: Vec<u16> = keys::KEYS.iter().map(|key| key.linux).collect();
+    let path = evdev::find_key_device(&codes)?;
+    log::info!("Using key device {}", path.display());
+    *cached = Some(path.clone());
+    Ok(path)
+}
+
+// Describes the input devices in use, for `andro input-info`.
+pub fn info() -> std::io::Result<String> {
+    let mut info = format!("Input backend: {}\n", backend_name());
+    let (protocol, ranges, scale, key_device) = match backend() {
+        Backend::Evdev => {
+            let path = touch_device()?;
+            let file = OpenOptions::new().write(true).open(&path)?;
+            info.push_str(&format!("Touch device: {}", path.display()));
+            if let Some(name) = evdev::name(&file) {
+                info.push_str(&format!(" ({})", name));
+            }
+            info.push('\n');
+            let ranges =
+                [ABS_MT_POSITION_X, ABS_MT_POSITION_Y].map(|code| evdev::abs_info(&file, code));
+            let protocol = detect_protocol(&file);
+            let key_device = match key_device() {
+                Ok(path) => path.display().to_string(),
+                Err(_) => "none found".to_string(),
+            };
+            (protocol, ranges, device_scale(&file), key_device)
+        }
+        Backend::Uinput(input) => {
+            info.push_str("Touch device: virtual (uinput)\n");
+            let protocol = Protocol::B {
+                slots: VIRTUAL_SLOTS,
+            };
+            let ranges = [Some(input.x), Some(input.y)];
+            (
+                protocol,
+                ranges,
+                axis_scale(input.x, input.y),
+                "virtual (uinput)".to_string(),
+            )
+        }
+    };
+    match protocol {
+        Protocol::A => info.push_str("Protocol: A\n"),
+        Protocol::B { slots } => info.push_str(&format!("Protocol: B ({} slots)\n", slots)),
+    }
+    for (axis, abs) in ["X", "Y"].into_iter().zip(ranges) {
+        if let Some(abs) = abs {
+            info.push_str(&format!(
+                "{} range: {}..{}\n",
+                axis, abs.minimum, abs.maximum
+            ));
+        }
+    }
+    match scale {
+        Some([x, y]) => info.push_str(&format!(
+            "Scaling: {}x{} screen pixels to the device range\n",
+            x.pixels, y.pixels
+        )),
+        None => info.push_str("Scaling: off\n"),
+    }
+    info.push_str(&format!("Rotation: {}\n", rotation::current()));
+    info.push_str(&format!("Key device: {}\n", key_device));
+    Ok(info)
+}
+
+// Picks protocol B if the device advertises ABS_MT_SLOT, protocol A otherwise
+// (including when the ioctls fail, e.g. on a plain file used for debugging).
+fn detect_protocol(file: &std::fs::File) -> Protocol {
+    if !evdev::has_abs(file, ABS_MT_SLOT) {
+        return Protocol::A;
+    }
+    // The maximum of ABS_MT_SLOT is the highest slot number.
+    match evdev::abs_info(file, ABS_MT_SLOT) {
+        Some(info) => Protocol::B {
+            slots: info.maximum + 1,
+        },
+        None => Protocol::A,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A protocol B screen writing to `file` instead of a device.
+    fn screen(file: &Arc<File>) -> TouchScreen {
+        TouchScreen {
+            file: Arc::clone(file),
+            protocol: Protocol::B { slots: 10 },
+            contacts: Vec::new(),
+            lifted: Vec::new(),
+            touching: false,
+            multi: false,
+            scale: None,
+            rotation: None,
+            gesture: None,
+        }
+    }
+
+    // (type, code, value) of every event in `bytes`.
+    fn events(bytes: &[u8]) -> Vec<(u16, u16, i32)> {
+        let time = 2 * mem::size_of::<KernelULong>();
+        bytes
+            .chunks_exact(INPUT_EVENT_SIZE)
+            .map(|event| {
+                let field = |at: usize| [event[time + at], event[time + at + 1]];
+                (
+                    u16::from_ne_bytes(field(0)),
+                    u16::from_ne_bytes(field(2)),
+                    i32::from_ne_bytes([
+                        event[time + 4],
+                        event[time + 5],
+                        event[time + 6],
+                        event[time + 7],
+                    ]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gestures_from_two_connections_do_not_interleave() {
+        let path = std::env::temp_dir().join(format!("gestures-{}", std::process::id()));
+        let file = Arc::new(
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap(),
+        );
+        thread::scope(|scope| {
+            for finger in 0..2 {
+                let file = &file;
+                scope.spawn(move || {
+                    for _ in 0..3 {
+                        let mut screen = screen(file);
+                        screen.down(&[(finger, 0)]).unwrap();
+                        thread::sleep(Duration::from_millis(5));
+                        screen.move_to(&[(finger, 100)]).unwrap();
+                        thread::sleep(Duration::from_millis(5));
+                        screen.up().unwrap();
+                    }
+                });
+            }
+        });
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Each touch must end before the next one starts, and keep to its own finger.
+        let mut touch: Option<i32> = None;
+        let mut touches = 0;
+        for (type_, code, value) in events(&bytes) {
+            match (type_, code) {
+                (3, ABS_MT_TRACKING_ID) if value >= 0 => {
+                    assert_eq!(touch, None, "a touch started inside another");
+                    touch = Some(-1);
+                    touches += 1;
+                }
+                (3, ABS_MT_TRACKING_ID) => touch = None,
+                (3, ABS_MT_POSITION_X) => match touch {
+                    Some(-1) => touch = Some(value),
+                    Some(finger) => assert_eq!(finger, value, "fingers mixed in one touch"),
+                    None => panic!("a move outside of a touch"),
+                },
+                _ => {}
+            }
+        }
+        assert_eq!(touches, 6);
+    }
+
+    #[test]
+    fn pixels_scale_onto_the_device_range() {
+        // A 1080 pixel wide screen on a 0..=4095 panel.
+        assert_eq!(scale_axis(0, 1080, 0, 4095, false), 0);
+        assert_eq!(scale_axis(540, 1080, 0, 4095, false), 2049);
+        assert_eq!(scale_axis(1079, 1080, 0, 4095, false), 4095);
+        // Ranges need not start at zero.
+        assert_eq!(scale_axis(100, 1080, -2048, 2047, false), -1668);
+        assert_eq!(scale_axis(1079, 1080, -2048, 2047, false), 2047);
+        // Off-screen positions stay on the panel.
+        assert_eq!(scale_axis(-5, 1080, 0, 4095, false), 0);
+        assert_eq!(scale_axis(5000, 1080, 0, 4095, false), 4095);
+        // Mirrored panels.
+        assert_eq!(scale_axis(0, 1080, 0, 4095, true), 4095);
+        assert_eq!(scale_axis(540, 1080, 0, 4095, true), 2046);
+        // A one pixel axis divides by nothing.
+        assert_eq!(scale_axis(0, 1, 0, 100, false), 0);
+    }
+
+    #[test]
+    fn unscale_undoes_scale() {
+        for (min, max, invert) in [(0, 4095, false), (0, 4095, true), (-300, 719, false)] {
+            let axis = Axis {
+                pixels: 1080,
+                min,
+                max,
+                invert,
+            };
+            // A panel coarser than the screen cannot tell neighbouring pixels apart.
+            let tolerance = if max - min < 1080 { 1 } else { 0 };
+            for pixel in 0..1080 {
+                let back = axis.unscale(axis.scale(pixel));
+                assert!(
+                    (back - pixel).abs() <= tolerance,
+                    "{} came back as {} on {}..={}",
+                    pixel,
+                    back,
+                    min,
+                    max
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn input_events_have_the_kernel_layout() {
+        // struct input_event: two __kernel_ulong_t of time, then type, code and value.
+        #[cfg(target_pointer_width = "64")]
+        const TIME: usize = 16;
+        #[cfg(target_pointer_width = "32")]
+        const TIME: usize = 8;
+        assert_eq!(INPUT_EVENT_SIZE, TIME + 8);
+
+        let mut bytes = Vec::new();
+        let frame = [
+            InputEvent::new(3, ABS_MT_POSITION_X, 540),
+            InputEvent::new(0, SYN_REPORT, 0),
+        ];
+        write_frame(&mut bytes, &frame).unwrap();
+        assert_eq!(bytes.len(), 2 * INPUT_EVENT_SIZE);
+        let event = &bytes[..INPUT_EVENT_SIZE];
+        assert_eq!(event[TIME..TIME + 2], 3u16.to_ne_bytes());
+        assert_eq!(event[TIME + 2..TIME + 4], ABS_MT_POSITION_X.to_ne_bytes());
+        assert_eq!(event[TIME + 4..], 540i32.to_ne_bytes());
+        assert_eq!(
+            events(&bytes),
+            [(3, ABS_MT_POSITION_X, 540), (0, SYN_REPORT, 0)]
+        );
+    }
+
+    // A file standing in for the touch device, and the events written to it once `f` ran.
+    fn record(name: &str, f: impl FnOnce(&Arc<File>)) -> Vec<(u16, u16, i32)> {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let file = Arc::new(File::create(&path).unwrap());
+        f(&file);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        events(&bytes)
+    }
+
+    #[test]
+    fn easing_curves_start_and_end_on_the_path() {
+        for ease in [linear, ease_in_out, overshoot] {
+            assert!(ease(0.0).abs() < 1e-5);
+            assert!((ease(1.0) - 1.0).abs() < 1e-5);
+        }
+        for i in 0..=100 {
+            let t = i as f32 / 100.0;
+            // Slow at both ends and symmetric around the middle.
+            assert!((ease_in_out(t) + ease_in_out(1.0 - t) - 1.0).abs() < 1e-5);
+            if i > 0 {
+                assert!(ease_in_out(t) >= ease_in_out(t - 0.01));
+            }
+        }
+        assert!(ease_in_out(0.1) < 0.1 && ease_in_out(0.9) > 0.9);
+    }
+
+    #[test]
+    fn overshoot_passes_the_target_by_about_ten_percent() {
+        let samples: Vec<f32> = (0..=1000).map(|i| overshoot(i as f32 / 1000.0)).collect();
+        let peak = samples.iter().cloned().fold(f32::MIN, f32::max);
+        assert!((peak - 1.1).abs() < 0.005, "peak {}", peak);
+        let at = samples.iter().position(|&v| v == peak).unwrap();
+        assert!((500..700).contains(&at), "peak at {}", at);
+        // Never back past the start.
+        assert!(samples.iter().all(|&v| v >= 0.0));
+    }
+
+    #[test]
+    fn swipes_move_in_steps_and_end_on_the_target() {
+        let moves = record("swipe", |file| {
+            let mut screen = screen(file);
+            screen.down(&[(0, 0)]).unwrap();
+            move_contacts(&mut screen, &[((0, 0), (100, 200))], 20, 2, ease_in_out).unwrap();
+            screen.up().unwrap();
+        });
+        let xs: Vec<i32> = moves
+            .iter()
+            .filter(|&&(type_, code, _)| (type_, code) == (3, ABS_MT_POSITION_X))
+            .map(|&(_, _, x)| x)
+            .collect();
+        // The touch down, then one move per step.
+        assert_eq!(xs.len(), 1 + 10);
+        assert_eq!(xs.last(), Some(&100));
+        assert!(xs.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Eased: the middle steps cover more ground than the first.
+        assert!(xs[5] - xs[4] > xs[1] - xs[0]);
+    }
+
+    // Counts the writes made to it.
+    #[derive(Default)]
+    struct Writes(Vec<usize>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_frame_is_one_write() {
+        let frame: Vec<InputEvent> = (0..10)
+            .map(|i| InputEvent::new(3, ABS_MT_POSITION_X, i))
+            .chain([InputEvent::new(0, SYN_REPORT, 0)])
+            .collect();
+        let mut writes = Writes::default();
+        write_frame(&mut writes, &frame).unwrap();
+        assert_eq!(writes.0, [11 * INPUT_EVENT_SIZE]);
+    }
+
+    // The events with tracking IDs of new contacts replaced by 1.
+    fn without_ids(events: &[(u16, u16, i32)]) -> Vec<(u16, u16, i32)> {
+        events
+            .iter()
+            .map(|&(type_, code, value)| match (type_, code) {
+                (3, ABS_MT_TRACKING_ID) if value >= 0 => (type_, code, 1),
+                _ => (type_, code, value),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn protocol_b_frames_carry_what_changed() {
+        let events = record("protocol-b", |file| {
+            let mut screen = screen(file);
+            screen.down(&[(10, 20), (30, 40)]).unwrap();
+            screen.contact_move(1, (35, 45)).unwrap();
+            screen.sync().unwrap();
+            screen.up().unwrap();
+        });
+        const SLOT: u16 = ABS_MT_SLOT;
+        const ID: u16 = ABS_MT_TRACKING_ID;
+        const X: u16 = ABS_MT_POSITION_X;
+        const Y: u16 = ABS_MT_POSITION_Y;
+        #[rustfmt::skip]
+        let expected = [
+            (3, SLOT, 0), (3, ID, 1), (3, X, 10), (3, Y, 20),
+            (3, SLOT, 1), (3, ID, 1), (3, X, 30), (3, Y, 40),
+            (1, BTN_TOUCH, 1), (0, SYN_REPORT, 0),
+            // Only the finger that moved.
+            (3, SLOT, 1), (3, X, 35), (3, Y, 45), (0, SYN_REPORT, 0),
+            (3, SLOT, 0), (3, ID, -1), (3, SLOT, 1), (3, ID, -1),
+            (1, BTN_TOUCH, 0), (0, SYN_REPORT, 0),
+        ];
+        assert_eq!(without_ids(&events), expected);
+    }
+
+    #[test]
+    fn protocol_a_frames_list_every_contact() {
+        let protocol_a = |file: &Arc<File>| TouchScreen {
+            protocol: Protocol::A,
+            ..screen(file)
+        };
+        let single = record("protocol-a-single", |file| {
+            let mut screen = protocol_a(file);
+            screen.down(&[(10, 20)]).unwrap();
+            screen.up().unwrap();
+        });
+        const X: u16 = ABS_MT_POSITION_X;
+        const Y: u16 = ABS_MT_POSITION_Y;
+        #[rustfmt::skip]
+        let expected = [
+            (3, X, 10), (3, Y, 20), (1, BTN_TOUCH, 1), (0, SYN_REPORT, 0),
+            // A single contact is released where it was.
+            (3, X, 10), (3, Y, 20), (1, BTN_TOUCH, 0), (0, SYN_REPORT, 0),
+        ];
+        assert_eq!(single, expected);
+
+        let multi = record("protocol-a-multi", |file| {
+            let mut screen = protocol_a(file);
+            screen.down(&[(10, 20), (30, 40)]).unwrap();
+            screen.up().unwrap();
+        });
+        #[rustfmt::skip]
+        let expected = [
+            (3, X, 10), (3, Y, 20), (0, SYN_MT_REPORT, 0),
+            (3, X, 30), (3, Y, 40), (0, SYN_MT_REPORT, 0),
+            (1, BTN_TOUCH, 1), (0, SYN_REPORT, 0),
+            (0, SYN_MT_REPORT, 0), (1, BTN_TOUCH, 0), (0, SYN_REPORT, 0),
+        ];
+        assert_eq!(multi, expected);
+    }
+
+    #[test]
+    fn dry_run_plans_gestures_without_writing_them() {
+        // A regular file answers none of the capability ioctls: protocol A, no scaling.
+        let path = std::env::temp_dir().join(format!("dry-run-device-{}", std::process::id()));
+        File::create(&path).unwrap();
+        *TOUCH_DEVICE.lock().unwrap() = Some(path.clone());
+        // The events of a plan, without the times in front of them.
+        let events = |plan: &str| -> Vec<String> {
+            plan.lines()
+                .filter_map(|line| line.split_once(" ms  "))
+                .map(|(_, event)| event.to_string())
+                .collect()
+        };
+
+        let (result, plan) = dry_run(|| tap(100, 200));
+        result.unwrap();
+        assert!(plan.starts_with(&format!(
+            "Touch device: {} (protocol A)\nScaling: off\n",
+            path.display()
+        )));
+        assert_eq!(
+            events(&plan),
+            [
+                "EV_ABS ABS_MT_POSITION_X 100",
+                "EV_ABS ABS_MT_POSITION_Y 200",
+                "EV_KEY BTN_TOUCH 1",
+                "EV_SYN SYN_REPORT 0",
+                "EV_ABS ABS_MT_POSITION_X 100",
+                "EV_ABS ABS_MT_POSITION_Y 200",
+                "EV_KEY BTN_TOUCH 0",
+                "EV_SYN SYN_REPORT 0",
+            ]
+        );
+
+        let (result, plan) = dry_run(|| swipe((0, 0), (0, 100), 40, Easing::Linear, 10));
+        result.unwrap();
+        let ys: Vec<_> = events(&plan)
+            .iter()
+            .filter_map(|event| event.strip_prefix("EV_ABS ABS_MT_POSITION_Y "))
+            .map(|y| y.parse::<i32>().unwrap())
+            .collect();
+        assert_eq!(ys, [0, 25, 50, 75, 100, 100]);
+
+        // Pinches are paced like swipes: 20 steps in 200 ms, not 200 ms plus the writes.
+        let started = Instant::now();
+        let (result, plan) = dry_run(|| pinch(500, 1000, 100, 300, 200));
+        result.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300));
+        let xs: Vec<_> = events(&plan)
+            .iter()
+            .filter_map(|event| event.strip_prefix("EV_ABS ABS_MT_POSITION_X "))
+            .map(|x| x.parse::<i32>().unwrap())
+            .collect();
+        // Down and 20 moves, two fingers each.
+        assert_eq!(xs.len(), 2 * 21);
+        assert_eq!(xs[..2], [450, 550]);
+        assert_eq!(xs[xs.len() - 2..], [350, 650]);
+
+        assert!(!dry_running());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stalled_touch_session_lifts_its_contacts() {
+        use bridge_core::Codec;
+        use clap::Parser;
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("stalled-touch-{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+        *TOUCH_HANDLE.lock().unwrap() = Some(TouchHandle {
+            file: Arc::new(file),
+            protocol: Protocol::B { slots: 10 },
+            scale: None,
+        });
+        let config = Config::parse_from(["bridge_server", "--idle-timeout", "1"]);
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+        let down = BridgeCommand::DirectTouchDown {
+            slot: 0,
+            x: Coord::Pixels(10),
+            y: Coord::Pixels(20),
+        };
+        let session = thread::spawn(move || touch_session(&mut server, &config, down));
+
+        let mut response = || {
+            let frame = Codec::Bincode
+                .read_frame(&mut client, crate::max_frame())
+                .unwrap()
+                .unwrap();
+            Codec::Bincode.decode_response(&frame).unwrap()
+        };
+        assert!(matches!(response(), BridgeResponse::Success(_)));
+        // The client keeps the finger down and says nothing more.
+        assert!(matches!(
+            response(),
+            BridgeResponse::Error(message) if message.starts_with("Idle Timeout")
+        ));
+        session.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3));
+        *TOUCH_HANDLE.lock().unwrap() = None;
+
+        let tracking: Vec<i32> = events(&std::fs::read(&path).unwrap())
+            .into_iter()
+            .filter(|&(type_, code, _)| (type_, code) == (3, ABS_MT_TRACKING_ID))
+            .map(|(_, _, id)| id)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(tracking.len(), 2);
+        assert_eq!(tracking[1], -1);
+    }
+
+    #[test]
+    fn gestures_give_up_on_a_held_device() {
+        static HELD: Mutex<()> = Mutex::new(());
+        let wait = Duration::from_millis(100);
+        let holder = HELD.lock().unwrap();
+        let started = Instant::now();
+        let e = lock_gesture(&HELD, wait).unwrap_err();
+        assert!(started.elapsed() >= wait);
+        assert!(e.to_string().starts_with("touch device busy"), "{}", e);
+        drop(holder);
+
+        // A gesture that ends within the wait only delays the next one.
+        let (taken, holding) = std::sync::mpsc::channel();
+        let release = thread::spawn(move || {
+            let _holder = HELD.lock().unwrap();
+            taken.send(()).unwrap();
+            thread::sleep(wait / 2);
+        });
+        holding.recv().unwrap();
+        let _gesture = lock_gesture(&HELD, wait).unwrap();
+        release.join().unwrap();
+    }
+}
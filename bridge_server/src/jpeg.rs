@@ -0,0 +1,326 @@
+// Minimal baseline JPEG encoder (4:4:4, standard tables), used to shrink screenshots.
+// Android ships no command line image encoder, so raw `screencap` frames are encoded here.
+
+// Quantization tables from Annex K of the JPEG specification, in natural order.
+const LUMA_QUANT: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANT: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// Natural index of each coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// Standard Huffman tables (Annex K.3): code counts per length, then symbols.
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// Huffman code (bits, length) for every symbol of a table.
+struct HuffmanTable {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanTable {
+    fn new(bits: &[u8; 16], values: &[u8]) -> HuffmanTable {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut symbols = values.iter();
+        for (length, &count) in bits.iter().enumerate() {
+            for _ in 0..count {
+                let symbol = *symbols.next().unwrap();
+                codes[symbol as usize] = (code, length as u8 + 1);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable { codes }
+    }
+}
+
+// Collects entropy coded bits, inserting the 0x00 stuffing byte after every 0xFF.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u16, length: u8) {
+        for i in (0..length).rev() {
+            self.buffer = (self.buffer << 1) | ((bits >> i) & 1) as u32;
+            self.count += 1;
+            if self.count == 8 {
+                let byte = self.buffer as u8;
+                self.out.push(byte);
+                if byte == 0xff {
+                    self.out.push(0);
+                }
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Pads the last byte with 1 bits, as the specification requires.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let padding = 8 - self.count;
+            self.write((1 << padding) - 1, padding);
+        }
+    }
+}
+
+// Encodes a `width` x `height` image; `pixel(x, y)` returns its RGB value.
+// `quality` follows the usual 1 (smallest) to 100 (best) scale.
+pub fn encode(
+    width: u16,
+    height: u16,
+    quality: u8,
+    pixel: impl Fn(usize, usize) -> [u8; 3],
+) -> Vec<u8> {
+    let luma_quant = scale_quant(&LUMA_QUANT, quality);
+    let chroma_quant = scale_quant(&CHROMA_QUANT, quality);
+    let tables = [
+        (
+            HuffmanTable::new(&DC_LUMA_BITS, &DC_VALUES),
+            HuffmanTable::new(&AC_LUMA_BITS, &AC_LUMA_VALUES),
+        ),
+        (
+            HuffmanTable::new(&DC_CHROMA_BITS, &DC_VALUES),
+            HuffmanTable::new(&AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+        ),
+    ];
+
+    let mut out = Vec::new();
+    write_headers(&mut out, width, height, &luma_quant, &chroma_quant);
+
+    let cosines = cosine_table();
+    let mut writer = BitWriter {
+        out,
+        buffer: 0,
+        count: 0,
+    };
+    let mut previous_dc = [0i32; 3];
+    let (width, height) = (width as usize, height as usize);
+    for block_y in (0..height).step_by(8) {
+        for block_x in (0..width).step_by(8) {
+            let mut blocks = [[0f32; 64]; 3];
+            for y in 0..8 {
+                for x in 0..8 {
+                    // Edge blocks repeat the last row/column instead of reading past the image.
+                    let [r, g, b] =
+                        pixel((block_x + x).min(width - 1), (block_y + y).min(height - 1));
+                    let (r, g, b) = (r as f32, g as f32, b as f32);
+                    let i = y * 8 + x;
+                    blocks[0][i] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+                    blocks[1][i] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    blocks[2][i] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+            }
+            for (component, block) in blocks.iter().enumerate() {
+                let (quant, (dc_table, ac_table)) = if component == 0 {
+                    (&luma_quant, &tables[0])
+                } else {
+                    (&chroma_quant, &tables[1])
+                };
+                let coefficients = transform(block, quant, &cosines);
+                encode_block(
+                    &mut writer,
+                    &coefficients,
+                    &mut previous_dc[component],
+                    dc_table,
+                    ac_table,
+                );
+            }
+        }
+    }
+    writer.flush();
+
+    let mut out = writer.out;
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+// Scales a base table the way libjpeg does, so quality values mean the same thing.
+fn scale_quant(base: &[u8; 64], quality: u8) -> [u8; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    let mut table = [0u8; 64];
+    for (scaled, &value) in table.iter_mut().zip(base) {
+        *scaled = ((value as u32 * scale + 50) / 100).clamp(1, 255) as u8;
+    }
+    table
+}
+
+fn write_headers(out: &mut Vec<u8>, width: u16, height: u16, luma: &[u8; 64], chroma: &[u8; 64]) {
+    out.extend_from_slice(&[0xff, 0xd8]); // SOI
+    // JFIF APP0: version 1.1, no density units, 1:1 aspect ratio, no thumbnail.
+    out.extend_from_slice(&[
+        0xff, 0xe0, 0, 16, b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0,
+    ]);
+
+    // DQT with both tables, stored in zigzag order.
+    out.extend_from_slice(&[0xff, 0xdb, 0, 132]);
+    for (id, table) in [luma, chroma].iter().enumerate() {
+        out.push(id as u8);
+        out.extend(ZIGZAG.iter().map(|&i| table[i]));
+    }
+
+    // SOF0: 8-bit precision, three components without subsampling.
+    out.extend_from_slice(&[0xff, 0xc0, 0, 17, 8]);
+    out.extend_from_slice(&height.to_be_bytes());
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&[3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+
+    // DHT: DC and AC tables for luma (id 0) and chroma (id 1).
+    let huffman: [(u8, &[u8; 16], &[u8]); 4] = [
+        (0x00, &DC_LUMA_BITS, &DC_VALUES),
+        (0x10, &AC_LUMA_BITS, &AC_LUMA_VALUES),
+        (0x01, &DC_CHROMA_BITS, &DC_VALUES),
+        (0x11, &AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+    ];
+    let length = 2 + huffman
+        .iter()
+        .map(|(_, _, values)| 17 + values.len())
+        .sum::<usize>();
+    out.extend_from_slice(&[0xff, 0xc4]);
+    out.extend_from_slice(&(length as u16).to_be_bytes());
+    for (class_id, bits, values) in huffman {
+        out.push(class_id);
+        out.extend_from_slice(bits);
+        out.extend_from_slice(values);
+    }
+
+    // SOS: all three components in one interleaved scan.
+    out.extend_from_slice(&[0xff, 0xda, 0, 12, 3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+}
+
+// cos((2x + 1) * u * pi / 16), indexed by [u][x].
+fn cosine_table() -> [[f32; 8]; 8] {
+    let mut table = [[0f32; 8]; 8];
+    for (u, row) in table.iter_mut().enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    table
+}
+
+// Forward DCT followed by quantization; returns coefficients in natural order.
+fn transform(block: &[f32; 64], quant: &[u8; 64], cosines: &[[f32; 8]; 8]) -> [i32; 64] {
+    // Separable DCT: rows first, then columns.
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| block[y * 8 + x] * cosines[u][x]).sum();
+        }
+    }
+    let mut coefficients = [0i32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            let sum: f32 = (0..8).map(|y| rows[y * 8 + u] * cosines[v][y]).sum();
+            let cu = if u == 0 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            let cv = if v == 0 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            let value = 0.25 * cu * cv * sum;
+            coefficients[v * 8 + u] = (value / quant[v * 8 + u] as f32).round() as i32;
+        }
+    }
+    coefficients
+}
+
+// Number of bits needed for `value`, and those bits in JPEG's one's complement form.
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value - 1 } else { value };
+    (size, (bits & ((1 << size) - 1)) as u16)
+}
+
+fn encode_block(
+    writer: &mut BitWriter,
+    coefficients: &[i32; 64],
+    previous_dc: &mut i32,
+    dc_table: &HuffmanTable,
+    ac_table: &HuffmanTable,
+) {
+    let dc = coefficients[0];
+    let (size, bits) = magnitude(dc - *previous_dc);
+    *previous_dc = dc;
+    let (code, length) = dc_table.codes[size as usize];
+    writer.write(code, length);
+    writer.write(bits, size);
+
+    let mut zeros = 0;
+    for &index in &ZIGZAG[1..] {
+        let value = coefficients[index];
+        if value == 0 {
+            zeros += 1;
+            continue;
+        }
+        while zeros >= 16 {
+            let (code, length) = ac_table.codes[0xf0]; // ZRL: sixteen zeros
+            writer.write(code, length);
+            zeros -= 16;
+        }
+        let (size, bits) = magnitude(value);
+        let (code, length) = ac_table.codes[(zeros << 4 | size) as usize];
+        writer.write(code, length);
+        writer.write(bits, size);
+        zeros = 0;
+    }
+    if zeros > 0 {
+        let (code, length) = ac_table.codes[0x00]; // EOB
+        writer.write(code, length);
+    }
+}
@@ -0,0 +1,154 @@
+// Resource limits of spawned host programs (ProcessOptions::limits): niceness, CPU affinity
+// and rlimits, set in the child between fork and exec. Everything that could make them fail
+// there is checked up front, so a limit the server cannot apply fails the request with a
+// reason instead of being dropped or surfacing as a spawn error of the program.
+
+use std::io;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use bridge_core::{BridgeError, ErrorKind, Limits};
+
+// Checks `limits` and has `command` apply them. `switches_user` tells that the child drops
+// root before the limits are set, which leaves it unable to raise its priority.
+pub fn apply(
+    command: &mut Command,
+    limits: &Limits,
+    switches_user: bool,
+) -> Result<(), BridgeError> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    let root = unsafe { libc::geteuid() } == 0;
+
+    if let Some(nice) = limits.nice {
+        if !(-20..=19).contains(&nice) {
+            return Err(invalid(format!(
+                "Invalid nice {}: expected -20 to 19",
+                nice
+            )));
+        }
+        if nice < 0 && (!root || switches_user) {
+            return Err(BridgeError {
+                kind: ErrorKind::PermissionDenied,
+                message: format!(
+                    "Cannot run at nice {}: raising the priority needs root, and the command would not run as root",
+                    nice
+                ),
+            });
+        }
+    }
+
+    let cpus = match limits.cpus {
+        Some(mask) => Some(cpu_set(mask)?),
+        None => None,
+    };
+
+    let mut rlimits = Vec::new();
+    for (resource, name, value) in [
+        (libc::RLIMIT_AS, "memory", limits.max_mem),
+        (libc::RLIMIT_FSIZE, "file size", limits.max_file_size),
+    ] {
+        let Some(value) = value else { continue };
+        if value == 0 {
+            return Err(invalid(format!("Invalid {} limit of 0 bytes", name)));
+        }
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(resource, &mut current) } == -1 {
+            let e = io::Error::last_os_error();
+            return Err(BridgeError {
+                kind: ErrorKind::of(&e),
+                message: format!("Cannot read the server's {} limit: {}", name, e),
+            });
+        }
+        // Only root may go past the hard limit the server itself runs under.
+        // The cast is a no-op on 64-bit targets, where rlim_t already is u64.
+        #[allow(clippy::unnecessary_cast)]
+        let hard = current.rlim_max as u64;
+        if value > hard && !root {
+            return Err(BridgeError {
+                kind: ErrorKind::PermissionDenied,
+                message: format!(
+                    "Cannot set a {} limit of {} bytes: the server is limited to {} and not running as root",
+                    name, value, hard
+                ),
+            });
+        }
+        // Hard and soft alike, so the program cannot lift it again.
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        rlimits.push((resource, limit));
+    }
+
+    let nice = limits.nice;
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(set) = &cpus
+                && libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), set) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            for (resource, limit) in &rlimits {
+                if libc::setrlimit(*resource, limit) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+// The CPUs of `mask` as a cpu_set_t, if the server may run on at least one of them.
+fn cpu_set(mask: u64) -> Result<libc::cpu_set_t, BridgeError> {
+    if mask == 0 {
+        return Err(invalid("Invalid CPU list: no CPU given".to_string()));
+    }
+    let mut allowed: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut allowed) } == -1
+    {
+        let e = io::Error::last_os_error();
+        return Err(BridgeError {
+            kind: ErrorKind::of(&e),
+            message: format!("Cannot read the server's CPUs: {}", e),
+        });
+    }
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    let mut usable = false;
+    for cpu in (0..64).filter(|cpu| mask & (1 << cpu) != 0) {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        usable |= unsafe { libc::CPU_ISSET(cpu, &allowed) };
+    }
+    if !usable {
+        let available: Vec<String> = (0..64)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &allowed) })
+            .map(|cpu| cpu.to_string())
+            .collect();
+        return Err(BridgeError {
+            kind: ErrorKind::NotFound,
+            message: format!(
+                "None of the CPUs asked for is available; the server may use {}",
+                available.join(",")
+            ),
+        });
+    }
+    Ok(set)
+}
+
+fn invalid(message: String) -> BridgeError {
+    BridgeError {
+        kind: ErrorKind::ProtocolError,
+        message,
+    }
+}
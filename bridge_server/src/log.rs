@@ -0,0 +1,170 @@
+// The server's log: leveled lines with a timestamp and the id of the connection they belong
+// to, so that the lines of clients served at the same time can be told apart. They go to
+// --log-file when set, otherwise warnings and errors to stderr and the rest to stdout.
+// Logging is best effort: a full disk or a closed stdout loses lines, never a connection.
+
+use std::cell::Cell;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+struct Logger {
+    level: Level,
+    file: Option<Mutex<File>>,
+    redact_args: bool,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // The connection the current thread works for.
+    static CONNECTION: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// Sets up the log from --log-level, --log-file and --log-redact-args. Until then, lines
+// are written as if at the default level.
+pub fn init(config: &Config) -> io::Result<()> {
+    let file = match &config.log_file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => None,
+    };
+    let _ = LOGGER.set(Logger {
+        level: config.log_level,
+        file,
+        redact_args: config.log_redact_args,
+    });
+    Ok(())
+}
+
+fn level() -> Level {
+    LOGGER.get().map_or(Level::Info, |logger| logger.level)
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= self::level()
+}
+
+// Gives the current thread a new connection id, included in the lines it logs from now on.
+pub fn new_connection() {
+    let id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    CONNECTION.with(|connection| connection.set(Some(id)));
+}
+
+// The connection of this thread, to hand on to threads it starts with `set_connection`.
+pub fn connection() -> Option<u64> {
+    CONNECTION.with(Cell::get)
+}
+
+pub fn set_connection(id: Option<u64>) {
+    CONNECTION.with(|connection| connection.set(id));
+}
+
+// The arguments of a command as logged: all of them, or only how many with
+// --log-redact-args, for commands that carry secrets.
+pub fn args(args: &[String]) -> String {
+    match LOGGER.get() {
+        Some(logger) if logger.redact_args => format!("[{} args redacted]", args.len()),
+        _ => format!("{:?}", args),
+    }
+}
+
+pub fn redacts_args() -> bool {
+    LOGGER.get().is_some_and(|logger| logger.redact_args)
+}
+
+pub fn write(level: Level, message: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let mut line = format!("{} {:<5} ", timestamp(), level);
+    if let Some(id) = connection() {
+        line.push_str(&format!("[conn {}] ", id));
+    }
+    line.push_str(&message.to_string());
+    line.push('\n');
+    match LOGGER.get().and_then(|logger| logger.file.as_ref()) {
+        Some(file) => {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = file.write_all(line.as_bytes());
+        }
+        None if level <= Level::Warn => {
+            let _ = io::stderr().write_all(line.as_bytes());
+        }
+        None => {
+            let _ = io::stdout().lock().write_all(line.as_bytes());
+        }
+    }
+}
+
+// The local time as YYYY-MM-DD HH:MM:SS.mmm.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let time = now.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis()
+    )
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
+
+pub(crate) use {debug, error, info, trace, warning};
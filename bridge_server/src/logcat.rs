@@ -0,0 +1,78 @@
+// Logcat: the log of one app, for `andro logcat --pid PACKAGE`. The server looks up the pid
+// of the package's running process itself, so the client needs no round trip of its own to
+// learn it, and the request then runs as the Stream of `logcat --pid` it stands for. Policy
+// checks and Cancel apply to that Stream like to any other.
+
+use std::process::{Command, Stdio};
+
+use bridge_core::{BridgeCommand, BridgeError, ErrorKind, ProcessOptions};
+
+use crate::log;
+
+// The Stream a Logcat request stands for.
+pub fn expand(
+    package: String,
+    mut args: Vec<String>,
+    options: ProcessOptions,
+) -> Result<BridgeCommand, BridgeError> {
+    let pid = pid_of(&package).map_err(|mut e| {
+        e.message = format!("Logcat Failed: {}", e.message);
+        e
+    })?;
+    log::info!("Logcat: {} runs as pid {}", package, pid);
+    // Ahead of the client's arguments, which may end with filterspecs.
+    args.insert(0, format!("--pid={}", pid));
+    Ok(BridgeCommand::Stream {
+        program: "logcat".to_string(),
+        args,
+        stdin: false,
+        options,
+    })
+}
+
+// The pid of the main process of `package`, which is named after it; the lowest if there are
+// several. Only if there is none does `pm` tell an app that is not running from one that is
+// not installed.
+fn pid_of(package: &str) -> Result<u32, BridgeError> {
+    let valid = !package.is_empty()
+        && !package.starts_with(['-', '.'])
+        && package
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_');
+    if !valid {
+        return Err(BridgeError {
+            kind: ErrorKind::ProtocolError,
+            message: format!("'{}' is not a package name", package),
+        });
+    }
+    let output = Command::new("pidof")
+        .arg(package)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| BridgeError {
+            kind: ErrorKind::of(&e),
+            message: format!("cannot run pidof: {}", e),
+        })?;
+    let pids = String::from_utf8_lossy(&output.stdout);
+    if let Some(pid) = pids
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .min()
+    {
+        return Ok(pid);
+    }
+    let installed = Command::new("pm")
+        .args(["path", package])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    Err(BridgeError {
+        kind: ErrorKind::NotFound,
+        message: match installed {
+            Ok(status) if !status.success() => format!("no package {} is installed", package),
+            _ => format!("{} is not running", package),
+        },
+    })
+}
@@ -1,200 +1,2046 @@
+use std::cell::Cell;
 use std::fs;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use bridge_core::{BridgeCommand, BridgeResponse};
+use clap::Parser;
 
+use bridge_core::keys::Keycode;
+use bridge_core::proto::{FrameError, FrameTooLarge, MAX_REQUEST_LEN};
+use bridge_core::transport::{JsonLines, Transport};
+use bridge_core::{
+    BUSY_PROTOCOL_VERSION, BridgeCommand, BridgeError, BridgeResponse, COMMAND_NAMES, Codec,
+    DIRECT_INPUT_COMMANDS, DecodeError, ERROR_KIND_PROTOCOL_VERSION, ErrorKind, Load,
+    OLDEST_PROTOCOL_VERSION, PRE_HANDSHAKE_VERSION, PROTOCOL_VERSION, ProcessOptions,
+    READS_COMPRESSED, SPILL_PROTOCOL_VERSION, ServerInfo, VERSION_MASK, abstract_name, negotiate,
+    protocol_mismatch,
+};
+
+mod auth;
+mod background;
+mod capture;
+mod clipboard;
+mod config;
+mod daemon;
+mod device;
+#[cfg(feature = "direct_input")]
+mod evdev;
+mod idle;
 #[cfg(feature = "direct_input")]
 mod input_manager;
+mod jpeg;
+mod limits;
+mod log;
+mod logcat;
+#[cfg(feature = "direct_input")]
+mod monitor;
+#[cfg(feature = "direct_input")]
+mod mouse;
+mod multiplex;
+mod policy;
+mod pool;
+mod process;
+mod prop;
+mod pty;
+#[cfg(feature = "direct_input")]
+mod recorder;
+#[cfg(feature = "direct_input")]
+mod rotation;
+mod screen_stream;
+mod screenshot;
+mod shell_session;
+mod shutdown;
+mod signal;
+mod transfer;
+#[cfg(feature = "direct_input")]
+mod uinput;
 
-// Unix socket location, as seen from the Android Host side.
+use config::Config;
+use process::{Deadline, ProcessGroup, ProcessSlot};
+
+// Default Unix socket location (--socket / BRIDGE_SOCKET), as seen from the Android Host side.
 // This path must be accessible from within the chroot environment.
 const SOCKET_PATH: &str = "/data/local/rootfs/ubuntu-resolute-26.04/tmp/bridge.sock";
 
+// --max-frame-size, for the readers of follow-up frames deep inside commands.
+static MAX_FRAME: AtomicU64 = AtomicU64::new(MAX_REQUEST_LEN);
+
+pub fn max_frame() -> u64 {
+    MAX_FRAME.load(Ordering::Relaxed)
+}
+
+thread_local! {
+    // The protocol version agreed with the client the current thread works for. Threads that
+    // were never told answer in the oldest one, which every client decodes.
+    static CLIENT_PROTOCOL: Cell<u32> = const { Cell::new(OLDEST_PROTOCOL_VERSION) };
+}
+
+// The client's protocol version, to hand on to threads it starts with `set_client_protocol`.
+pub fn client_protocol() -> u32 {
+    CLIENT_PROTOCOL.with(Cell::get)
+}
+
+pub fn set_client_protocol(version: u32) {
+    CLIENT_PROTOCOL.with(|protocol| protocol.set(version));
+}
+
+thread_local! {
+    // Whether the client the current thread works for reads compressed frames. Threads that
+    // were never told send plain ones, which every client reads.
+    static COMPRESSION: Cell<bool> = const { Cell::new(false) };
+}
+
+// Whether responses may be compressed, to hand on to threads like `client_protocol`.
+pub fn compression() -> bool {
+    COMPRESSION.with(Cell::get)
+}
+
+pub fn set_compression(enabled: bool) {
+    COMPRESSION.with(|compression| compression.set(enabled));
+}
+
 fn main() -> std::io::Result<()> {
-    // Ensure there are no leftover socket files from a previous session that could cause an error.
-    if Path::new(SOCKET_PATH).exists() {
-        fs::remove_file(SOCKET_PATH)?;
+    let mut config = Config::parse();
+    MAX_FRAME.store(config.max_frame_size, Ordering::Relaxed);
+    if config.stop || config.status {
+        let path = config.pidfile.as_deref().expect("clap requires --pidfile");
+        let code = if config.status {
+            daemon::status(path)?
+        } else {
+            // The server's own grace period, if it was started with the same one.
+            let wait = Duration::from_millis(config.shutdown_grace_ms) + Duration::from_secs(3);
+            daemon::stop(path, wait)?
+        };
+        std::process::exit(code);
     }
-
-    let listener = UnixListener::bind(SOCKET_PATH)?;
-    // Change the socket file permissions to be accessible by all users,
-    // including processes running inside the chroot with a different user.
-    Command::new("chmod").arg("777").arg(SOCKET_PATH).output()?;
-    println!("Bridge Server active at: {}", SOCKET_PATH);
+    log::init(&config).map_err(|e| {
+        let path = config.log_file.as_deref().unwrap_or_else(|| "".as_ref());
+        std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+    })?;
+    match &config.auth_token_file {
+        Some(path) => {
+            let token = auth::load_token(path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            config.auth_token = Some(token);
+        }
+        // TCP clients have no uid to check, and the port may be reachable from the network.
+        None if config.listen.is_some() => {
+            return Err(std::io::Error::other(
+                "--listen requires --auth-token-file, TCP clients cannot be identified otherwise",
+            ));
+        }
+        // Without a file there is no mode to restrict who connects, not even to root.
+        None if config.allow_uid.is_empty() && abstract_name(&config.socket).is_some() => {
+            return Err(std::io::Error::other(
+                "an abstract socket can be reached by every app on the device; \
+                 use it with --auth-token-file or --allow-uid",
+            ));
+        }
+        None if config.allow_uid.is_empty() => log::warning!(
+            "running without --auth-token-file or --allow-uid. Any process that can \
+             reach the socket can run commands as root on the host."
+        ),
+        None => {}
+    }
+    let mut pidfile = config
+        .pidfile
+        .as_deref()
+        .map(daemon::Pidfile::lock)
+        .transpose()?;
+    let ready = if config.daemon {
+        if config.log_file.is_none() {
+            log::warning!("--daemon without --log-file: the log is discarded");
+        }
+        Some(daemon::detach()?)
+    } else {
+        None
+    };
+    if let Some(pidfile) = &mut pidfile {
+        pidfile.write_pid()?;
+    }
+    let config = Arc::new(config);
+    let listener = listen(&config)?;
+    let tcp = match &config.listen {
+        Some(address) => {
+            let tcp = TcpListener::bind(address)?;
+            log::info!("Bridge Server active at: tcp://{}", tcp.local_addr()?);
+            Some(tcp)
+        }
+        None => None,
+    };
 
     #[cfg(feature = "direct_input")]
-    println!(" [Feature Enabled] Direct Kernel Input Module Loaded");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut socket) => {
-                // Each client connection is handled in a separate thread.
-                // This prevents one client from blocking others that want to connect,
-                // which is important for handling multiple requests simultaneously.
-                thread::spawn(move || {
-                    handle_client(&mut socket);
-                });
+    {
+        log::info!(" [Feature Enabled] Direct Kernel Input Module Loaded");
+        input_manager::init(&config);
+    }
+
+    process::init(&config);
+    capture::init(&config);
+    let signals = shutdown::install()?;
+    let freed = pool::install()?;
+    if let Some(ready) = ready {
+        ready.done(config.log_file.as_deref())?;
+    }
+    serve(&listener, tcp.as_ref(), &signals, &freed, &config);
+    log::info!("Shutting down");
+    shutdown::drain(
+        Duration::from_millis(config.shutdown_grace_ms),
+        config.keep_jobs,
+    );
+    if abstract_name(&config.socket).is_none() {
+        let _ = fs::remove_file(&config.socket);
+    }
+    if let (Some(pidfile), Some(path)) = (pidfile, &config.pidfile) {
+        daemon::remove(pidfile, path);
+    }
+    log::info!("Stopped");
+    Ok(())
+}
+
+// Accepts connections on the socket and the TCP port until a signal asks the server to stop.
+// While --max-connections are being served the listeners are left alone until `freed`, from
+// `pool::install`, tells that one has closed.
+fn serve(
+    listener: &UnixListener,
+    tcp: Option<&TcpListener>,
+    signals: &OwnedFd,
+    freed: &OwnedFd,
+    config: &Arc<Config>,
+) {
+    let watch = |fd: RawFd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let mut fds = vec![
+        watch(signals.as_raw_fd()),
+        watch(freed.as_raw_fd()),
+        watch(listener.as_raw_fd()),
+    ];
+    // A client that gives up between poll and accept must not block the loop.
+    let _ = listener.set_nonblocking(true);
+    if let Some(tcp) = tcp {
+        let _ = tcp.set_nonblocking(true);
+        fds.push(watch(tcp.as_raw_fd()));
+    }
+    loop {
+        let full = pool::full(config.max_connections);
+        for listening in &mut fds[2..] {
+            listening.events = if full { 0 } else { libc::POLLIN };
+            listening.revents = 0;
+        }
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
             }
-            Err(err) => {
-                eprintln!("Failed to accept connection: {}", err);
+            log::error!("Failed to wait for connections: {}", e);
+            return;
+        }
+        if fds[0].revents != 0 && shutdown::requested(signals) {
+            return;
+        }
+        if fds[1].revents != 0 {
+            pool::clear(freed.as_raw_fd());
+        }
+        if fds[2].revents & libc::POLLIN != 0 {
+            match listener.accept() {
+                Ok((socket, _)) => spawn_handler(socket, config),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => log::error!("Failed to accept connection: {}", e),
+            }
+        }
+        if let Some(tcp) = tcp
+            && fds[3].revents & libc::POLLIN != 0
+            && !pool::full(config.max_connections)
+        {
+            match tcp.accept() {
+                Ok((socket, _)) => {
+                    // Requests are small, so send them without waiting to fill a packet.
+                    let _ = socket.set_nodelay(true);
+                    if let Err(e) = keep_alive(&socket) {
+                        log::warning!("Failed to enable TCP keepalive: {}", e);
+                    }
+                    spawn_handler(socket, config);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => log::error!("Failed to accept connection: {}", e),
             }
         }
     }
+}
+
+// Probes an idle TCP connection after KEEPALIVE_IDLE_SECS, then every KEEPALIVE_INTERVAL_SECS,
+// and fails it after KEEPALIVE_PROBES unanswered ones. A peer that vanished without a FIN or
+// RST (a phone leaving Wi-Fi, a VM that was suspended) then shows as an error on the socket,
+// which the job monitor takes for a hang-up, instead of holding its jobs forever.
+const KEEPALIVE_IDLE_SECS: libc::c_int = 30;
+const KEEPALIVE_INTERVAL_SECS: libc::c_int = 10;
+const KEEPALIVE_PROBES: libc::c_int = 3;
+
+fn keep_alive(socket: &std::net::TcpStream) -> std::io::Result<()> {
+    let options = [
+        (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, KEEPALIVE_IDLE_SECS),
+        (
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPINTVL,
+            KEEPALIVE_INTERVAL_SECS,
+        ),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, KEEPALIVE_PROBES),
+    ];
+    for (level, name, value) in options {
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
     Ok(())
 }
 
+// Each client connection is handled on a thread of the pool. This prevents one client from
+// blocking others that want to connect, which is important for handling multiple requests
+// simultaneously.
+fn spawn_handler<S: Transport + 'static>(mut socket: S, config: &Arc<Config>) {
+    let config = Arc::clone(config);
+    let active = shutdown::Active::start(&socket);
+    pool::run(move || {
+        let _active = active;
+        log::new_connection();
+        set_client_protocol(OLDEST_PROTOCOL_VERSION);
+        set_compression(false);
+        let _owner = process::Owner::start(&socket);
+        log::debug!("Connection opened");
+        match first_byte(&socket, &config) {
+            Ok(b'{') => {
+                let mut socket = JsonLines(Box::new(socket));
+                handle_client(&mut socket, &config);
+            }
+            Ok(_) => handle_client(&mut socket, &config),
+            // Not even a byte within --idle-timeout. The codec is unknown, so whatever the
+            // client is, it gets the Error as bincode.
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                log::info!(
+                    "Closing connection, nothing received for {} s (--idle-timeout)",
+                    config.idle_timeout
+                );
+                let response = BridgeResponse::Error(format!(
+                    "Idle Timeout: nothing received for {} s, closing the connection",
+                    config.idle_timeout
+                ));
+                let _ = write_response(&mut socket, &response);
+            }
+            // The client went away without a word.
+            Err(_) => {}
+        }
+        log::debug!("Connection closed");
+    });
+}
+
+// The first byte the client sent, left in the socket for the codec to read. A JSON hello
+// opens with `{`, which a bincode frame (its length prefix starts with zero) never does.
+fn first_byte(socket: &dyn Transport, config: &Config) -> std::io::Result<u8> {
+    let mut byte = 0u8;
+    socket.set_read_timeout(idle::limit(config))?;
+    let n = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK,
+        )
+    };
+    let result = match n {
+        1 => Ok(byte),
+        0 => Err(std::io::ErrorKind::UnexpectedEof.into()),
+        _ => match std::io::Error::last_os_error() {
+            e if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+            e => Err(e),
+        },
+    };
+    socket.set_read_timeout(None)?;
+    result
+}
+
+// Binds the server socket. An abstract socket (`@name`) has no file: nothing is left over
+// to clean up, but there are no permissions either, so any process in the same network
+// namespace (Android apps included) can connect unless a token or uid allowlist is set.
+fn listen(config: &Config) -> std::io::Result<UnixListener> {
+    if let Some(name) = abstract_name(&config.socket) {
+        let listener = UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?)?;
+        log::info!(
+            "Bridge Server active at: {} (abstract)",
+            config.socket.display()
+        );
+        return Ok(listener);
+    }
+
+    // Ensure there are no leftover socket files from a previous session that could cause an error.
+    let socket_path = config.socket.as_path();
+    if socket_path.exists() {
+        // Only a socket nobody listens on; taking over a live one would cut off its server.
+        if UnixStream::connect(socket_path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("another server is listening on {}", socket_path.display()),
+            ));
+        }
+        fs::remove_file(socket_path)?;
+    } else if let Some(parent) = socket_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        // E.g. a rootfs whose tmp directory is only created on first boot.
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+    // Processes inside the chroot usually run as another user, so by default everyone may
+    // connect. Set before announcing the socket, so clients started after the log line
+    // never find it root-only.
+    if let Some(gid) = config.socket_group {
+        std::os::unix::fs::chown(socket_path, None, Some(gid))?;
+    }
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(config.socket_mode))?;
+    log::info!(
+        "Bridge Server active at: {} (mode {:o})",
+        socket_path.display(),
+        config.socket_mode
+    );
+    Ok(listener)
+}
+
 // Helper to send a response to the client with a length-prefix protocol.
 // [8-byte data length][data]
 // This ensures the client can read the message correctly, even if the data is fragmented.
-fn write_response(socket: &mut UnixStream, response: &BridgeResponse) -> std::io::Result<()> {
-    let bytes = bincode::serialize(response).unwrap();
-    let len = bytes.len() as u64;
-    socket.write_all(&len.to_be_bytes())?;
-    socket.write_all(&bytes)?;
-    Ok(())
+// JSON connections get a line instead. Large frames are compressed for clients that read them.
+fn write_response(socket: &mut dyn Transport, response: &BridgeResponse) -> std::io::Result<()> {
+    let codec = socket.codec();
+    let payload = codec.encode_response_for(response, client_protocol())?;
+    #[cfg(feature = "compress")]
+    if codec == Codec::Bincode && compression() {
+        return bridge_core::proto::write_compressed_frame(socket, &payload);
+    }
+    codec.write_frame(socket, &payload)
 }
 
-fn handle_client(socket: &mut UnixStream) {
-    let mut buffer = [0; 8192];
-    if let Ok(size) = socket.read(&mut buffer) {
-        if size == 0 {
-            return;
+fn handle_client(socket: &mut dyn Transport, config: &Config) {
+    if !handshake(socket, config) {
+        return;
+    }
+    // One command after another until the client hangs up, so one-shot clients end the
+    // loop simply by closing after their response.
+    while let Some(payload) = read_request(socket, config, &|| false) {
+        if !handle_request(socket, config, &payload) {
+            break;
         }
+    }
+}
 
-        match bincode::deserialize::<BridgeCommand>(&buffer[0..size]) {
-            Ok(cmd) => {
-                if let BridgeCommand::Stream { program, args } = cmd {
-                    handle_stream_request(socket, program, args);
-                } else {
-                    let response = execute_request(cmd);
-                    let _ = write_response(socket, &response);
-                }
+// Runs one command and reports whether the connection can carry another. Streams, transfers
+// and sessions use the rest of the connection for themselves, so they end it.
+fn handle_request(socket: &mut dyn Transport, config: &Config, payload: &[u8]) -> bool {
+    let command = socket
+        .codec()
+        .decode_command_for(payload, client_protocol());
+    if let Ok(cmd) = &command {
+        log::trace!("Request: {} ({} bytes)", cmd.name(), payload.len());
+    }
+    let command = match command {
+        Ok(BridgeCommand::Logcat {
+            package,
+            args,
+            options,
+        }) => match logcat::expand(package, args, options) {
+            Ok(stream) => Ok(stream),
+            Err(e) => return write_response(socket, &failure(e.kind, e.message)).is_ok(),
+        },
+        command => command,
+    };
+    if let Ok(cmd) = &command
+        && let Err(e) = policy::check(config, cmd)
+    {
+        log::warning!("Refused: {}", e);
+        return write_response(socket, &failure(ErrorKind::PolicyRejected, e)).is_ok();
+    }
+    let binary = matches!(command, Ok(BridgeCommand::ExecBytes { .. }));
+    match command {
+        Ok(BridgeCommand::Stream {
+            program,
+            args,
+            stdin,
+            options,
+        }) => {
+            handle_stream_request(socket, config, program, args, stdin, options);
+            false
+        }
+        Ok(BridgeCommand::ExecPty {
+            program,
+            args,
+            cols,
+            rows,
+        }) => {
+            pty::handle_pty_request(socket, program, args, cols, rows);
+            false
+        }
+        Ok(BridgeCommand::PushFile {
+            dest,
+            mode,
+            resume,
+            verify,
+        }) => {
+            transfer::handle_push(socket, dest, mode, resume, verify);
+            false
+        }
+        Ok(BridgeCommand::JobOutput { id, follow }) => {
+            background::handle_output(socket, id, follow);
+            false
+        }
+        // Ends with StreamEnd like a stream, but leaves the connection to the next line.
+        Ok(BridgeCommand::SessionExec { id, line }) => shell_session::handle_exec(socket, id, line),
+        Ok(BridgeCommand::PullFile {
+            src,
+            recursive,
+            offset,
+            verify,
+        }) => {
+            transfer::handle_pull(socket, config, src, recursive, offset, verify);
+            false
+        }
+        Ok(BridgeCommand::Screenshot { format }) => {
+            screenshot::handle_screenshot(socket, config, format);
+            false
+        }
+        Ok(BridgeCommand::ScreenStream {
+            format,
+            fps,
+            max_frames,
+        }) => {
+            screen_stream::handle_screen_stream(socket, format, fps, max_frames);
+            false
+        }
+        #[cfg(feature = "direct_input")]
+        Ok(BridgeCommand::RecordInput { source }) => {
+            recorder::handle_record(socket, source);
+            false
+        }
+        #[cfg(feature = "direct_input")]
+        Ok(BridgeCommand::MonitorInput { device, raw }) => {
+            monitor::handle_monitor(socket, device, raw);
+            false
+        }
+        #[cfg(feature = "direct_input")]
+        Ok(
+            cmd @ (BridgeCommand::DirectTouchDown { .. }
+            | BridgeCommand::DirectTouchMove { .. }
+            | BridgeCommand::DirectTouchUp { .. }),
+        ) => {
+            input_manager::handle_touch_session(socket, config, cmd);
+            false
+        }
+        Ok(
+            BridgeCommand::Exec {
+                program,
+                args,
+                stdin: true,
+                options,
             }
-            Err(e) => {
-                let response = BridgeResponse::Error(format!("Invalid Payload: {}", e));
-                let _ = write_response(socket, &response);
+            | BridgeCommand::ExecBytes {
+                program,
+                args,
+                stdin: true,
+                options,
+            },
+        ) => {
+            // The rest of the connection carries the child's stdin.
+            let response = match (socket.try_clone_box(), socket.try_clone_box()) {
+                (Ok(input), Ok(output)) => {
+                    let input = Some(follow_ups(input));
+                    let spill = replies(output);
+                    execute_exec(program, args, options, input, None, binary, Some(&spill))
+                }
+                (Err(e), _) | (_, Err(e)) => failure(ErrorKind::of(&e), e.to_string()),
+            };
+            let _ = write_response(socket, &response);
+            false
+        }
+        Ok(
+            BridgeCommand::Exec {
+                program,
+                args,
+                options,
+                ..
             }
-        };
+            | BridgeCommand::ExecBytes {
+                program,
+                args,
+                options,
+                ..
+            },
+        ) => {
+            // Output too big to hold goes ahead of the response, down a second handle.
+            let response = match socket.try_clone_box() {
+                Ok(output) => {
+                    let spill = replies(output);
+                    execute_exec(program, args, options, None, None, binary, Some(&spill))
+                }
+                Err(e) => failure(ErrorKind::of(&e), e.to_string()),
+            };
+            write_response(socket, &response).is_ok()
+        }
+        Ok(BridgeCommand::Batch {
+            commands,
+            stop_on_error,
+        }) => {
+            let response = run_batch(commands, stop_on_error, config);
+            write_response(socket, &response).is_ok()
+        }
+        Ok(BridgeCommand::Tagged {
+            request_id,
+            command,
+        }) => {
+            multiplex::serve(socket, config, request_id, *command);
+            false
+        }
+        Ok(cmd) => write_response(socket, &respond(cmd, config)).is_ok(),
+        Err(DecodeError::Unsupported(kind)) => {
+            log::warning!("Unsupported command kind {}", kind);
+            write_response(socket, &BridgeResponse::Unsupported { kind }).is_ok()
+        }
+        Err(e) => {
+            let response = failure(ErrorKind::ProtocolError, format!("Invalid Payload: {}", e));
+            write_response(socket, &response).is_ok()
+        }
     }
 }
 
-fn handle_stream_request(socket: &mut UnixStream, program: String, args: Vec<String>) {
-    println!("Stream: {} {:?}", program, args);
+fn handle_stream_request(
+    socket: &mut dyn Transport,
+    config: &Config,
+    program: String,
+    args: Vec<String>,
+    stdin: bool,
+    options: ProcessOptions,
+) {
+    // Output is written from threads of their own, through a handle of its own; without one
+    // (e.g. out of file descriptors) nothing is started.
+    let output = match socket.try_clone_box() {
+        Ok(output) => output,
+        Err(e) => {
+            log::warning!("Failed to set up stream connection: {}", e);
+            let response = failure(ErrorKind::of(&e), format!("Stream Failed: {}", e));
+            let _ = write_response(socket, &response);
+            let _ = write_response(socket, &stream_end(None));
+            return;
+        }
+    };
+    let follow_ups = match socket.try_clone_box() {
+        Ok(input) => Some(follow_ups(input)),
+        Err(e) => {
+            log::warning!("Failed to watch stream connection: {}", e);
+            None
+        }
+    };
+    run_stream(
+        program,
+        args,
+        stdin,
+        options,
+        config,
+        replies(output),
+        follow_ups,
+        None,
+    );
+}
 
-    let child = Command::new(&program)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn();
+// Writes responses down `output`. stdout and stderr are forwarded from threads of their own,
+// which share the connection behind a Mutex so that only one of them writes a frame at a time.
+fn replies(output: Box<dyn Transport>) -> Reply {
+    let output = Mutex::new(output);
+    Arc::new(move |response| write_response(&mut **output.lock().unwrap(), &response))
+}
+
+// Sends one response of a running command to its client: straight down the connection, or
+// through the writer of a multiplexed one.
+pub type Reply = Arc<dyn Fn(BridgeResponse) -> std::io::Result<()> + Send + Sync>;
+
+// The frames a client sends while its command runs (StdinChunk, StdinEnd, ...).
+pub type FollowUps = Box<dyn Iterator<Item = BridgeCommand> + Send>;
+
+// Reads follow-up frames from the connection until the client disconnects. Frames that do
+// not decode are skipped.
+fn follow_ups(mut input: Box<dyn Transport>) -> FollowUps {
+    Box::new(std::iter::from_fn(move || {
+        loop {
+            let payload = input.codec().read_frame(&mut *input, max_frame()).ok()??;
+            if let Ok(cmd) = input.codec().decode_command(&payload) {
+                return Some(cmd);
+            }
+        }
+    }))
+}
 
-    let mut child = match child {
+// Runs a program with its output forwarded as StdoutChunk and StderrChunk responses, ending
+// with StreamEnd. Once `follow_ups` runs out (the client went away) the process group is
+// cancelled. `slot` publishes the group for Cancel.
+#[allow(clippy::too_many_arguments)]
+pub fn run_stream(
+    program: String,
+    args: Vec<String>,
+    stdin: bool,
+    options: ProcessOptions,
+    config: &Config,
+    reply: Reply,
+    follow_ups: Option<FollowUps>,
+    slot: Option<&ProcessSlot>,
+) {
+    log::info!("Stream: {} {}", program, log::args(&args));
+
+    let child = host_command(&program, args, &options)
+        .map_err(|e| failure(e.kind, e.message))
+        .and_then(|mut command| {
+            command
+                .stdin(if stdin { Stdio::piped() } else { Stdio::null() })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            ProcessGroup::spawn(&mut command).map_err(|e| process::spawn_failed("", &e))
+        });
+
+    let (mut child, group) = match child {
         Ok(c) => c,
-        Err(e) => {
-            let _ = write_response(socket, &BridgeResponse::Error(e.to_string()));
-            let _ = write_response(socket, &BridgeResponse::StreamEnd);
+        Err(response) => {
+            let _ = reply(response);
+            let _ = reply(stream_end(None));
             return;
         }
     };
+    if let Some(slot) = slot {
+        slot.set(&group);
+    }
+    let deadline = Deadline::start(&group, options.timeout_ms);
 
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
-    // The `socket` needs to be accessed from multiple threads (for stdout and stderr).
-    // `Arc` (Atomic Reference Counting) allows for shared ownership.
-    // `Mutex` (Mutual Exclusion) ensures that only one thread can write to the socket at a time.
-    let socket = Arc::new(Mutex::new(socket.try_clone().unwrap()));
+    if let Some(follow_ups) = follow_ups {
+        let child_stdin = child.stdin.take();
+        let group = Arc::clone(&group);
+        let connection = log::connection();
+        thread::spawn(move || {
+            log::set_connection(connection);
+            watch_client(follow_ups, child_stdin, &group)
+        });
+    }
 
-    let stdout_socket = Arc::clone(&socket);
+    // Both pipes feed one channel, written out here in the order their reads completed, so
+    // stdout and stderr reach the client interleaved as a terminal would have shown them.
+    // It is bounded: a slow client holds up the readers instead of queueing output.
+    let chunk_size = config.chunk_size as usize;
+    let linger = Duration::from_millis(config.stream_coalesce_ms);
+    let (sender, chunks) = mpsc::sync_channel(CHUNK_QUEUE);
+    let stdout_sender = sender.clone();
     let stdout_thread = thread::spawn(move || {
-        let reader = BufReader::new(stdout);
-        for line_content in reader.lines().map_while(Result::ok) {
-            let response = BridgeResponse::StreamChunk(line_content);
-            let mut socket_guard = stdout_socket.lock().unwrap();
-            if write_response(&mut socket_guard, &response).is_err() {
-                break; // Stop if the client closes the connection
-            }
-        }
+        stream_pipe(
+            stdout,
+            &stdout_sender,
+            chunk_size,
+            BridgeResponse::StdoutChunk,
+        )
     });
-
-    let stderr_socket = Arc::clone(&socket);
     let stderr_thread = thread::spawn(move || {
-        let reader = BufReader::new(stderr);
-        for line_content in reader.lines().map_while(Result::ok) {
-            // Output from stderr is also sent as a StreamChunk, with a prefix for identification.
-            let response = BridgeResponse::StreamChunk(format!("[STDERR] {}", line_content));
-            let mut socket_guard = stderr_socket.lock().unwrap();
-            if write_response(&mut socket_guard, &response).is_err() {
-                break; // Stop if the client closes the connection
-            }
-        }
+        stream_pipe(stderr, &sender, chunk_size, BridgeResponse::StderrChunk)
     });
 
+    let mut held = None;
+    while let Some(chunk) = held.take().or_else(|| chunks.recv().ok()) {
+        let (frame, next) = coalesce(chunk, &chunks, chunk_size, linger);
+        held = next;
+        if reply(frame).is_err() {
+            // The client closed the connection: stop the child instead of letting it run on.
+            group.cancel();
+            break;
+        }
+    }
     stdout_thread.join().unwrap();
     stderr_thread.join().unwrap();
 
-    let _ = child.wait();
+    // Both pipes are drained (threads joined) before the end frame, so it is always last.
+    let status = child.wait().ok();
+    group.mark_reaped();
+
+    if deadline.is_some_and(Deadline::finish) {
+        let timed_out = BridgeResponse::TimedOut {
+            timeout_ms: options.timeout_ms.unwrap_or_default(),
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        let _ = reply(timed_out);
+    }
     // Send the final signal to notify the client that all output has been sent.
-    let mut socket_guard = socket.lock().unwrap();
-    let _ = write_response(&mut socket_guard, &BridgeResponse::StreamEnd);
+    let _ = reply(stream_end(status));
 }
 
-fn execute_request(cmd: BridgeCommand) -> BridgeResponse {
-    #[allow(unreachable_patterns)]
-    match cmd {
-        BridgeCommand::Exec { program, args } => {
-            println!("Exec: {} {:?}", program, args);
-
-            if program == "logcat" {
-                // We only allow `logcat -d` and `logcat -c` with exec.
-                // Any other `logcat` command should be streamed to avoid blocking.
-                let is_valid_exec_logcat = match args.len() {
-                    1 => args[0] == "-d" || args[0] == "-c",
-                    _ => false, // Disallow `logcat` with no args or multiple args via exec
-                };
-
-                if !is_valid_exec_logcat {
-                    return BridgeResponse::Error(
-                        "For live logcat, use the -s flag. Only 'logcat -d' and 'logcat -c' are allowed with -e.".to_string()
-                    );
-                }
+// Builds the Command for a host process according to the client's options.
+// The child leads its own process group so that everything it starts
+// can be killed together on timeout or when the client goes away.
+// Invalid options are reported as an error message before anything is spawned.
+fn host_command(
+    program: &str,
+    args: Vec<String>,
+    options: &ProcessOptions,
+) -> Result<Command, BridgeError> {
+    let mut command = Command::new(program);
+    // Variables are added on top of the server's environment; an empty list inherits it as is.
+    command
+        .args(args)
+        .envs(options.env.iter().map(|(key, value)| (key, value)))
+        .process_group(0);
+
+    if let Some(cwd) = &options.cwd {
+        // Checked up front: a missing directory would otherwise surface as a spawn error
+        // that looks like the program itself was not found.
+        match fs::metadata(cwd) {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => {
+                return Err(BridgeError {
+                    kind: ErrorKind::NotFound,
+                    message: format!("Working directory '{}' is not a directory", cwd),
+                });
             }
+            Err(e) => {
+                return Err(BridgeError {
+                    kind: ErrorKind::of(&e),
+                    message: format!("Working directory '{}' is not accessible: {}", cwd, e),
+                });
+            }
+        }
+        command.current_dir(cwd);
+    }
 
-            let output = Command::new(&program).args(args).output();
-            match output {
-                Ok(o) => {
-                    if o.status.success() {
-                        BridgeResponse::Success(String::from_utf8_lossy(&o.stdout).to_string())
-                    } else {
-                        BridgeResponse::Error(String::from_utf8_lossy(&o.stderr).to_string())
-                    }
+    if options.uid.is_some() || options.gid.is_some() {
+        // Only root may switch credentials. Without this check the child's setuid would fail
+        // after fork and be reported like a permission problem of the target binary.
+        let euid = unsafe { libc::geteuid() };
+        if euid != 0 {
+            return Err(BridgeError {
+                kind: ErrorKind::PermissionDenied,
+                message: format!(
+                    "Cannot switch to uid {:?} / gid {:?}: the bridge server is not running as root (euid {})",
+                    options.uid, options.gid, euid
+                ),
+            });
+        }
+        if let Some(gid) = options.gid {
+            command.gid(gid);
+        }
+        if let Some(uid) = options.uid {
+            command.uid(uid);
+        }
+    }
+    let switches_user = options.uid.is_some_and(|uid| uid != 0);
+    limits::apply(&mut command, &options.limits, switches_user)?;
+    Ok(command)
+}
+
+// Runs a program to completion and collects its output.
+// When `input` is given, its StdinChunk frames are piped into the child's stdin until
+// StdinEnd (or the client disconnecting) closes the pipe. `slot` publishes the group for Cancel.
+// Output past --exec-buffer goes to `spill` ahead of the response, see `capture`.
+pub fn execute_exec(
+    program: String,
+    args: Vec<String>,
+    options: ProcessOptions,
+    input: Option<FollowUps>,
+    slot: Option<&ProcessSlot>,
+    // Answer with CompletedBytes (ExecBytes) instead of Completed.
+    binary: bool,
+    spill: Option<&Reply>,
+) -> BridgeResponse {
+    log::info!("Exec: {} {}", program, log::args(&args));
+
+    if program == "logcat" {
+        // We only allow `logcat -d` and `logcat -c` with exec.
+        // Any other `logcat` command should be streamed to avoid blocking.
+        let is_valid_exec_logcat = match args.len() {
+            1 => args[0] == "-d" || args[0] == "-c",
+            _ => false, // Disallow `logcat` with no args or multiple args via exec
+        };
+
+        if !is_valid_exec_logcat {
+            return failure(
+                ErrorKind::PolicyRejected,
+                "For live logcat, use the -s flag. Only 'logcat -d' and 'logcat -c' are allowed with -e.".to_string(),
+            );
+        }
+    }
+
+    let child = host_command(&program, args, &options)
+        .map_err(|e| not_started(e.message, None))
+        .and_then(|mut command| {
+            command
+                .stdin(if input.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            ProcessGroup::spawn(&mut command).map_err(|e| match e.raw_os_error() {
+                Some(errno) => {
+                    not_started(format!("Cannot start '{}': {}", program, e), Some(errno))
                 }
-                Err(e) => BridgeResponse::Error(e.to_string()),
+                // Busy, rather than the program failing to start.
+                None => process::spawn_failed("", &e),
+            })
+        });
+
+    let (mut child, group) = match child {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+    if let Some(slot) = slot {
+        slot.set(&group);
+    }
+    let deadline = Deadline::start(&group, options.timeout_ms);
+
+    if let Some(input) = input {
+        // The watcher is not joined: if the child exits without reading all of its input,
+        // the thread ends on its own once the client closes the connection.
+        let child_stdin = child.stdin.take();
+        let group = Arc::clone(&group);
+        let connection = log::connection();
+        thread::spawn(move || {
+            log::set_connection(connection);
+            watch_client(input, child_stdin, &group)
+        });
+    }
+
+    // On timeout the watchdog kills the group, which closes the pipes and lets `capture`
+    // return with whatever had been captured up to that point.
+    let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    // Older clients take nothing but the response.
+    let spill = spill.filter(|_| client_protocol() >= SPILL_PROTOCOL_VERSION);
+    let captured = capture::capture(stdout, stderr, &group, spill);
+    let status = child.wait();
+    group.mark_reaped();
+    let timed_out = deadline.is_some_and(Deadline::finish);
+
+    let output = status.map(|status| std::process::Output {
+        status,
+        stdout: captured.stdout,
+        stderr: captured.stderr,
+    });
+    match output {
+        Ok(_) if group.cancelled() => failure(
+            ErrorKind::Internal,
+            "Exec Cancelled: stopped at the client's request".to_string(),
+        ),
+        Ok(_) if captured.overflowed => failure(
+            ErrorKind::Internal,
+            format!(
+                "Exec Failed: more than {} bytes of output, too much for a single response; stream it with -s instead",
+                max_frame()
+            ),
+        ),
+        Ok(o) if timed_out => BridgeResponse::TimedOut {
+            timeout_ms: options.timeout_ms.unwrap_or_default(),
+            stdout: if binary {
+                String::new()
+            } else {
+                String::from_utf8_lossy(&o.stdout).to_string()
+            },
+            stderr: String::from_utf8_lossy(&o.stderr).to_string(),
+        },
+        Ok(o) if binary => BridgeResponse::CompletedBytes {
+            stdout: o.stdout,
+            stderr: String::from_utf8_lossy(&o.stderr).to_string(),
+            code: exit_code(o.status),
+        },
+        Ok(o) => BridgeResponse::Completed {
+            stdout: String::from_utf8_lossy(&o.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&o.stderr).to_string(),
+            code: exit_code(o.status),
+        },
+        Err(e) => failure(ErrorKind::of(&e), e.to_string()),
+    }
+}
+
+// A failed command, as the client's protocol allows: before ERROR_KIND_PROTOCOL_VERSION only
+// as an Error.
+pub fn failure(kind: ErrorKind, message: String) -> BridgeResponse {
+    if client_protocol() < ERROR_KIND_PROTOCOL_VERSION {
+        return BridgeResponse::Error(message);
+    }
+    BridgeResponse::Failed(BridgeError { kind, message })
+}
+
+// Answers a request over --max-frame-size, as the client's protocol allows.
+fn frame_too_large(too_large: &FrameTooLarge) -> BridgeResponse {
+    if client_protocol() < BUSY_PROTOCOL_VERSION {
+        return failure(
+            ErrorKind::ProtocolError,
+            format!("Protocol Error: {} (--max-frame-size)", too_large),
+        );
+    }
+    BridgeResponse::FrameTooLarge {
+        len: too_large.len,
+        limit: too_large.limit,
+    }
+}
+
+// Answers an Exec whose program never ran, as the client's protocol allows.
+fn not_started(message: String, os_error: Option<i32>) -> BridgeResponse {
+    if client_protocol() == OLDEST_PROTOCOL_VERSION {
+        return BridgeResponse::Error(message);
+    }
+    BridgeResponse::SpawnFailed { os_error, message }
+}
+
+// Reads the client side of a running process until the client disconnects.
+// StdinChunk frames go to the child's stdin (if it has one) until StdinEnd closes it, and
+// Cancel stops the process. Once the connection is gone the process group is cancelled the
+// same way, so nothing keeps running on the host for a client that is no longer listening.
+fn watch_client(
+    follow_ups: FollowUps,
+    mut child_stdin: Option<ChildStdin>,
+    group: &Arc<ProcessGroup>,
+) {
+    for cmd in follow_ups {
+        match cmd {
+            BridgeCommand::StdinChunk(data) => {
+                if let Some(stdin) = child_stdin.as_mut()
+                    && stdin.write_all(&data).is_err()
+                {
+                    child_stdin = None; // The child closed its stdin
+                }
+            }
+            // Dropping the pipe is what delivers EOF to the child.
+            BridgeCommand::StdinEnd => child_stdin = None,
+            BridgeCommand::Cancel { .. } if group.cancel() => log::info!("Cancelled by the client"),
+            _ => {}
+        }
+    }
+    group.cancel();
+}
+
+// How many chunks of a stream may wait for the connection.
+const CHUNK_QUEUE: usize = 16;
+
+// Merges the chunks queued behind `frame` into it while they come from the same pipe and the
+// frame stays within `chunk_size`, so a chatty child that gets ahead of the connection costs
+// fewer frames. With a `linger` (--stream-coalesce-ms) it also waits that long for more output
+// to join a frame that is not yet full. Returns the frame and the chunk that did not fit.
+fn coalesce(
+    mut frame: BridgeResponse,
+    chunks: &Receiver<BridgeResponse>,
+    chunk_size: usize,
+    linger: Duration,
+) -> (BridgeResponse, Option<BridgeResponse>) {
+    let deadline = Instant::now() + linger;
+    loop {
+        let len = match &frame {
+            BridgeResponse::StdoutChunk(bytes) | BridgeResponse::StderrChunk(bytes) => bytes.len(),
+            _ => return (frame, None),
+        };
+        if len >= chunk_size {
+            return (frame, None);
+        }
+        let next = match chunks.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(next) => next,
+            Err(_) => return (frame, None),
+        };
+        match (&mut frame, next) {
+            (BridgeResponse::StdoutChunk(bytes), BridgeResponse::StdoutChunk(more))
+            | (BridgeResponse::StderrChunk(bytes), BridgeResponse::StderrChunk(more))
+                if len + more.len() <= chunk_size =>
+            {
+                bytes.extend_from_slice(&more)
             }
+            (_, next) => return (frame, Some(next)),
+        }
+    }
+}
+
+// Forwards one output pipe of a streamed child to the stream's writer, tagging every chunk
+// with `wrap` so the client can tell stdout and stderr apart.
+// Whatever a single read returns is sent right away: output is passed through as raw bytes
+// (binary data survives intact) and partial lines such as `\r` progress bars show up live.
+fn stream_pipe<R: Read>(
+    mut pipe: R,
+    chunks: &SyncSender<BridgeResponse>,
+    chunk_size: usize,
+    wrap: fn(Vec<u8>) -> BridgeResponse,
+) {
+    let mut buffer = vec![0u8; chunk_size];
+    loop {
+        let n = match pipe.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        // Fails once the writer has given up on the client.
+        if chunks.send(wrap(buffer[..n].to_vec())).is_err() {
+            break;
         }
-        BridgeCommand::Ping => BridgeResponse::Success("Pong!".to_string()),
+    }
+}
+
+// Runs a single-response command, as a dry run if it asks for one or the server forces it.
+fn respond(cmd: BridgeCommand, config: &Config) -> BridgeResponse {
+    #[cfg(feature = "direct_input")]
+    if input_manager::dry_run_forced() || wants_dry_run(&cmd) {
+        let (response, plan) = input_manager::dry_run(|| execute_request(cmd, config));
+        return input_manager::with_plan(response, plan);
+    }
+    execute_request(cmd, config)
+}
+
+// Runs the commands of a Batch in order, one response each. With `stop_on_error` the first
+// failure ends the batch, so fewer responses than commands come back.
+fn run_batch(commands: Vec<BridgeCommand>, stop_on_error: bool, config: &Config) -> BridgeResponse {
+    log::info!("Batch: {} commands", commands.len());
+    // Checked before anything runs, so a bad entry never leaves the batch half done.
+    for (i, cmd) in commands.iter().enumerate() {
+        if let Some(reason) = unbatchable(cmd) {
+            return failure(
+                ErrorKind::ProtocolError,
+                format!(
+                    "Batch Failed: command {} ({}) {}",
+                    i + 1,
+                    cmd.name(),
+                    reason
+                ),
+            );
+        }
+    }
+    let mut responses = Vec::with_capacity(commands.len());
+    for cmd in commands {
+        let response = respond(cmd, config);
+        let failed = failed(&response);
+        responses.push(response);
+        if failed && stop_on_error {
+            break;
+        }
+    }
+    BridgeResponse::Batch(responses)
+}
+
+// Why `cmd` cannot be part of a batch, if it cannot: a batch only holds commands answered by
+// a single response, and does not nest.
+fn unbatchable(cmd: &BridgeCommand) -> Option<&'static str> {
+    match cmd {
+        BridgeCommand::Batch { .. } => Some("cannot be nested"),
+        BridgeCommand::Tagged { .. } | BridgeCommand::Cancel { .. } => {
+            Some("only works on a multiplexed connection")
+        }
+        BridgeCommand::Exec { stdin: true, .. }
+        | BridgeCommand::ExecBytes { stdin: true, .. }
+        | BridgeCommand::Stream { .. }
+        | BridgeCommand::Logcat { .. }
+        | BridgeCommand::ExecPty { .. }
+        | BridgeCommand::PushFile { .. }
+        | BridgeCommand::PullFile { .. }
+        | BridgeCommand::Screenshot { .. }
+        | BridgeCommand::ScreenStream { .. }
+        | BridgeCommand::RecordInput { .. }
+        | BridgeCommand::MonitorInput { .. }
+        | BridgeCommand::JobOutput { .. }
+        | BridgeCommand::SessionExec { .. }
+        | BridgeCommand::DirectTouchDown { .. }
+        | BridgeCommand::DirectTouchMove { .. }
+        | BridgeCommand::DirectTouchUp { .. }
+        | BridgeCommand::StdinChunk(_)
+        | BridgeCommand::StdinEnd
+        | BridgeCommand::Resize { .. }
+        | BridgeCommand::FileChunk(_)
+        | BridgeCommand::FileEnd => Some("needs a connection of its own"),
+        _ => None,
+    }
+}
+
+fn failed(response: &BridgeResponse) -> bool {
+    match response {
+        BridgeResponse::Completed { code, .. } | BridgeResponse::CompletedBytes { code, .. } => {
+            *code != 0
+        }
+        BridgeResponse::Error(_)
+        | BridgeResponse::Failed(_)
+        | BridgeResponse::SpawnFailed { .. }
+        | BridgeResponse::TimedOut { .. }
+        | BridgeResponse::Unsupported { .. } => true,
+        _ => false,
+    }
+}
+
+fn execute_request(cmd: BridgeCommand, config: &Config) -> BridgeResponse {
+    #[allow(unreachable_patterns)]
+    match cmd {
+        BridgeCommand::Exec {
+            program,
+            args,
+            options,
+            ..
+        } => execute_exec(program, args, options, None, None, false, None),
+        BridgeCommand::ExecBytes {
+            program,
+            args,
+            options,
+            ..
+        } => execute_exec(program, args, options, None, None, true, None),
+        BridgeCommand::Ping => BridgeResponse::Success(pong()),
+        BridgeCommand::Cancel { request_id } => failure(
+            ErrorKind::ProtocolError,
+            format!(
+                "Cancel Failed: request {} is not on a multiplexed connection, only Tagged requests can be cancelled",
+                request_id
+            ),
+        ),
+        BridgeCommand::Info => BridgeResponse::ServerInfo(server_info(config)),
+        BridgeCommand::DeviceInfo => BridgeResponse::DeviceInfo(Box::new(device::info())),
+        BridgeCommand::GetProp { name } => prop::get(name),
+        BridgeCommand::ListProps { prefix } => prop::list(prefix),
+        BridgeCommand::SetProp { name, value } => prop::set(name, value),
+        BridgeCommand::ListJobs => BridgeResponse::Jobs(process::jobs()),
+        BridgeCommand::JobStart {
+            program,
+            args,
+            options,
+        } => background::start(config, program, args, options),
+        BridgeCommand::JobList => background::list(),
+        BridgeCommand::JobKill { id, signal } => background::kill(id, signal),
+        BridgeCommand::SessionOpen { shell } => shell_session::open(config, shell),
+        BridgeCommand::SessionSignal { id, signal } => shell_session::handle_signal(id, signal),
+        BridgeCommand::SessionClose { id } => shell_session::handle_close(id),
+        BridgeCommand::Signal { target, signal } => signal::handle(config, target, signal),
+        BridgeCommand::Sleep { ms } if ms > config.max_sleep_ms => failure(
+            ErrorKind::ProtocolError,
+            format!(
+                "Sleep Failed: {} ms is longer than the {} ms allowed (--max-sleep-ms)",
+                ms, config.max_sleep_ms
+            ),
+        ),
+        BridgeCommand::Sleep { ms } => {
+            thread::sleep(Duration::from_millis(ms));
+            BridgeResponse::Success(String::new())
+        }
+        BridgeCommand::KeyEvent {
+            keycode,
+            long_press,
+        } => key_event(keycode, long_press, config),
+        BridgeCommand::ClipboardGet => clipboard::get(),
+        BridgeCommand::ClipboardSet(text) => clipboard::set(text),
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectTap { at, .. } => {
+            let mut warnings = Vec::new();
+            match input_manager::point(at, &mut warnings)
+                .and_then(|(x, y)| input_manager::tap(x, y))
+            {
+                Ok(_) => input_success(warnings),
+                Err(e) => failure(ErrorKind::InputDeviceError, format!("Tap Failed: {}", e)),
+            }
+        }
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectTapBatch { taps } => match input_manager::tap_batch(&taps) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err((delivered, e)) => failure(
+                ErrorKind::InputDeviceError,
+                format!(
+                    "Tap Batch Failed after {} of {} taps: {}",
+                    delivered,
+                    taps.len(),
+                    e
+                ),
+            ),
+        },
         #[cfg(feature = "direct_input")]
-        BridgeCommand::DirectTap { x, y } => match input_manager::tap(x, y) {
+        BridgeCommand::DirectMultiSwipe {
+            fingers,
+            x1,
+            y1,
+            x2,
+            y2,
+            spacing,
+            duration_ms,
+        } => match input_manager::multi_swipe(fingers, (x1, y1), (x2, y2), spacing, duration_ms) {
             Ok(_) => BridgeResponse::Success("".to_string()),
-            Err(e) => BridgeResponse::Error(format!("Tap Failed: {}", e)),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Swipe Failed: {}", e)),
         },
         #[cfg(feature = "direct_input")]
         BridgeCommand::DirectSwipe {
+            from,
+            to,
+            duration_ms,
+            easing,
+            step_ms,
+            ..
+        } => {
+            let mut warnings = Vec::new();
+            let result = input_manager::point(from, &mut warnings).and_then(|start| {
+                let end = input_manager::point(to, &mut warnings)?;
+                input_manager::swipe(start, end, duration_ms, easing, step_ms)
+            });
+            match result {
+                Ok(_) => input_success(warnings),
+                Err(e) => failure(ErrorKind::InputDeviceError, format!("Swipe Failed: {}", e)),
+            }
+        }
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectLongPress { x, y, duration_ms } => {
+            match input_manager::long_press(x, y, duration_ms) {
+                Ok(_) => BridgeResponse::Success("".to_string()),
+                Err(e) => failure(
+                    ErrorKind::InputDeviceError,
+                    format!("Long Press Failed: {}", e),
+                ),
+            }
+        }
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectDrag {
             x1,
             y1,
             x2,
             y2,
+            hold_before_ms,
+            move_duration_ms,
+            hold_after_ms,
+        } => match input_manager::drag(
+            (x1, y1),
+            (x2, y2),
+            hold_before_ms,
+            move_duration_ms,
+            hold_after_ms,
+        ) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Drag Failed: {}", e)),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectPinch {
+            cx,
+            cy,
+            start_dist,
+            end_dist,
             duration_ms,
-        } => match input_manager::swipe(x1, y1, x2, y2, duration_ms) {
+        } => match input_manager::pinch(cx, cy, start_dist, end_dist, duration_ms) {
             Ok(_) => BridgeResponse::Success("".to_string()),
-            Err(e) => BridgeResponse::Error(format!("Swipe Failed: {}", e)),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Pinch Failed: {}", e)),
         },
-        _ => {
-            BridgeResponse::Error("Command not supported or feature disabled on server".to_string())
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectKey {
+            keycode,
+            long_press,
+        } => {
+            let hold_ms = if long_press { config.long_press_ms } else { 0 };
+            match input_manager::key(keycode, hold_ms) {
+                Ok(_) => BridgeResponse::Success("".to_string()),
+                Err(e) => failure(ErrorKind::InputDeviceError, format!("Key Failed: {}", e)),
+            }
+        }
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectText { text, delay_ms } => {
+            match input_manager::type_text(&text, delay_ms) {
+                Ok(_) => BridgeResponse::Success("".to_string()),
+                Err(e) => failure(ErrorKind::InputDeviceError, format!("Typing Failed: {}", e)),
+            }
+        }
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectPath { gesture } => match input_manager::path(&gesture) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Path Failed: {}", e)),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectReplay {
+            source,
+            events,
+            speed,
+        } => match recorder::replay(source, &events, speed) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Replay Failed: {}", e)),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::InputInfo => match input_manager::info() {
+            Ok(info) => BridgeResponse::Success(info),
+            Err(e) => failure(
+                ErrorKind::InputDeviceError,
+                format!("Input Info Failed: {}", e),
+            ),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::SetRotation(value) => match rotation::set(value) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(
+                ErrorKind::InputDeviceError,
+                format!("Rotation Failed: {}", e),
+            ),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::GetRotation => BridgeResponse::Success(format!("{}\n", rotation::current())),
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectMouseMove { dx, dy } => match mouse::move_by(dx, dy) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Mouse Failed: {}", e)),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectMouseClick { button } => match mouse::click(button) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Mouse Failed: {}", e)),
+        },
+        #[cfg(feature = "direct_input")]
+        BridgeCommand::DirectScroll { dy } => match mouse::scroll(dy) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Scroll Failed: {}", e)),
+        },
+        _ => failure(
+            ErrorKind::Internal,
+            "Command not supported or feature disabled on server".to_string(),
+        ),
+    }
+}
+
+// Exchanges hello frames with the client. Clients speaking no protocol version the server
+// does are answered with an Error explaining which side to update; returns whether to go on.
+// Older clients the server still speaks to get their version back in its hello.
+fn handshake(socket: &mut dyn Transport, config: &Config) -> bool {
+    let Some(hello) = read_request(socket, config, &|| false) else {
+        return false;
+    };
+    let codec = socket.codec();
+    // Clients from before the handshake start with their request; they still decode Error.
+    // JSON clients are never that old, so there a first line other than a hello is malformed.
+    let (client, token) = match codec.parse_hello(&hello) {
+        Some(hello) => hello,
+        None if codec == Codec::Json => {
+            let message = format!(
+                "Protocol Error: expected a hello line like {{\"protocol\":{}}}",
+                PROTOCOL_VERSION
+            );
+            let _ = write_response(socket, &BridgeResponse::Error(message));
+            return false;
+        }
+        None => (PRE_HANDSHAKE_VERSION, Vec::new()),
+    };
+    let reads_compressed = codec == Codec::Bincode && client & READS_COMPRESSED != 0;
+    let client = client & VERSION_MASK;
+    let version = negotiate(PROTOCOL_VERSION, client);
+    let hello = version.unwrap_or(PROTOCOL_VERSION);
+    if client != PRE_HANDSHAKE_VERSION && codec.write_hello(socket, hello, &[]).is_err() {
+        return false;
+    }
+    let refusal = if let Some(version) = version {
+        if let Some(refusal) = check_peer(socket, config) {
+            refusal
+        } else {
+            match &config.auth_token {
+                Some(expected) if !auth::token_matches(expected, &token) => {
+                    let problem = if token.is_empty() { "missing" } else { "wrong" };
+                    format!("Authentication Failed: {} token", problem)
+                }
+                _ => {
+                    set_client_protocol(version);
+                    set_compression(reads_compressed && cfg!(feature = "compress"));
+                    return write_response(socket, &BridgeResponse::Success(String::new())).is_ok();
+                }
+            }
+        }
+    } else {
+        protocol_mismatch(PROTOCOL_VERSION, client)
+    };
+    log::warning!("Rejected client: {}", refusal);
+    let _ = write_response(socket, &BridgeResponse::Error(refusal));
+    false
+}
+
+// Checks the client's uid against --allow-uid; returns the reason if it is refused.
+fn check_peer(socket: &dyn Transport, config: &Config) -> Option<String> {
+    if config.allow_uid.is_empty() {
+        return None;
+    }
+    match auth::peer_uid(socket) {
+        Ok(uid) if config.allow_uid.contains(&uid) => {
+            log::info!("Accepted client uid {}", uid);
+            None
+        }
+        Ok(uid) => Some(format!("Access Denied: uid {} not permitted", uid)),
+        Err(e) => Some(format!("Access Denied: cannot identify the client: {}", e)),
+    }
+}
+
+// Reads the next frame from the client. Malformed frames are answered with an Error, and so
+// is a client that takes longer than --idle-timeout to send one, unless `busy` says the
+// connection still has requests running.
+pub fn read_request(
+    socket: &mut dyn Transport,
+    config: &Config,
+    busy: &dyn Fn() -> bool,
+) -> Option<Vec<u8>> {
+    // Commands use the same length-prefix framing as responses, so a request is
+    // only decoded once it has arrived in full, regardless of how it was fragmented.
+    let codec = socket.codec();
+    let read = match idle::limit(config) {
+        Some(limit) => codec.read_frame(
+            &mut idle::FrameReader::new(socket, limit, busy),
+            max_frame(),
+        ),
+        None => codec.read_frame(socket, max_frame()),
+    };
+    match read {
+        Ok(payload) => payload,
+        Err(FrameError::TooLarge(too_large)) => {
+            log::warning!("Closing connection, {} (--max-frame-size)", too_large);
+            let _ = write_response(socket, &frame_too_large(&too_large));
+            None
+        }
+        Err(FrameError::Malformed(e)) => {
+            let response = failure(ErrorKind::ProtocolError, format!("Protocol Error: {}", e));
+            let _ = write_response(socket, &response);
+            None
+        }
+        Err(FrameError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+            log::info!(
+                "Closing connection, no complete request for {} s (--idle-timeout)",
+                config.idle_timeout
+            );
+            let response = failure(
+                ErrorKind::Timeout,
+                format!(
+                    "Idle Timeout: no complete request for {} s, closing the connection",
+                    config.idle_timeout
+                ),
+            );
+            let _ = write_response(socket, &response);
+            None
+        }
+        // The connection broke, or the client went away in the middle of a request.
+        Err(FrameError::Io(e)) => {
+            log::debug!("Failed to read a request: {}", e);
+            None
+        }
+    }
+}
+
+// Reply to Ping. Direct input builds add the active input backend on a line of its own,
+// which clients that only know the bare "Pong!" simply print.
+fn pong() -> String {
+    #[cfg(feature = "direct_input")]
+    return format!("Pong!\nInput backend: {}\n", input_manager::backend_name());
+    #[cfg(not(feature = "direct_input"))]
+    "Pong!".to_string()
+}
+
+// Frames that only follow another command on its connection, never requests of their own.
+const FOLLOW_UP_FRAMES: &[&str] = &["StdinChunk", "StdinEnd", "Resize", "FileChunk", "FileEnd"];
+
+// Reply to Info.
+fn server_info(config: &Config) -> ServerInfo {
+    let direct_input = cfg!(feature = "direct_input");
+    let commands = COMMAND_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !FOLLOW_UP_FRAMES.contains(name))
+        .filter(|(_, name)| direct_input || !DIRECT_INPUT_COMMANDS.contains(name))
+        .map(|(discriminant, _)| discriminant as u32)
+        .collect();
+    // Connections carry more than one command, and can run several at once (Tagged).
+    let mut features = vec!["persistent".to_string(), "multiplex".to_string()];
+    if direct_input {
+        features.push("direct_input".to_string());
+    }
+    if cfg!(feature = "compress") {
+        features.push("compress".to_string());
+    }
+    #[cfg(feature = "direct_input")]
+    let touch_device = input_manager::touch_device_name();
+    #[cfg(not(feature = "direct_input"))]
+    let touch_device = None;
+    ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol: PROTOCOL_VERSION,
+        features,
+        touch_device,
+        commands,
+        load: load(config),
+    }
+}
+
+fn load(config: &Config) -> Load {
+    let (jobs, max_jobs, queued) = process::load();
+    Load {
+        connections: pool::busy(),
+        max_connections: config.max_connections,
+        jobs,
+        max_jobs,
+        queued,
+    }
+}
+
+// Builds the StreamEnd frame that closes a stream, carrying the child's exit status if known.
+fn stream_end(status: Option<ExitStatus>) -> BridgeResponse {
+    BridgeResponse::StreamEnd {
+        code: status.and_then(|s| s.code()),
+        signal: status.and_then(|s| s.signal()),
+    }
+}
+
+// Converts a child's exit status into a shell-style exit code.
+// Children terminated by a signal have no code, so they map to 128 + signal number.
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+// Whether an input command asks to be planned instead of executed.
+#[cfg(feature = "direct_input")]
+fn wants_dry_run(cmd: &BridgeCommand) -> bool {
+    matches!(
+        cmd,
+        BridgeCommand::DirectTap { dry_run: true, .. }
+            | BridgeCommand::DirectSwipe { dry_run: true, .. }
+    )
+}
+
+// Success for an input command, carrying any warnings about adjusted coordinates.
+#[cfg(feature = "direct_input")]
+fn input_success(warnings: Vec<String>) -> BridgeResponse {
+    let mut message = String::new();
+    for warning in warnings {
+        message.push_str(&format!("Warning: {}\n", warning));
+    }
+    BridgeResponse::Success(message)
+}
+
+// Presses the key, directly through the key device when this build supports it and the
+// device is usable, otherwise with `input keyevent`.
+#[cfg_attr(not(feature = "direct_input"), allow(unused_variables))]
+fn key_event(keycode: Keycode, long_press: bool, config: &Config) -> BridgeResponse {
+    #[cfg(feature = "direct_input")]
+    if let Some(key) = keycode.key()
+        && input_manager::key_device_available()
+    {
+        let hold_ms = if long_press { config.long_press_ms } else { 0 };
+        return match input_manager::key(key.linux, hold_ms) {
+            Ok(_) => BridgeResponse::Success("".to_string()),
+            Err(e) => failure(ErrorKind::InputDeviceError, format!("Key Failed: {}", e)),
+        };
+    }
+
+    let mut args = vec!["keyevent".to_string()];
+    if long_press {
+        args.push("--longpress".to_string());
+    }
+    args.push(keycode.0.to_string());
+    #[cfg(feature = "direct_input")]
+    if input_manager::dry_running() {
+        return BridgeResponse::Success(format!("Would run: input {}\n", args.join(" ")));
+    }
+    execute_exec(
+        "input".to_string(),
+        args,
+        ProcessOptions::default(),
+        None,
+        None,
+        false,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A connection that cannot be duplicated, as when the server is out of descriptors.
+    struct Unclonable(UnixStream);
+
+    impl std::io::Read for Unclonable {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl std::io::Write for Unclonable {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl AsRawFd for Unclonable {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl Transport for Unclonable {
+        fn try_clone_box(&self) -> std::io::Result<Box<dyn Transport>> {
+            Err(std::io::Error::from_raw_os_error(libc::EMFILE))
+        }
+
+        fn set_timeouts(&self, _: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Responses from `client` until StreamEnd, which is left out.
+    fn stream_responses(client: &mut UnixStream) -> impl Iterator<Item = BridgeResponse> + '_ {
+        std::iter::from_fn(move || {
+            let frame = Codec::Bincode.read_frame(client, MAX_REQUEST_LEN).ok()??;
+            match Codec::Bincode.decode_response(&frame).unwrap() {
+                BridgeResponse::StreamEnd { .. } => None,
+                response => Some(response),
+            }
+        })
+    }
+
+    #[test]
+    fn stream_is_killed_when_the_client_disconnects() {
+        set_client_protocol(PROTOCOL_VERSION);
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let handler = thread::spawn(move || {
+            let config = Config::parse_from(["bridge_server"]);
+            let args = vec!["-c".to_string(), "echo $$; exec sleep 30".to_string()];
+            let options = ProcessOptions::default();
+            handle_stream_request(&mut server, &config, "sh".into(), args, false, options);
+        });
+        let pid: i32 = match stream_responses(&mut client).next() {
+            Some(BridgeResponse::StdoutChunk(data)) => {
+                String::from_utf8(data).unwrap().trim().parse().unwrap()
+            }
+            other => panic!("expected the pid, got {:?}", other),
+        };
+        let started = Instant::now();
+        drop(client);
+        handler.join().unwrap();
+        // Killed and reaped within the second the request allows, with a little room for a
+        // loaded machine.
+        assert!(
+            started.elapsed() < Duration::from_millis(1200),
+            "took {:?}",
+            started.elapsed()
+        );
+        // Reaped, so not even a zombie is left.
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[test]
+    fn tcp_connections_get_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        keep_alive(&socket).unwrap();
+        let option = |level, name| {
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    socket.as_raw_fd(),
+                    level,
+                    name,
+                    &mut value as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, 0);
+            value
+        };
+        assert_eq!(option(libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+        assert_eq!(
+            option(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE),
+            KEEPALIVE_IDLE_SECS
+        );
+        assert_eq!(
+            option(libc::IPPROTO_TCP, libc::TCP_KEEPCNT),
+            KEEPALIVE_PROBES
+        );
+    }
+
+    #[test]
+    fn stream_without_descriptors_fails_instead_of_panicking() {
+        set_client_protocol(PROTOCOL_VERSION);
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let config = Config::parse_from(["bridge_server"]);
+        let mut socket = Unclonable(server);
+        let options = ProcessOptions::default();
+        handle_stream_request(&mut socket, &config, "true".into(), vec![], false, options);
+        drop(socket);
+
+        let mut next = || {
+            let frame = Codec::Bincode
+                .read_frame(&mut client, MAX_REQUEST_LEN)
+                .unwrap()
+                .unwrap();
+            Codec::Bincode.decode_response(&frame).unwrap()
+        };
+        match next() {
+            BridgeResponse::Failed(e) => assert!(e.message.starts_with("Stream Failed:")),
+            other => panic!("expected Failed, got {:?}", other),
+        }
+        assert!(matches!(next(), BridgeResponse::StreamEnd { .. }));
+    }
+
+    #[test]
+    fn frame_too_large_for_clients_that_know_it() {
+        set_client_protocol(BUSY_PROTOCOL_VERSION);
+        let too_large = FrameTooLarge {
+            len: 1 << 30,
+            limit: MAX_REQUEST_LEN,
+        };
+        assert!(matches!(
+            frame_too_large(&too_large),
+            BridgeResponse::FrameTooLarge { len, limit } if len == 1 << 30 && limit == MAX_REQUEST_LEN
+        ));
+    }
+
+    #[test]
+    fn error_instead_of_frame_too_large_for_protocol_4() {
+        set_client_protocol(OLDEST_PROTOCOL_VERSION);
+        let too_large = FrameTooLarge {
+            len: 1 << 30,
+            limit: MAX_REQUEST_LEN,
+        };
+        match frame_too_large(&too_large) {
+            BridgeResponse::Error(message) => assert!(message.contains("exceeds the")),
+            other => panic!("expected Error, got {:?}", other),
+        }
+    }
+
+    // Runs the server's side of the handshake after `client` sent `first`; returns whether
+    // the server went on, with every frame it answered.
+    fn handshake_after(first: &[u8]) -> (bool, Vec<Vec<u8>>) {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        Codec::Bincode.write_frame(&mut client, first).unwrap();
+        let accepted = handshake(&mut server, &Config::parse_from(["bridge_server"]));
+        drop(server);
+        let frames = std::iter::from_fn(|| {
+            Codec::Bincode
+                .read_frame(&mut client, MAX_REQUEST_LEN)
+                .unwrap()
+        });
+        (accepted, frames.collect())
+    }
+
+    fn hello(version: u32) -> Vec<u8> {
+        [&bridge_core::HELLO_MAGIC[..], &version.to_be_bytes()].concat()
+    }
+
+    // The version in the server's hello.
+    fn hello_version(frame: &[u8]) -> u32 {
+        Codec::Bincode.parse_hello(frame).unwrap().0
+    }
+
+    fn error(frame: &[u8]) -> String {
+        match Codec::Bincode.decode_response(frame).unwrap() {
+            BridgeResponse::Error(message) => message,
+            other => panic!("expected Error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn handshake_settles_on_the_older_version() {
+        for (client, spoken) in [
+            (PROTOCOL_VERSION, PROTOCOL_VERSION),
+            (PROTOCOL_VERSION + 1, PROTOCOL_VERSION),
+            (OLDEST_PROTOCOL_VERSION, OLDEST_PROTOCOL_VERSION),
+        ] {
+            let (accepted, frames) = handshake_after(&hello(client));
+            assert!(accepted);
+            assert_eq!(hello_version(&frames[0]), spoken);
+            assert!(matches!(
+                Codec::Bincode.decode_response(&frames[1]).unwrap(),
+                BridgeResponse::Success(message) if message.is_empty()
+            ));
+            assert_eq!(client_protocol(), spoken);
+        }
+    }
+
+    #[test]
+    fn handshake_refuses_a_client_too_old_to_speak_to() {
+        let old = OLDEST_PROTOCOL_VERSION - 1;
+        let (accepted, frames) = handshake_after(&hello(old));
+        assert!(!accepted);
+        assert_eq!(hello_version(&frames[0]), PROTOCOL_VERSION);
+        assert_eq!(error(&frames[1]), protocol_mismatch(PROTOCOL_VERSION, old));
+        assert!(error(&frames[1]).ends_with("please update bridge_client"));
+    }
+
+    #[test]
+    fn handshake_answers_a_client_without_one_with_a_readable_error() {
+        // A build from before the handshake opens with its request, a bare bincode command.
+        let (accepted, frames) =
+            handshake_after(&bincode::serialize(&BridgeCommand::Ping).unwrap());
+        assert!(!accepted);
+        // No hello, which such a client could not read, only the Error it can.
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            error(&frames[0]),
+            protocol_mismatch(PROTOCOL_VERSION, PRE_HANDSHAKE_VERSION)
+        );
+    }
+
+    #[test]
+    fn allow_uid_admits_listed_peers_only() {
+        let (server, _client) = UnixStream::pair().unwrap();
+        let uid = unsafe { libc::getuid() };
+        let allow =
+            |uid: u32| Config::parse_from(["bridge_server", "--allow-uid", &uid.to_string()]);
+        assert_eq!(
+            check_peer(&server, &Config::parse_from(["bridge_server"])),
+            None
+        );
+        assert_eq!(check_peer(&server, &allow(uid)), None);
+        assert_eq!(
+            check_peer(&server, &allow(uid + 1)),
+            Some(format!("Access Denied: uid {} not permitted", uid))
+        );
+    }
+
+    #[test]
+    fn handshake_closes_on_a_peer_that_is_not_allowed() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let other = unsafe { libc::getuid() } + 1;
+        let config = Config::parse_from(["bridge_server", "--allow-uid", &other.to_string()]);
+        Codec::Bincode
+            .write_frame(&mut client, &hello(PROTOCOL_VERSION))
+            .unwrap();
+        assert!(!handshake(&mut server, &config));
+        let mut next = || {
+            Codec::Bincode
+                .read_frame(&mut client, MAX_REQUEST_LEN)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(hello_version(&next()), PROTOCOL_VERSION);
+        assert!(error(&next()).ends_with("not permitted"));
+    }
+
+    #[test]
+    fn exec_round_trip_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let listen = format!("tcp://{}", address);
+        let server = thread::spawn(move || {
+            let mut config = Config::parse_from(["bridge_server", "--listen", &listen]);
+            config.auth_token = Some("secret".to_string());
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().unwrap();
+                handle_client(&mut socket, &config);
+            }
+        });
+        let next = |client: &mut std::net::TcpStream| {
+            Codec::Bincode
+                .read_frame(client, MAX_REQUEST_LEN)
+                .unwrap()
+                .unwrap()
+        };
+        let connect = |token: &[u8]| {
+            let mut client = bridge_core::transport::connect_tcp(&address).unwrap();
+            Codec::Bincode
+                .write_hello(&mut client, PROTOCOL_VERSION, token)
+                .unwrap();
+            assert_eq!(hello_version(&next(&mut client)), PROTOCOL_VERSION);
+            let answer = Codec::Bincode.decode_response(&next(&mut client)).unwrap();
+            (client, answer)
+        };
+
+        match connect(b"wrong") {
+            (_, BridgeResponse::Error(message)) => {
+                assert_eq!(message, "Authentication Failed: wrong token")
+            }
+            (_, other) => panic!("expected Error, got {:?}", other),
+        }
+        let (mut client, answer) = connect(b"secret");
+        assert!(matches!(answer, BridgeResponse::Success(message) if message.is_empty()));
+        let exec = BridgeCommand::Exec {
+            program: "echo".to_string(),
+            args: vec!["over".to_string(), "tcp".to_string()],
+            stdin: false,
+            options: ProcessOptions::default(),
+        };
+        let payload = Codec::Bincode.encode_command(&exec).unwrap();
+        Codec::Bincode.write_frame(&mut client, &payload).unwrap();
+        match Codec::Bincode.decode_response(&next(&mut client)).unwrap() {
+            BridgeResponse::Completed { stdout, code, .. } => {
+                assert_eq!((stdout.as_str(), code), ("over tcp\n", 0))
+            }
+            other => panic!("expected Completed, got {:?}", other),
+        }
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn stalled_client_is_told_why_it_is_closed() {
+        set_client_protocol(PROTOCOL_VERSION);
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let config = Config::parse_from(["bridge_server", "--idle-timeout", "1"]);
+        // Half of a length header, then nothing.
+        client.write_all(&[0, 0, 0, 0]).unwrap();
+        assert_eq!(read_request(&mut server, &config, &|| false), None);
+        let frame = Codec::Bincode
+            .read_frame(&mut client, MAX_REQUEST_LEN)
+            .unwrap()
+            .unwrap();
+        match Codec::Bincode.decode_response(&frame).unwrap() {
+            BridgeResponse::Failed(e) => {
+                assert_eq!(e.kind, ErrorKind::Timeout);
+                assert!(
+                    e.message
+                        .starts_with("Idle Timeout: no complete request for 1 s")
+                );
+            }
+            other => panic!("expected Failed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sleeps_over_the_limit_are_refused() {
+        set_client_protocol(PROTOCOL_VERSION);
+        let config = Config::parse_from(["bridge_server", "--max-sleep-ms", "100"]);
+        let started = Instant::now();
+        assert!(matches!(
+            respond(BridgeCommand::Sleep { ms: 50 }, &config),
+            BridgeResponse::Success(_)
+        ));
+        match respond(BridgeCommand::Sleep { ms: u64::MAX }, &config) {
+            BridgeResponse::Failed(e) => {
+                assert_eq!(e.kind, ErrorKind::ProtocolError);
+                assert_eq!(
+                    e.message,
+                    "Sleep Failed: 18446744073709551615 ms is longer than the 100 ms allowed (--max-sleep-ms)"
+                );
+            }
+            other => panic!("expected a Failed, got {:?}", other),
         }
+        assert!(started.elapsed() < Duration::from_secs(1));
     }
 }
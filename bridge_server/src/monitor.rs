@@ -0,0 +1,153 @@
+// A getevent-like view of an input device (`andro monitor-input`), for calibrating
+// coordinates. Output is sent as StdoutChunk frames, like a streamed command.
+#![cfg(feature = "direct_input")]
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeResponse, ErrorKind};
+
+use crate::evdev::{self, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT};
+use crate::input_manager::{self, ABS_MT_TRACKING_ID, SYN_REPORT};
+use crate::log;
+use crate::recorder;
+use crate::{failure, write_response};
+
+const EV_SYN: u16 = 0;
+const EV_ABS: u16 = 3;
+const SYN_MT_REPORT: u16 = 2;
+
+pub fn handle_monitor(socket: &mut dyn Transport, device: Option<String>, raw: bool) {
+    let result = match device {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => input_manager::touch_device(),
+    }
+    .and_then(|path| {
+        log::info!("Monitoring input from {}", path.display());
+        let mut file = File::open(&path)?;
+        monitor(socket, &mut file, raw)
+    });
+    if let Err(e) = result {
+        let message = format!("Monitor Failed: {}", e);
+        let _ = write_response(socket, &failure(ErrorKind::InputDeviceError, message));
+    }
+}
+
+// Streams the device's events until the client disconnects. Raw mode sends the
+// `input_event` records unchanged; otherwise each event becomes a line, and every
+// SYN_REPORT is followed by the positions of the contacts down at that point.
+fn monitor(socket: &mut dyn Transport, file: &mut File, raw: bool) -> io::Result<()> {
+    let mapping = input_manager::screen_mapping(file);
+    let mut contacts = Contacts::new(file);
+    let client = socket.as_raw_fd();
+    let mut output = Vec::new();
+    recorder::read_events(file, client, |record| {
+        let (time, type_, code, value) = input_manager::read_event(record);
+        if raw {
+            output.extend_from_slice(record);
+        } else {
+            let line = format!(
+                "[{:>5}.{:06}] {}\n",
+                time.as_secs(),
+                time.subsec_micros(),
+                evdev::describe(type_, code, value)
+            );
+            output.extend_from_slice(line.as_bytes());
+            contacts.update(type_, code, value);
+            if type_ == EV_SYN && code == SYN_REPORT {
+                for (slot, (x, y)) in contacts.take_frame() {
+                    let mut line = format!("               slot {}: x={} y={}", slot, x, y);
+                    if let Some(mapping) = &mapping {
+                        let (px, py) = mapping((x, y));
+                        line.push_str(&format!(" (screen {},{})", px, py));
+                    }
+                    line.push('\n');
+                    output.extend_from_slice(line.as_bytes());
+                }
+            }
+        }
+        // Sent per frame, so output is not held back while the device is idle.
+        if type_ == EV_SYN && code == SYN_REPORT {
+            write_response(
+                socket,
+                &BridgeResponse::StdoutChunk(std::mem::take(&mut output)),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+// Contacts of the device as the events describe them, for either multitouch protocol.
+struct Contacts {
+    // Protocol B: the slot being updated and the position of each active slot.
+    slotted: bool,
+    slot: i32,
+    slots: BTreeMap<i32, (i32, i32)>,
+    // Protocol A: the contact being reported and those completed by SYN_MT_REPORT.
+    position: Option<(i32, i32)>,
+    reported: Vec<(i32, i32)>,
+}
+
+impl Contacts {
+    fn new(file: &File) -> Contacts {
+        let slotted = evdev::has_abs(file, ABS_MT_SLOT);
+        let slot = match evdev::abs_info(file, ABS_MT_SLOT) {
+            Some(info) if slotted => info.value,
+            _ => 0,
+        };
+        Contacts {
+            slotted,
+            slot,
+            slots: BTreeMap::new(),
+            position: None,
+            reported: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, type_: u16, code: u16, value: i32) {
+        match (type_, code) {
+            (EV_ABS, ABS_MT_SLOT) => self.slot = value,
+            (EV_ABS, ABS_MT_TRACKING_ID) if value < 0 => {
+                self.slots.remove(&self.slot);
+            }
+            (EV_ABS, ABS_MT_TRACKING_ID) => {
+                self.slots.entry(self.slot).or_insert((0, 0));
+            }
+            (EV_ABS, ABS_MT_POSITION_X) => self.position_mut().0 = value,
+            (EV_ABS, ABS_MT_POSITION_Y) => self.position_mut().1 = value,
+            (EV_SYN, SYN_MT_REPORT) => self.reported.extend(self.position.take()),
+            _ => {}
+        }
+    }
+
+    // The position being reported. A contact no tracking ID announced (protocol A, or a
+    // protocol B contact already down when monitoring started) is started at 0,0.
+    fn position_mut(&mut self) -> &mut (i32, i32) {
+        if self.slotted {
+            self.slots.entry(self.slot).or_insert((0, 0))
+        } else {
+            self.position.get_or_insert((0, 0))
+        }
+    }
+
+    // The contacts to show for the frame that just ended.
+    fn take_frame(&mut self) -> Vec<(i32, (i32, i32))> {
+        if self.slotted {
+            return self
+                .slots
+                .iter()
+                .map(|(&slot, &point)| (slot, point))
+                .collect();
+        }
+        // Single-contact protocol A frames skip SYN_MT_REPORT.
+        self.reported.extend(self.position.take());
+        std::mem::take(&mut self.reported)
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| (index as i32, point))
+            .collect()
+    }
+}
@@ -0,0 +1,83 @@
+// Relative pointer input through a virtual uinput mouse, for desktop mode and apps that
+// capture the pointer. Phones rarely have a mouse, so the device is created on first use
+// and kept for the lifetime of the server.
+#![cfg(feature = "direct_input")]
+
+use std::fs::File;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use bridge_core::MouseButton;
+
+use crate::input_manager::{self, SYN_REPORT, write_event};
+use crate::log;
+use crate::uinput::{Capabilities, VirtualDevice};
+
+const EV_SYN: u16 = 0;
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0;
+const REL_Y: u16 = 1;
+const REL_WHEEL: u16 = 8;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+static MOUSE: Mutex<Option<VirtualDevice>> = Mutex::new(None);
+
+// Moves the pointer by (dx, dy) pixels.
+pub fn move_by(dx: i32, dy: i32) -> io::Result<()> {
+    with_mouse(|file| {
+        if dx != 0 {
+            write_event(file, EV_REL, REL_X, dx)?;
+        }
+        if dy != 0 {
+            write_event(file, EV_REL, REL_Y, dy)?;
+        }
+        write_event(file, EV_SYN, SYN_REPORT, 0)
+    })
+}
+
+pub fn click(button: MouseButton) -> io::Result<()> {
+    let code = match button {
+        MouseButton::Left => BTN_LEFT,
+        MouseButton::Right => BTN_RIGHT,
+        MouseButton::Middle => BTN_MIDDLE,
+    };
+    with_mouse(|file| {
+        write_event(file, EV_KEY, code, 1)?;
+        write_event(file, EV_SYN, SYN_REPORT, 0)?;
+        thread::sleep(Duration::from_millis(20));
+        write_event(file, EV_KEY, code, 0)?;
+        write_event(file, EV_SYN, SYN_REPORT, 0)
+    })
+}
+
+// Turns the wheel by `dy` notches; positive scrolls up, negative down.
+pub fn scroll(dy: i32) -> io::Result<()> {
+    with_mouse(|file| {
+        write_event(file, EV_REL, REL_WHEEL, dy)?;
+        write_event(file, EV_SYN, SYN_REPORT, 0)
+    })
+}
+
+fn with_mouse(f: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    if input_manager::dry_running() {
+        // Planned events are never written, so any handle does; no need for a device.
+        input_manager::plan_note("Mouse: virtual (uinput)".to_string());
+        return f(&mut File::open("/dev/null")?);
+    }
+    let mut mouse = MOUSE.lock().unwrap();
+    if mouse.is_none() {
+        let caps = Capabilities {
+            keys: &[BTN_LEFT, BTN_RIGHT, BTN_MIDDLE],
+            rel: &[REL_X, REL_Y, REL_WHEEL],
+            ..Default::default()
+        };
+        *mouse = Some(VirtualDevice::create("NativeBridge Mouse", &caps)?);
+        log::info!("Created virtual mouse");
+    }
+    f(mouse.as_mut().unwrap().file())
+}
@@ -0,0 +1,364 @@
+// Multiplexed connections, entered with the first Tagged command. Every request runs on a
+// thread of its own, so a Stream can keep running while taps go through next to it. All
+// responses are wrapped in Tagged and go out through one writer thread, which keeps frames of
+// different requests from interleaving. Frames that cannot be routed to a request (garbage,
+// untagged commands) are answered untagged.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Scope};
+
+use bridge_core::BUSY_PROTOCOL_VERSION;
+use bridge_core::BridgeCommand;
+use bridge_core::BridgeResponse;
+use bridge_core::DecodeError;
+use bridge_core::ErrorKind;
+use bridge_core::SignalTarget;
+use bridge_core::transport::Transport;
+
+use crate::config::Config;
+use crate::log;
+use crate::logcat;
+use crate::process::ProcessSlot;
+use crate::{
+    FollowUps, Reply, execute_exec, failure, policy, read_request, respond, run_batch, run_stream,
+    signal, stream_end, unbatchable, write_response,
+};
+
+// A frame for the writer, with the id of the request it completes, if it is the last one.
+type Outgoing = (BridgeResponse, Option<u64>);
+
+// A request that is still running.
+struct InFlight {
+    // Where its StdinChunk and StdinEnd frames go; dropping it tells the request its client
+    // is gone.
+    follow_ups: Option<Sender<BridgeCommand>>,
+    process: Arc<ProcessSlot>,
+}
+
+type Requests = Mutex<HashMap<u64, InFlight>>;
+
+// Serves the rest of the connection, starting with the Tagged command that switched to it.
+pub fn serve(socket: &mut dyn Transport, config: &Config, request_id: u64, command: BridgeCommand) {
+    log::debug!("Multiplexing connection");
+    let output = match socket.try_clone_box() {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = write_response(socket, &failure(ErrorKind::of(&e), e.to_string()));
+            return;
+        }
+    };
+    let requests = Requests::default();
+    let (outgoing, queue) = mpsc::channel();
+    let compression = crate::compression();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            crate::set_compression(compression);
+            write_all(output, queue, &requests)
+        });
+        let dispatcher = Dispatcher {
+            scope,
+            config,
+            outgoing,
+            requests: &requests,
+        };
+        dispatcher.dispatch(request_id, command);
+        // Requests still running keep the connection from counting as idle.
+        let busy = || !requests.lock().unwrap().is_empty();
+        while let Some(payload) = read_request(socket, config, &busy) {
+            let response = match socket
+                .codec()
+                .decode_command_for(&payload, crate::client_protocol())
+            {
+                Ok(BridgeCommand::Tagged {
+                    request_id,
+                    command,
+                }) => {
+                    dispatcher.dispatch(request_id, *command);
+                    continue;
+                }
+                Ok(cmd) => failure(
+                    ErrorKind::ProtocolError,
+                    format!(
+                        "Protocol Error: {} is not Tagged, a multiplexed connection only takes Tagged commands",
+                        cmd.name()
+                    ),
+                ),
+                Err(DecodeError::Unsupported(kind)) => BridgeResponse::Unsupported { kind },
+                Err(e) => failure(ErrorKind::ProtocolError, format!("Invalid Payload: {}", e)),
+            };
+            let _ = dispatcher.outgoing.send((response, None));
+        }
+        // The client is gone: every request still running is cancelled, as if it had sent
+        // Cancel for each of them. The scope then waits for them to finish.
+        for (_, request) in requests.lock().unwrap().drain() {
+            request.process.cancel();
+        }
+    });
+}
+
+// Writes the responses of all requests, forgetting each request as its last frame goes out,
+// so the client can reuse the id as soon as it has seen the end.
+fn write_all(mut output: Box<dyn Transport>, queue: mpsc::Receiver<Outgoing>, requests: &Requests) {
+    for (response, finished) in queue {
+        if let Some(id) = finished {
+            requests.lock().unwrap().remove(&id);
+        }
+        if write_response(&mut *output, &response).is_err() {
+            // Dropping the queue makes every reply fail, which stops streams.
+            break;
+        }
+    }
+}
+
+struct Dispatcher<'scope, 'env> {
+    scope: &'scope Scope<'scope, 'env>,
+    config: &'env Config,
+    outgoing: Sender<Outgoing>,
+    requests: &'env Requests,
+}
+
+impl<'scope, 'env> Dispatcher<'scope, 'env> {
+    fn dispatch(&self, id: u64, cmd: BridgeCommand) {
+        if let BridgeCommand::StdinChunk(_) | BridgeCommand::StdinEnd = cmd {
+            // Input for a request that has already finished is dropped.
+            let requests = self.requests.lock().unwrap();
+            if let Some(follow_ups) = requests.get(&id).and_then(|r| r.follow_ups.as_ref()) {
+                let _ = follow_ups.send(cmd);
+            }
+            return;
+        }
+
+        let streaming = matches!(
+            cmd,
+            BridgeCommand::Stream { .. } | BridgeCommand::Logcat { .. }
+        );
+        if self.requests.lock().unwrap().contains_key(&id) {
+            // Not the end of anything: the request holding the id goes on.
+            let refusal = failure(
+                ErrorKind::ProtocolError,
+                format!("Request Failed: id {} is already in flight", id),
+            );
+            let _ = self.outgoing.send((tagged(id, refusal), None));
+            return;
+        }
+        let reply = self.reply(id, streaming);
+        // A failed request still has to end the way the client expects it to.
+        let refuse = |kind, message| {
+            let _ = reply(failure(kind, message));
+            if streaming {
+                let _ = reply(stream_end(None));
+            }
+        };
+
+        if let BridgeCommand::Cancel { request_id } = cmd {
+            let process = self
+                .requests
+                .lock()
+                .unwrap()
+                .get(&request_id)
+                .map(|r| Arc::clone(&r.process));
+            let response = match process {
+                Some(process) if process.cancel() => {
+                    log::info!("Cancelled request {}", request_id);
+                    BridgeResponse::Success("".to_string())
+                }
+                Some(_) => failure(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Cancel Failed: request {} has no running process",
+                        request_id
+                    ),
+                ),
+                None => failure(
+                    ErrorKind::NotFound,
+                    format!("Cancel Failed: no request {} in flight", request_id),
+                ),
+            };
+            let _ = reply(response);
+            return;
+        }
+        if let BridgeCommand::Signal {
+            target: SignalTarget::Request(request_id),
+            signal,
+        } = cmd
+        {
+            let process = self
+                .requests
+                .lock()
+                .unwrap()
+                .get(&request_id)
+                .map(|r| Arc::clone(&r.process));
+            let _ = reply(signal::to_request(process, request_id, signal));
+            return;
+        }
+        let cmd = match cmd {
+            BridgeCommand::Logcat {
+                package,
+                args,
+                options,
+            } => match logcat::expand(package, args, options) {
+                Ok(stream) => stream,
+                Err(e) => return refuse(e.kind, e.message),
+            },
+            cmd => cmd,
+        };
+        if let Err(e) = policy::check(self.config, &cmd) {
+            log::warning!("Refused: {}", e);
+            return refuse(ErrorKind::PolicyRejected, e);
+        }
+        let multiplexable = matches!(
+            cmd,
+            BridgeCommand::Exec { .. }
+                | BridgeCommand::ExecBytes { .. }
+                | BridgeCommand::Stream { .. }
+                | BridgeCommand::Batch { .. }
+        ) || unbatchable(&cmd).is_none();
+        if !multiplexable {
+            return refuse(
+                ErrorKind::ProtocolError,
+                format!(
+                    "Request Failed: {} needs a connection of its own",
+                    cmd.name()
+                ),
+            );
+        }
+
+        let (follow_ups, input) = match &cmd {
+            BridgeCommand::Exec { stdin: true, .. }
+            | BridgeCommand::ExecBytes { stdin: true, .. }
+            | BridgeCommand::Stream { .. } => {
+                let (sender, receiver) = mpsc::channel();
+                (
+                    Some(sender),
+                    Some(Box::new(receiver.into_iter()) as FollowUps),
+                )
+            }
+            _ => (None, None),
+        };
+        let process = Arc::new(ProcessSlot::default());
+        let in_flight = InFlight {
+            follow_ups,
+            process: Arc::clone(&process),
+        };
+        {
+            // Each request gets a thread, so the connection may only run so many at once.
+            let mut requests = self.requests.lock().unwrap();
+            let running = requests.len() as u32;
+            if running >= self.config.max_in_flight {
+                drop(requests);
+                log::warning!(
+                    "Refused request {}, {} requests in flight (--max-in-flight)",
+                    id,
+                    running
+                );
+                let _ = reply(busy(running, self.config.max_in_flight));
+                if streaming {
+                    let _ = reply(stream_end(None));
+                }
+                return;
+            }
+            requests.insert(id, in_flight);
+        }
+
+        let config = self.config;
+        let connection = log::connection();
+        let protocol = crate::client_protocol();
+        let compression = crate::compression();
+        self.scope.spawn(move || {
+            log::set_connection(connection);
+            crate::set_client_protocol(protocol);
+            crate::set_compression(compression);
+            log::trace!("Request {}: {}", id, cmd.name());
+            match cmd {
+                BridgeCommand::Stream {
+                    program,
+                    args,
+                    stdin,
+                    options,
+                } => {
+                    run_stream(
+                        program,
+                        args,
+                        stdin,
+                        options,
+                        config,
+                        reply,
+                        input,
+                        Some(&process),
+                    );
+                }
+                BridgeCommand::Exec {
+                    program,
+                    args,
+                    options,
+                    ..
+                } => {
+                    let slot = Some(&*process);
+                    let response =
+                        execute_exec(program, args, options, input, slot, false, Some(&reply));
+                    let _ = reply(response);
+                }
+                BridgeCommand::ExecBytes {
+                    program,
+                    args,
+                    options,
+                    ..
+                } => {
+                    let slot = Some(&*process);
+                    let response =
+                        execute_exec(program, args, options, input, slot, true, Some(&reply));
+                    let _ = reply(response);
+                }
+                BridgeCommand::Batch {
+                    commands,
+                    stop_on_error,
+                } => {
+                    let _ = reply(run_batch(commands, stop_on_error, config));
+                }
+                cmd => {
+                    let _ = reply(respond(cmd, config));
+                }
+            }
+        });
+    }
+
+    // Tags the responses of request `id` for the writer. A Stream ends with StreamEnd, any
+    // other request with its first response that is not a chunk.
+    fn reply(&self, id: u64, streaming: bool) -> Reply {
+        let outgoing = self.outgoing.clone();
+        Arc::new(move |response| {
+            // Output an Exec sends ahead of its response is not the end of it either.
+            let last = match response {
+                BridgeResponse::StreamEnd { .. } => true,
+                BridgeResponse::StdoutChunk(_) | BridgeResponse::StderrChunk(_) => false,
+                _ => !streaming,
+            };
+            outgoing
+                .send((tagged(id, response), last.then_some(id)))
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        })
+    }
+}
+
+// Busy for a client that knows it, an Error for older ones.
+fn busy(running: u32, limit: u32) -> BridgeResponse {
+    if crate::client_protocol() >= BUSY_PROTOCOL_VERSION {
+        return BridgeResponse::Busy { running, limit };
+    }
+    failure(
+        ErrorKind::Internal,
+        format!(
+            "Request Failed: server busy: {} of {} requests in flight (--max-in-flight)",
+            running, limit
+        ),
+    )
+}
+
+fn tagged(request_id: u64, response: BridgeResponse) -> BridgeResponse {
+    BridgeResponse::Tagged {
+        request_id,
+        response: Box::new(response),
+    }
+}
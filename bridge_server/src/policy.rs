@@ -0,0 +1,330 @@
+// Which host programs clients may run through Exec, Stream, ExecPty, JobStart, SessionOpen
+// and SetProp, which runs setprop (`--allow-program`), and where PushFile and PullFile may
+// reach (`--allow-path`). Transfers as root could replace any program the policy allows, so
+// once either list is set they are held to the second, which may be empty.
+// Without rules everything is allowed.
+
+use std::path::{Component, Path, PathBuf};
+
+use bridge_core::{BridgeCommand, ProcessOptions};
+
+use crate::config::Config;
+use crate::shell_session;
+
+// Checks a request against the policy; commands that spawn nothing always pass.
+pub fn check(config: &Config, cmd: &BridgeCommand) -> Result<(), String> {
+    if config.allow_program.is_empty() && config.allow_path.is_empty() {
+        return Ok(());
+    }
+    match cmd {
+        BridgeCommand::Batch { commands, .. } => {
+            return commands.iter().try_for_each(|cmd| check(config, cmd));
+        }
+        BridgeCommand::Tagged { command, .. } => return check(config, command),
+        BridgeCommand::PushFile { dest: path, .. } | BridgeCommand::PullFile { src: path, .. } => {
+            if !permits_path(&config.allow_path, path) {
+                return Err(format!("path '{}' not permitted by policy", path));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    if config.allow_program.is_empty() {
+        return Ok(());
+    }
+    match cmd {
+        // What runs in the session is up to the shell, so the shell is what must be allowed.
+        BridgeCommand::SessionOpen { shell } => {
+            let shell = shell.as_deref().unwrap_or(shell_session::DEFAULT_SHELL);
+            if !permits(&config.allow_program, shell, &[]) {
+                return Err(format!("shell '{}' not permitted by policy", shell));
+            }
+            return Ok(());
+        }
+        // Setting properties changes the device, as running setprop through Exec would.
+        BridgeCommand::SetProp { name, value } => {
+            if !permits(
+                &config.allow_program,
+                "setprop",
+                &[name.clone(), value.clone()],
+            ) {
+                return Err("program 'setprop' not permitted by policy".to_string());
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    let (program, args, options) = match cmd {
+        BridgeCommand::Exec {
+            program,
+            args,
+            options,
+            ..
+        }
+        | BridgeCommand::ExecBytes {
+            program,
+            args,
+            options,
+            ..
+        }
+        | BridgeCommand::Stream {
+            program,
+            args,
+            options,
+            ..
+        }
+        | BridgeCommand::JobStart {
+            program,
+            args,
+            options,
+        } => (program, args, Some(options)),
+        BridgeCommand::ExecPty { program, args, .. } => (program, args, None),
+        _ => return Ok(()),
+    };
+    if !permits(&config.allow_program, program, args) {
+        return Err(format!("program '{}' not permitted by policy", program));
+    }
+    if let Some(name) = options.and_then(overridden_loader_env) {
+        return Err(format!(
+            "setting {} is not permitted by policy, it changes what '{}' runs",
+            name, program
+        ));
+    }
+    Ok(())
+}
+
+// A rule is a program, optionally followed by the arguments a command has to start with:
+// `am start` permits `am start -n ...` but not `am force-stop`. Programs match exactly, so
+// `input` permits `input` (found through PATH) but not `/system/bin/input`, which needs a
+// rule of its own; neither basenames nor patterns are matched, so a rule cannot be
+// satisfied by a look-alike binary elsewhere.
+pub fn permits(rules: &[String], program: &str, args: &[String]) -> bool {
+    rules.iter().any(|rule| {
+        let mut words = rule.split_whitespace();
+        words.next() == Some(program)
+            && words
+                .enumerate()
+                .all(|(i, word)| args.get(i).is_some_and(|arg| arg == word))
+    })
+}
+
+// Whether `path` lies in one of the directories `dirs`. Only absolute paths without `..`
+// qualify, and both sides are compared with their symlinks resolved, so a link inside an
+// allowed directory cannot lead out of it. Parts of `path` that do not exist yet (the file a
+// push creates) are taken as written.
+pub fn permits_path(dirs: &[PathBuf], path: &str) -> bool {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return false;
+    }
+    let path = resolve(path);
+    dirs.iter()
+        .any(|dir| path.starts_with(dir.canonicalize().unwrap_or_else(|_| dir.clone())))
+}
+
+// `path` with its longest existing prefix canonicalized.
+fn resolve(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return rest
+                .iter()
+                .rev()
+                .fold(resolved, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+// Environment variables that would let an allowed program run something else.
+fn overridden_loader_env(options: &ProcessOptions) -> Option<&str> {
+    options
+        .env
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .find(|name| *name == "PATH" || name.starts_with("LD_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(std::iter::once("bridge_server").chain(args.iter().copied()))
+    }
+
+    fn exec(program: &str, args: &[&str]) -> BridgeCommand {
+        BridgeCommand::Exec {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            stdin: false,
+            options: ProcessOptions::default(),
+        }
+    }
+
+    fn push(dest: &str) -> BridgeCommand {
+        BridgeCommand::PushFile {
+            dest: dest.to_string(),
+            mode: 0o644,
+            resume: false,
+            verify: false,
+        }
+    }
+
+    fn pull(src: &str) -> BridgeCommand {
+        BridgeCommand::PullFile {
+            src: src.to_string(),
+            recursive: false,
+            offset: 0,
+            verify: false,
+        }
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn rules_match_programs_and_leading_arguments() {
+        let rules = strings(&["input", "am start"]);
+        assert!(permits(&rules, "input", &strings(&["tap", "1", "2"])));
+        assert!(permits(&rules, "am", &strings(&["start", "-n", "x/.Y"])));
+        assert!(!permits(&rules, "am", &strings(&["force-stop", "x"])));
+        assert!(!permits(&rules, "am", &[]));
+        assert!(!permits(&rules, "/system/bin/input", &[]));
+        assert!(!permits(&rules, "inputx", &[]));
+        assert!(!permits(&[], "input", &[]));
+    }
+
+    #[test]
+    fn no_rules_allow_everything() {
+        let config = config(&[]);
+        assert!(check(&config, &exec("rm", &["-rf", "/"])).is_ok());
+        assert!(check(&config, &push("/system/bin/input")).is_ok());
+    }
+
+    #[test]
+    fn programs_outside_the_rules_are_refused() {
+        let config = config(&["--allow-program", "input"]);
+        assert!(check(&config, &exec("input", &["tap", "1", "2"])).is_ok());
+        let e = check(&config, &exec("rm", &[])).unwrap_err();
+        assert_eq!(e, "program 'rm' not permitted by policy");
+        let pty = BridgeCommand::ExecPty {
+            program: "sh".to_string(),
+            args: vec![],
+            cols: 80,
+            rows: 24,
+        };
+        assert!(check(&config, &pty).is_err());
+        assert!(check(&config, &BridgeCommand::Ping).is_ok());
+    }
+
+    #[test]
+    fn batches_and_tagged_commands_are_checked_inside() {
+        let config = config(&["--allow-program", "input"]);
+        let batch = |commands| BridgeCommand::Batch {
+            commands,
+            stop_on_error: false,
+        };
+        assert!(
+            check(
+                &config,
+                &batch(vec![exec("input", &[]), BridgeCommand::Ping])
+            )
+            .is_ok()
+        );
+        assert!(check(&config, &batch(vec![exec("input", &[]), exec("sh", &[])])).is_err());
+        let tagged = |command| BridgeCommand::Tagged {
+            request_id: 1,
+            command: Box::new(command),
+        };
+        assert!(check(&config, &tagged(exec("input", &[]))).is_ok());
+        assert!(check(&config, &tagged(exec("sh", &[]))).is_err());
+        assert!(check(&config, &tagged(batch(vec![exec("sh", &[])]))).is_err());
+    }
+
+    #[test]
+    fn loader_variables_are_refused() {
+        let config = config(&["--allow-program", "input"]);
+        for name in ["PATH", "LD_PRELOAD", "LD_LIBRARY_PATH"] {
+            let cmd = BridgeCommand::Stream {
+                program: "input".to_string(),
+                args: vec![],
+                stdin: false,
+                options: ProcessOptions {
+                    env: vec![(name.to_string(), "/data/local/tmp".to_string())],
+                    ..ProcessOptions::default()
+                },
+            };
+            let e = check(&config, &cmd).unwrap_err();
+            assert!(e.contains(name), "{}", e);
+        }
+        let harmless = BridgeCommand::JobStart {
+            program: "input".to_string(),
+            args: vec![],
+            options: ProcessOptions {
+                env: vec![("LANG".to_string(), "C".to_string())],
+                ..ProcessOptions::default()
+            },
+        };
+        assert!(check(&config, &harmless).is_ok());
+    }
+
+    #[test]
+    fn sessions_and_setprop_need_their_programs() {
+        let config = config(&["--allow-program", "setprop debug.x"]);
+        let open = |shell: &str| BridgeCommand::SessionOpen {
+            shell: Some(shell.to_string()),
+        };
+        assert!(check(&config, &open("sh")).is_err());
+        let set = |name: &str| BridgeCommand::SetProp {
+            name: name.to_string(),
+            value: "1".to_string(),
+        };
+        assert!(check(&config, &set("debug.x")).is_ok());
+        assert!(check(&config, &set("persist.y")).is_err());
+    }
+
+    #[test]
+    fn transfers_are_refused_under_a_program_policy() {
+        let config = config(&["--allow-program", "input"]);
+        assert!(check(&config, &push("/data/local/tmp/x")).is_err());
+        assert!(check(&config, &pull("/data/local/tmp/x")).is_err());
+    }
+
+    #[test]
+    fn transfers_stay_in_allowed_directories() {
+        let root = std::env::temp_dir().join(format!("policy-{}", std::process::id()));
+        let allowed = root.join("allowed");
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::os::unix::fs::symlink("/", allowed.join("escape")).unwrap();
+        let config = config(&["--allow-path", allowed.to_str().unwrap()]);
+        let inside = |name: &str| format!("{}/{}", allowed.display(), name);
+
+        assert!(check(&config, &push(&inside("new.apk"))).is_ok());
+        assert!(check(&config, &pull(&inside("sub/dir/file"))).is_ok());
+        // A program policy is not implied by a path policy.
+        assert!(check(&config, &exec("sh", &[])).is_ok());
+
+        assert!(check(&config, &push("/system/bin/input")).is_err());
+        assert!(check(&config, &push(&format!("{}x/file", allowed.display()))).is_err());
+        assert!(check(&config, &push(&inside("../outside"))).is_err());
+        assert!(check(&config, &pull(&inside("escape/etc/passwd"))).is_err());
+        assert!(check(&config, &push("relative/file")).is_err());
+        let batch = BridgeCommand::Batch {
+            commands: vec![pull("/etc/passwd")],
+            stop_on_error: false,
+        };
+        assert!(check(&config, &batch).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+// The threads that serve connections: at most --max-connections of them, started as they
+// are needed and kept for the next connection once theirs closes. While every one of them
+// is busy the accept loop leaves new clients waiting in the listen backlog, so a client that
+// opens connections in a loop cannot exhaust the server's threads.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    threads: 0,
+    busy: 0,
+    tasks: Vec::new(),
+});
+
+// Notified when a task is queued.
+static QUEUED: Condvar = Condvar::new();
+
+// The write end of the pipe that wakes the accept loop once a thread is free again.
+static WAKE: Mutex<Option<OwnedFd>> = Mutex::new(None);
+
+struct Pool {
+    threads: u32,
+    busy: u32,
+    tasks: Vec<Task>,
+}
+
+// Creates the pipe behind `wake_fd`: its read end becomes readable whenever a connection
+// closes, for the accept loop to poll next to its listeners.
+pub fn install() -> io::Result<OwnedFd> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // pipe2 returned two fresh descriptors.
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    *WAKE.lock().unwrap() = Some(write);
+    Ok(read)
+}
+
+// Empties the pipe from `install` after poll reported it readable.
+pub fn clear(fd: RawFd) {
+    let mut buffer = [0u8; 64];
+    while unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } > 0 {}
+}
+
+// Whether `max` connections are being served, so another one has to wait.
+pub fn full(max: u32) -> bool {
+    POOL.lock().unwrap().busy >= max
+}
+
+// Connections being served, for Info.
+pub fn busy() -> u32 {
+    POOL.lock().unwrap().busy
+}
+
+// Runs `task` on an idle thread, or on a new one. Only call it when not `full`.
+pub fn run(task: impl FnOnce() + Send + 'static) {
+    let mut pool = POOL.lock().unwrap();
+    pool.busy += 1;
+    pool.tasks.push(Box::new(task));
+    if pool.threads < pool.busy {
+        pool.threads += 1;
+        thread::spawn(work);
+    } else {
+        QUEUED.notify_one();
+    }
+}
+
+fn work() {
+    let mut pool = POOL.lock().unwrap();
+    loop {
+        pool = QUEUED
+            .wait_while(pool, |pool| pool.tasks.is_empty())
+            .unwrap();
+        let task = pool.tasks.pop().unwrap();
+        drop(pool);
+        // A handler that panics loses its connection, not the thread.
+        let _ = panic::catch_unwind(AssertUnwindSafe(task));
+        pool = POOL.lock().unwrap();
+        pool.busy -= 1;
+        if let Some(wake) = WAKE.lock().unwrap().as_ref() {
+            let byte = 1u8;
+            // If the pipe is full, the accept loop has a wake-up pending anyway.
+            unsafe {
+                libc::write(
+                    wake.as_raw_fd(),
+                    &byte as *const u8 as *const libc::c_void,
+                    1,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,543 @@
+// Process group bookkeeping for spawned host commands.
+// Children are started in their own process group, so pipelines run through `sh -c`
+// can be terminated as a whole without leaving orphans behind.
+//
+// Every group is also a job in the server-wide registry behind ListJobs, which records the
+// connection it was started for. A connection that closes, or whose client hangs up while
+// its handler is still busy, has its jobs marked orphaned and cancelled. A group dropped
+// before its leader was waited for is killed and reaped in the background, so an early
+// return cannot leave a zombie behind.
+//
+// With --max-jobs, a group takes one of that many slots from its spawn until its leader is
+// reaped. Spawns beyond it wait for a slot, at most --job-queue of them, or fail with a
+// Busy error under `--when-busy reject`.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Once, OnceLock, Weak, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bridge_core::{BUSY_PROTOCOL_VERSION, BridgeResponse, ErrorKind, Job};
+
+use crate::config::{Config, WhenBusy};
+use crate::log;
+
+// How long a cancelled group gets to exit after SIGTERM before it is killed.
+const CANCEL_GRACE: Duration = Duration::from_secs(2);
+
+// How often the sockets of connections with running jobs are checked for a hang-up.
+const MONITOR_INTERVAL: Duration = Duration::from_millis(500);
+
+// Every group that has been started, for ListJobs and for stopping them all when the server
+// shuts down.
+static GROUPS: Mutex<Vec<Weak<ProcessGroup>>> = Mutex::new(Vec::new());
+
+// The open connections by id, with their sockets.
+static OWNERS: Mutex<Vec<(u64, RawFd)>> = Mutex::new(Vec::new());
+
+static MONITOR: Once = Once::new();
+
+// --max-jobs, --when-busy and --job-queue; without them, spawns are not limited.
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+static SLOTS: Mutex<Slots> = Mutex::new(Slots {
+    running: 0,
+    queued: 0,
+});
+
+// Notified when a slot is given back.
+static SLOT_FREED: Condvar = Condvar::new();
+
+struct Limits {
+    max_jobs: u32,
+    queue: Option<u32>,
+}
+
+struct Slots {
+    running: u32,
+    queued: u32,
+}
+
+// Why a spawn was refused: `limit` groups are running. Carried by the io::Error of the spawn.
+#[derive(Debug)]
+pub struct Busy {
+    running: u32,
+    limit: u32,
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "server busy: {} of {} host processes running (--max-jobs)",
+            self.running, self.limit
+        )
+    }
+}
+
+impl std::error::Error for Busy {}
+
+// Sets up the slots from --max-jobs, --when-busy and --job-queue.
+pub fn init(config: &Config) {
+    let queue = (config.when_busy == WhenBusy::Queue).then_some(config.job_queue);
+    let _ = LIMITS.set(Limits {
+        max_jobs: config.max_jobs,
+        queue,
+    });
+}
+
+// Takes a slot for a new group, waiting for one if the limits say so.
+fn take_slot() -> io::Result<()> {
+    let mut slots = SLOTS.lock().unwrap();
+    if let Some(limits) = LIMITS.get().filter(|limits| limits.max_jobs > 0)
+        && slots.running >= limits.max_jobs
+    {
+        let busy = Busy {
+            running: slots.running,
+            limit: limits.max_jobs,
+        };
+        match limits.queue {
+            Some(queue) if slots.queued < queue => {
+                log::debug!("Waiting for a process slot, {}", busy);
+                slots.queued += 1;
+                slots = SLOT_FREED
+                    .wait_while(slots, |slots| slots.running >= limits.max_jobs)
+                    .unwrap();
+                slots.queued -= 1;
+            }
+            _ => {
+                log::warning!("Refused to start a process, {}", busy);
+                return Err(io::Error::new(io::ErrorKind::ResourceBusy, busy));
+            }
+        }
+    }
+    slots.running += 1;
+    Ok(())
+}
+
+fn give_back_slot() {
+    SLOTS.lock().unwrap().running -= 1;
+    SLOT_FREED.notify_one();
+}
+
+// The response to a spawn that failed with `e`: Busy if the slots ran out and the client
+// knows Busy, otherwise an Error with `context` in front of `e`.
+pub fn spawn_failed(context: &str, e: &io::Error) -> BridgeResponse {
+    match e.get_ref().and_then(|e| e.downcast_ref::<Busy>()) {
+        Some(busy) if crate::client_protocol() >= BUSY_PROTOCOL_VERSION => BridgeResponse::Busy {
+            running: busy.running,
+            limit: busy.limit,
+        },
+        _ => crate::failure(ErrorKind::of(e), format!("{}{}", context, e)),
+    }
+}
+
+// Host processes running and commands waiting for a slot, with --max-jobs, for Info.
+pub fn load() -> (u32, u32, u32) {
+    let slots = SLOTS.lock().unwrap();
+    let max_jobs = LIMITS.get().map_or(0, |limits| limits.max_jobs);
+    (slots.running, max_jobs, slots.queued)
+}
+
+pub struct ProcessGroup {
+    pgid: libc::pid_t,
+    // Cleared once the leader has been reaped, after which its id may be reused.
+    running: Mutex<bool>,
+    cancelled: AtomicBool,
+    command: Vec<String>,
+    started_ms: u64,
+    connection: Option<u64>,
+    background: bool,
+    orphaned: AtomicBool,
+}
+
+impl ProcessGroup {
+    // Spawns `command`, which must make the child lead its own process group, e.g. with
+    // `CommandExt::process_group(0)` or as the leader of a session, and registers it as a
+    // job of the current connection.
+    pub fn spawn(command: &mut Command) -> std::io::Result<(Child, Arc<ProcessGroup>)> {
+        Self::start(command, log::connection(), false)
+    }
+
+    // Like `spawn`, for a process that belongs to no connection: a shell session, or with
+    // `background` a job, which --keep-jobs leaves running when the server shuts down.
+    pub fn spawn_detached(
+        command: &mut Command,
+        background: bool,
+    ) -> std::io::Result<(Child, Arc<ProcessGroup>)> {
+        Self::start(command, None, background)
+    }
+
+    fn start(
+        command: &mut Command,
+        connection: Option<u64>,
+        background: bool,
+    ) -> std::io::Result<(Child, Arc<ProcessGroup>)> {
+        take_slot()?;
+        let child = command.spawn().inspect_err(|_| give_back_slot())?;
+        let started_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let group = Arc::new(ProcessGroup {
+            pgid: child.id() as libc::pid_t,
+            running: Mutex::new(true),
+            cancelled: AtomicBool::new(false),
+            command: command_line(command),
+            started_ms,
+            connection,
+            background,
+            orphaned: AtomicBool::new(false),
+        });
+        let mut groups = GROUPS.lock().unwrap();
+        groups.retain(|group| group.strong_count() > 0);
+        groups.push(Arc::downgrade(&group));
+        MONITOR.call_once(|| {
+            thread::spawn(monitor);
+        });
+        Ok((child, group))
+    }
+
+    // Sends SIGKILL to every process in the group, unless it has already been reaped.
+    // Returns whether the signal was sent.
+    pub fn kill(&self) -> bool {
+        self.signal(libc::SIGKILL)
+    }
+
+    // Sends `signal` to every process in the group, unless it has already been reaped.
+    pub fn signal(&self, signal: libc::c_int) -> bool {
+        let running = self.running.lock().unwrap();
+        if *running {
+            unsafe {
+                libc::kill(-self.pgid, signal);
+            }
+        }
+        *running
+    }
+
+    // Like `signal`, with the error kill(2) failed with; ESRCH once the leader has been reaped.
+    pub fn send(&self, signal: libc::c_int) -> io::Result<()> {
+        let running = self.running.lock().unwrap();
+        if !*running {
+            return Err(io::Error::from_raw_os_error(libc::ESRCH));
+        }
+        if unsafe { libc::kill(-self.pgid, signal) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Stops the group on behalf of its client, for Cancel and for clients that went away:
+    // SIGTERM now, so it can clean up, and SIGKILL after CANCEL_GRACE if it is still running.
+    // Returns whether the group was running.
+    pub fn cancel(self: &Arc<Self>) -> bool {
+        {
+            let running = self.running.lock().unwrap();
+            if !*running {
+                return false;
+            }
+            self.cancelled.store(true, Ordering::SeqCst);
+            unsafe {
+                libc::kill(-self.pgid, libc::SIGTERM);
+            }
+        }
+        let group = Arc::clone(self);
+        thread::spawn(move || {
+            thread::sleep(CANCEL_GRACE);
+            group.kill();
+        });
+        true
+    }
+
+    // Program and arguments, as ListJobs shows them.
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Must be called right after the leader has been waited for.
+    pub fn mark_reaped(&self) {
+        if mem::replace(&mut *self.running.lock().unwrap(), false) {
+            give_back_slot();
+        }
+    }
+
+    // Cancels the group because the connection it was started for is gone.
+    fn orphan(self: &Arc<Self>) {
+        if !self.orphaned.swap(true, Ordering::SeqCst) && self.cancel() {
+            log::info!(
+                "Stopping pid {} of closed connection {}",
+                self.pgid,
+                self.connection.unwrap_or_default()
+            );
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        // Nobody is left to wait for the leader: kill the group and reap it here.
+        if *self.running.get_mut().unwrap() {
+            let pgid = self.pgid;
+            log::debug!("Reaping pid {}, abandoned while running", pgid);
+            unsafe {
+                libc::kill(-pgid, libc::SIGKILL);
+            }
+            thread::spawn(move || {
+                unsafe {
+                    libc::waitpid(pgid, std::ptr::null_mut(), 0);
+                }
+                give_back_slot();
+            });
+        }
+    }
+}
+
+// Program and arguments as ListJobs shows them; only the program with --log-redact-args.
+fn command_line(command: &Command) -> Vec<String> {
+    let mut line = vec![command.get_program().to_string_lossy().into_owned()];
+    if !log::redacts_args() {
+        line.extend(
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned()),
+        );
+    }
+    line
+}
+
+// The groups that are still registered; some may have exited already.
+fn running() -> Vec<Arc<ProcessGroup>> {
+    GROUPS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+// The answer to ListJobs: every group whose leader has not been reaped, oldest first.
+pub fn jobs() -> Vec<Job> {
+    running()
+        .iter()
+        .filter(|group| *group.running.lock().unwrap())
+        .map(|group| Job {
+            pid: group.pgid as u32,
+            command: group.command.clone(),
+            started_ms: group.started_ms,
+            connection: group.connection,
+            orphaned: group.orphaned.load(Ordering::SeqCst),
+        })
+        .collect()
+}
+
+fn orphan_jobs(connection: u64) {
+    for group in running() {
+        if group.connection == Some(connection) {
+            group.orphan();
+        }
+    }
+}
+
+// A connection being handled, whose jobs are orphaned once it is dropped, which must happen
+// before its socket is closed.
+pub struct Owner(u64);
+
+impl Owner {
+    // For the connection of the current thread, see `log::new_connection`.
+    pub fn start(socket: &dyn AsRawFd) -> Option<Owner> {
+        let id = log::connection()?;
+        OWNERS.lock().unwrap().push((id, socket.as_raw_fd()));
+        Some(Owner(id))
+    }
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        OWNERS.lock().unwrap().retain(|&(id, _)| id != self.0);
+        orphan_jobs(self.0);
+    }
+}
+
+// Watches the connections that have jobs running, for clients that hang up while their
+// handler is busy with a command that does not read from them (an Exec without stdin).
+// Only a full close or an error counts: a client that shut down its sending side (socat does
+// at the end of its input) may still be waiting for the response, so POLLRDHUP is not one.
+// TCP peers that vanish without closing are failed by keepalive, see `keep_alive`.
+fn monitor() {
+    loop {
+        thread::sleep(MONITOR_INTERVAL);
+        let busy: Vec<u64> = running()
+            .iter()
+            .filter(|group| !group.orphaned.load(Ordering::SeqCst))
+            .filter_map(|group| group.connection)
+            .collect();
+        let hung_up: Vec<u64> = OWNERS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, fd)| busy.contains(id) && hung_up(*fd))
+            .map(|&(id, _)| id)
+            .collect();
+        for id in hung_up {
+            orphan_jobs(id);
+        }
+    }
+}
+
+// Whether the client on `fd` has closed its end of the connection.
+pub fn hung_up(fd: RawFd) -> bool {
+    let mut poll = libc::pollfd {
+        fd,
+        events: 0,
+        revents: 0,
+    };
+    let n = unsafe { libc::poll(&mut poll, 1, 0) };
+    n == 1 && poll.revents & (libc::POLLHUP | libc::POLLERR) != 0
+}
+
+// Cancels every group, as if each client had sent Cancel; background jobs too unless
+// `keep_jobs`.
+pub fn cancel_all(keep_jobs: bool) {
+    for group in running() {
+        if !(keep_jobs && group.background) {
+            group.cancel();
+        }
+    }
+}
+
+// Kills what is left of every group, like `cancel_all`.
+pub fn kill_all(keep_jobs: bool) {
+    for group in running() {
+        if !(keep_jobs && group.background) {
+            group.kill();
+        }
+    }
+}
+
+// Where a request's process group is published once it has been spawned, so that a Cancel
+// or Signal handled on another thread can find it.
+#[derive(Default)]
+pub struct ProcessSlot(Mutex<Option<Arc<ProcessGroup>>>);
+
+impl ProcessSlot {
+    pub fn set(&self, group: &Arc<ProcessGroup>) {
+        *self.0.lock().unwrap() = Some(Arc::clone(group));
+    }
+
+    // Cancels the published group; false if there is none or it has already exited.
+    pub fn cancel(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(ProcessGroup::cancel)
+    }
+
+    // The published group, if the request has spawned one.
+    pub fn group(&self) -> Option<Arc<ProcessGroup>> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+// Watchdog that kills a process group once its timeout expires.
+// Call `finish` as soon as the child has exited to disarm it.
+pub struct Deadline {
+    _done: mpsc::Sender<()>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    // Returns None when `timeout_ms` is None or zero, i.e. the command has no timeout.
+    pub fn start(group: &Arc<ProcessGroup>, timeout_ms: Option<u64>) -> Option<Deadline> {
+        let timeout = Duration::from_millis(timeout_ms.filter(|&ms| ms > 0)?);
+        let (done, finished) = mpsc::channel::<()>();
+        let expired = Arc::new(AtomicBool::new(false));
+
+        let group = Arc::clone(group);
+        let watchdog_expired = Arc::clone(&expired);
+        thread::spawn(move || {
+            // The sender is dropped (disconnecting the channel) when the deadline is disarmed.
+            if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(timeout)
+                && group.kill()
+            {
+                watchdog_expired.store(true, Ordering::SeqCst);
+            }
+        });
+
+        Some(Deadline {
+            _done: done,
+            expired,
+        })
+    }
+
+    // Disarms the watchdog and reports whether it had already fired.
+    pub fn finish(self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn busy() -> io::Error {
+        io::Error::other(Busy {
+            running: 2,
+            limit: 2,
+        })
+    }
+
+    #[test]
+    fn busy_for_clients_that_know_it() {
+        crate::set_client_protocol(BUSY_PROTOCOL_VERSION);
+        assert!(matches!(
+            spawn_failed("Exec Failed: ", &busy()),
+            BridgeResponse::Busy {
+                running: 2,
+                limit: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn error_instead_of_busy_for_protocol_4() {
+        crate::set_client_protocol(bridge_core::OLDEST_PROTOCOL_VERSION);
+        match spawn_failed("Exec Failed: ", &busy()) {
+            BridgeResponse::Error(message) => assert_eq!(
+                message,
+                "Exec Failed: server busy: 2 of 2 host processes running (--max-jobs)"
+            ),
+            other => panic!("expected Error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_a_full_close_is_a_hang_up() {
+        use std::os::unix::net::UnixStream;
+        let (server, client) = UnixStream::pair().unwrap();
+        assert!(!hung_up(server.as_raw_fd()));
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(!hung_up(server.as_raw_fd()));
+        drop(client);
+        assert!(hung_up(server.as_raw_fd()));
+    }
+
+    #[test]
+    fn other_spawn_errors_are_failures() {
+        crate::set_client_protocol(bridge_core::PROTOCOL_VERSION);
+        let e = io::Error::from(io::ErrorKind::NotFound);
+        match spawn_failed("Exec Failed: ", &e) {
+            BridgeResponse::Failed(e) => assert_eq!(e.kind, ErrorKind::NotFound),
+            other => panic!("expected Failed, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// System properties through getprop and setprop, answered with the properties themselves
+// rather than the tools' text. setprop says nothing when the property service turns a value
+// down on older releases, so a set is only reported done once the property reads back.
+
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+
+use bridge_core::{BridgeResponse, ErrorKind};
+
+use crate::failure;
+
+pub fn get(name: String) -> BridgeResponse {
+    if let Err(response) = check_name("GetProp", &name) {
+        return response;
+    }
+    match read(&name) {
+        Ok(value) if value.is_empty() => failure(
+            ErrorKind::NotFound,
+            format!("GetProp Failed: property {} is not set", name),
+        ),
+        Ok(value) => BridgeResponse::Property { name, value },
+        Err(e) => failure(ErrorKind::Internal, format!("GetProp Failed: {}", e)),
+    }
+}
+
+pub fn list(prefix: Option<String>) -> BridgeResponse {
+    match dump() {
+        Ok(mut properties) => {
+            if let Some(prefix) = prefix {
+                properties.retain(|name, _| name.starts_with(&prefix));
+            }
+            BridgeResponse::Properties(properties)
+        }
+        Err(e) => failure(ErrorKind::Internal, format!("ListProps Failed: {}", e)),
+    }
+}
+
+pub fn set(name: String, value: String) -> BridgeResponse {
+    if let Err(response) = check_name("SetProp", &name) {
+        return response;
+    }
+    if let Err(e) = run(Command::new("setprop").args([&name, &value])) {
+        return failure(
+            ErrorKind::PermissionDenied,
+            format!("SetProp Failed: {}: {}", name, e),
+        );
+    }
+    // ctl.* properties start and stop services rather than holding a value.
+    if name.starts_with("ctl.") {
+        return BridgeResponse::Success(String::new());
+    }
+    match read(&name) {
+        Ok(current) if current == value => BridgeResponse::Success(String::new()),
+        Ok(_) if name.starts_with("ro.") => failure(
+            ErrorKind::PermissionDenied,
+            format!("SetProp Failed: {} is read-only and already set", name),
+        ),
+        Ok(_) => failure(
+            ErrorKind::PermissionDenied,
+            format!(
+                "SetProp Failed: the property service did not take the value of {} (refused by SELinux, or too long)",
+                name
+            ),
+        ),
+        Err(e) => failure(ErrorKind::Internal, format!("SetProp Failed: {}", e)),
+    }
+}
+
+// Every property, from getprop's dump of `[name]: [value]` lines.
+pub fn dump() -> Result<BTreeMap<String, String>, String> {
+    let stdout = run(&mut Command::new("getprop"))?;
+    Ok(stdout
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once("]: [")?;
+            let name = name.strip_prefix('[')?;
+            let value = value.strip_suffix(']')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect())
+}
+
+// The value of one property; empty if it is not set.
+fn read(name: &str) -> Result<String, String> {
+    let stdout = run(Command::new("getprop").arg(name))?;
+    Ok(stdout.strip_suffix('\n').unwrap_or(&stdout).to_string())
+}
+
+// Property names are letters, digits and `._-:@`; anything else, or a leading dash that
+// getprop would take for an option, is refused before a tool sees it.
+fn check_name(command: &str, name: &str) -> Result<(), BridgeResponse> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-:@".contains(c));
+    if valid {
+        return Ok(());
+    }
+    Err(failure(
+        ErrorKind::ProtocolError,
+        format!("{} Failed: '{}' is not a property name", command, name),
+    ))
+}
+
+// Runs a host tool and returns its stdout, turning a failed exit into its stderr.
+fn run(command: &mut Command) -> Result<String, String> {
+    let output = command.stdin(Stdio::null()).output().map_err(|e| {
+        format!(
+            "cannot run {}: {}",
+            command.get_program().to_string_lossy(),
+            e
+        )
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = match stderr.trim() {
+            "" => format!("exit code {}", output.status.code().unwrap_or(-1)),
+            stderr => stderr.to_string(),
+        };
+        return Err(message);
+    }
+    Ok(stdout)
+}
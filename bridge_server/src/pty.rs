@@ -0,0 +1,174 @@
+// Pseudo-terminal backed execution, so full-screen and prompt-driven programs
+// (`top`, `vi`, an interactive `sh`) behave as they would in a real terminal.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use crate::log;
+use crate::process::{self, ProcessGroup};
+use crate::{stream_end, write_response};
+
+pub fn handle_pty_request(
+    socket: &mut dyn Transport,
+    program: String,
+    args: Vec<String>,
+    cols: u16,
+    rows: u16,
+) {
+    log::info!("Pty: {} {} ({}x{})", program, log::args(&args), cols, rows);
+
+    let child = open_pty(cols, rows).and_then(|(master, slave)| {
+        let child = spawn_on_pty(&program, args, slave)?;
+        Ok((master, child))
+    });
+    // The child leads a session of its own, so it is the group the server stops on shutdown.
+    let (master, (mut child, group)) = match child {
+        Ok(pair) => pair,
+        Err(e) => {
+            let _ = write_response(socket, &process::spawn_failed("", &e));
+            let _ = write_response(socket, &stream_end(None));
+            return;
+        }
+    };
+
+    let (mut master_reader, master_writer, mut output_socket, mut input) = match (
+        master.try_clone(),
+        socket.try_clone_box(),
+        socket.try_clone_box(),
+    ) {
+        (Ok(writer), Ok(output), Ok(input)) => (master, writer, output, input),
+        _ => {
+            group.kill();
+            let status = child.wait().ok();
+            group.mark_reaped();
+            let _ = write_response(socket, &stream_end(status));
+            return;
+        }
+    };
+
+    // Client -> terminal: keystrokes and window size changes.
+    // A disconnecting client hangs up the terminal, like closing an SSH session would.
+    let pid = child.id() as libc::pid_t;
+    thread::spawn(move || {
+        forward_input(&mut *input, master_writer);
+        unsafe {
+            libc::kill(pid, libc::SIGHUP);
+        }
+    });
+
+    // Terminal -> client. Reading the master fails with EIO once every process
+    // holding the slave side has exited, which ends the session.
+    let output_thread = thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = match master_reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let response = BridgeResponse::PtyOutput(buffer[..n].to_vec());
+            if write_response(&mut *output_socket, &response).is_err() {
+                break; // Stop if the client closes the connection
+            }
+        }
+    });
+
+    output_thread.join().unwrap();
+    let status = child.wait().ok();
+    group.mark_reaped();
+    let _ = write_response(socket, &stream_end(status));
+}
+
+fn forward_input(input: &mut dyn Transport, mut master: File) {
+    while let Ok(Some(payload)) = input.codec().read_frame(input, crate::max_frame()) {
+        match input.codec().decode_command(&payload) {
+            Ok(BridgeCommand::StdinChunk(data)) => {
+                if master.write_all(&data).is_err() {
+                    break;
+                }
+            }
+            Ok(BridgeCommand::Resize { cols, rows }) => {
+                let _ = set_window_size(&master, cols, rows);
+            }
+            // In raw mode the client sends Ctrl-D as a byte, so StdinEnd only means
+            // its stdin was not a terminal and has run dry; the session continues.
+            Ok(BridgeCommand::StdinEnd) => {}
+            _ => break,
+        }
+    }
+}
+
+// Allocates a master/slave pair with the requested initial window size.
+fn open_pty(cols: u16, rows: u16) -> io::Result<(File, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let result =
+        unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // openpty returned two fresh descriptors that nothing else owns.
+    unsafe { Ok((File::from_raw_fd(master), OwnedFd::from_raw_fd(slave))) }
+}
+
+fn spawn_on_pty(
+    program: &str,
+    args: Vec<String>,
+    slave: OwnedFd,
+) -> io::Result<(std::process::Child, Arc<ProcessGroup>)> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // Curses programs refuse to start without a terminal type.
+    if std::env::var_os("TERM").is_none() {
+        command.env("TERM", "xterm-256color");
+    }
+    // Make the child a session leader with the pty as its controlling terminal,
+    // so job control and Ctrl-C (delivered as a byte through the master) work.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // `command` holds the last copies of the slave fd and drops them when it goes out of
+    // scope, leaving only the child attached to the terminal.
+    ProcessGroup::spawn(&mut command)
+}
+
+fn set_window_size(master: &File, cols: u16, rows: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+// Recording raw input events from a device and replaying them (`andro record`/`replay`).
+#![cfg(feature = "direct_input")]
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeResponse, ErrorKind, InputSource, RecordedEvent};
+
+use crate::evdev::{self, ABS_MT_SLOT};
+use crate::input_manager::{self, ABS_MT_TRACKING_ID, INPUT_EVENT_SIZE, SYN_REPORT, write_event};
+use crate::log;
+use crate::{failure, write_response};
+
+const EV_SYN: u16 = 0;
+const EV_KEY: u16 = 1;
+const EV_ABS: u16 = 3;
+
+fn device(source: InputSource) -> io::Result<PathBuf> {
+    match source {
+        InputSource::Touch => input_manager::touch_device(),
+        InputSource::Keys => input_manager::key_device(),
+    }
+}
+
+pub fn handle_record(socket: &mut dyn Transport, source: InputSource) {
+    let result = device(source).and_then(|path| {
+        log::info!("Recording input from {}", path.display());
+        let mut file = File::open(&path)?;
+        record(socket, &mut file, source)
+    });
+    if let Err(e) = result {
+        let message = format!("Record Failed: {}", e);
+        let _ = write_response(socket, &failure(ErrorKind::InputDeviceError, message));
+    }
+}
+
+// Sends the device's events frame by frame until the client disconnects.
+fn record(socket: &mut dyn Transport, file: &mut File, source: InputSource) -> io::Result<()> {
+    let mut frame = Vec::new();
+    // Protocol B devices only report slot changes, so the recording starts by selecting the
+    // slot the device is on; otherwise the first moves would replay into whatever slot the
+    // device happens to be on then.
+    if source == InputSource::Touch
+        && evdev::has_abs(file, ABS_MT_SLOT)
+        && let Some(slot) = evdev::abs_info(file, ABS_MT_SLOT)
+    {
+        frame.push(RecordedEvent {
+            offset_us: 0,
+            type_: EV_ABS,
+            code: ABS_MT_SLOT,
+            value: slot.value,
+        });
+    }
+
+    let mut start = None;
+    let client = socket.as_raw_fd();
+    read_events(file, client, |raw| {
+        let (time, type_, code, value) = input_manager::read_event(raw);
+        let start = *start.get_or_insert(time);
+        frame.push(RecordedEvent {
+            offset_us: time.saturating_sub(start).as_micros() as u64,
+            type_,
+            code,
+            value,
+        });
+        if type_ == EV_SYN && code == SYN_REPORT {
+            write_response(
+                socket,
+                &BridgeResponse::InputEvents(std::mem::take(&mut frame)),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+// Hands every event read from `file` to `handle` until the client on the socket `client`
+// disconnects.
+pub fn read_events(
+    file: &mut File,
+    client: RawFd,
+    mut handle: impl FnMut(&[u8; INPUT_EVENT_SIZE]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buffer = [0u8; INPUT_EVENT_SIZE * 64];
+    loop {
+        // Blocks until there is input, or the client hangs up (which shows up as readable).
+        let mut fds = [
+            libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: client,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fds[1].revents != 0 {
+            return Ok(());
+        }
+
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            return Err(io::Error::other("the input device was closed"));
+        }
+        for raw in buffer[..n].chunks_exact(INPUT_EVENT_SIZE) {
+            handle(raw.try_into().unwrap())?;
+        }
+    }
+}
+
+// Writes `events` to the device with their recorded spacing divided by `speed`.
+pub fn replay(source: InputSource, events: &[RecordedEvent], speed: f32) -> io::Result<()> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(io::Error::other(format!("invalid speed {}", speed)));
+    }
+    let mut file = OpenOptions::new().write(true).open(device(source)?)?;
+
+    // Recorded tracking IDs are swapped for fresh ones so they cannot collide with the
+    // contacts of a real finger; slots and held keys are tracked to release them at the end.
+    let mut tracking_ids = HashMap::new();
+    let mut slot = 0;
+    let mut active_slots = BTreeSet::new();
+    let mut pressed = BTreeSet::new();
+    let start = Instant::now();
+    for event in events {
+        let due = Duration::from_secs_f64(event.offset_us as f64 / 1e6 / speed as f64);
+        thread::sleep((start + due).saturating_duration_since(Instant::now()));
+
+        let mut value = event.value;
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_MT_SLOT) => slot = value,
+            (EV_ABS, ABS_MT_TRACKING_ID) if value >= 0 => {
+                value = *tracking_ids
+                    .entry(value)
+                    .or_insert_with(input_manager::next_tracking_id);
+                active_slots.insert(slot);
+            }
+            (EV_ABS, ABS_MT_TRACKING_ID) => {
+                active_slots.remove(&slot);
+            }
+            (EV_KEY, code) if value != 0 => {
+                pressed.insert(code);
+            }
+            (EV_KEY, code) => {
+                pressed.remove(&code);
+            }
+            _ => {}
+        }
+        write_event(&mut file, event.type_, event.code, value)?;
+    }
+
+    // A recording stopped in the middle of a gesture would otherwise leave it down.
+    if active_slots.is_empty() && pressed.is_empty() {
+        return Ok(());
+    }
+    for slot in active_slots {
+        write_event(&mut file, EV_ABS, ABS_MT_SLOT, slot)?;
+        write_event(&mut file, EV_ABS, ABS_MT_TRACKING_ID, -1)?;
+    }
+    for code in pressed {
+        write_event(&mut file, EV_KEY, code, 0)?;
+    }
+    write_event(&mut file, EV_SYN, SYN_REPORT, 0)
+}
@@ -0,0 +1,127 @@
+// Display rotation. The touch panel always reports in its natural orientation, while the
+// coordinates users pick (e.g. from a landscape screenshot) follow the rotated display,
+// so input is mapped back onto the panel before it is scaled to the device range.
+#![cfg(feature = "direct_input")]
+
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{device, log};
+
+// Surface rotation as Android numbers it: 0 natural, 1 90°, 2 180°, 3 270°.
+static ROTATION: AtomicU8 = AtomicU8::new(0);
+
+// With --auto-rotate the rotation is looked up before input commands. The result is
+// reused for a moment, so one command resolving points and then touching the screen
+// does not run dumpsys twice.
+static AUTO: AtomicBool = AtomicBool::new(false);
+static LAST_QUERY: Mutex<Option<Instant>> = Mutex::new(None);
+const QUERY_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn init(auto: bool) {
+    AUTO.store(auto, Ordering::Relaxed);
+}
+
+pub fn set(rotation: u8) -> io::Result<()> {
+    if rotation > 3 {
+        return Err(io::Error::other(format!(
+            "rotation must be 0 to 3, got {}",
+            rotation
+        )));
+    }
+    ROTATION.store(rotation, Ordering::Relaxed);
+    Ok(())
+}
+
+// The rotation to apply to the current command.
+pub fn current() -> u8 {
+    if AUTO.load(Ordering::Relaxed) {
+        let mut last = LAST_QUERY.lock().unwrap();
+        if last.is_none_or(|at| at.elapsed() >= QUERY_INTERVAL) {
+            // A failed lookup keeps the previous value rather than failing the input.
+            match device::display_rotation() {
+                Ok(rotation) => ROTATION.store(rotation, Ordering::Relaxed),
+                Err(e) => log::warning!("cannot read the display rotation: {}", e),
+            }
+            *last = Some(Instant::now());
+        }
+    }
+    ROTATION.load(Ordering::Relaxed)
+}
+
+// Maps a point on the display rotated by `rotation` to the panel in its natural
+// orientation, `natural` being the panel size in pixels (width, height). This undoes the
+// transform Android's input reader applies to raw touches.
+pub fn to_panel((x, y): (i32, i32), rotation: u8, (width, height): (i32, i32)) -> (i32, i32) {
+    match rotation {
+        1 => (width - 1 - y, x),
+        2 => (width - 1 - x, height - 1 - y),
+        3 => (y, height - 1 - x),
+        _ => (x, y),
+    }
+}
+
+// The inverse of `to_panel`: where a point on the panel appears on the rotated display.
+pub fn to_display((x, y): (i32, i32), rotation: u8, (width, height): (i32, i32)) -> (i32, i32) {
+    match rotation {
+        1 => (y, width - 1 - x),
+        2 => (width - 1 - x, height - 1 - y),
+        3 => (height - 1 - y, x),
+        _ => (x, y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PANEL: (i32, i32) = (1080, 2400);
+
+    #[test]
+    fn display_corners_land_on_panel_corners() {
+        // The top left corner of the display as the user sees it, then its top right.
+        let cases = [
+            (0, [(0, 0), (1079, 0)]),
+            // Landscape, 2400 wide: the panel's top edge is on the display's left.
+            (1, [(1079, 0), (1079, 2399)]),
+            (2, [(1079, 2399), (0, 2399)]),
+            (3, [(0, 2399), (0, 0)]),
+        ];
+        for (rotation, [top_left, top_right]) in cases {
+            let width = if rotation % 2 == 1 { 2400 } else { 1080 };
+            assert_eq!(to_panel((0, 0), rotation, PANEL), top_left);
+            assert_eq!(to_panel((width - 1, 0), rotation, PANEL), top_right);
+        }
+    }
+
+    #[test]
+    fn to_display_undoes_to_panel() {
+        let (width, height) = (4, 6);
+        for rotation in 0..4 {
+            let (display_width, display_height) = if rotation % 2 == 1 {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            let mut seen = Vec::new();
+            for x in 0..display_width {
+                for y in 0..display_height {
+                    let panel = to_panel((x, y), rotation, (width, height));
+                    assert!((0..width).contains(&panel.0) && (0..height).contains(&panel.1));
+                    assert_eq!(to_display(panel, rotation, (width, height)), (x, y));
+                    seen.push(panel);
+                }
+            }
+            seen.sort();
+            seen.dedup();
+            assert_eq!(seen.len(), (width * height) as usize);
+        }
+    }
+
+    #[test]
+    fn rotations_past_270_are_refused() {
+        assert!(set(4).is_err());
+    }
+}
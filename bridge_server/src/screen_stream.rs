@@ -0,0 +1,87 @@
+// Continuous screen capture for automation loops.
+// Capturing and sending run on separate threads joined by a small bounded queue: when
+// the client falls behind, new frames are dropped instead of piling up in memory.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeResponse, ErrorKind, ImageFormat};
+
+use crate::log;
+use crate::{failure, screenshot, write_response};
+
+// Frames waiting to be sent; anything beyond this is dropped.
+const QUEUE_DEPTH: usize = 2;
+
+pub fn handle_screen_stream(
+    socket: &mut dyn Transport,
+    format: ImageFormat,
+    fps: u32,
+    max_frames: Option<u64>,
+) {
+    log::info!("ScreenStream: {:?} at {} fps", format, fps);
+
+    let interval = (fps > 0).then(|| Duration::from_secs_f64(1.0 / fps as f64));
+    let (frames_tx, frames_rx) = mpsc::sync_channel(QUEUE_DEPTH);
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let capture_stop = Arc::clone(&stop);
+    let capture_thread = thread::spawn(move || {
+        let mut dropped = 0u64;
+        while !capture_stop.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            let timestamp_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64);
+            let frame = match screenshot::capture(format) {
+                Ok(data) => (timestamp_ms, data),
+                Err(e) => {
+                    // Errors are never dropped, the client needs to know why frames stopped.
+                    let _ = frames_tx.send(Err(e));
+                    break;
+                }
+            };
+            match frames_tx.try_send(Ok(frame)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => dropped += 1,
+                Err(TrySendError::Disconnected(_)) => break,
+            }
+            if let Some(remaining) = interval.and_then(|i| i.checked_sub(started.elapsed())) {
+                thread::sleep(remaining);
+            }
+        }
+        dropped
+    });
+
+    let mut frames = 0u64;
+    for frame in frames_rx.iter() {
+        let response = match frame {
+            Ok((timestamp_ms, data)) => BridgeResponse::ScreenFrame {
+                index: frames,
+                timestamp_ms,
+                data,
+            },
+            Err(e) => failure(ErrorKind::Internal, format!("Screen capture failed: {}", e)),
+        };
+        let is_error = matches!(
+            response,
+            BridgeResponse::Error(_) | BridgeResponse::Failed(_)
+        );
+        if write_response(socket, &response).is_err() || is_error {
+            break;
+        }
+        frames += 1;
+        if max_frames.is_some_and(|max| frames >= max) {
+            break;
+        }
+    }
+    // Dropping the receiver also unblocks a capture thread that is about to queue a frame.
+    stop.store(true, Ordering::SeqCst);
+    drop(frames_rx);
+    let dropped = capture_thread.join().unwrap();
+    let _ = write_response(socket, &BridgeResponse::ScreenStreamEnd { frames, dropped });
+}
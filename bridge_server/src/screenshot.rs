@@ -0,0 +1,136 @@
+// Screen capture through the host's `screencap`, sent back as a FileChunk stream.
+
+use std::io;
+use std::process::Command;
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeResponse, ErrorKind, ImageFormat};
+
+use crate::config::Config;
+use crate::log;
+use crate::transfer::send_chunks;
+use crate::{failure, jpeg, transfer, write_response};
+
+// android::PixelFormat values screencap may report in its raw header.
+const PIXEL_FORMAT_RGBA_8888: u32 = 1;
+const PIXEL_FORMAT_RGBX_8888: u32 = 2;
+const PIXEL_FORMAT_RGB_888: u32 = 3;
+const PIXEL_FORMAT_BGRA_8888: u32 = 5;
+
+pub fn handle_screenshot(socket: &mut dyn Transport, config: &Config, format: ImageFormat) {
+    log::info!("Screenshot: {:?}", format);
+
+    let result = match format {
+        // screencap encodes PNG itself, so its output is streamed as it is produced.
+        ImageFormat::Png => {
+            transfer::send_output(socket, config, Command::new("screencap").arg("-p"))
+        }
+        ImageFormat::Jpeg { quality } => send_jpeg(socket, config, quality),
+    };
+    if let Err(e) = result {
+        let message = format!("Screenshot failed: {}", e);
+        let _ = write_response(socket, &failure(ErrorKind::Internal, message));
+    }
+}
+
+fn send_jpeg(socket: &mut dyn Transport, config: &Config, quality: u8) -> io::Result<()> {
+    let image = capture(ImageFormat::Jpeg { quality })?;
+    write_response(
+        socket,
+        &BridgeResponse::FileHeader {
+            size: Some(image.len() as u64),
+        },
+    )?;
+    send_chunks(socket, &mut image.as_slice(), config.chunk_size as usize)?;
+    write_response(socket, &BridgeResponse::FileEnd { sha256: None })
+}
+
+// Captures one complete image in memory.
+pub fn capture(format: ImageFormat) -> io::Result<Vec<u8>> {
+    match format {
+        ImageFormat::Png => screencap(&["-p"]),
+        ImageFormat::Jpeg { quality } => RawFrame::parse(screencap(&[])?)?.encode_jpeg(quality),
+    }
+}
+
+// An uncompressed frame as printed by `screencap` without `-p`.
+struct RawFrame {
+    width: u32,
+    height: u32,
+    format: u32,
+    pixels: Vec<u8>,
+}
+
+// Runs `screencap` and returns its output. Failures (no display, a secure window
+// blocking capture) come back as errors carrying screencap's own message.
+fn screencap(args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("screencap").args(args).output()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.trim() {
+            "" => "screencap produced no image",
+            reason => reason,
+        };
+        return Err(io::Error::other(reason.to_string()));
+    }
+    Ok(output.stdout)
+}
+
+impl RawFrame {
+    // The header is width, height and pixel format as 32-bit little endian values, followed
+    // on Android 12+ by a dataspace word; its length is derived from the pixel data size.
+    fn parse(mut data: Vec<u8>) -> io::Result<RawFrame> {
+        let word = |at: usize| {
+            data.get(at..at + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let (width, height, format) = match (word(0), word(4), word(8)) {
+            (Some(width), Some(height), Some(format)) => (width, height, format),
+            _ => return Err(invalid("truncated screencap header")),
+        };
+        let bytes_per_pixel = match format {
+            PIXEL_FORMAT_RGBA_8888 | PIXEL_FORMAT_RGBX_8888 | PIXEL_FORMAT_BGRA_8888 => 4,
+            PIXEL_FORMAT_RGB_888 => 3,
+            _ => return Err(invalid(&format!("unsupported pixel format {}", format))),
+        };
+        let pixel_bytes = width as usize * height as usize * bytes_per_pixel;
+        let header = data.len().checked_sub(pixel_bytes);
+        if !matches!(header, Some(12) | Some(16)) {
+            return Err(invalid("screencap output does not match its header"));
+        }
+        data.drain(..header.unwrap());
+        Ok(RawFrame {
+            width,
+            height,
+            format,
+            pixels: data,
+        })
+    }
+
+    fn encode_jpeg(&self, quality: u8) -> io::Result<Vec<u8>> {
+        let (width, height) = match (u16::try_from(self.width), u16::try_from(self.height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err(invalid("frame size is not encodable as JPEG")),
+        };
+        let stride = self.width as usize;
+        let pixel = |x: usize, y: usize| {
+            let bytes_per_pixel = if self.format == PIXEL_FORMAT_RGB_888 {
+                3
+            } else {
+                4
+            };
+            let at = (y * stride + x) * bytes_per_pixel;
+            let p = &self.pixels[at..at + 3];
+            if self.format == PIXEL_FORMAT_BGRA_8888 {
+                [p[2], p[1], p[0]]
+            } else {
+                [p[0], p[1], p[2]]
+            }
+        };
+        Ok(jpeg::encode(width, height, quality, pixel))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
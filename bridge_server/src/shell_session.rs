@@ -0,0 +1,361 @@
+// Persistent shell sessions (`andro session`): a long-lived shell on the host that runs one
+// line after another, so the working directory, exported variables and functions carry over.
+// Each line is run through `eval` with stdin from /dev/null, then followed by a marker on
+// stdout carrying its exit status and one on stderr; what the shell writes before the markers
+// is the line's output. The marker holds a random token, so output cannot fake it.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeResponse, ErrorKind};
+
+use crate::auth;
+use crate::config::Config;
+use crate::log;
+use crate::process::{self, ProcessGroup};
+use crate::{failure, write_response};
+
+pub const DEFAULT_SHELL: &str = "sh";
+
+// How often a client waiting on a line is checked for having gone away.
+const CLIENT_POLL: Duration = Duration::from_millis(500);
+
+// How often sessions are checked against --session-ttl.
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+static SESSIONS: Mutex<Vec<Arc<Session>>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+static REAPER: Once = Once::new();
+
+struct Session {
+    id: u64,
+    pid: libc::pid_t,
+    group: Arc<ProcessGroup>,
+    token: String,
+    // Locked while a line runs.
+    state: Mutex<State>,
+}
+
+struct State {
+    stdin: ChildStdin,
+    events: mpsc::Receiver<Event>,
+    // Taken by whoever reaps the shell.
+    child: Option<Child>,
+    last_used: Instant,
+}
+
+enum Event {
+    // Output of the current line, true for stderr.
+    Output(bool, Vec<u8>),
+    // A pipe reached the marker; the one on stdout carries the exit status.
+    Done(bool, Option<i32>),
+    // A pipe closed: the shell has exited.
+    Closed,
+}
+
+// SessionOpen.
+pub fn open(config: &Config, shell: Option<String>) -> BridgeResponse {
+    let shell = shell.unwrap_or_else(|| DEFAULT_SHELL.to_string());
+    match start(&shell) {
+        Ok(session) => {
+            log::info!("Session {}: {} (pid {})", session.id, shell, session.pid);
+            if config.session_ttl > 0 {
+                let ttl = Duration::from_secs(config.session_ttl);
+                REAPER.call_once(|| {
+                    thread::spawn(move || reap_idle(ttl));
+                });
+            }
+            let id = session.id;
+            SESSIONS.lock().unwrap().push(session);
+            BridgeResponse::SessionOpened { id }
+        }
+        Err(e) => process::spawn_failed(&format!("SessionOpen Failed: {}: ", shell), &e),
+    }
+}
+
+fn start(shell: &str) -> std::io::Result<Arc<Session>> {
+    let token = auth::generate_token()?;
+    let mut command = Command::new(shell);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    let (mut child, group) = ProcessGroup::spawn_detached(&mut command, false)?;
+    let (events, receiver) = mpsc::channel();
+    for (stderr, pipe) in [
+        (
+            false,
+            Box::new(child.stdout.take().unwrap()) as Box<dyn Read + Send>,
+        ),
+        (true, Box::new(child.stderr.take().unwrap())),
+    ] {
+        let events = events.clone();
+        let marker = marker(&token).into_bytes();
+        thread::spawn(move || read_marked(pipe, &marker, stderr, &events));
+    }
+    Ok(Arc::new(Session {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        pid: child.id() as libc::pid_t,
+        group,
+        token,
+        state: Mutex::new(State {
+            stdin: child.stdin.take().unwrap(),
+            events: receiver,
+            child: Some(child),
+            last_used: Instant::now(),
+        }),
+    }))
+}
+
+fn marker(token: &str) -> String {
+    format!("__andro_{}", token)
+}
+
+// What the shell is sent for `line`. The marker is printed in two halves, so that not even
+// `set -x` echoes it whole.
+fn script(line: &str, token: &str) -> String {
+    format!(
+        "eval '{}' </dev/null\nprintf '%s%s %d\\n' __andro_ {} \"$?\"\nprintf '%s%s\\n' __andro_ {} >&2\n",
+        line.replace('\'', r"'\''"),
+        token,
+        token
+    )
+}
+
+// Forwards a pipe of the shell as Output events, cut at each marker into a Done event.
+fn read_marked(
+    mut pipe: Box<dyn Read + Send>,
+    marker: &[u8],
+    stderr: bool,
+    events: &mpsc::Sender<Event>,
+) {
+    let mut pending = Vec::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        pending.extend_from_slice(&buffer[..n]);
+        loop {
+            if let Some(at) = find(&pending, marker) {
+                // The marker runs to the end of its line, which holds the exit status.
+                let Some(end) = pending[at..].iter().position(|&b| b == b'\n') else {
+                    send_output(events, stderr, pending.drain(..at).collect());
+                    break;
+                };
+                let status = String::from_utf8_lossy(&pending[at + marker.len()..at + end])
+                    .trim()
+                    .parse()
+                    .ok();
+                send_output(events, stderr, pending[..at].to_vec());
+                pending.drain(..at + end + 1);
+                let _ = events.send(Event::Done(stderr, status));
+            } else {
+                // Held back: the start of a marker the next read completes.
+                let keep = (1..marker.len().min(pending.len() + 1))
+                    .rev()
+                    .find(|&len| pending.ends_with(&marker[..len]))
+                    .unwrap_or(0);
+                send_output(
+                    events,
+                    stderr,
+                    pending.drain(..pending.len() - keep).collect(),
+                );
+                break;
+            }
+        }
+    }
+    send_output(events, stderr, pending);
+    let _ = events.send(Event::Closed);
+}
+
+fn send_output(events: &mpsc::Sender<Event>, stderr: bool, data: Vec<u8>) {
+    if !data.is_empty() {
+        let _ = events.send(Event::Output(stderr, data));
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn find_session(id: u64) -> Option<Arc<Session>> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|s| s.id == id)
+        .cloned()
+}
+
+fn remove(id: u64) {
+    SESSIONS.lock().unwrap().retain(|session| session.id != id);
+}
+
+// SessionExec. Returns whether the connection can carry another command, which it can unless
+// the client went away.
+pub fn handle_exec(socket: &mut dyn Transport, id: u64, line: String) -> bool {
+    let Some(session) = find_session(id) else {
+        let message = format!("SessionExec Failed: no session {}", id);
+        return write_response(socket, &failure(ErrorKind::NotFound, message)).is_ok();
+    };
+    let Ok(mut state) = session.state.try_lock() else {
+        let message = format!("SessionExec Failed: session {} is running another line", id);
+        return write_response(socket, &failure(ErrorKind::Internal, message)).is_ok();
+    };
+    log::info!("Session {}: {}", id, log::args(std::slice::from_ref(&line)));
+
+    let client = socket.as_raw_fd();
+    let sent = state
+        .stdin
+        .write_all(script(&line, &session.token).as_bytes())
+        .and_then(|()| state.stdin.flush());
+    let (mut stdout_done, mut stderr_done) = (false, false);
+    let mut code = None;
+    while sent.is_ok() && !(stdout_done && stderr_done) {
+        let response = match state.events.recv_timeout(CLIENT_POLL) {
+            Ok(Event::Output(false, data)) => BridgeResponse::StdoutChunk(data),
+            Ok(Event::Output(true, data)) => BridgeResponse::StderrChunk(data),
+            Ok(Event::Done(false, status)) => {
+                stdout_done = true;
+                code = status;
+                continue;
+            }
+            Ok(Event::Done(true, _)) => {
+                stderr_done = true;
+                continue;
+            }
+            Ok(Event::Closed) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if process::hung_up(client) {
+                    log::info!("Closing session {}, its client went away", id);
+                    drop(state);
+                    close(&session);
+                    return false;
+                }
+                continue;
+            }
+        };
+        if write_response(socket, &response).is_err() {
+            drop(state);
+            close(&session);
+            return false;
+        }
+    }
+    if stdout_done && stderr_done {
+        state.last_used = Instant::now();
+        let end = BridgeResponse::StreamEnd { code, signal: None };
+        return write_response(socket, &end).is_ok();
+    }
+
+    // The shell has exited, `exit` in the line for instance.
+    remove(id);
+    let status = reap(&session, &mut state);
+    log::info!("Session {} ended", id);
+    let end = BridgeResponse::SessionEnded {
+        code: status.and_then(|s| s.code()),
+        signal: status.and_then(|s| s.signal()),
+    };
+    write_response(socket, &end).is_ok()
+}
+
+fn reap(session: &Session, state: &mut State) -> Option<ExitStatus> {
+    let mut child = state.child.take()?;
+    let status = child.wait().ok();
+    session.group.mark_reaped();
+    status
+}
+
+// Stops the shell and what it runs; the shell is reaped once a running line has noticed.
+fn close(session: &Arc<Session>) {
+    remove(session.id);
+    session.group.cancel();
+    let session = Arc::clone(session);
+    thread::spawn(move || {
+        let mut state = session.state.lock().unwrap();
+        reap(&session, &mut state);
+    });
+}
+
+// SessionClose.
+pub fn handle_close(id: u64) -> BridgeResponse {
+    let Some(session) = find_session(id) else {
+        return failure(
+            ErrorKind::NotFound,
+            format!("SessionClose Failed: no session {}", id),
+        );
+    };
+    log::info!("Session {} closed", id);
+    close(&session);
+    BridgeResponse::Success(String::new())
+}
+
+// SessionSignal: `signal` goes to every process of the session's group but the shell.
+pub fn handle_signal(id: u64, signal: i32) -> BridgeResponse {
+    let Some(session) = find_session(id) else {
+        return failure(
+            ErrorKind::NotFound,
+            format!("SessionSignal Failed: no session {}", id),
+        );
+    };
+    for pid in group_members(session.pid) {
+        if pid != session.pid {
+            unsafe {
+                libc::kill(pid, signal);
+            }
+        }
+    }
+    BridgeResponse::Success(String::new())
+}
+
+// The processes in process group `pgid`, from /proc.
+fn group_members(pgid: libc::pid_t) -> Vec<libc::pid_t> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter(|&pid: &libc::pid_t| {
+            // The fields after the command name, which is in parentheses and may hold spaces:
+            // state, ppid, pgrp, ...
+            fs::read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| {
+                    let fields = &stat[stat.rfind(')')? + 1..];
+                    fields.split_whitespace().nth(2)?.parse().ok()
+                })
+                == Some(pgid)
+        })
+        .collect()
+}
+
+// Closes the sessions that have not run a line for `ttl`.
+fn reap_idle(ttl: Duration) {
+    loop {
+        thread::sleep(REAP_INTERVAL);
+        let sessions = SESSIONS.lock().unwrap().clone();
+        for session in sessions {
+            let idle = match session.state.try_lock() {
+                Ok(state) => state.last_used.elapsed() >= ttl,
+                Err(_) => false,
+            };
+            if idle {
+                log::info!("Closing session {}, idle for {:?}", session.id, ttl);
+                close(&session);
+            }
+        }
+    }
+}
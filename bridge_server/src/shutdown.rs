@@ -0,0 +1,111 @@
+// Graceful shutdown on SIGTERM and SIGINT. The handler only writes a byte to a pipe, which
+// the accept loop polls next to its listeners; everything else happens on the main thread
+// once `poll` returns: stop accepting, cancel the running commands, give the connections
+// --shutdown-grace-ms to finish, then remove the socket file. A second signal stops the
+// server at once, like it would without a handler.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::process;
+
+// The write end of the pipe, for the signal handler.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+// The sockets of the connections being handled.
+static ACTIVE: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = PIPE.load(Ordering::Relaxed);
+    let byte = 1u8;
+    // write(2) is async-signal-safe; if the pipe is full a signal is already pending.
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+// Installs the handlers and returns the end of the pipe that becomes readable once a
+// signal has arrived.
+pub fn install() -> io::Result<OwnedFd> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // pipe2 returned two fresh descriptors; the write end lives as long as the process.
+    let read = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+    PIPE.store(fds[1], Ordering::Relaxed);
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART | libc::SA_RESETHAND;
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(read)
+}
+
+// Counts a connection as in flight until it is dropped, which must happen before its
+// socket is closed.
+pub struct Active(RawFd);
+
+impl Active {
+    pub fn start(socket: &dyn AsRawFd) -> Active {
+        let fd = socket.as_raw_fd();
+        ACTIVE.lock().unwrap().push(fd);
+        Active(fd)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        let mut active = ACTIVE.lock().unwrap();
+        if let Some(i) = active.iter().position(|&fd| fd == self.0) {
+            active.swap_remove(i);
+        }
+    }
+}
+
+fn active() -> usize {
+    ACTIVE.lock().unwrap().len()
+}
+
+// Stops the running commands and waits up to `grace` for the connections to finish.
+// Background jobs are stopped too, unless `keep_jobs`.
+pub fn drain(grace: Duration, keep_jobs: bool) {
+    process::cancel_all(keep_jobs);
+    // Handlers waiting for a client's next command read EOF instead; responses still in
+    // the works can be written.
+    for &fd in ACTIVE.lock().unwrap().iter() {
+        unsafe {
+            libc::shutdown(fd, libc::SHUT_RD);
+        }
+    }
+    let deadline = Instant::now() + grace;
+    while active() > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    let left = active();
+    if left > 0 {
+        log::warning!("{} connection(s) still open after {:?}", left, grace);
+    }
+    process::kill_all(keep_jobs);
+}
+
+// Whether the pipe from `install` says a signal has arrived.
+pub fn requested(pipe: &OwnedFd) -> bool {
+    let mut byte = 0u8;
+    let n = unsafe {
+        libc::read(
+            pipe.as_raw_fd(),
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+        )
+    };
+    n == 1
+}
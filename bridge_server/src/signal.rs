@@ -0,0 +1,124 @@
+// Signal: delivers a signal to a process the server runs without stopping it, e.g. SIGHUP for
+// a daemon to reload its configuration. Jobs and requests get it for their whole process
+// group, the way JobKill and Cancel reach them. A raw pid may name any process on the host,
+// which for a server running as root amounts to root's `kill`, so it takes
+// --allow-raw-pid-signals.
+
+use std::io;
+use std::sync::Arc;
+
+use bridge_core::{BridgeError, BridgeResponse, ErrorKind, SignalTarget};
+
+use crate::background;
+use crate::config::Config;
+use crate::failure;
+use crate::log;
+use crate::process::ProcessSlot;
+
+// Signal, for job and pid targets. Request targets only exist on a multiplexed connection,
+// which answers them with `to_request`.
+pub fn handle(config: &Config, target: SignalTarget, signal: i32) -> BridgeResponse {
+    let sent = match target {
+        SignalTarget::Job(id) => match background::group(id) {
+            Some(group) => group.send(signal).map_err(|e| refused(e, target, signal)),
+            None => Err(BridgeError {
+                kind: ErrorKind::NotFound,
+                message: format!("no job {}", id),
+            }),
+        },
+        SignalTarget::Request(id) => Err(BridgeError {
+            kind: ErrorKind::ProtocolError,
+            message: format!(
+                "request {} is not on a multiplexed connection, only Tagged requests can be signalled",
+                id
+            ),
+        }),
+        SignalTarget::Pid(pid) => to_pid(config, pid, signal),
+    };
+    answer(sent, target, signal)
+}
+
+// Signal for request `id` of a multiplexed connection, whose process is published in `slot`
+// if the request is in flight.
+pub fn to_request(slot: Option<Arc<ProcessSlot>>, id: u64, signal: i32) -> BridgeResponse {
+    let sent = match slot.and_then(|slot| slot.group()) {
+        Some(group) => group
+            .send(signal)
+            .map_err(|e| refused(e, SignalTarget::Request(id), signal)),
+        None => Err(BridgeError {
+            kind: ErrorKind::NotFound,
+            message: format!("no request {} with a running process", id),
+        }),
+    };
+    answer(sent, SignalTarget::Request(id), signal)
+}
+
+fn to_pid(config: &Config, pid: u32, signal: i32) -> Result<(), BridgeError> {
+    if !config.allow_raw_pid_signals {
+        return Err(BridgeError {
+            kind: ErrorKind::PolicyRejected,
+            message: format!(
+                "signals to raw pids are disabled; start the server with --allow-raw-pid-signals to send them to pid {}",
+                pid
+            ),
+        });
+    }
+    // kill(2) reads 0 and negative pids as process groups, -1 as every process there is.
+    let Ok(raw) = libc::pid_t::try_from(pid) else {
+        return Err(invalid_pid(pid));
+    };
+    if raw == 0 {
+        return Err(invalid_pid(pid));
+    }
+    if unsafe { libc::kill(raw, signal) } == -1 {
+        return Err(refused(
+            io::Error::last_os_error(),
+            SignalTarget::Pid(pid),
+            signal,
+        ));
+    }
+    Ok(())
+}
+
+fn answer(sent: Result<(), BridgeError>, target: SignalTarget, signal: i32) -> BridgeResponse {
+    match sent {
+        Ok(()) => {
+            log::info!("Sent signal {} to {}", signal, describe(target));
+            BridgeResponse::Success(String::new())
+        }
+        Err(e) => failure(e.kind, format!("Signal Failed: {}", e.message)),
+    }
+}
+
+// Why kill(2) failed for `target`.
+fn refused(e: io::Error, target: SignalTarget, signal: i32) -> BridgeError {
+    let what = describe(target);
+    let (kind, message) = match e.raw_os_error() {
+        Some(libc::ESRCH) => (ErrorKind::NotFound, format!("{} is not running", what)),
+        Some(libc::EPERM) => (
+            ErrorKind::PermissionDenied,
+            format!("not permitted to signal {}", what),
+        ),
+        Some(libc::EINVAL) => (
+            ErrorKind::ProtocolError,
+            format!("invalid signal {}", signal),
+        ),
+        _ => (ErrorKind::of(&e), format!("cannot signal {}: {}", what, e)),
+    };
+    BridgeError { kind, message }
+}
+
+fn describe(target: SignalTarget) -> String {
+    match target {
+        SignalTarget::Job(id) => format!("job {}", id),
+        SignalTarget::Request(id) => format!("request {}", id),
+        SignalTarget::Pid(pid) => format!("pid {}", pid),
+    }
+}
+
+fn invalid_pid(pid: u32) -> BridgeError {
+    BridgeError {
+        kind: ErrorKind::ProtocolError,
+        message: format!("invalid pid {}", pid),
+    }
+}
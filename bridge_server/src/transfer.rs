@@ -0,0 +1,255 @@
+// File transfers between the chroot and the host.
+// Data always travels in bounded FileChunk frames, so file size is not limited by memory.
+
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use bridge_core::sha256::digest_reader;
+use bridge_core::transport::Transport;
+use bridge_core::{BridgeCommand, BridgeResponse, ErrorKind};
+
+use crate::config::Config;
+use crate::log;
+use crate::process::ProcessGroup;
+use crate::{failure, write_response};
+
+pub fn handle_push(
+    socket: &mut dyn Transport,
+    dest: String,
+    mode: u32,
+    resume: bool,
+    verify: bool,
+) {
+    log::info!("Push: {} (mode {:o})", dest, mode);
+
+    let result = receive_file(socket, &dest, mode, resume).and_then(|bytes| {
+        let sha256 = if verify {
+            Some(File::open(&dest).and_then(digest_reader)?)
+        } else {
+            None
+        };
+        Ok((bytes, sha256))
+    });
+    let response = match result {
+        Ok((bytes, sha256)) => BridgeResponse::Transferred { bytes, sha256 },
+        Err(e) => failure(
+            ErrorKind::of(&e),
+            format!("Push to '{}' failed: {}", dest, e),
+        ),
+    };
+    let _ = write_response(socket, &response);
+}
+
+// Writes the incoming data to a temporary file next to `dest` and renames it into place
+// only once everything arrived, so a dropped connection never leaves a truncated file.
+// An interrupted push keeps the partial file, which a later push can resume.
+// Returns the final file size.
+fn receive_file(
+    socket: &mut dyn Transport,
+    dest: &str,
+    mode: u32,
+    resume: bool,
+) -> io::Result<u64> {
+    let partial = format!("{}.nbpart", dest);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(!resume)
+        .open(&partial)?;
+    let offset = file.seek(SeekFrom::End(0))?;
+    write_response(socket, &BridgeResponse::TransferReady { offset })?;
+
+    let result = copy_chunks(socket, &mut file).and_then(|bytes| {
+        // Set explicitly rather than at creation time so the umask does not interfere.
+        file.set_permissions(Permissions::from_mode(mode))?;
+        file.sync_all()?;
+        fs::rename(&partial, dest)?;
+        Ok(offset + bytes)
+    });
+    if result.is_err() && file.metadata().is_ok_and(|meta| meta.len() == 0) {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+// Appends FileChunk frames to `file` until FileEnd; returns the number of bytes written.
+fn copy_chunks(socket: &mut dyn Transport, file: &mut File) -> io::Result<u64> {
+    let mut bytes = 0;
+    loop {
+        let payload = socket
+            .codec()
+            .read_frame(socket, crate::max_frame())?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        match socket.codec().decode_command(&payload) {
+            Ok(BridgeCommand::FileChunk(data)) => {
+                file.write_all(&data)?;
+                bytes += data.len() as u64;
+            }
+            Ok(BridgeCommand::FileEnd) => return Ok(bytes),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected frame during file transfer",
+                ));
+            }
+        }
+    }
+}
+
+pub fn handle_pull(
+    socket: &mut dyn Transport,
+    config: &Config,
+    src: String,
+    recursive: bool,
+    offset: u64,
+    verify: bool,
+) {
+    log::info!(
+        "Pull: {}{}",
+        src,
+        if recursive { " (recursive)" } else { "" }
+    );
+
+    let result = match fs::metadata(&src) {
+        Ok(meta) if meta.is_dir() && recursive => send_tree(socket, config, &src),
+        Ok(meta) if meta.is_dir() => {
+            let message = format!("'{}' is a directory (use --recursive to pull it)", src);
+            let _ = write_response(socket, &failure(ErrorKind::Internal, message));
+            return;
+        }
+        // Device nodes and pipes report no meaningful size, so only regular files announce one.
+        Ok(meta) => {
+            let size = meta.is_file().then_some(meta.len());
+            send_file(socket, config, &src, size, offset, verify)
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let message = format!("Pull of '{}' failed: {}", src, e);
+        let _ = write_response(socket, &failure(ErrorKind::of(&e), message));
+    }
+}
+
+fn send_file(
+    socket: &mut dyn Transport,
+    config: &Config,
+    src: &str,
+    size: Option<u64>,
+    offset: u64,
+    verify: bool,
+) -> io::Result<()> {
+    // Opening before the header turns permission errors into a plain Error response.
+    let mut file = File::open(src)?;
+    if let Some(size) = size
+        && offset > size
+    {
+        return Err(io::Error::other(format!(
+            "resume offset {} is past the end of the file ({} bytes)",
+            offset, size
+        )));
+    }
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset))?;
+    }
+    write_response(socket, &BridgeResponse::FileHeader { size })?;
+    let sent = offset + send_chunks(socket, &mut file, config.chunk_size as usize)?;
+    if let Some(size) = size
+        && sent != size
+    {
+        return Err(io::Error::other(format!(
+            "file changed during transfer ({} of {} bytes sent)",
+            sent, size
+        )));
+    }
+    // Hashed separately from the transfer, since a resumed pull only sends the tail.
+    let sha256 = if verify {
+        Some(File::open(src).and_then(digest_reader)?)
+    } else {
+        None
+    };
+    write_response(socket, &BridgeResponse::FileEnd { sha256 })
+}
+
+// Streams a directory as a tar archive, created by the host's tar (toybox provides one).
+fn send_tree(socket: &mut dyn Transport, config: &Config, src: &str) -> io::Result<()> {
+    let path = Path::new(src);
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => (parent, name),
+        (_, Some(name)) => (Path::new("."), name),
+        // `/` or a path ending in `..`, archive the directory contents instead.
+        _ => (path, ".".as_ref()),
+    };
+    let mut tar = Command::new("tar");
+    tar.arg("-cf").arg("-").arg("-C").arg(parent).arg(name);
+    send_output(socket, config, &mut tar)
+}
+
+// Streams the stdout of `command` as a FileHeader/FileChunk/FileEnd sequence of unknown
+// size. A failing command ends the sequence with an Error carrying its stderr.
+pub fn send_output(
+    socket: &mut dyn Transport,
+    config: &Config,
+    command: &mut Command,
+) -> io::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    let (mut child, group) = ProcessGroup::spawn(command)?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    // Drained separately so a chatty command cannot block on a full stderr pipe.
+    let stderr_thread = thread::spawn(move || {
+        let mut messages = String::new();
+        let _ = stderr.read_to_string(&mut messages);
+        messages
+    });
+
+    let sent = write_response(socket, &BridgeResponse::FileHeader { size: None })
+        .and_then(|_| send_chunks(socket, &mut stdout, config.chunk_size as usize));
+    if sent.is_err() {
+        // The client is gone, there is nobody left to read the output.
+        group.kill();
+    }
+    drop(stdout);
+    let status = child.wait()?;
+    group.mark_reaped();
+    let messages = stderr_thread.join().unwrap();
+    sent?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with status {}: {}",
+            program,
+            crate::exit_code(status),
+            messages.trim()
+        )));
+    }
+    write_response(socket, &BridgeResponse::FileEnd { sha256: None })
+}
+
+// Sends everything readable from `reader` as FileChunk frames; returns the number of bytes.
+pub fn send_chunks<R: Read>(
+    socket: &mut dyn Transport,
+    reader: &mut R,
+    chunk_size: usize,
+) -> io::Result<u64> {
+    let mut buffer = vec![0u8; chunk_size];
+    let mut bytes = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(bytes),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write_response(socket, &BridgeResponse::FileChunk(buffer[..n].to_vec()))?;
+        bytes += n as u64;
+    }
+}
@@ -0,0 +1,133 @@
+// Virtual input devices created through /dev/uinput, for input the hardware does not have
+// (a mouse) or cannot accept injected events for.
+#![cfg(feature = "direct_input")]
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use crate::evdev::AbsInfo;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const UINPUT_MAX_NAME_SIZE: usize = 80;
+const ABS_CNT: usize = 0x40;
+const BUS_VIRTUAL: u16 = 0x06;
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const EV_ABS: u16 = 3;
+
+// What a virtual device reports.
+#[derive(Default)]
+pub struct Capabilities<'a> {
+    pub keys: &'a [u16],
+    pub rel: &'a [u16],
+    pub abs: &'a [(u16, AbsInfo)],
+    // INPUT_PROP_* bits, e.g. INPUT_PROP_DIRECT for a touch screen.
+    pub props: &'a [u16],
+}
+
+// A uinput device; it disappears again when this is dropped.
+pub struct VirtualDevice {
+    file: File,
+}
+
+impl VirtualDevice {
+    pub fn create(name: &str, caps: &Capabilities) -> io::Result<VirtualDevice> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(UINPUT_PATH)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot open {}: {}", UINPUT_PATH, e)))?;
+
+        // UI_SET_EVBIT, UI_SET_KEYBIT, UI_SET_RELBIT, UI_SET_ABSBIT and UI_SET_PROPBIT.
+        let set = |nr: usize, bit: u16| ioctl_int(&file, nr, bit as i32);
+        if !caps.keys.is_empty() {
+            set(100, EV_KEY)?;
+        }
+        for &key in caps.keys {
+            set(101, key)?;
+        }
+        if !caps.rel.is_empty() {
+            set(100, EV_REL)?;
+        }
+        for &rel in caps.rel {
+            set(102, rel)?;
+        }
+        if !caps.abs.is_empty() {
+            set(100, EV_ABS)?;
+        }
+        for &(abs, _) in caps.abs {
+            set(103, abs)?;
+        }
+        for &prop in caps.props {
+            set(110, prop)?;
+        }
+
+        file.write_all(&user_dev(name, caps.abs))?;
+        // UI_DEV_CREATE
+        if unsafe { libc::ioctl(file.as_raw_fd(), ioc(0, 1, 0) as _) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The framework picks up new devices asynchronously; events sent before it has
+        // opened the device are lost.
+        thread::sleep(Duration::from_millis(200));
+        Ok(VirtualDevice { file })
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    // A second handle for writing events; the device stays until this one is dropped too.
+    pub fn try_clone(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+}
+
+impl Drop for VirtualDevice {
+    fn drop(&mut self) {
+        // UI_DEV_DESTROY
+        unsafe { libc::ioctl(self.file.as_raw_fd(), ioc(0, 2, 0) as _) };
+    }
+}
+
+// The legacy `struct uinput_user_dev`, which every kernel with uinput understands:
+// name, struct input_id, ff_effects_max, then absmax, absmin, absfuzz and absflat arrays.
+fn user_dev(name: &str, abs: &[(u16, AbsInfo)]) -> Vec<u8> {
+    let mut bytes = vec![0u8; UINPUT_MAX_NAME_SIZE];
+    let name = &name.as_bytes()[..name.len().min(UINPUT_MAX_NAME_SIZE - 1)];
+    bytes[..name.len()].copy_from_slice(name);
+    for id in [BUS_VIRTUAL, 0x1209, 0x4e42, 1] {
+        bytes.extend_from_slice(&id.to_ne_bytes()); // bustype, vendor, product, version
+    }
+    bytes.extend_from_slice(&0u32.to_ne_bytes()); // ff_effects_max
+
+    let mut tables = [[0i32; ABS_CNT]; 4];
+    for &(code, info) in abs {
+        let code = code as usize;
+        tables[0][code] = info.maximum;
+        tables[1][code] = info.minimum;
+        tables[2][code] = info.fuzz;
+        tables[3][code] = info.flat;
+    }
+    for value in tables.iter().flatten() {
+        bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+    bytes
+}
+
+fn ioctl_int(file: &File, nr: usize, value: i32) -> io::Result<()> {
+    // _IOW('U', nr, int) takes the int by value.
+    let request = ioc(1, nr, std::mem::size_of::<i32>());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, value) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The kernel's _IOC(dir, 'U', nr, size) for the uinput ioctls (dir: 0 none, 1 write).
+fn ioc(dir: u64, nr: usize, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | ((b'U' as u64) << 8) | nr as u64
+}
@@ -0,0 +1,88 @@
+// A bridge_server started on a temp socket for the integration tests, and just enough of a
+// client to talk to it. Each test uses what it needs of it.
+#![allow(dead_code)]
+
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bridge_core::proto::MAX_FRAME_LEN;
+use bridge_core::{BridgeCommand, BridgeResponse, Codec, PROTOCOL_VERSION};
+
+pub struct Server {
+    pub process: Child,
+    pub socket: PathBuf,
+}
+
+impl Server {
+    // Starts the server with `args` on a socket named after `name`, and waits for it.
+    pub fn start(name: &str, args: &[&str]) -> Server {
+        let socket = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let process = Command::new(env!("CARGO_BIN_EXE_bridge_server"))
+            .arg("--socket")
+            .arg(&socket)
+            .args(args)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server { process, socket };
+        assert!(
+            wait_for(|| server.socket.exists()),
+            "the server did not start"
+        );
+        server
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.process.id() as i32
+    }
+
+    // A connection that has been through the handshake.
+    pub fn connect(&self) -> UnixStream {
+        let mut client = UnixStream::connect(&self.socket).unwrap();
+        Codec::Bincode
+            .write_hello(&mut client, PROTOCOL_VERSION, b"")
+            .unwrap();
+        Codec::Bincode
+            .read_frame(&mut client, MAX_FRAME_LEN)
+            .unwrap();
+        assert!(matches!(response(&mut client), BridgeResponse::Success(_)));
+        client
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+pub fn send(client: &mut UnixStream, cmd: &BridgeCommand) {
+    let payload = Codec::Bincode.encode_command(cmd).unwrap();
+    Codec::Bincode.write_frame(client, &payload).unwrap();
+}
+
+pub fn response(client: &mut UnixStream) -> BridgeResponse {
+    let frame = Codec::Bincode
+        .read_frame(client, MAX_FRAME_LEN)
+        .unwrap()
+        .expect("the server closed the connection");
+    Codec::Bincode.decode_response(&frame).unwrap()
+}
+
+// Waits up to five seconds for `done`.
+pub fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
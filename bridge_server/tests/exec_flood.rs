@@ -0,0 +1,96 @@
+// An Exec that writes 100 MiB arrives whole, as chunks ahead of the Completed response,
+// while the server's peak memory stays far below the output. The same command in a Batch,
+// which cannot take chunks, fails once the output passes a frame instead of being buffered
+// whole.
+
+mod common;
+
+use bridge_core::{BridgeCommand, BridgeResponse, ProcessOptions};
+
+use common::{Server, response, send};
+
+const OUTPUT: u64 = 100 * 1024 * 1024;
+// Generous against the few MiB the server needs for itself.
+const PEAK_LIMIT: u64 = 48 * 1024 * 1024;
+
+fn flood() -> BridgeCommand {
+    BridgeCommand::Exec {
+        program: "head".to_string(),
+        args: vec![
+            "-c".to_string(),
+            OUTPUT.to_string(),
+            "/dev/zero".to_string(),
+        ],
+        stdin: false,
+        options: ProcessOptions::default(),
+    }
+}
+
+// VmHWM of `pid`, its peak resident set.
+fn peak(pid: i32) -> u64 {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    let kib: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|kib| kib.trim().trim_end_matches("kB").trim().parse().ok())
+        .unwrap();
+    kib * 1024
+}
+
+// A response without the output it carries, for the messages below.
+fn short(response: &BridgeResponse) -> String {
+    match response {
+        BridgeResponse::Completed { stdout, code, .. } => {
+            format!("Completed with {} bytes, code {}", stdout.len(), code)
+        }
+        response => format!("{:?}", response),
+    }
+}
+
+#[test]
+fn huge_exec_output_arrives_in_chunks_with_bounded_memory() {
+    let server = Server::start("exec-flood", &[]);
+    let mut client = server.connect();
+    send(&mut client, &flood());
+    let (mut chunks, mut bytes) = (0u64, 0u64);
+    loop {
+        match response(&mut client) {
+            BridgeResponse::StdoutChunk(data) => {
+                chunks += 1;
+                bytes += data.len() as u64;
+            }
+            BridgeResponse::Completed {
+                stdout,
+                stderr,
+                code,
+            } => {
+                assert_eq!(bytes + stdout.len() as u64, OUTPUT);
+                assert!(stderr.is_empty());
+                assert_eq!(code, 0);
+                break;
+            }
+            other => panic!("after {} bytes: {}", bytes, short(&other)),
+        }
+    }
+    assert!(chunks > 1);
+    let peak = peak(server.pid());
+    assert!(peak < PEAK_LIMIT, "server peak {} KiB", peak / 1024);
+}
+
+#[test]
+fn batch_refuses_output_it_cannot_hold() {
+    let server = Server::start("exec-flood-batch", &[]);
+    let mut client = server.connect();
+    let batch = BridgeCommand::Batch {
+        commands: vec![flood()],
+        stop_on_error: false,
+    };
+    send(&mut client, &batch);
+    match response(&mut client) {
+        BridgeResponse::Batch(responses) => match responses.as_slice() {
+            [BridgeResponse::Failed(_)] => {}
+            other => panic!("{:?}", other.iter().map(short).collect::<Vec<_>>()),
+        },
+        other => panic!("{}", short(&other)),
+    }
+}
@@ -0,0 +1,63 @@
+// One multiplexed connection firing far more Tagged requests than --max-in-flight: the ones
+// over the cap are answered Busy at once instead of each getting a thread, and the connection
+// takes requests again once the running ones are done.
+
+mod common;
+
+use std::collections::HashMap;
+
+use bridge_core::{BridgeCommand, BridgeResponse};
+
+use common::{Server, response, send};
+
+fn tagged(request_id: u64, command: BridgeCommand) -> BridgeCommand {
+    BridgeCommand::Tagged {
+        request_id,
+        command: Box::new(command),
+    }
+}
+
+#[test]
+fn requests_over_the_cap_are_busy() {
+    let server = Server::start("multiplex-flood", &["--max-in-flight", "8"]);
+    let mut client = server.connect();
+    for id in 0..200 {
+        send(&mut client, &tagged(id, BridgeCommand::Sleep { ms: 300 }));
+    }
+
+    let mut answers = HashMap::new();
+    while answers.len() < 200 {
+        match response(&mut client) {
+            BridgeResponse::Tagged {
+                request_id,
+                response,
+            } => assert!(answers.insert(request_id, *response).is_none()),
+            other => panic!("expected a Tagged response, got {:?}", other),
+        }
+    }
+    let done = answers
+        .values()
+        .filter(|answer| matches!(answer, BridgeResponse::Success(_)))
+        .count();
+    let busy = answers
+        .values()
+        .filter(|answer| {
+            matches!(
+                answer,
+                BridgeResponse::Busy {
+                    running: 8,
+                    limit: 8
+                }
+            )
+        })
+        .count();
+    assert_eq!((done, busy), (8, 192));
+
+    // Everything has finished, so there is room again.
+    send(&mut client, &tagged(200, BridgeCommand::Ping));
+    assert!(matches!(
+        response(&mut client),
+        BridgeResponse::Tagged { request_id: 200, response }
+            if matches!(*response, BridgeResponse::Success(_))
+    ));
+}
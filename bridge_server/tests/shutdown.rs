@@ -0,0 +1,38 @@
+// SIGTERM against a running server: it stops the command a client is streaming, removes
+// its socket file and exits 0.
+
+mod common;
+
+use bridge_core::{BridgeCommand, BridgeResponse, ProcessOptions};
+
+use common::{Server, response, send, wait_for};
+
+#[test]
+fn sigterm_stops_streams_and_removes_the_socket() {
+    let mut server = Server::start("shutdown", &[]);
+    let mut client = server.connect();
+    let stream = BridgeCommand::Stream {
+        program: "sh".to_string(),
+        args: vec!["-c".to_string(), "echo $$; exec sleep 30".to_string()],
+        stdin: false,
+        options: ProcessOptions::default(),
+    };
+    send(&mut client, &stream);
+    let child: i32 = match response(&mut client) {
+        BridgeResponse::StdoutChunk(data) => {
+            String::from_utf8(data).unwrap().trim().parse().unwrap()
+        }
+        other => panic!("expected the pid, got {:?}", other),
+    };
+
+    unsafe { libc::kill(server.pid(), libc::SIGTERM) };
+    let mut status = None;
+    assert!(wait_for(|| {
+        status = server.process.try_wait().unwrap();
+        status.is_some()
+    }));
+    assert!(status.unwrap().success());
+    assert!(!server.socket.exists());
+    // The server waited for it, so not even a zombie is left.
+    assert_eq!(unsafe { libc::kill(child, 0) }, -1);
+}
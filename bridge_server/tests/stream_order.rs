@@ -0,0 +1,68 @@
+// A streamed child that alternates lines on stdout and stderr: they arrive in the order they
+// were written. The child pauses between writes, so only a few lines may come out of order to
+// scheduler noise.
+
+mod common;
+
+use bridge_core::{BridgeCommand, BridgeResponse, ProcessOptions};
+
+use common::{Server, response, send};
+
+const LINES: usize = 100;
+const TOLERATED: usize = 2;
+
+#[test]
+fn stdout_and_stderr_arrive_in_the_order_written() {
+    let server = Server::start("stream-order", &[]);
+    let mut client = server.connect();
+    let script = format!(
+        "i=0; while [ $i -lt {} ]; do echo out $i; sleep 0.005; echo err $i >&2; sleep 0.005; i=$((i+1)); done",
+        LINES
+    );
+    let cmd = BridgeCommand::Stream {
+        program: "sh".to_string(),
+        args: vec!["-c".to_string(), script],
+        stdin: false,
+        options: ProcessOptions::default(),
+    };
+    send(&mut client, &cmd);
+
+    // Chunks can hold several lines, or part of one.
+    let (mut stdout, mut stderr) = (String::new(), String::new());
+    let mut arrived = Vec::new();
+    loop {
+        let (pending, name) = match response(&mut client) {
+            BridgeResponse::StdoutChunk(bytes) => {
+                stdout.push_str(&String::from_utf8_lossy(&bytes));
+                (&mut stdout, "out")
+            }
+            BridgeResponse::StderrChunk(bytes) => {
+                stderr.push_str(&String::from_utf8_lossy(&bytes));
+                (&mut stderr, "err")
+            }
+            BridgeResponse::StreamEnd { code: Some(0), .. } => break,
+            other => panic!("unexpected {:?}", other),
+        };
+        while let Some(end) = pending.find('\n') {
+            let line: String = pending.drain(..=end).collect();
+            let number = line.trim().strip_prefix(name).unwrap_or_default().trim();
+            arrived.push(format!("{} {}", name, number));
+        }
+    }
+
+    let written: Vec<String> = (0..LINES)
+        .flat_map(|i| [format!("out {}", i), format!("err {}", i)])
+        .collect();
+    assert_eq!(arrived.len(), written.len());
+    let misplaced = arrived
+        .iter()
+        .zip(&written)
+        .filter(|(arrived, written)| arrived != written)
+        .count();
+    assert!(
+        misplaced <= TOLERATED,
+        "{} lines out of order, at most {} tolerated",
+        misplaced,
+        TOLERATED
+    );
+}